@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+CREATE TABLE loans
+(
+    id BIGSERIAL PRIMARY KEY,
+    asset_id BIGINT NOT NULL REFERENCES assets(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    location_id BIGINT NULL REFERENCES locations(id),
+    room_id BIGINT NULL REFERENCES rooms(id),
+    alert_id BIGINT NULL REFERENCES alerts(id),
+    due_at TIMESTAMP NOT NULL,
+    returned_at TIMESTAMP NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- An asset can only be checked out once at a time
+CREATE UNIQUE INDEX loans_open_asset_id ON loans (asset_id) WHERE returned_at IS NULL
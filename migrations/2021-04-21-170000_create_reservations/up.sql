@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+CREATE TABLE reservations
+(
+    id BIGSERIAL PRIMARY KEY,
+    asset_id BIGINT NOT NULL REFERENCES assets(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    series_id BIGINT NULL,
+    starts_at TIMESTAMP NOT NULL,
+    ends_at TIMESTAMP NOT NULL,
+    note TEXT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK (starts_at < ends_at)
+);
+
+CREATE INDEX reservations_asset_id_starts_at ON reservations (asset_id, starts_at)
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE maintenance_records;
+DROP TABLE maintenance_plans
\ No newline at end of file
@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+CREATE TABLE maintenance_plans
+(
+    id BIGSERIAL PRIMARY KEY,
+    asset_id BIGINT NOT NULL REFERENCES assets(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    alert_id BIGINT NULL REFERENCES alerts(id),
+    name VARCHAR NOT NULL,
+    interval_days INTEGER NOT NULL CHECK (interval_days > 0),
+    next_due_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE maintenance_records
+(
+    id BIGSERIAL PRIMARY KEY,
+    asset_id BIGINT NOT NULL REFERENCES assets(id),
+    maintenance_plan_id BIGINT NULL REFERENCES maintenance_plans(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    performed_at TIMESTAMP NOT NULL,
+    notes TEXT NULL,
+    certificate TEXT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+CREATE TABLE attachments
+(
+    id BIGSERIAL PRIMARY KEY,
+    asset_id BIGINT NOT NULL REFERENCES assets(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    filename VARCHAR NOT NULL,
+    content_type VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    sha256 VARCHAR NOT NULL,
+    storage_key VARCHAR NOT NULL UNIQUE,
+    thumbnail_key VARCHAR NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX attachments_asset_id ON attachments(asset_id)
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE comment_revisions;
+
+DELETE FROM comments WHERE asset_tag_id IS NULL;
+
+ALTER TABLE comments
+    DROP CONSTRAINT comments_one_target,
+    DROP COLUMN parent_id,
+    DROP COLUMN alert_id,
+    DROP COLUMN location_id,
+    DROP COLUMN asset_id,
+    ALTER COLUMN asset_tag_id SET NOT NULL
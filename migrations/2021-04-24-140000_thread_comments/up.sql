@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+ALTER TABLE comments
+    ALTER COLUMN asset_tag_id DROP NOT NULL,
+    ADD COLUMN asset_id BIGINT NULL REFERENCES assets(id),
+    ADD COLUMN location_id BIGINT NULL REFERENCES locations(id),
+    ADD COLUMN alert_id BIGINT NULL REFERENCES alerts(id),
+    ADD COLUMN parent_id BIGINT NULL REFERENCES comments(id) ON DELETE CASCADE,
+    ADD CONSTRAINT comments_one_target
+        CHECK (num_nonnulls(asset_tag_id, asset_id, location_id, alert_id) = 1);
+
+CREATE INDEX comments_parent_id ON comments(parent_id);
+
+CREATE TABLE comment_revisions
+(
+    id BIGSERIAL PRIMARY KEY,
+    comment_id BIGINT NOT NULL REFERENCES comments(id) ON DELETE CASCADE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    content TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX comment_revisions_comment_id ON comment_revisions(comment_id)
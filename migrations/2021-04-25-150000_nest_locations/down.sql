@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE rooms
+    DROP CONSTRAINT rooms_location_id_name,
+    ADD CONSTRAINT rooms_name_key UNIQUE (name);
+
+ALTER TABLE locations
+    DROP CONSTRAINT locations_parent_id_not_self,
+    DROP CONSTRAINT locations_parent_id_name,
+    DROP COLUMN kind,
+    DROP COLUMN parent_id
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Existing locations become the buildings at the root of the tree
+ALTER TABLE locations
+    ADD COLUMN parent_id BIGINT NULL REFERENCES locations(id),
+    ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'building'
+        CHECK (kind IN ('campus', 'building', 'floor')),
+    ADD CONSTRAINT locations_parent_id_name UNIQUE (parent_id, name),
+    ADD CONSTRAINT locations_parent_id_not_self CHECK (parent_id <> id);
+
+CREATE INDEX locations_parent_id ON locations(parent_id);
+
+-- Rooms are the leaves of the tree, named uniquely within the location they belong to
+ALTER TABLE rooms
+    DROP CONSTRAINT rooms_name_key,
+    ADD CONSTRAINT rooms_location_id_name UNIQUE (location_id, name)
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE locations DROP COLUMN footprint
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- A ring of [longitude, latitude] points outlining the location, as in GeoJSON
+ALTER TABLE locations ADD COLUMN footprint JSONB NULL
\ No newline at end of file
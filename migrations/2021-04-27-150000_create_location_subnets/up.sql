@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+-- Subnets whose addresses are at a location, matched by longest prefix
+CREATE TABLE location_subnets
+(
+    id BIGSERIAL PRIMARY KEY,
+    location_id BIGINT NOT NULL REFERENCES locations(id) ON DELETE CASCADE,
+    subnet INET NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX location_subnets_location_id ON location_subnets(location_id)
\ No newline at end of file
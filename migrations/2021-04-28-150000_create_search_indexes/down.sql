@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX comments_search;
+DROP INDEX rooms_search;
+DROP INDEX locations_search;
+DROP INDEX attachments_search;
+DROP INDEX asset_tags_search
\ No newline at end of file
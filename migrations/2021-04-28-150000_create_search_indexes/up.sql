@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Each expression must match the document of its table in src/search/model.rs for the index to be used
+CREATE INDEX asset_tags_search ON asset_tags USING GIN ((
+    setweight(to_tsvector('english', name), 'A')
+    || setweight(to_tsvector('english', serial_number), 'A')
+    || setweight(to_tsvector('english', coalesce(description, '')), 'B')
+));
+
+CREATE INDEX attachments_search ON attachments USING GIN ((to_tsvector('english', filename)));
+
+CREATE INDEX locations_search ON locations USING GIN ((to_tsvector('english', coalesce(name, ''))));
+
+CREATE INDEX rooms_search ON rooms USING GIN ((to_tsvector('english', name)));
+
+CREATE INDEX comments_search ON comments USING GIN ((to_tsvector('english', content)))
\ No newline at end of file
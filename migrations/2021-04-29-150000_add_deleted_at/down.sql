@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE assets DROP COLUMN deleted_at;
+ALTER TABLE asset_tags DROP COLUMN deleted_at;
+ALTER TABLE contact_events DROP COLUMN deleted_at
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- When a row was soft-deleted, so the retention job can purge it after a while
+ALTER TABLE assets ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE asset_tags ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE contact_events ADD COLUMN deleted_at TIMESTAMP NULL;
+
+UPDATE assets SET deleted_at = updated_at WHERE deleted;
+UPDATE asset_tags SET deleted_at = updated_at WHERE deleted;
+UPDATE contact_events SET deleted_at = updated_at WHERE deleted
\ No newline at end of file
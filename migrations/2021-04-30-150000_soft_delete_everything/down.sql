@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE alerts DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE asset_scanners DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE comments DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE locations DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE roles DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE rooms DROP COLUMN deleted, DROP COLUMN deleted_at;
+ALTER TABLE users DROP COLUMN deleted, DROP COLUMN deleted_at
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Every table soft-deletes now, like assets, asset_tags and contact_events
+ALTER TABLE alerts ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE asset_scanners ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE comments ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE locations ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE roles ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE rooms ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL;
+ALTER TABLE users ADD COLUMN deleted BOOLEAN NOT NULL DEFAULT FALSE, ADD COLUMN deleted_at TIMESTAMP NULL
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- Tokens that only read calendar feeds, so the API token never goes in a URL.
+-- Only a hash is kept, and a user has at most one, replaced when reissued
+CREATE TABLE feed_tokens
+(
+    user_id BIGINT PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)
\ No newline at end of file
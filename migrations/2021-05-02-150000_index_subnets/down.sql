@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+DROP INDEX locations_ip;
+
+DROP INDEX location_subnets_subnet
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Resolving an address looks up the subnets that contain it
+CREATE INDEX location_subnets_subnet ON location_subnets USING gist (subnet inet_ops);
+
+CREATE INDEX locations_ip ON locations USING gist (ip inet_ops)
\ No newline at end of file
@@ -1,13 +1,28 @@
 use crate::db;
 use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::alerts;
+use crate::soft_delete::{soft_delete_methods, Reference};
 use crate::users::User;
+use crate::validation::{self, MAX_NAME, MAX_TEXT};
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(User)]
 #[table_name = "alerts"]
@@ -18,26 +33,67 @@ pub struct Alert {
     pub user_id: i64,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "alerts"]
+#[validate(context = PgConnection)]
 pub struct MaybeAlert {
+    #[validate(length(max = MAX_TEXT))]
     pub message: Option<String>,
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub reason: String,
+    #[validate(custom(function = "validation::user_exists", use_context))]
     pub user_id: i64,
 }
 
+// Foreign keys that keep an alert from being purged
+const REFERENCES: &[Reference] = &[
+    Reference {
+        table: "comments",
+        column: "alert_id",
+    },
+    Reference {
+        table: "contact_events",
+        column: "alert_id",
+    },
+    Reference {
+        table: "loans",
+        column: "alert_id",
+    },
+    Reference {
+        table: "maintenance_plans",
+        column: "alert_id",
+    },
+];
+
 impl Alert {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let alerts = alerts::table.load::<Alert>(&conn)?;
+        let alerts = alerts::table
+            .filter(alerts::deleted.eq(false))
+            .load::<Alert>(&conn)?;
         Ok(alerts)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            alerts::table.filter(alerts::deleted.eq(false)),
+            alerts::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let alert = alerts::table.filter(alerts::id.eq(id)).first(&conn)?;
+        let alert = alerts::table
+            .filter(alerts::id.eq(id))
+            .filter(alerts::deleted.eq(false))
+            .first(&conn)?;
         Ok(alert)
     }
 
@@ -45,6 +101,7 @@ impl Alert {
         let conn = db::connection()?;
         let alerts = alerts::table
             .filter(alerts::user_id.eq(id))
+            .filter(alerts::deleted.eq(false))
             .load::<Alert>(&conn)?;
         Ok(alerts)
     }
@@ -58,17 +115,42 @@ impl Alert {
     }
 
     pub fn update(id: i64, alert: MaybeAlert) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(alert))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAlert, CustomError>,
+    {
         let conn = db::connection()?;
-        let alert = diesel::update(alerts::table)
-            .filter(alerts::id.eq(id))
-            .set(alert)
-            .get_result(&conn)?;
-        Ok(alert)
+        conn.transaction(|| {
+            let alert = changes(&conn, &Self::lock(&conn, id)?)?;
+            let alert = diesel::update(alerts::table.find(id))
+                .set(alert)
+                .get_result(&conn)?;
+            Ok(alert)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAlert, CustomError>,
+    {
         let conn = db::connection()?;
-        let res = diesel::delete(alerts::table.filter(alerts::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        conn.transaction(|| {
+            let alert = changes(&conn, &Self::lock(&conn, id)?)?;
+            let alert = diesel::update(alerts::table.find(id))
+                .set((
+                    alerts::message.eq(alert.message),
+                    alerts::reason.eq(alert.reason),
+                    alerts::user_id.eq(alert.user_id),
+                ))
+                .get_result(&conn)?;
+            Ok(alert)
+        })
     }
+
+    soft_delete_methods!(alerts, Alert, "Alert", REFERENCES);
 }
@@ -1,63 +1,264 @@
 use crate::alerts::{Alert, MaybeAlert};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::include::{self, IncludeQuery};
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/alerts",
+    params(
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The alerts", body = [Alert]),
+    )
+)]
 #[get("/alerts")]
-async fn find_all() -> Result<HttpResponse, CustomError> {
-    let alerts = Alert::find_all()?;
+async fn find_all(query: web::Query<IncludeQuery>) -> Result<HttpResponse, CustomError> {
+    let alerts = db::run(move || include::alerts(Alert::find_all()?, &query.include)).await?;
     Ok(HttpResponse::Ok().json(alerts))
 }
 
+#[utoipa::path(
+    get,
+    path = "/alerts/all",
+    responses(
+        (status = 200, description = "The alerts, deleted or not", body = [Alert]),
+    )
+)]
+#[get("/alerts/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let alerts = db::run(Alert::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(alerts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/alerts/deleted",
+    responses(
+        (status = 200, description = "The deleted alerts", body = [Alert]),
+    )
+)]
+#[get("/alerts/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let alerts = db::run(Alert::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(alerts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/alerts/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The alert", body = Alert),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/alerts/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("GET /alerts/id/{}", &id);
-    let alert = Alert::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(alert))
+    log::trace!("GET /alerts/id/{} {:?}", &id, &query);
+    let alert =
+        db::run(move || include::single(Alert::find_by_id(id)?, &query.include, include::alerts))
+            .await?;
+    etag::respond(&req, &alert)
 }
 
+#[utoipa::path(
+    get,
+    path = "/alerts/user/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The alerts of the user", body = [Alert]),
+    )
+)]
 #[get("/alerts/user/{id}")]
 async fn find_by_user(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /alerts/user/{}", &id);
-    let alerts = Alert::find_by_user(id)?;
+    let alerts = db::run(move || Alert::find_by_user(id)).await?;
     Ok(HttpResponse::Ok().json(alerts))
 }
 
+#[utoipa::path(
+    post,
+    path = "/alerts",
+    request_body = MaybeAlert,
+    responses(
+        (status = 200, description = "The created alert", body = Alert),
+    )
+)]
 #[post("/alerts")]
 async fn create(alert: web::Json<MaybeAlert>) -> Result<HttpResponse, CustomError> {
     let alert = alert.into_inner();
     log::trace!("POST /alerts/ {:?}", &alert);
-    let alert = Alert::create(alert)?;
+    let alert = validation::validated(alert).await?;
+    let alert = db::run(move || Alert::create(alert)).await?;
     Ok(HttpResponse::Ok().json(alert))
 }
 
+#[utoipa::path(
+    put,
+    path = "/alerts/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+    ),
+    request_body = MaybeAlert,
+    responses(
+        (status = 200, description = "The updated alert", body = Alert),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/alerts/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     alert: web::Json<MaybeAlert>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let alert = alert.into_inner();
     log::trace!("PUT /alerts/{} {:?}", &id, &alert);
-    let alert = Alert::update(id, alert)?;
-    Ok(HttpResponse::Ok().json(alert))
+    let alert = validation::validated(alert).await?;
+    let precondition = etag::precondition(&req)?;
+    let alert = db::run(move || {
+        Alert::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(alert)
+        })
+    })
+    .await?;
+    etag::ok(&alert)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/alerts/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated alert", body = Alert),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/alerts/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /alerts/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let alert = db::run(move || {
+        Alert::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&alert)
+}
+
+#[utoipa::path(
+    post,
+    path = "/alerts/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+    ),
+    responses(
+        (status = 200, description = "The restored alert", body = Alert),
+    )
+)]
+#[post("/alerts/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /alerts/{}/restore", &id);
+    let res = db::run(move || Alert::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/alerts/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted alert", body = Alert),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/alerts/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /alerts/{}", &id);
-    let res = Alert::delete(id)?;
+    log::trace!("DELETE /alerts/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            Alert::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Alert::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_user,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(find_by_id);
     comfig.service(find_by_user);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
@@ -1,36 +1,64 @@
 use crate::db;
 use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::asset_scanners;
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation::MAX_NAME;
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable)]
+#[derive(
+    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, ToSchema,
+)]
 #[table_name = "asset_scanners"]
 pub struct AssetScanner {
     pub id: i64,
     pub name: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "asset_scanners"]
+#[validate(context = PgConnection)]
 pub struct MaybeAssetScanner {
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub name: String,
 }
 
+// Nothing references asset scanners, so they can always be purged
+const REFERENCES: &[Reference] = &[];
+
 impl AssetScanner {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let asset_scanners = asset_scanners::table.load::<AssetScanner>(&conn)?;
+        let asset_scanners = asset_scanners::table
+            .filter(asset_scanners::deleted.eq(false))
+            .load::<AssetScanner>(&conn)?;
         Ok(asset_scanners)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            asset_scanners::table.filter(asset_scanners::deleted.eq(false)),
+            asset_scanners::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_name(name: String) -> Result<Self, CustomError> {
         let conn = db::connection()?;
         let asset_scanner = asset_scanners::table
             .filter(asset_scanners::name.eq(name))
+            .filter(asset_scanners::deleted.eq(false))
             .first(&conn)?;
         Ok(asset_scanner)
     }
@@ -39,6 +67,7 @@ impl AssetScanner {
         let conn = db::connection()?;
         let asset_scanner = asset_scanners::table
             .filter(asset_scanners::id.eq(id))
+            .filter(asset_scanners::deleted.eq(false))
             .first(&conn)?;
         Ok(asset_scanner)
     }
@@ -52,18 +81,23 @@ impl AssetScanner {
     }
 
     pub fn update(id: i64, asset_scanner: MaybeAssetScanner) -> Result<Self, CustomError> {
-        let conn = db::connection()?;
-        let asset_scanner = diesel::update(asset_scanners::table)
-            .filter(asset_scanners::id.eq(id))
-            .set(asset_scanner)
-            .get_result(&conn)?;
-        Ok(asset_scanner)
+        Self::update_with(id, |_, _| Ok(asset_scanner))
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAssetScanner, CustomError>,
+    {
         let conn = db::connection()?;
-        let res = diesel::delete(asset_scanners::table.filter(asset_scanners::id.eq(id)))
-            .execute(&conn)?;
-        Ok(res)
+        conn.transaction(|| {
+            let asset_scanner = changes(&conn, &Self::lock(&conn, id)?)?;
+            let asset_scanner = diesel::update(asset_scanners::table.find(id))
+                .set(asset_scanner)
+                .get_result(&conn)?;
+            Ok(asset_scanner)
+        })
     }
+
+    soft_delete_methods!(asset_scanners, AssetScanner, "Asset scanner", REFERENCES);
 }
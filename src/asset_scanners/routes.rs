@@ -1,63 +1,257 @@
 use crate::asset_scanners::{AssetScanner, MaybeAssetScanner};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/asset_scanners",
+    responses(
+        (status = 200, description = "The asset scanners", body = [AssetScanner]),
+    )
+)]
 #[get("/asset_scanners")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
-    let asset_scanners = AssetScanner::find_all()?;
+    let asset_scanners = db::run(AssetScanner::find_all).await?;
     Ok(HttpResponse::Ok().json(asset_scanners))
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_scanners/all",
+    responses(
+        (status = 200, description = "The asset scanners, deleted or not", body = [AssetScanner]),
+    )
+)]
+#[get("/asset_scanners/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let asset_scanners = db::run(AssetScanner::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(asset_scanners))
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_scanners/deleted",
+    responses(
+        (status = 200, description = "The deleted asset scanners", body = [AssetScanner]),
+    )
+)]
+#[get("/asset_scanners/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let asset_scanners = db::run(AssetScanner::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(asset_scanners))
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_scanners/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset scanner"),
+    ),
+    responses(
+        (status = 200, description = "The asset scanner", body = AssetScanner),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/asset_scanners/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /asset_scanners/id/{}", &id);
-    let asset_scanner = AssetScanner::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(asset_scanner))
+    let asset_scanner = db::run(move || AssetScanner::find_by_id(id)).await?;
+    etag::respond(&req, &asset_scanner)
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_scanners/name/{name}",
+    params(
+        ("name" = String, Path, description = "Name of the asset scanner"),
+    ),
+    responses(
+        (status = 200, description = "The asset scanner with the name", body = AssetScanner),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/asset_scanners/name/{name}")]
-async fn find_by_name(name: web::Path<String>) -> Result<HttpResponse, CustomError> {
+async fn find_by_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
     let name = name.into_inner();
     log::trace!("GET /asset_scanners/name/{}", &name);
-    let asset_scanner = AssetScanner::find_by_name(name)?;
-    Ok(HttpResponse::Ok().json(asset_scanner))
+    let asset_scanner = db::run(move || AssetScanner::find_by_name(name)).await?;
+    etag::respond(&req, &asset_scanner)
 }
 
+#[utoipa::path(
+    post,
+    path = "/asset_scanners",
+    request_body = MaybeAssetScanner,
+    responses(
+        (status = 200, description = "The created asset scanner", body = AssetScanner),
+    )
+)]
 #[post("/asset_scanners")]
 async fn create(asset_scanner: web::Json<MaybeAssetScanner>) -> Result<HttpResponse, CustomError> {
     let asset_scanner = asset_scanner.into_inner();
     log::trace!("POST /asset_scanners/ {:?}", &asset_scanner);
-    let asset_scanner = AssetScanner::create(asset_scanner)?;
+    let asset_scanner = validation::validated(asset_scanner).await?;
+    let asset_scanner = db::run(move || AssetScanner::create(asset_scanner)).await?;
     Ok(HttpResponse::Ok().json(asset_scanner))
 }
 
+#[utoipa::path(
+    put,
+    path = "/asset_scanners/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset scanner"),
+    ),
+    request_body = MaybeAssetScanner,
+    responses(
+        (status = 200, description = "The updated asset scanner", body = AssetScanner),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/asset_scanners/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     asset_scanner: web::Json<MaybeAssetScanner>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let asset_scanner = asset_scanner.into_inner();
     log::trace!("PUT /asset_scanners/{} {:?}", &id, &asset_scanner);
-    let asset_scanner = AssetScanner::update(id, asset_scanner)?;
-    Ok(HttpResponse::Ok().json(asset_scanner))
+    let asset_scanner = validation::validated(asset_scanner).await?;
+    let precondition = etag::precondition(&req)?;
+    let asset_scanner = db::run(move || {
+        AssetScanner::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(asset_scanner)
+        })
+    })
+    .await?;
+    etag::ok(&asset_scanner)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/asset_scanners/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset scanner"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated asset scanner", body = AssetScanner),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/asset_scanners/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /asset_scanners/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let asset_scanner = db::run(move || {
+        AssetScanner::update_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&asset_scanner)
+}
+
+#[utoipa::path(
+    post,
+    path = "/asset_scanners/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the asset scanner"),
+    ),
+    responses(
+        (status = 200, description = "The restored asset scanner", body = AssetScanner),
+    )
+)]
+#[post("/asset_scanners/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /asset_scanners/{}/restore", &id);
+    let res = db::run(move || AssetScanner::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/asset_scanners/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset scanner"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted asset scanner", body = AssetScanner),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/asset_scanners/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /asset_scanners/{}", &id);
-    let res = AssetScanner::delete(id)?;
+    log::trace!("DELETE /asset_scanners/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            AssetScanner::purge_checked(id, |current| precondition.check(current))
+        } else {
+            AssetScanner::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_name,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(find_by_id);
     comfig.service(find_by_name);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
@@ -1,13 +1,29 @@
 use crate::assets::Asset;
 use crate::db;
 use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::asset_tags;
+use crate::soft_delete::{self, soft_delete_methods, Reference};
+use crate::validation::{MAX_NAME, MAX_TEXT};
 use chrono::NaiveDateTime;
+use diesel::dsl::now;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(Asset)]
 #[table_name = "asset_tags"]
@@ -20,18 +36,40 @@ pub struct AssetTag {
     pub updated_at: NaiveDateTime,
     pub asset_id: Option<i64>,
     pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "asset_tags"]
+#[validate(context = PgConnection)]
 pub struct MaybeAssetTag {
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub name: String,
+    #[validate(length(max = MAX_TEXT))]
     pub description: Option<String>,
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub serial_number: String,
+    #[validate(range(min = 1))]
     pub asset_id: Option<i64>,
     pub deleted: bool,
 }
 
+// Foreign keys that keep a asset tag from being purged
+const REFERENCES: &[Reference] = &[
+    Reference {
+        table: "assets",
+        column: "asset_tag_id",
+    },
+    Reference {
+        table: "comments",
+        column: "asset_tag_id",
+    },
+    Reference {
+        table: "contact_events",
+        column: "asset_tag_id",
+    },
+];
+
 impl AssetTag {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
@@ -41,24 +79,29 @@ impl AssetTag {
         Ok(asset_tags)
     }
 
-    pub fn find_with_deleted() -> Result<Vec<Self>, CustomError> {
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
         let conn = db::connection()?;
-        let asset_tags = asset_tags::table.load::<AssetTag>(&conn)?;
-        Ok(asset_tags)
+        paginate!(
+            asset_tags::table.filter(asset_tags::deleted.eq(false)),
+            asset_tags::id,
+            page,
+            &conn
+        )
     }
 
-    pub fn find_deleted() -> Result<Vec<Self>, CustomError> {
+    pub fn find_by_name(name: String) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let asset_tags = asset_tags::table
-            .filter(asset_tags::deleted.eq(true))
-            .load::<AssetTag>(&conn)?;
-        Ok(asset_tags)
+        let asset_tag = asset_tags::table
+            .filter(asset_tags::name.eq(name))
+            .filter(asset_tags::deleted.eq(false))
+            .first(&conn)?;
+        Ok(asset_tag)
     }
 
-    pub fn find_by_name(name: String) -> Result<Self, CustomError> {
+    pub fn find_by_serial_number(serial_number: String) -> Result<Self, CustomError> {
         let conn = db::connection()?;
         let asset_tag = asset_tags::table
-            .filter(asset_tags::name.eq(name))
+            .filter(asset_tags::serial_number.eq(serial_number))
             .filter(asset_tags::deleted.eq(false))
             .first(&conn)?;
         Ok(asset_tag)
@@ -91,32 +134,62 @@ impl AssetTag {
     }
 
     pub fn update(id: i64, asset_tag: MaybeAssetTag) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(asset_tag))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAssetTag, CustomError>,
+    {
         let conn = db::connection()?;
-        let asset_tag = diesel::update(asset_tags::table)
-            .filter(asset_tags::id.eq(id))
-            .filter(asset_tags::deleted.eq(false))
-            .set(asset_tag)
-            .get_result(&conn)?;
-        Ok(asset_tag)
+        conn.transaction(|| {
+            let asset_tag = changes(&conn, &Self::lock(&conn, id)?)?;
+            let asset_tag = diesel::update(asset_tags::table.find(id))
+                .set(asset_tag)
+                .get_result(&conn)?;
+            Ok(asset_tag)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<Self, CustomError> {
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAssetTag, CustomError>,
+    {
         let conn = db::connection()?;
-        let asset_tags = diesel::update(asset_tags::table)
-            .filter(asset_tags::id.eq(id))
-            .filter(asset_tags::deleted.eq(false))
-            .set(asset_tags::deleted.eq(true))
-            .get_result(&conn)?;
-        Ok(asset_tags)
+        conn.transaction(|| {
+            let asset_tag = changes(&conn, &Self::lock(&conn, id)?)?;
+            let asset_tag = diesel::update(asset_tags::table.find(id))
+                .set((
+                    asset_tags::name.eq(asset_tag.name),
+                    asset_tags::description.eq(asset_tag.description),
+                    asset_tags::serial_number.eq(asset_tag.serial_number),
+                    asset_tags::asset_id.eq(asset_tag.asset_id),
+                    asset_tags::deleted.eq(asset_tag.deleted),
+                ))
+                .get_result(&conn)?;
+            Ok(asset_tag)
+        })
     }
 
     pub fn delete_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let asset_tags = diesel::update(asset_tags::table)
-            .filter(asset_tags::asset_id.eq(id))
-            .filter(asset_tags::deleted.eq(false))
-            .set(asset_tags::deleted.eq(true))
-            .load::<AssetTag>(&conn)?;
-        Ok(asset_tags)
+        conn.transaction(|| {
+            let asset_tags = diesel::update(asset_tags::table)
+                .filter(asset_tags::asset_id.eq(id))
+                .filter(asset_tags::deleted.eq(false))
+                .set((
+                    asset_tags::deleted.eq(true),
+                    asset_tags::deleted_at.eq(now.nullable()),
+                ))
+                .load::<AssetTag>(&conn)?;
+            for asset_tag in &asset_tags {
+                soft_delete::cascade(&conn, "asset_tags", asset_tag.id, asset_tag.deleted_at)?;
+            }
+            Ok(asset_tags)
+        })
     }
+
+    soft_delete_methods!(asset_tags, AssetTag, "Asset tag", REFERENCES);
 }
@@ -1,86 +1,303 @@
 use crate::asset_tags::{AssetTag, MaybeAssetTag};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::include::{self, IncludeQuery};
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags",
+    params(
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The asset tags", body = [AssetTag]),
+    )
+)]
 #[get("/asset_tags")]
-async fn find_all() -> Result<HttpResponse, CustomError> {
-    let asset_tags = AssetTag::find_all()?;
+async fn find_all(query: web::Query<IncludeQuery>) -> Result<HttpResponse, CustomError> {
+    let asset_tags =
+        db::run(move || include::asset_tags(AssetTag::find_all()?, &query.include)).await?;
     Ok(HttpResponse::Ok().json(asset_tags))
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags/all",
+    responses(
+        (status = 200, description = "The asset tags, deleted or not", body = [AssetTag]),
+    )
+)]
 #[get("/asset_tags/all")]
 async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
-    let asset_tags = AssetTag::find_with_deleted()?;
+    let asset_tags = db::run(AssetTag::find_with_deleted).await?;
     Ok(HttpResponse::Ok().json(asset_tags))
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags/deleted",
+    responses(
+        (status = 200, description = "The deleted asset tags", body = [AssetTag]),
+    )
+)]
 #[get("/asset_tags/deleted")]
 async fn find_deleted() -> Result<HttpResponse, CustomError> {
-    let asset_tags = AssetTag::find_deleted()?;
+    let asset_tags = db::run(AssetTag::find_deleted).await?;
     Ok(HttpResponse::Ok().json(asset_tags))
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The asset tag", body = AssetTag),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/asset_tags/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("GET /asset_tags/id/{}", &id);
-    let asset_tag = AssetTag::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(asset_tag))
+    log::trace!("GET /asset_tags/id/{} {:?}", &id, &query);
+    let asset_tag = db::run(move || {
+        include::single(
+            AssetTag::find_by_id(id)?,
+            &query.include,
+            include::asset_tags,
+        )
+    })
+    .await?;
+    etag::respond(&req, &asset_tag)
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags/name/{name}",
+    params(
+        ("name" = String, Path, description = "Name of the asset tag"),
+    ),
+    responses(
+        (status = 200, description = "The asset tag with the name", body = AssetTag),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/asset_tags/name/{name}")]
-async fn find_by_name(name: web::Path<String>) -> Result<HttpResponse, CustomError> {
+async fn find_by_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
     let name = name.into_inner();
     log::trace!("GET /asset_tags/name/{}", &name);
-    let asset_tag = AssetTag::find_by_name(name)?;
-    Ok(HttpResponse::Ok().json(asset_tag))
+    let asset_tag = db::run(move || AssetTag::find_by_name(name)).await?;
+    etag::respond(&req, &asset_tag)
 }
 
+#[utoipa::path(
+    get,
+    path = "/asset_tags/asset_id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The asset tags of the asset", body = [AssetTag]),
+    )
+)]
 #[get("/asset_tags/asset_id/{id}")]
 async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /asset_tags/asset_id/{}", &id);
-    let asset_tags = AssetTag::find_by_asset(id)?;
+    let asset_tags = db::run(move || AssetTag::find_by_asset(id)).await?;
     Ok(HttpResponse::Ok().json(asset_tags))
 }
 
+#[utoipa::path(
+    post,
+    path = "/asset_tags",
+    request_body = MaybeAssetTag,
+    responses(
+        (status = 200, description = "The created asset tag", body = AssetTag),
+    )
+)]
 #[post("/asset_tags")]
 async fn create(asset_tag: web::Json<MaybeAssetTag>) -> Result<HttpResponse, CustomError> {
     let asset_tag = asset_tag.into_inner();
     log::trace!("POST /asset_tags/ {:?}", &asset_tag);
-    let asset_tag = AssetTag::create(asset_tag)?;
+    let asset_tag = validation::validated(asset_tag).await?;
+    let asset_tag = db::run(move || AssetTag::create(asset_tag)).await?;
     Ok(HttpResponse::Ok().json(asset_tag))
 }
 
+#[utoipa::path(
+    put,
+    path = "/asset_tags/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    request_body = MaybeAssetTag,
+    responses(
+        (status = 200, description = "The updated asset tag", body = AssetTag),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/asset_tags/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     asset_tag: web::Json<MaybeAssetTag>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let asset_tag = asset_tag.into_inner();
     log::trace!("PUT /asset_tags/{} {:?}", &id, &asset_tag);
-    let asset_tag = AssetTag::update(id, asset_tag)?;
-    Ok(HttpResponse::Ok().json(asset_tag))
+    let asset_tag = validation::validated(asset_tag).await?;
+    let precondition = etag::precondition(&req)?;
+    let asset_tag = db::run(move || {
+        AssetTag::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(asset_tag)
+        })
+    })
+    .await?;
+    etag::ok(&asset_tag)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/asset_tags/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated asset tag", body = AssetTag),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/asset_tags/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /asset_tags/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let asset_tag = db::run(move || {
+        AssetTag::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&asset_tag)
+}
+
+#[utoipa::path(
+    post,
+    path = "/asset_tags/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    responses(
+        (status = 200, description = "The restored asset tag", body = AssetTag),
+    )
+)]
+#[post("/asset_tags/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /asset_tags/{}/restore", &id);
+    let res = db::run(move || AssetTag::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/asset_tags/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted asset tag", body = AssetTag),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/asset_tags/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /asset_tags/{}", &id);
-    let res = AssetTag::delete(id)?;
+    log::trace!("DELETE /asset_tags/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            AssetTag::purge_checked(id, |current| precondition.check(current))
+        } else {
+            AssetTag::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/asset_tags/asset_id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The deleted asset tags", body = [AssetTag]),
+    )
+)]
 #[delete("/asset_tags/asset_id/{id}")]
 async fn delete_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("DELETE /asset_tags/asset_id/{}", &id);
-    let res = AssetTag::delete_by_asset(id)?;
+    let res = db::run(move || AssetTag::delete_by_asset(id)).await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_asset,
+    find_by_name,
+    create,
+    update,
+    patch,
+    restore,
+    delete,
+    delete_by_asset
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
     comfig.service(find_with_deleted);
@@ -90,6 +307,8 @@ pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_by_name);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
     comfig.service(delete_by_asset);
 }
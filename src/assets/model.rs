@@ -1,13 +1,30 @@
 use crate::asset_tags::AssetTag;
+use crate::contact_events::ContactEvent;
 use crate::db;
 use crate::error_handler::CustomError;
-use crate::schema::assets;
+use crate::locations::Location;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::schema::{asset_tags, assets, loans, locations, rooms};
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation;
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(AssetTag)]
 #[table_name = "assets"]
@@ -17,15 +34,65 @@ pub struct Asset {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "assets"]
+#[validate(context = PgConnection)]
 pub struct MaybeAsset {
+    #[validate(custom(function = "validation::asset_tag_exists", use_context))]
     pub asset_tag_id: Option<i64>,
     pub deleted: bool,
 }
 
+// Where an asset was last seen, according to the latest contact event of its tag
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AssetPosition {
+    pub asset_id: i64,
+    pub asset_tag_id: i64,
+    pub asset_tag_name: String,
+    pub location_id: i64,
+    pub location_name: Option<String>,
+    pub room_id: Option<i64>,
+    pub room_name: Option<String>,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub seen_at: NaiveDateTime,
+}
+
+// Foreign keys that keep an asset from being purged
+const REFERENCES: &[Reference] = &[
+    Reference {
+        table: "asset_tags",
+        column: "asset_id",
+    },
+    Reference {
+        table: "attachments",
+        column: "asset_id",
+    },
+    Reference {
+        table: "comments",
+        column: "asset_id",
+    },
+    Reference {
+        table: "loans",
+        column: "asset_id",
+    },
+    Reference {
+        table: "maintenance_plans",
+        column: "asset_id",
+    },
+    Reference {
+        table: "maintenance_records",
+        column: "asset_id",
+    },
+    Reference {
+        table: "reservations",
+        column: "asset_id",
+    },
+];
+
 impl Asset {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
@@ -35,18 +102,14 @@ impl Asset {
         Ok(assets)
     }
 
-    pub fn find_with_deleted() -> Result<Vec<Self>, CustomError> {
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
         let conn = db::connection()?;
-        let assets = assets::table.load::<Asset>(&conn)?;
-        Ok(assets)
-    }
-
-    pub fn find_deleted() -> Result<Vec<Self>, CustomError> {
-        let conn = db::connection()?;
-        let assets = assets::table
-            .filter(assets::deleted.eq(true))
-            .load::<Asset>(&conn)?;
-        Ok(assets)
+        paginate!(
+            assets::table.filter(assets::deleted.eq(false)),
+            assets::id,
+            page,
+            &conn
+        )
     }
 
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
@@ -76,22 +139,110 @@ impl Asset {
     }
 
     pub fn update(id: i64, asset: MaybeAsset) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(asset))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAsset, CustomError>,
+    {
         let conn = db::connection()?;
-        let asset = diesel::update(assets::table)
-            .filter(assets::id.eq(id))
-            .filter(assets::deleted.eq(false))
-            .set(asset)
-            .get_result(&conn)?;
-        Ok(asset)
+        conn.transaction(|| {
+            let asset = changes(&conn, &Self::lock(&conn, id)?)?;
+            let asset = diesel::update(assets::table.find(id))
+                .set(asset)
+                .get_result(&conn)?;
+            Ok(asset)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<Self, CustomError> {
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeAsset, CustomError>,
+    {
         let conn = db::connection()?;
-        let asset = diesel::update(assets::table)
-            .filter(assets::id.eq(id))
+        conn.transaction(|| {
+            let asset = changes(&conn, &Self::lock(&conn, id)?)?;
+            let asset = diesel::update(assets::table.find(id))
+                .set((
+                    assets::asset_tag_id.eq(asset.asset_tag_id),
+                    assets::deleted.eq(asset.deleted),
+                ))
+                .get_result(&conn)?;
+            Ok(asset)
+        })
+    }
+
+    // Last known positions of assets, optionally only those seen at one of the locations,
+    // along with the room an asset is lent out to
+    pub fn find_positions(location_ids: Option<&[i64]>) -> Result<Vec<AssetPosition>, CustomError> {
+        let contact_events: Vec<ContactEvent> = ContactEvent::find_latest()?
+            .into_iter()
+            .filter(|contact_event| {
+                location_ids.is_none_or(|ids| ids.contains(&contact_event.location_id))
+            })
+            .collect();
+        let asset_tag_ids: Vec<i64> = contact_events
+            .iter()
+            .map(|contact_event| contact_event.asset_tag_id)
+            .collect();
+        let location_ids: Vec<i64> = contact_events
+            .iter()
+            .map(|contact_event| contact_event.location_id)
+            .collect();
+
+        let conn = db::connection()?;
+        let asset_tags = asset_tags::table
+            .select((asset_tags::id, asset_tags::name, asset_tags::asset_id))
+            .filter(asset_tags::id.eq_any(&asset_tag_ids))
+            .load::<(i64, String, Option<i64>)>(&conn)?;
+        let locations = locations::table
+            .filter(locations::id.eq_any(&location_ids))
+            .load::<Location>(&conn)?;
+        let asset_ids = assets::table
+            .select(assets::id)
+            .filter(assets::id.eq_any(asset_tags.iter().filter_map(|(_, _, asset_id)| *asset_id)))
             .filter(assets::deleted.eq(false))
-            .set(assets::deleted.eq(true))
-            .get_result(&conn)?;
-        Ok(asset)
+            .load::<i64>(&conn)?;
+        let rooms = loans::table
+            .inner_join(rooms::table)
+            .select((loans::asset_id, rooms::id, rooms::name))
+            .filter(loans::asset_id.eq_any(&asset_ids))
+            .filter(loans::returned_at.is_null())
+            .filter(rooms::deleted.eq(false))
+            .order(loans::created_at.desc())
+            .load::<(i64, i64, String)>(&conn)?;
+
+        let mut positions: Vec<AssetPosition> = contact_events
+            .into_iter()
+            .filter_map(|contact_event| {
+                let (asset_tag_id, asset_tag_name, asset_id) = asset_tags
+                    .iter()
+                    .find(|(id, _, _)| *id == contact_event.asset_tag_id)?;
+                let asset_id = asset_id.filter(|asset_id| asset_ids.contains(asset_id))?;
+                let location = locations
+                    .iter()
+                    .find(|location| location.id == contact_event.location_id)?;
+                let room = rooms.iter().find(|(id, _, _)| *id == asset_id);
+                Some(AssetPosition {
+                    asset_id,
+                    asset_tag_id: *asset_tag_id,
+                    asset_tag_name: asset_tag_name.clone(),
+                    location_id: location.id,
+                    location_name: location.name.clone(),
+                    room_id: room.map(|(_, room_id, _)| *room_id),
+                    room_name: room.map(|(_, _, room_name)| room_name.clone()),
+                    latitude: location.latitude,
+                    longitude: location.longitude,
+                    seen_at: contact_event.created_at,
+                })
+            })
+            .collect();
+        positions.sort_by_key(|position| (position.asset_id, position.asset_tag_id));
+        Ok(positions)
     }
+
+    soft_delete_methods!(assets, Asset, "Asset", REFERENCES);
 }
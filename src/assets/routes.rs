@@ -1,77 +1,339 @@
-use crate::assets::{Asset, MaybeAsset};
+use crate::assets::{Asset, AssetPosition, MaybeAsset};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::geojson;
+use crate::include::{self, IncludeQuery};
+use crate::locations::Location;
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
 
+// Filters like those of /locations/{id}/assets
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PositionsQuery {
+    location_id: Option<i64>,
+    recursive: Option<bool>,
+}
+
+fn find_positions(query: PositionsQuery) -> Result<Vec<AssetPosition>, CustomError> {
+    let location_ids = match query.location_id {
+        Some(id) => Some(Location::find_ids(id, query.recursive.unwrap_or(false))?),
+        None => None,
+    };
+    Asset::find_positions(location_ids.as_deref())
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets",
+    params(
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The assets", body = [Asset]),
+    )
+)]
 #[get("/assets")]
-async fn find_all() -> Result<HttpResponse, CustomError> {
-    let assets = Asset::find_all()?;
+async fn find_all(query: web::Query<IncludeQuery>) -> Result<HttpResponse, CustomError> {
+    let assets = db::run(move || include::assets(Asset::find_all()?, &query.include)).await?;
     Ok(HttpResponse::Ok().json(assets))
 }
 
+#[utoipa::path(
+    get,
+    path = "/assets/all",
+    responses(
+        (status = 200, description = "The assets, deleted or not", body = [Asset]),
+    )
+)]
 #[get("/assets/all")]
 async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
-    let assets = Asset::find_with_deleted()?;
+    let assets = db::run(Asset::find_with_deleted).await?;
     Ok(HttpResponse::Ok().json(assets))
 }
 
+#[utoipa::path(
+    get,
+    path = "/assets/deleted",
+    responses(
+        (status = 200, description = "The deleted assets", body = [Asset]),
+    )
+)]
 #[get("/assets/deleted")]
 async fn find_deleted() -> Result<HttpResponse, CustomError> {
-    let assets = Asset::find_deleted()?;
+    let assets = db::run(Asset::find_deleted).await?;
     Ok(HttpResponse::Ok().json(assets))
 }
 
+#[utoipa::path(
+    get,
+    path = "/assets/positions",
+    params(
+        PositionsQuery,
+    ),
+    responses(
+        (status = 200, description = "The last known position of each asset", body = [AssetPosition]),
+    )
+)]
+#[get("/assets/positions")]
+async fn find_all_positions(
+    query: web::Query<PositionsQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /assets/positions {:?}", &query);
+    let positions = db::run(move || find_positions(query)).await?;
+    Ok(HttpResponse::Ok().json(positions))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/positions.geojson",
+    params(
+        PositionsQuery,
+    ),
+    responses(
+        (status = 200, description = "The last known positions as a GeoJSON FeatureCollection", content_type = "application/geo+json"),
+    )
+)]
+#[get("/assets/positions.geojson")]
+async fn find_all_positions_geojson(
+    query: web::Query<PositionsQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /assets/positions.geojson {:?}", &query);
+    let mut features = Vec::new();
+    for position in db::run(move || find_positions(query)).await? {
+        let properties = geojson::properties(&position, &["latitude", "longitude"])?;
+        let geometry = geojson::point(position.latitude, position.longitude);
+        // An asset is wherever each of its tags was last seen, so the features are per tag
+        features.push(geojson::feature(
+            position.asset_tag_id,
+            geometry,
+            properties,
+        ));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(geojson::CONTENT_TYPE)
+        .json(geojson::feature_collection(features)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The asset", body = Asset),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/assets/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("GET /assets/id/{}", &id);
-    let asset = Asset::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(asset))
+    log::trace!("GET /assets/id/{} {:?}", &id, &query);
+    let asset =
+        db::run(move || include::single(Asset::find_by_id(id)?, &query.include, include::assets))
+            .await?;
+    etag::respond(&req, &asset)
 }
 
+#[utoipa::path(
+    get,
+    path = "/assets/asset_tag/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    responses(
+        (status = 200, description = "The assets of the asset tag", body = [Asset]),
+    )
+)]
 #[get("/assets/asset_tag/{id}")]
 async fn find_by_asset_tag(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /assets/asset_tag/{}", &id);
-    let assets = Asset::find_by_asset_tag(id)?;
+    let assets = db::run(move || Asset::find_by_asset_tag(id)).await?;
     Ok(HttpResponse::Ok().json(assets))
 }
 
+#[utoipa::path(
+    post,
+    path = "/assets",
+    request_body = MaybeAsset,
+    responses(
+        (status = 200, description = "The created asset", body = Asset),
+    )
+)]
 #[post("/assets")]
 async fn create(asset: web::Json<MaybeAsset>) -> Result<HttpResponse, CustomError> {
     let asset = asset.into_inner();
     log::trace!("POST /assets/ {:?}", &asset);
-    let asset = Asset::create(asset)?;
+    let asset = validation::validated(asset).await?;
+    let asset = db::run(move || Asset::create(asset)).await?;
     Ok(HttpResponse::Ok().json(asset))
 }
 
+#[utoipa::path(
+    put,
+    path = "/assets/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    request_body = MaybeAsset,
+    responses(
+        (status = 200, description = "The updated asset", body = Asset),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/assets/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     asset: web::Json<MaybeAsset>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let asset = asset.into_inner();
     log::trace!("PUT /assets/{} {:?}", &id, &asset);
-    let asset = Asset::update(id, asset)?;
-    Ok(HttpResponse::Ok().json(asset))
+    let asset = validation::validated(asset).await?;
+    let precondition = etag::precondition(&req)?;
+    let asset = db::run(move || {
+        Asset::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(asset)
+        })
+    })
+    .await?;
+    etag::ok(&asset)
 }
 
+#[utoipa::path(
+    patch,
+    path = "/assets/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated asset", body = Asset),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/assets/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /assets/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let asset = db::run(move || {
+        Asset::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&asset)
+}
+
+#[utoipa::path(
+    post,
+    path = "/assets/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The restored asset", body = Asset),
+    )
+)]
+#[post("/assets/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /assets/{}/restore", &id);
+    let res = db::run(move || Asset::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/assets/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted asset", body = Asset),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/assets/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /assets/{}", &id);
-    let res = Asset::delete(id)?;
+    log::trace!("DELETE /assets/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            Asset::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Asset::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_all_positions,
+    find_all_positions_geojson,
+    find_by_id,
+    find_by_asset_tag,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
     comfig.service(find_with_deleted);
     comfig.service(find_deleted);
+    comfig.service(find_all_positions);
+    comfig.service(find_all_positions_geojson);
     comfig.service(find_by_id);
     comfig.service(find_by_asset_tag);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
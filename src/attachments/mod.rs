@@ -0,0 +1,6 @@
+mod model;
+mod routes;
+pub mod storage;
+
+pub use model::*;
+pub use routes::{init_routes, openapi};
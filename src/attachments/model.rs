@@ -0,0 +1,221 @@
+use crate::assets::Asset;
+use crate::attachments::storage;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::schema::attachments;
+use crate::users::User;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use image::error::{LimitError, LimitErrorKind};
+use image::io::Reader;
+use image::{DynamicImage, ImageError, ImageOutputFormat, ImageResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+pub const THUMBNAIL_SIZE: u32 = 256;
+pub const THUMBNAIL_CONTENT_TYPE: &str = "image/png";
+// Decoding allocates for the dimensions an image claims, which a small upload can make
+// huge, so larger images are stored without a thumbnail
+pub const MAX_IMAGE_DIMENSION: u32 = 10_000;
+pub const MAX_IMAGE_PIXELS: u64 = 25_000_000;
+
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, Associations, ToSchema)]
+#[belongs_to(Asset)]
+#[belongs_to(User)]
+#[table_name = "attachments"]
+pub struct Attachment {
+    pub id: i64,
+    pub asset_id: i64,
+    pub user_id: i64,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub sha256: String,
+    pub storage_key: String,
+    pub thumbnail_key: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "attachments"]
+struct NewAttachment {
+    asset_id: i64,
+    user_id: i64,
+    filename: String,
+    content_type: String,
+    size: i64,
+    sha256: String,
+    storage_key: String,
+    thumbnail_key: Option<String>,
+}
+
+// A file received from a client, before it is stored
+#[derive(Debug)]
+pub struct Upload {
+    pub filename: String,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+// Read the dimensions from the header and refuse images over the limits before decoding
+fn decode(data: &[u8]) -> ImageResult<DynamicImage> {
+    let reader = || Reader::new(Cursor::new(data)).with_guessed_format();
+    let (width, height) = reader()?.into_dimensions()?;
+    if width > MAX_IMAGE_DIMENSION
+        || height > MAX_IMAGE_DIMENSION
+        || u64::from(width) * u64::from(height) > MAX_IMAGE_PIXELS
+    {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    }
+    reader()?.decode()
+}
+
+// Images that cannot be decoded are still stored, just without a thumbnail
+fn thumbnail(upload: &Upload) -> Option<Vec<u8>> {
+    if !upload.content_type.starts_with("image/") {
+        return None;
+    }
+    let image = match decode(&upload.data) {
+        Ok(image) => image,
+        Err(err) => {
+            log::warn!("Failed to decode image '{}': {}", upload.filename, err);
+            return None;
+        }
+    };
+    let mut thumbnail = Vec::new();
+    match image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut thumbnail, ImageOutputFormat::Png)
+    {
+        Ok(_) => Some(thumbnail),
+        Err(err) => {
+            log::warn!("Failed to thumbnail image '{}': {}", upload.filename, err);
+            None
+        }
+    }
+}
+
+impl Attachment {
+    pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let attachment = attachments::table
+            .filter(attachments::id.eq(id))
+            .first(&conn)?;
+        Ok(attachment)
+    }
+
+    // Attachments of an asset, most recent first
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let attachments = attachments::table
+            .filter(attachments::asset_id.eq(id))
+            .order(attachments::created_at.desc())
+            .load::<Attachment>(&conn)?;
+        Ok(attachments)
+    }
+
+    pub fn create(asset_id: i64, user_id: i64, upload: Upload) -> Result<Self, CustomError> {
+        Asset::find_by_id(asset_id)?;
+        if upload.data.len() > *storage::MAX_BYTES {
+            return Err(CustomError::new(
+                413,
+                format!("Attachments are limited to {} bytes", *storage::MAX_BYTES),
+            ));
+        }
+
+        let storage = storage::storage();
+        let storage_key = Uuid::new_v4().to_string();
+        storage.put(&storage_key, &upload.data)?;
+        let thumbnail_key = match thumbnail(&upload) {
+            Some(thumbnail) => {
+                let thumbnail_key = format!("{}.thumbnail", storage_key);
+                storage.put(&thumbnail_key, &thumbnail)?;
+                Some(thumbnail_key)
+            }
+            None => None,
+        };
+
+        let attachment = NewAttachment {
+            asset_id,
+            user_id,
+            filename: upload.filename,
+            content_type: upload.content_type,
+            size: upload.data.len() as i64,
+            sha256: format!("{:x}", Sha256::digest(&upload.data)),
+            storage_key,
+            thumbnail_key,
+        };
+        let conn = db::connection()?;
+        match diesel::insert_into(attachments::table)
+            .values(&attachment)
+            .get_result::<Attachment>(&conn)
+        {
+            Ok(attachment) => Ok(attachment),
+            Err(err) => {
+                Self::delete_contents(&attachment.storage_key, &attachment.thumbnail_key);
+                Err(err.into())
+            }
+        }
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>, CustomError> {
+        storage::storage().get(&self.storage_key)
+    }
+
+    pub fn read_thumbnail(&self) -> Result<Vec<u8>, CustomError> {
+        match &self.thumbnail_key {
+            Some(thumbnail_key) => storage::storage().get(thumbnail_key),
+            None => Err(CustomError::new(
+                404,
+                format!("Attachment {} has no thumbnail", self.id),
+            )),
+        }
+    }
+
+    pub fn delete(id: i64) -> Result<usize, CustomError> {
+        Self::delete_checked(id, |_| Ok(()))
+    }
+
+    // Delete, unless check refuses the attachment as it is once locked
+    pub fn delete_checked<F>(id: i64, check: F) -> Result<usize, CustomError>
+    where
+        F: FnOnce(&Self) -> Result<(), CustomError>,
+    {
+        let conn = db::connection()?;
+        let attachment = conn.transaction::<_, CustomError, _>(|| {
+            let attachment: Option<Attachment> = attachments::table
+                .find(id)
+                .for_update()
+                .first(&conn)
+                .optional()?;
+            if let Some(attachment) = &attachment {
+                check(attachment)?;
+                diesel::delete(attachment).execute(&conn)?;
+            }
+            Ok(attachment)
+        })?;
+        match attachment {
+            Some(attachment) => {
+                Self::delete_contents(&attachment.storage_key, &attachment.thumbnail_key);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    // A blob that outlives its row only wastes space, so failures are logged rather than returned
+    fn delete_contents(storage_key: &str, thumbnail_key: &Option<String>) {
+        let storage = storage::storage();
+        for key in std::iter::once(storage_key).chain(thumbnail_key.as_deref()) {
+            if let Err(err) = storage.delete(key) {
+                log::warn!("Failed to delete attachment contents {}: {}", key, err);
+            }
+        }
+    }
+}
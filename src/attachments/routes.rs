@@ -0,0 +1,195 @@
+use crate::attachments::{storage, Attachment, Upload, THUMBNAIL_CONTENT_TYPE};
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::users::User;
+use actix_multipart::Multipart;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{delete, get, post, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use utoipa::OpenApi;
+
+// Read the first file in the form, refusing to buffer more than the size limit
+async fn read_upload(mut payload: Multipart) -> Result<Upload, CustomError> {
+    let bad_request = |err| CustomError::new(400, format!("Invalid multipart upload: {}", err));
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(bad_request)?;
+        let filename = match field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename().map(String::from))
+        {
+            Some(filename) => filename,
+            None => continue,
+        };
+        let content_type = field.content_type().to_string();
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(bad_request)?;
+            if data.len() + chunk.len() > *storage::MAX_BYTES {
+                return Err(CustomError::new(
+                    413,
+                    format!("Attachments are limited to {} bytes", *storage::MAX_BYTES),
+                ));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        return Ok(Upload {
+            filename,
+            content_type,
+            data,
+        });
+    }
+    Err(CustomError::new(
+        400,
+        String::from("The multipart upload does not contain a file"),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/{id}/attachments",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The attachments of the asset", body = [Attachment]),
+    )
+)]
+#[get("/assets/{id}/attachments")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /assets/{}/attachments", &id);
+    let attachments = db::run(move || Attachment::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(attachments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/attachments/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the attachment"),
+    ),
+    responses(
+        (status = 200, description = "The attachment", body = Attachment),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/attachments/id/{id}")]
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /attachments/id/{}", &id);
+    let attachment = db::run(move || Attachment::find_by_id(id)).await?;
+    etag::respond(&req, &attachment)
+}
+
+#[utoipa::path(
+    get,
+    path = "/attachments/{id}/download",
+    params(
+        ("id" = i64, Path, description = "Id of the attachment"),
+    ),
+    responses(
+        (status = 200, description = "The attached file", content_type = "application/octet-stream"),
+    )
+)]
+#[get("/attachments/{id}/download")]
+async fn download(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /attachments/{}/download", &id);
+    let (attachment, data) = db::run(move || {
+        let attachment = Attachment::find_by_id(id)?;
+        let data = attachment.read()?;
+        Ok((attachment, data))
+    })
+    .await?;
+    Ok(HttpResponse::Ok()
+        .content_type(attachment.content_type.as_str())
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(attachment.filename)],
+        })
+        .body(data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/attachments/{id}/thumbnail",
+    params(
+        ("id" = i64, Path, description = "Id of the attachment"),
+    ),
+    responses(
+        (status = 200, description = "A PNG thumbnail of the attached image", content_type = "image/png"),
+    )
+)]
+#[get("/attachments/{id}/thumbnail")]
+async fn thumbnail(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /attachments/{}/thumbnail", &id);
+    let data = db::run(move || Attachment::find_by_id(id)?.read_thumbnail()).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(THUMBNAIL_CONTENT_TYPE)
+        .body(data))
+}
+
+#[utoipa::path(
+    post,
+    path = "/assets/{id}/attachments",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    request_body(content = String, content_type = "multipart/form-data", description = "The file in a part named file"),
+    responses(
+        (status = 200, description = "The created attachment", body = Attachment),
+    )
+)]
+#[post("/assets/{id}/attachments")]
+async fn create(
+    user: User,
+    id: web::Path<i64>,
+    payload: Multipart,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /assets/{}/attachments", &id);
+    let upload = read_upload(payload).await?;
+    let attachment = db::run(move || Attachment::create(id, user.id, upload)).await?;
+    Ok(HttpResponse::Ok().json(attachment))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/attachments/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the attachment"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted attachments", body = usize),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[delete("/attachments/{id}")]
+async fn delete(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /attachments/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let res =
+        db::run(move || Attachment::delete_checked(id, |current| precondition.check(current)))
+            .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(find_by_asset, find_by_id, download, thumbnail, create, delete))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_by_asset);
+    comfig.service(find_by_id);
+    comfig.service(download);
+    comfig.service(thumbnail);
+    comfig.service(create);
+    comfig.service(delete);
+}
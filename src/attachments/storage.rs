@@ -0,0 +1,90 @@
+use crate::error_handler::CustomError;
+use lazy_static::lazy_static;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+ * Storage backends for attachment contents
+ *
+ * The database only keeps attachment metadata and the key of each blob. The
+ * blobs themselves live in a Storage backend, which is the local filesystem
+ * under ATTACHMENTS_DIR unless another backend is plugged in here.
+ */
+
+pub trait Storage: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), CustomError>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, CustomError>;
+    fn delete(&self, key: &str) -> Result<(), CustomError>;
+}
+
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: PathBuf) -> Result<Self, CustomError> {
+        fs::create_dir_all(&root)?;
+        Ok(LocalStorage { root })
+    }
+
+    // Keys are generated by us, but never let one escape the root directory
+    fn path(&self, key: &str) -> Result<PathBuf, CustomError> {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            || key.starts_with('.')
+        {
+            return Err(CustomError::new(
+                400,
+                format!("Invalid storage key '{}'", key),
+            ));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), CustomError> {
+        fs::write(self.path(key)?, data)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, CustomError> {
+        Ok(fs::read(self.path(key)?)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), CustomError> {
+        fs::remove_file(self.path(key)?)?;
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref STORAGE: Box<dyn Storage> = {
+        let root = match env::var("ATTACHMENTS_DIR") {
+            Ok(root) => PathBuf::from(root),
+            Err(_) if cfg!(test) => env::temp_dir().join("qsib_asset_attachments"),
+            Err(_) => PathBuf::from("attachments"),
+        };
+        log::info!("Storing attachments in {}", root.display());
+        Box::new(LocalStorage::new(root).expect("Failed to create attachments directory"))
+    };
+    pub static ref MAX_BYTES: usize = env::var("ATTACHMENTS_MAX_BYTES")
+        .ok()
+        .and_then(|max_bytes| max_bytes.parse::<usize>().ok())
+        .unwrap_or(match cfg!(test) {
+            true => 1024 * 1024,
+            false => 25 * 1024 * 1024,
+        });
+}
+
+pub fn init() {
+    lazy_static::initialize(&STORAGE);
+    lazy_static::initialize(&MAX_BYTES);
+}
+
+pub fn storage() -> &'static dyn Storage {
+    STORAGE.as_ref()
+}
@@ -1,12 +1,13 @@
-use actix_web::{dev::ServiceRequest, Error};
+use actix_web::{dev::ServiceRequest, web, Error};
 use actix_web_httpauth::extractors::{
     bearer::{BearerAuth, Config},
     AuthenticationError,
 };
 use http::Method;
+use std::collections::HashMap;
 use std::convert::TryInto;
 
-use super::users;
+use super::{db, users, versioning};
 
 pub fn init() {
     users::init();
@@ -30,37 +31,57 @@ pub fn init() {
     }
 }
 
+// Calendar apps subscribe to .ics feeds without headers, so those accept ?token= instead.
+// It is a feed token, which reads nothing else, as URLs end up in logs and Referer headers
+fn feed_token(req: &ServiceRequest) -> Option<String> {
+    if !req.path().ends_with(".ics") {
+        return None;
+    }
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok()?;
+    query.get("token").cloned()
+}
+
+fn unauthorized(req: &ServiceRequest) -> Error {
+    let config = req.app_data::<Config>().cloned().unwrap_or_default();
+    AuthenticationError::from(config).into()
+}
+
 pub async fn validator(
     req: ServiceRequest,
     credentials: BearerAuth,
 ) -> Result<ServiceRequest, Error> {
     match credentials.token() {
         "_" => {
-            if req.path() == "/health" || 
-             (req.path() == "/login" && req.method() == Method::POST) {
+            let path = versioning::unversioned(req.path());
+            if path == "/health"
+                || (path == "/login" && req.method() == Method::POST)
+                || path == "/openapi.json"
+                || path == "/docs"
+                || path.starts_with("/docs/")
+            {
                 Ok(req)
+            } else if let Some(token) = feed_token(&req) {
+                match db::run(move || users::User::find_by_feed_token(token)).await {
+                    Ok(record) => {
+                        log::trace!("Allowing feed for user: {:?}", record);
+                        Ok(req)
+                    }
+                    Err(_) => Err(unauthorized(&req)),
+                }
             } else {
-                let config = req
-                    .app_data::<Config>()
-                    .map(|data| data.clone())
-                    .unwrap_or_else(Default::default);
-
-                Err(AuthenticationError::from(config).into())
+                Err(unauthorized(&req))
             }
         }
-        token => match users::User::find_by_token(String::from(token)) {
-            Ok(record) => {
-                log::trace!("Allowing user: {:?}", record);
-                Ok(req)
+        token => {
+            let token = String::from(token);
+            match db::run(move || users::User::find_by_token(token)).await {
+                Ok(record) => {
+                    log::trace!("Allowing user: {:?}", record);
+                    Ok(req)
+                }
+                Err(error) if error.error_status_code == 400 => Err(error.into()),
+                Err(_) => Err(unauthorized(&req)),
             }
-            Err(_) => {
-                let config = req
-                    .app_data::<Config>()
-                    .map(|data| data.clone())
-                    .unwrap_or_else(Default::default);
-
-                Err(AuthenticationError::from(config).into())
-            }
-        },
+        }
     }
 }
@@ -2,4 +2,4 @@ mod model;
 mod routes;
 
 pub use model::*;
-pub use routes::init_routes;
+pub use routes::{init_routes, openapi};
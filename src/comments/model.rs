@@ -1,45 +1,218 @@
+use crate::alerts::{Alert, MaybeAlert};
 use crate::asset_tags::AssetTag;
+use crate::assets::Asset;
 use crate::db;
 use crate::error_handler::CustomError;
-use crate::schema::comments;
+use crate::locations::Location;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::schema::{alerts, comment_revisions, comments, users};
+use crate::soft_delete::{soft_delete_methods, Reference};
 use crate::users::User;
+use crate::validation::{self, MAX_TEXT};
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const MENTION_REASON: &str = "mention";
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(User)]
 #[belongs_to(AssetTag)]
+#[belongs_to(Asset)]
+#[belongs_to(Location)]
+#[belongs_to(Alert)]
 #[table_name = "comments"]
 pub struct Comment {
     pub id: i64,
     pub content: String,
     pub user_id: i64,
-    pub asset_tag_id: i64,
+    pub asset_tag_id: Option<i64>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub asset_id: Option<i64>,
+    pub location_id: Option<i64>,
+    pub alert_id: Option<i64>,
+    pub parent_id: Option<i64>,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+// A comment is on exactly one of an asset tag, asset, location or alert
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "comments"]
+#[validate(context = PgConnection)]
 pub struct MaybeComment {
+    #[validate(length(min = 1, max = MAX_TEXT))]
+    pub content: String,
+    #[validate(custom(function = "validation::user_exists", use_context))]
+    pub user_id: i64,
+    #[validate(custom(function = "validation::asset_tag_exists", use_context))]
+    pub asset_tag_id: Option<i64>,
+    #[validate(range(min = 1))]
+    pub asset_id: Option<i64>,
+    #[validate(custom(function = "validation::location_exists", use_context))]
+    pub location_id: Option<i64>,
+    #[validate(range(min = 1))]
+    pub alert_id: Option<i64>,
+    #[validate(range(min = 1))]
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema, Validate)]
+#[validate(context = PgConnection)]
+pub struct CommentEdit {
+    #[validate(length(min = 1, max = MAX_TEXT))]
     pub content: String,
+}
+
+// The content of a comment before an edit, and who made the edit
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, Associations, ToSchema)]
+#[belongs_to(Comment)]
+#[belongs_to(User)]
+#[table_name = "comment_revisions"]
+pub struct CommentRevision {
+    pub id: i64,
+    pub comment_id: i64,
     pub user_id: i64,
-    pub asset_tag_id: i64,
+    pub content: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "comment_revisions"]
+struct NewCommentRevision {
+    comment_id: i64,
+    user_id: i64,
+    content: String,
+}
+
+// Usernames mentioned as @username, in order of their first mention
+pub fn mentions(content: &str) -> Vec<String> {
+    let is_username_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.' || c == '-';
+    let mut mentions: Vec<String> = Vec::new();
+    for (i, _) in content.match_indices('@') {
+        // Skip the @ inside email addresses and the like
+        if content[..i].chars().last().is_some_and(is_username_char) {
+            continue;
+        }
+        let username: String = content[i + 1..]
+            .chars()
+            .take_while(|c| is_username_char(*c))
+            .collect();
+        let username = username.trim_end_matches(&['.', '-'][..]);
+        if !username.is_empty() && !mentions.iter().any(|mention| mention == username) {
+            mentions.push(String::from(username));
+        }
+    }
+    mentions
+}
+
+// Alert each mentioned user, except the author mentioning themselves
+fn notify_mentions(
+    conn: &PgConnection,
+    comment: &Comment,
+    usernames: &[String],
+) -> Result<(), CustomError> {
+    if usernames.is_empty() {
+        return Ok(());
+    }
+    let mentioned = users::table
+        .select(users::id)
+        .filter(users::username.eq_any(usernames))
+        .filter(users::id.ne(comment.user_id))
+        .filter(users::deleted.eq(false))
+        .load::<i64>(conn)?;
+    let mentions: Vec<MaybeAlert> = mentioned
+        .into_iter()
+        .map(|user_id| MaybeAlert {
+            message: Some(format!("You were mentioned in comment {}", comment.id)),
+            reason: String::from(MENTION_REASON),
+            user_id,
+        })
+        .collect();
+    diesel::insert_into(alerts::table)
+        .values(mentions)
+        .execute(conn)?;
+    Ok(())
 }
 
+impl MaybeComment {
+    fn target(&self) -> [Option<i64>; 4] {
+        [
+            self.asset_tag_id,
+            self.asset_id,
+            self.location_id,
+            self.alert_id,
+        ]
+    }
+
+    fn validate(&self) -> Result<(), CustomError> {
+        if self.target().iter().filter(|id| id.is_some()).count() != 1 {
+            return Err(CustomError::new(
+                400,
+                String::from(
+                    "A comment must be on exactly one of an asset tag, asset, location or alert",
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Replies would be purged along with the comment, so they have to go first
+const REFERENCES: &[Reference] = &[Reference {
+    table: "comments",
+    column: "parent_id",
+}];
+
 impl Comment {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let comments = comments::table.load::<Comment>(&conn)?;
+        let comments = comments::table
+            .filter(comments::deleted.eq(false))
+            .load::<Comment>(&conn)?;
         Ok(comments)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            comments::table.filter(comments::deleted.eq(false)),
+            comments::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let comment = comments::table.filter(comments::id.eq(id)).first(&conn)?;
+        let comment = comments::table
+            .filter(comments::id.eq(id))
+            .filter(comments::deleted.eq(false))
+            .first(&conn)?;
+        Ok(comment)
+    }
+
+    pub fn find_deleted_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let comment = comments::table
+            .filter(comments::id.eq(id))
+            .filter(comments::deleted.eq(true))
+            .first(&conn)?;
         Ok(comment)
     }
 
@@ -47,6 +220,7 @@ impl Comment {
         let conn = db::connection()?;
         let comments = comments::table
             .filter(comments::user_id.eq(id))
+            .filter(comments::deleted.eq(false))
             .load::<Comment>(&conn)?;
         Ok(comments)
     }
@@ -55,30 +229,149 @@ impl Comment {
         let conn = db::connection()?;
         let comments = comments::table
             .filter(comments::asset_tag_id.eq(id))
+            .filter(comments::deleted.eq(false))
             .load::<Comment>(&conn)?;
         Ok(comments)
     }
 
-    pub fn create(comment: MaybeComment) -> Result<Self, CustomError> {
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let comment = diesel::insert_into(comments::table)
-            .values(comment)
-            .get_result(&conn)?;
-        Ok(comment)
+        let comments = comments::table
+            .filter(comments::asset_id.eq(id))
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(&conn)?;
+        Ok(comments)
     }
 
-    pub fn update(id: i64, comment: MaybeComment) -> Result<Self, CustomError> {
+    pub fn find_by_location(id: i64) -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let comment = diesel::update(comments::table)
-            .filter(comments::id.eq(id))
-            .set(comment)
-            .get_result(&conn)?;
-        Ok(comment)
+        let comments = comments::table
+            .filter(comments::location_id.eq(id))
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(&conn)?;
+        Ok(comments)
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    pub fn find_by_alert(id: i64) -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let res = diesel::delete(comments::table.filter(comments::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        let comments = comments::table
+            .filter(comments::alert_id.eq(id))
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(&conn)?;
+        Ok(comments)
+    }
+
+    // Direct replies to a comment, oldest first
+    pub fn find_replies(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let comments = comments::table
+            .filter(comments::parent_id.eq(id))
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(&conn)?;
+        Ok(comments)
     }
+
+    // Edit history of a comment, oldest first
+    pub fn find_revisions(id: i64) -> Result<Vec<CommentRevision>, CustomError> {
+        let conn = db::connection()?;
+        let revisions = comment_revisions::table
+            .filter(comment_revisions::comment_id.eq(id))
+            .order((comment_revisions::created_at, comment_revisions::id))
+            .load::<CommentRevision>(&conn)?;
+        Ok(revisions)
+    }
+
+    // Only the author of a comment or an admin may change it
+    pub fn authorize(&self, user: &User) -> Result<(), CustomError> {
+        if self.user_id == user.id || user.is_admin()? {
+            Ok(())
+        } else {
+            Err(CustomError::new(403, String::from("Forbidden")))
+        }
+    }
+
+    // Replies default to the target of the comment they reply to
+    pub fn create(mut comment: MaybeComment) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            if let Some(parent_id) = comment.parent_id {
+                let parent: Comment = comments::table
+                    .filter(comments::id.eq(parent_id))
+                    .filter(comments::deleted.eq(false))
+                    .first(&conn)?;
+                let parent_target = [
+                    parent.asset_tag_id,
+                    parent.asset_id,
+                    parent.location_id,
+                    parent.alert_id,
+                ];
+                if comment.target().iter().all(|id| id.is_none()) {
+                    comment.asset_tag_id = parent.asset_tag_id;
+                    comment.asset_id = parent.asset_id;
+                    comment.location_id = parent.location_id;
+                    comment.alert_id = parent.alert_id;
+                } else if comment.target() != parent_target {
+                    return Err(CustomError::new(
+                        400,
+                        format!(
+                            "A reply must be on the same target as comment {}",
+                            parent.id
+                        ),
+                    ));
+                }
+            }
+            comment.validate()?;
+
+            let comment: Comment = diesel::insert_into(comments::table)
+                .values(comment)
+                .get_result(&conn)?;
+            notify_mentions(&conn, &comment, &mentions(&comment.content))?;
+            Ok(comment)
+        })
+    }
+
+    // Keeps the previous content as a revision and only notifies users who are newly mentioned
+    pub fn update(id: i64, editor_id: i64, edit: CommentEdit) -> Result<Self, CustomError> {
+        Self::update_with(id, editor_id, |_, _| Ok(edit))
+    }
+
+    // Like update, with the edit that changes makes of the comment as it is once locked
+    pub fn update_with<F>(id: i64, editor_id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<CommentEdit, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            let comment = Self::lock(&conn, id)?;
+            let edit = changes(&conn, &comment)?;
+            if comment.content == edit.content {
+                return Ok(comment);
+            }
+
+            diesel::insert_into(comment_revisions::table)
+                .values(NewCommentRevision {
+                    comment_id: comment.id,
+                    user_id: editor_id,
+                    content: comment.content.clone(),
+                })
+                .execute(&conn)?;
+            let updated: Comment = diesel::update(&comment)
+                .set(comments::content.eq(edit.content))
+                .get_result(&conn)?;
+
+            let mentioned = mentions(&comment.content);
+            let newly_mentioned: Vec<String> = mentions(&updated.content)
+                .into_iter()
+                .filter(|username| !mentioned.contains(username))
+                .collect();
+            notify_mentions(&conn, &updated, &newly_mentioned)?;
+            Ok(updated)
+        })
+    }
+
+    soft_delete_methods!(comments, Comment, "Comment", REFERENCES);
 }
@@ -1,72 +1,385 @@
-use crate::comments::{Comment, MaybeComment};
+use crate::comments::{Comment, CommentEdit, CommentRevision, MaybeComment};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/comments",
+    responses(
+        (status = 200, description = "The comments", body = [Comment]),
+    )
+)]
 #[get("/comments")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
-    let comments = Comment::find_all()?;
+    let comments = db::run(Comment::find_all).await?;
     Ok(HttpResponse::Ok().json(comments))
 }
 
+#[utoipa::path(
+    get,
+    path = "/comments/all",
+    responses(
+        (status = 200, description = "The comments, deleted or not", body = [Comment]),
+    )
+)]
+#[get("/comments/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let comments = db::run(Comment::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/deleted",
+    responses(
+        (status = 200, description = "The deleted comments", body = [Comment]),
+    )
+)]
+#[get("/comments/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let comments = db::run(Comment::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "The comment", body = Comment),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/comments/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /comments/id/{}", &id);
-    let comment = Comment::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(comment))
+    let comment = db::run(move || Comment::find_by_id(id)).await?;
+    etag::respond(&req, &comment)
 }
 
+#[utoipa::path(
+    get,
+    path = "/comments/user/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The comments of the user", body = [Comment]),
+    )
+)]
 #[get("/comments/user/{id}")]
 async fn find_by_user(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /comments/user/{}", &id);
-    let comments = Comment::find_by_user(id)?;
+    let comments = db::run(move || Comment::find_by_user(id)).await?;
     Ok(HttpResponse::Ok().json(comments))
 }
 
+#[utoipa::path(
+    get,
+    path = "/comments/asset_tag/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    responses(
+        (status = 200, description = "The comments of the asset tag", body = [Comment]),
+    )
+)]
 #[get("/comments/asset_tag/{id}")]
 async fn find_by_asset_tag(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /comments/asset_tag/{}", &id);
-    let comments = Comment::find_by_asset_tag(id)?;
+    let comments = db::run(move || Comment::find_by_asset_tag(id)).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/asset/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The comments of the asset", body = [Comment]),
+    )
+)]
+#[get("/comments/asset/{id}")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /comments/asset/{}", &id);
+    let comments = db::run(move || Comment::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/location/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The comments of the location", body = [Comment]),
+    )
+)]
+#[get("/comments/location/{id}")]
+async fn find_by_location(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /comments/location/{}", &id);
+    let comments = db::run(move || Comment::find_by_location(id)).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/alert/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+    ),
+    responses(
+        (status = 200, description = "The comments of the alert", body = [Comment]),
+    )
+)]
+#[get("/comments/alert/{id}")]
+async fn find_by_alert(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /comments/alert/{}", &id);
+    let comments = db::run(move || Comment::find_by_alert(id)).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/{id}/replies",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "The replies to the comment", body = [Comment]),
+    )
+)]
+#[get("/comments/{id}/replies")]
+async fn find_replies(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /comments/{}/replies", &id);
+    let comments = db::run(move || Comment::find_replies(id)).await?;
     Ok(HttpResponse::Ok().json(comments))
 }
 
+#[utoipa::path(
+    get,
+    path = "/comments/{id}/revisions",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "The earlier contents of the comment", body = [CommentRevision]),
+    )
+)]
+#[get("/comments/{id}/revisions")]
+async fn find_revisions(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /comments/{}/revisions", &id);
+    let revisions = db::run(move || Comment::find_revisions(id)).await?;
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/comments",
+    request_body = MaybeComment,
+    responses(
+        (status = 200, description = "The created comment", body = Comment),
+    )
+)]
 #[post("/comments")]
-async fn create(comment: web::Json<MaybeComment>) -> Result<HttpResponse, CustomError> {
+async fn create(user: User, comment: web::Json<MaybeComment>) -> Result<HttpResponse, CustomError> {
     let comment = comment.into_inner();
     log::trace!("POST /comments/ {:?}", &comment);
-    let comment = Comment::create(comment)?;
+    let comment = validation::validated(comment).await?;
+    if comment.user_id != user.id {
+        return Err(CustomError::new(403, String::from("Forbidden")));
+    }
+    let comment = db::run(move || Comment::create(comment)).await?;
     Ok(HttpResponse::Ok().json(comment))
 }
 
+#[utoipa::path(
+    put,
+    path = "/comments/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    request_body = CommentEdit,
+    responses(
+        (status = 200, description = "The updated comment", body = Comment),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/comments/{id}")]
 async fn update(
+    req: HttpRequest,
+    user: User,
     id: web::Path<i64>,
-    comment: web::Json<MaybeComment>,
+    edit: web::Json<CommentEdit>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    let comment = comment.into_inner();
-    log::trace!("PUT /comments/{} {:?}", &id, &comment);
-    let comment = Comment::update(id, comment)?;
-    Ok(HttpResponse::Ok().json(comment))
+    let edit = edit.into_inner();
+    log::trace!("PUT /comments/{} {:?}", &id, &edit);
+    let edit = validation::validated(edit).await?;
+    let precondition = etag::precondition(&req)?;
+    let comment = db::run(move || {
+        Comment::find_by_id(id)?.authorize(&user)?;
+        Comment::update_with(id, user.id, |_, current| {
+            precondition.check(current)?;
+            Ok(edit)
+        })
+    })
+    .await?;
+    etag::ok(&comment)
 }
 
+#[utoipa::path(
+    patch,
+    path = "/comments/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated comment", body = Comment),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/comments/{id}")]
+async fn patch(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /comments/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let comment = db::run(move || {
+        Comment::find_by_id(id)?.authorize(&user)?;
+        Comment::update_with(id, user.id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&comment)
+}
+
+#[utoipa::path(
+    post,
+    path = "/comments/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+    ),
+    responses(
+        (status = 200, description = "The restored comment", body = Comment),
+    )
+)]
+#[post("/comments/{id}/restore")]
+async fn restore(user: User, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /comments/{}/restore", &id);
+    let res = db::run(move || {
+        Comment::find_deleted_by_id(id)?.authorize(&user)?;
+        Comment::restore(id)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/comments/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the comment"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted comment", body = Comment),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/comments/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /comments/{}", &id);
-    let res = Comment::delete(id)?;
+    log::trace!("DELETE /comments/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            Comment::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Comment::find_by_id(id)?.authorize(&user)?;
+            Comment::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_user,
+    find_by_asset_tag,
+    find_by_asset,
+    find_by_location,
+    find_by_alert,
+    find_replies,
+    find_revisions,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(find_by_id);
     comfig.service(find_by_user);
     comfig.service(find_by_asset_tag);
+    comfig.service(find_by_asset);
+    comfig.service(find_by_location);
+    comfig.service(find_by_alert);
+    comfig.service(find_replies);
+    comfig.service(find_revisions);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
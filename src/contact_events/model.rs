@@ -3,13 +3,28 @@ use crate::asset_tags::AssetTag;
 use crate::db;
 use crate::error_handler::CustomError;
 use crate::locations::Location;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::contact_events;
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation;
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(Alert)]
 #[belongs_to(AssetTag)]
@@ -23,17 +38,27 @@ pub struct ContactEvent {
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
     pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+// Without a location_id, POST /contact_events resolves one from the client address
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "contact_events"]
+#[validate(context = PgConnection)]
 pub struct MaybeContactEvent {
+    #[validate(custom(function = "validation::asset_tag_exists", use_context))]
     pub asset_tag_id: i64,
-    pub location_id: i64,
+    #[serde(default)]
+    #[validate(custom(function = "validation::location_exists", use_context))]
+    pub location_id: Option<i64>,
+    #[validate(range(min = 1))]
     pub alert_id: Option<i64>,
     pub deleted: bool,
 }
 
+// Nothing references contact events, so they can always be purged
+const REFERENCES: &[Reference] = &[];
+
 impl ContactEvent {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
@@ -43,18 +68,14 @@ impl ContactEvent {
         Ok(contact_events)
     }
 
-    pub fn find_with_deleted() -> Result<Vec<Self>, CustomError> {
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
         let conn = db::connection()?;
-        let contact_events = contact_events::table.load::<ContactEvent>(&conn)?;
-        Ok(contact_events)
-    }
-
-    pub fn find_deleted() -> Result<Vec<Self>, CustomError> {
-        let conn = db::connection()?;
-        let contact_events = contact_events::table
-            .filter(contact_events::deleted.eq(true))
-            .load::<ContactEvent>(&conn)?;
-        Ok(contact_events)
+        paginate!(
+            contact_events::table.filter(contact_events::deleted.eq(false)),
+            contact_events::id,
+            page,
+            &conn
+        )
     }
 
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
@@ -93,7 +114,28 @@ impl ContactEvent {
         Ok(contact_events)
     }
 
+    // The most recent contact event of each asset tag
+    pub fn find_latest() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let contact_events = contact_events::table
+            .filter(contact_events::deleted.eq(false))
+            .distinct_on(contact_events::asset_tag_id)
+            .order((
+                contact_events::asset_tag_id,
+                contact_events::created_at.desc(),
+                contact_events::id.desc(),
+            ))
+            .load::<ContactEvent>(&conn)?;
+        Ok(contact_events)
+    }
+
     pub fn create(contact_event: MaybeContactEvent) -> Result<Self, CustomError> {
+        if contact_event.location_id.is_none() {
+            return Err(CustomError::new(
+                400,
+                String::from("A contact event needs a location_id"),
+            ));
+        }
         let conn = db::connection()?;
         let contact_event = diesel::insert_into(contact_events::table)
             .values(contact_event)
@@ -102,22 +144,47 @@ impl ContactEvent {
     }
 
     pub fn update(id: i64, contact_event: MaybeContactEvent) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(contact_event))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeContactEvent, CustomError>,
+    {
         let conn = db::connection()?;
-        let contact_event = diesel::update(contact_events::table)
-            .filter(contact_events::id.eq(id))
-            .filter(contact_events::deleted.eq(false))
-            .set(contact_event)
-            .get_result(&conn)?;
-        Ok(contact_event)
+        conn.transaction(|| {
+            let contact_event = changes(&conn, &Self::lock(&conn, id)?)?;
+            let contact_event = diesel::update(contact_events::table.find(id))
+                .set(contact_event)
+                .get_result(&conn)?;
+            Ok(contact_event)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<Self, CustomError> {
+    // Like update_with, but a missing alert_id is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeContactEvent, CustomError>,
+    {
         let conn = db::connection()?;
-        let contact_event = diesel::update(contact_events::table)
-            .filter(contact_events::id.eq(id))
-            .filter(contact_events::deleted.eq(false))
-            .set(contact_events::deleted.eq(true))
-            .get_result(&conn)?;
-        Ok(contact_event)
+        conn.transaction(|| {
+            let contact_event = changes(&conn, &Self::lock(&conn, id)?)?;
+            // Only a new contact event has its location resolved from the address
+            let location_id = contact_event.location_id.ok_or_else(|| {
+                CustomError::new(400, String::from("location_id cannot be cleared"))
+            })?;
+            let contact_event = diesel::update(contact_events::table.find(id))
+                .set((
+                    contact_events::asset_tag_id.eq(contact_event.asset_tag_id),
+                    contact_events::location_id.eq(location_id),
+                    contact_events::alert_id.eq(contact_event.alert_id),
+                    contact_events::deleted.eq(contact_event.deleted),
+                ))
+                .get_result(&conn)?;
+            Ok(contact_event)
+        })
     }
+
+    soft_delete_methods!(contact_events, ContactEvent, "Contact event", REFERENCES);
 }
@@ -1,86 +1,303 @@
 use crate::contact_events::{ContactEvent, MaybeContactEvent};
+use crate::db;
 use crate::error_handler::CustomError;
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::locations::{self, Location};
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/contact_events",
+    responses(
+        (status = 200, description = "The contact events", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
-    let contact_events = ContactEvent::find_all()?;
+    let contact_events = db::run(ContactEvent::find_all).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/all",
+    responses(
+        (status = 200, description = "The contact events, deleted or not", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events/all")]
 async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
-    let contact_events = ContactEvent::find_with_deleted()?;
+    let contact_events = db::run(ContactEvent::find_with_deleted).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/deleted",
+    responses(
+        (status = 200, description = "The deleted contact events", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events/deleted")]
 async fn find_deleted() -> Result<HttpResponse, CustomError> {
-    let contact_events = ContactEvent::find_deleted()?;
+    let contact_events = db::run(ContactEvent::find_deleted).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the contact event"),
+    ),
+    responses(
+        (status = 200, description = "The contact event", body = ContactEvent),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/contact_events/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /contact_events/id/{}", &id);
-    let contact_event = ContactEvent::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(contact_event))
+    let contact_event = db::run(move || ContactEvent::find_by_id(id)).await?;
+    etag::respond(&req, &contact_event)
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/asset_tag/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+    ),
+    responses(
+        (status = 200, description = "The contact events of the asset tag", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events/asset_tag/{id}")]
 async fn find_by_asset_tag(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /contact_events/asset_tag/{}", &id);
-    let contact_events = ContactEvent::find_by_asset_tag(id)?;
+    let contact_events = db::run(move || ContactEvent::find_by_asset_tag(id)).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/location/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The contact events of the location", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events/location/{id}")]
 async fn find_by_location(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /contact_events/location/{}", &id);
-    let contact_events = ContactEvent::find_by_location(id)?;
+    let contact_events = db::run(move || ContactEvent::find_by_location(id)).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    get,
+    path = "/contact_events/alert/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+    ),
+    responses(
+        (status = 200, description = "The contact events of the alert", body = [ContactEvent]),
+    )
+)]
 #[get("/contact_events/alert/{id}")]
 async fn find_by_alert(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /contact_events/alert/{}", &id);
-    let contact_events = ContactEvent::find_by_alert(id)?;
+    let contact_events = db::run(move || ContactEvent::find_by_alert(id)).await?;
     Ok(HttpResponse::Ok().json(contact_events))
 }
 
+#[utoipa::path(
+    post,
+    path = "/contact_events",
+    request_body = MaybeContactEvent,
+    responses(
+        (status = 200, description = "The created contact event", body = ContactEvent),
+    )
+)]
 #[post("/contact_events")]
-async fn create(contact_event: web::Json<MaybeContactEvent>) -> Result<HttpResponse, CustomError> {
+async fn create(
+    req: HttpRequest,
+    contact_event: web::Json<MaybeContactEvent>,
+) -> Result<HttpResponse, CustomError> {
     let contact_event = contact_event.into_inner();
     log::trace!("POST /contact_events/ {:?}", &contact_event);
-    let contact_event = ContactEvent::create(contact_event)?;
+    let mut contact_event = validation::validated(contact_event).await?;
+    if contact_event.location_id.is_none() {
+        if let Some(ip) = locations::source_ip(&req) {
+            contact_event.location_id = match db::run(move || Location::resolve(ip)).await {
+                Ok(resolved) => Some(resolved.location.id),
+                Err(err) if err.error_status_code == 404 => {
+                    return Err(CustomError::new(
+                        400,
+                        format!(
+                            "No location_id was given and no location subnet contains {}",
+                            ip
+                        ),
+                    ))
+                }
+                Err(err) => return Err(err),
+            };
+        }
+    }
+    let contact_event = db::run(move || ContactEvent::create(contact_event)).await?;
     Ok(HttpResponse::Ok().json(contact_event))
 }
 
+#[utoipa::path(
+    put,
+    path = "/contact_events/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the contact event"),
+    ),
+    request_body = MaybeContactEvent,
+    responses(
+        (status = 200, description = "The updated contact event", body = ContactEvent),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/contact_events/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     contact_event: web::Json<MaybeContactEvent>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let contact_event = contact_event.into_inner();
     log::trace!("PUT /contact_events/{} {:?}", &id, &contact_event);
-    let contact_event = ContactEvent::update(id, contact_event)?;
-    Ok(HttpResponse::Ok().json(contact_event))
+    let contact_event = validation::validated(contact_event).await?;
+    let precondition = etag::precondition(&req)?;
+    let contact_event = db::run(move || {
+        ContactEvent::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(contact_event)
+        })
+    })
+    .await?;
+    etag::ok(&contact_event)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/contact_events/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the contact event"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated contact event", body = ContactEvent),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/contact_events/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /contact_events/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let contact_event = db::run(move || {
+        ContactEvent::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&contact_event)
 }
 
+#[utoipa::path(
+    post,
+    path = "/contact_events/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the contact event"),
+    ),
+    responses(
+        (status = 200, description = "The restored contact event", body = ContactEvent),
+    )
+)]
+#[post("/contact_events/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /contact_events/{}/restore", &id);
+    let res = db::run(move || ContactEvent::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/contact_events/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the contact event"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted contact event", body = ContactEvent),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/contact_events/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /contact_events/{}", &id);
-    let res = ContactEvent::delete(id)?;
+    log::trace!("DELETE /contact_events/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            ContactEvent::purge_checked(id, |current| precondition.check(current))
+        } else {
+            ContactEvent::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_asset_tag,
+    find_by_location,
+    find_by_alert,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
     comfig.service(find_with_deleted);
@@ -91,5 +308,7 @@ pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_by_alert);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
@@ -1,4 +1,5 @@
 use crate::error_handler::CustomError;
+use actix_web::web;
 use diesel::pg::PgConnection;
 use diesel::r2d2::ConnectionManager;
 use diesel::Connection;
@@ -51,3 +52,17 @@ pub fn connection() -> Result<DbConnection, CustomError> {
     POOL.get()
         .map_err(|e| CustomError::new(500, format!("Failed getting db connection: {}", e)))
 }
+
+/*
+ * Diesel blocks the thread it queries on, so handlers hand their database work
+ * to actix's blocking thread pool rather than stalling the worker, and with it
+ * every other request the worker is serving. The pool has ACTIX_THREADPOOL
+ * threads, five per CPU unless set.
+ */
+pub async fn run<F, T>(f: F) -> Result<T, CustomError>
+where
+    F: FnOnce() -> Result<T, CustomError> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(CustomError::from)
+}
@@ -0,0 +1,161 @@
+use crate::error_handler::{ErrorBody, ErrorDetail};
+use crate::versioning;
+use crate::{
+    alerts, asset_scanners, asset_tags, assets, attachments, comments, contact_events, export,
+    graphql, health, import, labels, loans, locations, maintenance_plans, maintenance_records,
+    reservations, roles, rooms, scan, search, users, v2,
+};
+use utoipa::openapi::content::Content;
+use utoipa::openapi::path::{Operation, PathItem};
+use utoipa::openapi::response::{Response, ResponseBuilder};
+use utoipa::openapi::security::{
+    ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityRequirement, SecurityScheme,
+};
+use utoipa::openapi::{Deprecated, Info, OpenApi, Paths, Ref, RefOr};
+use utoipa::{PartialSchema, ToSchema};
+
+/*
+ * The OpenAPI document of the whole service
+ *
+ * Each module documents its own routes next to their handlers, and the
+ * document has them under each version that serves them. Handlers are named
+ * alike across modules, so operation ids are prefixed with the version and
+ * the module, which also tags them. The unversioned routes are left out, as
+ * they are the same as v1.
+ */
+
+// The routes the auth validator lets through without a token
+const PUBLIC: [(&str, &str); 5] = [
+    ("get", "/docs"),
+    ("get", "/docs/{file}"),
+    ("get", "/health"),
+    ("get", "/openapi.json"),
+    ("post", "/login"),
+];
+
+fn operations(item: &mut PathItem) -> impl Iterator<Item = (&'static str, &mut Operation)> {
+    vec![
+        ("get", item.get.as_mut()),
+        ("put", item.put.as_mut()),
+        ("post", item.post.as_mut()),
+        ("delete", item.delete.as_mut()),
+        ("patch", item.patch.as_mut()),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| operation.map(|operation| (method, operation)))
+}
+
+fn security(method: &str, path: &str) -> Vec<SecurityRequirement> {
+    let bearer = SecurityRequirement::new("bearer", Vec::<String>::new());
+    if PUBLIC.contains(&(method, path)) {
+        Vec::new()
+    } else if path.ends_with(".ics") {
+        // Calendar apps can't send headers, so these take a feed token, see auth::feed_token
+        vec![
+            bearer,
+            SecurityRequirement::new("token", Vec::<String>::new()),
+        ]
+    } else {
+        vec![bearer]
+    }
+}
+
+// Any route can fail with the same error body
+fn error_response() -> RefOr<Response> {
+    RefOr::T(
+        ResponseBuilder::new()
+            .description("An error")
+            .content(
+                "application/json",
+                Content::new(Some(Ref::from_schema_name(ErrorBody::name()))),
+            )
+            .build(),
+    )
+}
+
+// The routes of every module, as v1 has them
+fn modules() -> Vec<(&'static str, OpenApi)> {
+    vec![
+        ("alerts", alerts::openapi()),
+        ("asset_scanners", asset_scanners::openapi()),
+        ("asset_tags", asset_tags::openapi()),
+        ("assets", assets::openapi()),
+        ("attachments", attachments::openapi()),
+        ("comments", comments::openapi()),
+        ("contact_events", contact_events::openapi()),
+        ("docs", super::routes::openapi()),
+        ("export", export::openapi()),
+        ("graphql", graphql::openapi()),
+        ("health", health::openapi()),
+        ("import", import::openapi()),
+        ("labels", labels::openapi()),
+        ("loans", loans::openapi()),
+        ("locations", locations::openapi()),
+        ("maintenance_plans", maintenance_plans::openapi()),
+        ("maintenance_records", maintenance_records::openapi()),
+        ("reservations", reservations::openapi()),
+        ("roles", roles::openapi()),
+        ("rooms", rooms::openapi()),
+        ("scan", scan::openapi()),
+        ("search", search::openapi()),
+        ("users", users::openapi()),
+    ]
+}
+
+// Mount a module's routes under a version
+fn mount(doc: &mut OpenApi, prefix: &str, module: &str, mut module_doc: OpenApi, deprecated: bool) {
+    let version = prefix.rsplit('/').next().unwrap_or_default();
+    let mut paths = Paths::new();
+    for (path, mut item) in std::mem::take(&mut module_doc.paths.paths) {
+        for (method, operation) in operations(&mut item) {
+            let id = operation.operation_id.take().unwrap_or_default();
+            operation.operation_id = Some(format!("{}_{}_{}", version, module, id));
+            operation.tags = Some(vec![String::from(module)]);
+            operation.security = Some(security(method, &path));
+            operation
+                .responses
+                .responses
+                .entry(String::from("default"))
+                .or_insert_with(error_response);
+            if deprecated {
+                operation.deprecated = Some(Deprecated::True);
+            }
+        }
+        paths.paths.insert(format!("{}{}", prefix, path), item);
+    }
+    module_doc.paths = paths;
+    doc.merge(module_doc);
+}
+
+pub fn openapi() -> OpenApi {
+    let mut doc = OpenApi::new(
+        Info::new(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        Paths::new(),
+    );
+    for (module, module_doc) in modules() {
+        mount(&mut doc, versioning::V1, module, module_doc, true);
+    }
+    // Merging keeps the first operation of a method and path, so v2's own go first
+    mount(&mut doc, versioning::V2, "v2", v2::openapi(), false);
+    for (module, module_doc) in modules() {
+        mount(&mut doc, versioning::V2, module, module_doc, false);
+    }
+
+    let mut components = doc.components.take().unwrap_or_default();
+    components
+        .schemas
+        .insert(ErrorBody::name().into_owned(), ErrorBody::schema());
+    components
+        .schemas
+        .insert(ErrorDetail::name().into_owned(), ErrorDetail::schema());
+    components.add_security_scheme(
+        "bearer",
+        SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+    );
+    components.add_security_scheme(
+        "token",
+        SecurityScheme::ApiKey(ApiKey::Query(ApiKeyValue::new("token"))),
+    );
+    doc.components = Some(components);
+    doc
+}
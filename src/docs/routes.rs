@@ -0,0 +1,101 @@
+use crate::docs;
+use crate::error_handler::CustomError;
+use actix_web::{get, web, HttpResponse};
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::Config;
+
+// Swagger UI, served from the copy built into the binary and pointed at the document below
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>API documentation</title>
+    <link rel="stylesheet" href="/docs/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="/docs/swagger-ui-bundle.js"></script>
+    <script>
+      window.onload = () => {
+        window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+      };
+    </script>
+  </body>
+</html>
+"##;
+
+lazy_static! {
+    static ref OPENAPI: utoipa::openapi::OpenApi = docs::openapi();
+    static ref SWAGGER_CONFIG: Arc<Config<'static>> = Arc::new(Config::from("/openapi.json"));
+}
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    responses(
+        (status = 200, description = "This document", body = serde_json::Value),
+    )
+)]
+#[get("/openapi.json")]
+async fn find_openapi() -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::Ok().json(&*OPENAPI))
+}
+
+#[utoipa::path(
+    get,
+    path = "/docs",
+    responses(
+        (status = 200, description = "Swagger UI for this document", content_type = "text/html"),
+    )
+)]
+#[get("/docs")]
+async fn find_docs() -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI))
+}
+
+#[utoipa::path(
+    get,
+    path = "/docs/{file}",
+    params(
+        ("file" = String, Path, description = "A file of Swagger UI, like swagger-ui.css"),
+    ),
+    responses(
+        (status = 200, description = "The file"),
+        (status = 404, description = "Swagger UI has no such file"),
+    )
+)]
+#[get("/docs/{file}")]
+async fn find_docs_file(file: web::Path<String>) -> Result<HttpResponse, CustomError> {
+    let file = file.into_inner();
+    match utoipa_swagger_ui::serve(&file, SWAGGER_CONFIG.clone()) {
+        Ok(Some(file)) => Ok(HttpResponse::Ok()
+            .content_type(file.content_type)
+            .body(file.bytes.into_owned())),
+        Ok(None) => Err(CustomError::new(
+            404,
+            format!("Swagger UI has no file {}", file),
+        )),
+        Err(err) => Err(CustomError::new(
+            500,
+            format!("Failed to serve {}: {}", file, err),
+        )),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(find_openapi, find_docs, find_docs_file))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_openapi);
+    comfig.service(find_docs);
+    comfig.service(find_docs_file);
+}
@@ -1,15 +1,76 @@
+use actix_web::dev::{Body, ResponseBody, ServiceResponse};
+use actix_web::error::{
+    BlockingError, InternalError, JsonPayloadError, PathError, QueryPayloadError,
+};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
 use actix_web::http::StatusCode;
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{dev::ServiceRequest, web, HttpResponse, ResponseError};
+use async_graphql::ErrorExtensions;
 use crypto::symmetriccipher;
-use diesel::result::Error as DieselError;
-use serde::Deserialize;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-#[derive(Debug, Deserialize)]
+/*
+ * Errors as the API returns them
+ *
+ * Every error body has a stable, machine-readable code next to the message
+ * meant for people, the constraint a database error violated, the fields a
+ * request got wrong, and the id of the request so it can be found in the
+ * logs. Messages of server errors are never shown.
+ */
+
+// The header a request id is read from and returned in
+pub const REQUEST_ID: &str = "x-request-id";
+
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct ErrorDetail {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct CustomError {
     pub error_status_code: u16,
     pub error_message: String,
+    pub error_code: String,
+    pub constraint: Option<String>,
+    pub details: Vec<ErrorDetail>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = Error)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// The code of an error that has nothing more specific to say than its status
+fn default_code(status_code: u16) -> &'static str {
+    match status_code {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        405 => "method_not_allowed",
+        409 => "conflict",
+        412 => "precondition_failed",
+        413 => "payload_too_large",
+        415 => "unsupported_media_type",
+        422 => "validation_failed",
+        status_code if status_code < 500 => "client_error",
+        _ => "internal_error",
+    }
 }
 
 impl CustomError {
@@ -17,6 +78,54 @@ impl CustomError {
         CustomError {
             error_status_code,
             error_message,
+            error_code: String::from(default_code(error_status_code)),
+            constraint: None,
+            details: Vec::new(),
+        }
+    }
+
+    pub fn with_code(mut self, error_code: &str) -> CustomError {
+        self.error_code = String::from(error_code);
+        self
+    }
+
+    pub fn with_constraint(mut self, constraint: Option<&str>) -> CustomError {
+        self.constraint = constraint.map(String::from);
+        self
+    }
+
+    pub fn with_details(mut self, details: Vec<ErrorDetail>) -> CustomError {
+        self.details = details;
+        self
+    }
+
+    pub fn malformed_token() -> CustomError {
+        CustomError::new(400, String::from("The token is malformed")).with_code("malformed_token")
+    }
+
+    fn http_status(&self) -> StatusCode {
+        match StatusCode::from_u16(self.error_status_code) {
+            Ok(status_code) => status_code,
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn body(&self, request_id: Option<&str>) -> ErrorBody {
+        match self.http_status().is_server_error() {
+            false => ErrorBody {
+                code: self.error_code.clone(),
+                message: self.error_message.clone(),
+                constraint: self.constraint.clone(),
+                details: self.details.clone(),
+                request_id: request_id.map(String::from),
+            },
+            true => ErrorBody {
+                code: String::from(default_code(500)),
+                message: String::from("Internal server error"),
+                constraint: None,
+                details: Vec::new(),
+                request_id: request_id.map(String::from),
+            },
         }
     }
 }
@@ -30,66 +139,133 @@ impl fmt::Display for CustomError {
 impl From<DieselError> for CustomError {
     fn from(error: DieselError) -> CustomError {
         match error {
-            DieselError::DatabaseError(_, err) => CustomError::new(409, err.message().to_string()),
+            DieselError::DatabaseError(kind, info) => {
+                let constraint = info.constraint_name();
+                match kind {
+                    DatabaseErrorKind::UniqueViolation => CustomError::new(
+                        409,
+                        format!(
+                            "A record with the same values already exists, violating {}",
+                            constraint.unwrap_or("a unique constraint")
+                        ),
+                    )
+                    .with_code("already_exists")
+                    .with_constraint(constraint),
+                    // Postgres words violations by the referencing row "insert or update on ..."
+                    DatabaseErrorKind::ForeignKeyViolation
+                        if info.message().starts_with("insert or update") =>
+                    {
+                        CustomError::new(
+                            422,
+                            format!(
+                                "The record references one that does not exist, violating {}",
+                                constraint.unwrap_or("a foreign key")
+                            ),
+                        )
+                        .with_code("invalid_reference")
+                        .with_constraint(constraint)
+                    }
+                    DatabaseErrorKind::ForeignKeyViolation => CustomError::new(
+                        409,
+                        format!(
+                            "The record is still referenced by others, violating {}",
+                            constraint.unwrap_or("a foreign key")
+                        ),
+                    )
+                    .with_code("still_referenced")
+                    .with_constraint(constraint),
+                    DatabaseErrorKind::SerializationFailure => CustomError::new(
+                        409,
+                        String::from("The record was changed concurrently, please retry"),
+                    )
+                    .with_code("serialization_failure"),
+                    // Diesel doesn't tell check violations apart, but they name their constraint
+                    _ if constraint.is_some() => CustomError::new(
+                        422,
+                        format!("The record violates {}", constraint.unwrap_or_default()),
+                    )
+                    .with_code("constraint_violation")
+                    .with_constraint(constraint),
+                    _ => {
+                        CustomError::new(500, format!("Unknown database error: {}", info.message()))
+                    }
+                }
+            }
             DieselError::NotFound => CustomError::new(404, "The record is not found".to_string()),
             err => CustomError::new(500, format!("Unknown Diesel error: {}", err)),
         }
     }
 }
 
+impl From<std::io::Error> for CustomError {
+    fn from(error: std::io::Error) -> CustomError {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => {
+                CustomError::new(404, "The file is not found".to_string())
+            }
+            _ => CustomError::new(500, format!("Unknown IO error: {}", error)),
+        }
+    }
+}
+
 impl From<String> for CustomError {
     fn from(error: String) -> CustomError {
-        CustomError {
-            error_message: error,
-            error_status_code: 501,
-        }
+        CustomError::new(500, error)
     }
 }
 
 impl From<std::array::TryFromSliceError> for CustomError {
     fn from(_error: std::array::TryFromSliceError) -> CustomError {
-        CustomError {
-            error_message: String::from("Internal server error"),
-            error_status_code: 501,
-        }
+        CustomError::new(500, String::from("Internal server error"))
     }
 }
 
 impl From<symmetriccipher::SymmetricCipherError> for CustomError {
     fn from(_error: symmetriccipher::SymmetricCipherError) -> CustomError {
-        CustomError {
-            error_message: String::from("Internal server error"),
-            error_status_code: 501,
-        }
+        CustomError::new(500, String::from("Internal server error"))
     }
 }
 
+// Tokens are the only base64 and UTF-8 the API decodes, so these are bad tokens
 impl From<base64::DecodeError> for CustomError {
     fn from(_error: base64::DecodeError) -> CustomError {
-        CustomError {
-            error_message: String::from("Internal server error"),
-            error_status_code: 501,
-        }
+        CustomError::malformed_token()
     }
 }
 
 impl From<std::string::FromUtf8Error> for CustomError {
     fn from(_error: std::string::FromUtf8Error) -> CustomError {
-        CustomError {
-            error_message: String::from("Internal server error"),
-            error_status_code: 501,
+        CustomError::malformed_token()
+    }
+}
+
+impl From<BlockingError<CustomError>> for CustomError {
+    fn from(error: BlockingError<CustomError>) -> CustomError {
+        match error {
+            BlockingError::Error(error) => error,
+            BlockingError::Canceled => {
+                CustomError::new(500, String::from("The blocking thread pool is gone"))
+            }
         }
     }
 }
 
 impl From<actix_web::error::Error> for CustomError {
-    fn from(_error: actix_web::error::Error) -> CustomError {
-        CustomError {
-            error_message: String::from("Bad request"),
-            error_status_code: 400,
+    fn from(error: actix_web::error::Error) -> CustomError {
+        CustomError::from(&error)
+    }
+}
+
+impl From<&actix_web::error::Error> for CustomError {
+    fn from(error: &actix_web::error::Error) -> CustomError {
+        if let Some(error) = error.as_error::<CustomError>() {
+            return error.clone();
         }
+        let status_code = error.as_response_error().status_code();
+        CustomError::new(status_code.as_u16(), error.to_string())
     }
 }
+
 impl
     From<
         actix_web_httpauth::extractors::AuthenticationError<
@@ -102,25 +278,175 @@ impl
             actix_web_httpauth::headers::www_authenticate::bearer::Bearer,
         >,
     ) -> CustomError {
-        CustomError {
-            error_message: String::from("Bad request"),
-            error_status_code: 400,
+        CustomError::new(400, String::from("Bad request"))
+    }
+}
+
+impl From<JsonPayloadError> for CustomError {
+    fn from(error: JsonPayloadError) -> CustomError {
+        match error {
+            JsonPayloadError::Overflow => {
+                CustomError::new(413, String::from("The JSON body is too large"))
+            }
+            JsonPayloadError::ContentType => CustomError::new(
+                415,
+                String::from("The body must be JSON with the application/json content type"),
+            ),
+            JsonPayloadError::Deserialize(err) if err.is_data() => {
+                CustomError::new(422, format!("The JSON body is invalid: {}", err))
+                    .with_code("invalid_body")
+            }
+            JsonPayloadError::Deserialize(err) => {
+                CustomError::new(400, format!("The body is not valid JSON: {}", err))
+                    .with_code("malformed_json")
+            }
+            JsonPayloadError::Payload(err) => {
+                CustomError::new(400, format!("The body could not be read: {}", err))
+            }
         }
     }
 }
 
-impl ResponseError for CustomError {
-    fn error_response(&self) -> HttpResponse {
-        let status_code = match StatusCode::from_u16(self.error_status_code) {
-            Ok(status_code) => status_code,
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+impl From<QueryPayloadError> for CustomError {
+    fn from(error: QueryPayloadError) -> CustomError {
+        match error {
+            QueryPayloadError::Deserialize(err) => {
+                CustomError::new(400, format!("The query string is invalid: {}", err))
+                    .with_code("invalid_query")
+            }
+        }
+    }
+}
+
+// A path that doesn't parse, like a word for an id, names no record
+impl From<PathError> for CustomError {
+    fn from(error: PathError) -> CustomError {
+        match error {
+            PathError::Deserialize(err) => {
+                CustomError::new(404, format!("The path is invalid: {}", err))
+            }
+        }
+    }
+}
+
+// Extractors reject requests with the same bodies as handlers
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _req| CustomError::from(err).into())
+}
+
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|err, _req| CustomError::from(err).into())
+}
+
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|err, _req| CustomError::from(err).into())
+}
+
+// The id of the request being handled, for handlers that log errors themselves
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// The id the client gave a request, or a new one
+pub fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= 128)
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
 
-        let error_message = match status_code.as_u16() < 500 {
-            true => self.error_message.clone(),
-            false => "Internal server error".to_string(),
+// The message of a server error is only in the logs, under the request id
+pub fn log_server_error(error: &CustomError, request_id: &str) {
+    if error.http_status().is_server_error() {
+        log::error!(
+            "Request {} failed with {}: {}",
+            request_id,
+            error.error_status_code,
+            error.error_message
+        );
+    }
+}
+
+// Give a response the error's JSON body and the request id
+fn render(headers: &mut HeaderMap, error: &CustomError, request_id: &str) -> Body {
+    log_server_error(error, request_id);
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        headers.insert(HeaderName::from_static(REQUEST_ID), value);
+    }
+    match serde_json::to_string(&error.body(Some(request_id))) {
+        Ok(body) => Body::from(body),
+        Err(_) => Body::Empty,
+    }
+}
+
+/*
+ * Return the request id with every response, and give the errors of
+ * middleware and actix itself the same JSON body as the handlers' own.
+ * Responses a handler built on purpose, like an import report, are kept.
+ * Middleware errors have no request to respond to, so they stay errors
+ * that carry the response to send.
+ */
+pub fn respond(
+    request_id: String,
+    res: Result<ServiceResponse<Body>, actix_web::Error>,
+) -> Result<ServiceResponse<Body>, actix_web::Error> {
+    match res {
+        Ok(mut res) => match res.response().error().map(CustomError::from) {
+            Some(error) => Ok(res.map_body(|head, _| {
+                ResponseBody::Body(render(&mut head.headers, &error, &request_id))
+            })),
+            None => {
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(REQUEST_ID), value);
+                }
+                Ok(res)
+            }
+        },
+        Err(error) => {
+            let mut response = error.as_response_error().error_response();
+            let error = CustomError::from(&error);
+            let body = render(response.headers_mut(), &error, &request_id);
+            Err(InternalError::from_response(error, response.set_body(body)).into())
+        }
+    }
+}
+
+/*
+ * GraphQL errors carry the status and code the same error gets from the REST
+ * routes. The error itself is their source, which isn't sent, so the route can
+ * log the message of a server error under the request id.
+ */
+impl ErrorExtensions for CustomError {
+    fn extend(&self) -> async_graphql::Error {
+        let body = self.body(None);
+        let error = async_graphql::Error {
+            source: Some(Arc::new(self.clone())),
+            ..async_graphql::Error::new(body.message.clone())
         };
+        error.extend_with(|_, extensions| {
+            extensions.set("status", self.http_status().as_u16());
+            extensions.set("code", body.code.clone());
+            if let Some(constraint) = &body.constraint {
+                extensions.set("constraint", constraint.clone());
+            }
+            if !body.details.is_empty() {
+                if let Ok(details) = async_graphql::Value::from_json(json!(body.details)) {
+                    extensions.set("details", details);
+                }
+            }
+        })
+    }
+}
 
-        HttpResponse::build(status_code).json(json!({ "message": error_message }))
+impl ResponseError for CustomError {
+    fn status_code(&self) -> StatusCode {
+        self.http_status()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.http_status()).json(self.body(None))
     }
 }
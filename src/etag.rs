@@ -0,0 +1,84 @@
+use crate::error_handler::CustomError;
+use actix_web::http::header::{self, EntityTag, Header, IfMatch, IfNoneMatch};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/*
+ * Conditional requests on single resources
+ *
+ * The ETag of a resource hashes its JSON representation, so any change to one
+ * of its fields gives it a new tag. GETs answer a matching If-None-Match with
+ * 304 Not Modified. PUTs, PATCHes and DELETEs with an If-Match that no longer
+ * names the current version fail with 412 Precondition Failed, rather than
+ * overwriting an edit made since the client last read it. The version is
+ * compared with the row locked by the write, so of two clients sending the
+ * same If-Match only the first gets through.
+ */
+
+pub fn etag<T: Serialize>(resource: &T) -> Result<EntityTag, CustomError> {
+    let json = serde_json::to_vec(resource)
+        .map_err(|err| CustomError::new(500, format!("Failed to serialize: {}", err)))?;
+    Ok(EntityTag::strong(format!("{:x}", Sha256::digest(&json))))
+}
+
+// 200 with the resource and its ETag
+pub fn ok<T: Serialize>(resource: &T) -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::Ok()
+        .set(header::ETag(etag(resource)?))
+        .json(resource))
+}
+
+// Like ok, but 304 when If-None-Match already names the current version
+pub fn respond<T: Serialize>(req: &HttpRequest, resource: &T) -> Result<HttpResponse, CustomError> {
+    let tag = etag(resource)?;
+    let not_modified = match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|other| other.weak_eq(&tag)),
+        Err(_) => false,
+    };
+    if not_modified {
+        Ok(HttpResponse::NotModified().set(header::ETag(tag)).finish())
+    } else {
+        Ok(HttpResponse::Ok().set(header::ETag(tag)).json(resource))
+    }
+}
+
+// The If-Match header of a request, if it has one, to check against the current
+// version once the write has locked the row, so no other write can land between
+// the check and the write
+pub struct Precondition(Option<IfMatch>);
+
+pub fn precondition(req: &HttpRequest) -> Result<Precondition, CustomError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return Ok(Precondition(None));
+    }
+    match IfMatch::parse(req) {
+        Ok(if_match) => Ok(Precondition(Some(if_match))),
+        Err(_) => Err(CustomError::new(
+            400,
+            String::from("The If-Match header is not a list of ETags"),
+        )),
+    }
+}
+
+impl Precondition {
+    // Fail with 412 unless If-Match is absent or names the current version
+    pub fn check<T: Serialize>(&self, current: &T) -> Result<(), CustomError> {
+        let matches = match &self.0 {
+            None | Some(IfMatch::Any) => true,
+            Some(IfMatch::Items(tags)) => {
+                let tag = etag(current)?;
+                tags.iter().any(|other| other.strong_eq(&tag))
+            }
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(CustomError::new(
+                412,
+                String::from("The resource has changed since the If-Match ETag was read"),
+            ))
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use actix_web::web::Bytes;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Nullable, Text, Timestamp};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/*
+ * Exports of the inventory and sighting history for spreadsheets and scripts
+ *
+ * Rows are read in batches by keyset pagination and written out as they are
+ * read, so an export never holds more than one batch in memory. Foreign keys
+ * are replaced by the names people know things by.
+ */
+
+pub const BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+pub trait ExportRow: Serialize {
+    const COLUMNS: &'static [&'static str];
+
+    // Batches continue after the key of the last row of the previous batch
+    fn key(&self) -> i64;
+}
+
+// Each asset tag of an asset, where it was last seen and who has it on loan
+#[derive(Debug, Serialize, Deserialize, QueryableByName)]
+pub struct AssetRow {
+    #[sql_type = "BigInt"]
+    pub asset_id: i64,
+    #[sql_type = "Timestamp"]
+    pub asset_created_at: NaiveDateTime,
+    #[sql_type = "Nullable<Text>"]
+    pub tag_name: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub serial_number: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub description: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub last_seen_location: Option<String>,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub last_seen_at: Option<NaiveDateTime>,
+    #[sql_type = "Nullable<Text>"]
+    pub borrower: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub loan_location: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub loan_room: Option<String>,
+    #[sql_type = "Nullable<Timestamp>"]
+    pub loan_due_at: Option<NaiveDateTime>,
+}
+
+impl ExportRow for AssetRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "asset_id",
+        "asset_created_at",
+        "tag_name",
+        "serial_number",
+        "description",
+        "last_seen_location",
+        "last_seen_at",
+        "borrower",
+        "loan_location",
+        "loan_room",
+        "loan_due_at",
+    ];
+
+    fn key(&self) -> i64 {
+        self.asset_id
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, QueryableByName)]
+pub struct ContactEventRow {
+    #[sql_type = "BigInt"]
+    pub contact_event_id: i64,
+    #[sql_type = "Timestamp"]
+    pub seen_at: NaiveDateTime,
+    #[sql_type = "Text"]
+    pub tag_name: String,
+    #[sql_type = "Text"]
+    pub serial_number: String,
+    #[sql_type = "Nullable<BigInt>"]
+    pub asset_id: Option<i64>,
+    #[sql_type = "Nullable<Text>"]
+    pub location: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub parent_location: Option<String>,
+    #[sql_type = "Nullable<Text>"]
+    pub alert_reason: Option<String>,
+}
+
+impl ExportRow for ContactEventRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "contact_event_id",
+        "seen_at",
+        "tag_name",
+        "serial_number",
+        "asset_id",
+        "location",
+        "parent_location",
+        "alert_reason",
+    ];
+
+    fn key(&self) -> i64 {
+        self.contact_event_id
+    }
+}
+
+// Batches hold whole assets, so the rows of an asset with several tags are never split
+pub fn find_assets(after: i64) -> Result<Vec<AssetRow>, CustomError> {
+    let conn = db::connection()?;
+    let rows = diesel::sql_query(
+        "SELECT a.id AS asset_id, a.created_at AS asset_created_at,
+            t.name AS tag_name, t.serial_number, t.description,
+            l.name AS last_seen_location, ce.created_at AS last_seen_at,
+            u.username AS borrower, ll.name AS loan_location, r.name AS loan_room,
+            lo.due_at AS loan_due_at
+        FROM (
+            SELECT id, created_at FROM assets WHERE NOT deleted AND id > $1 ORDER BY id LIMIT $2
+        ) a
+        LEFT JOIN asset_tags t ON t.asset_id = a.id AND NOT t.deleted
+        LEFT JOIN LATERAL (
+            SELECT location_id, created_at FROM contact_events
+            WHERE asset_tag_id = t.id AND NOT deleted
+            ORDER BY created_at DESC, id DESC LIMIT 1
+        ) ce ON true
+        LEFT JOIN locations l ON l.id = ce.location_id
+        LEFT JOIN LATERAL (
+            SELECT user_id, location_id, room_id, due_at FROM loans
+            WHERE asset_id = a.id AND returned_at IS NULL
+            ORDER BY created_at DESC, id DESC LIMIT 1
+        ) lo ON true
+        LEFT JOIN users u ON u.id = lo.user_id
+        LEFT JOIN locations ll ON ll.id = lo.location_id
+        LEFT JOIN rooms r ON r.id = lo.room_id
+        ORDER BY a.id, t.id",
+    )
+    .bind::<BigInt, _>(after)
+    .bind::<BigInt, _>(BATCH_SIZE)
+    .load::<AssetRow>(&conn)?;
+    Ok(rows)
+}
+
+// Sightings from `from` up to but excluding `to`, oldest first
+pub fn find_contact_events(
+    after: i64,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>,
+) -> Result<Vec<ContactEventRow>, CustomError> {
+    let conn = db::connection()?;
+    let rows = diesel::sql_query(
+        "SELECT ce.id AS contact_event_id, ce.created_at AS seen_at,
+            t.name AS tag_name, t.serial_number, t.asset_id,
+            l.name AS location, p.name AS parent_location, al.reason AS alert_reason
+        FROM contact_events ce
+        JOIN asset_tags t ON t.id = ce.asset_tag_id
+        JOIN locations l ON l.id = ce.location_id
+        LEFT JOIN locations p ON p.id = l.parent_id
+        LEFT JOIN alerts al ON al.id = ce.alert_id
+        WHERE NOT ce.deleted AND ce.id > $1
+            AND ($2::timestamp IS NULL OR ce.created_at >= $2)
+            AND ($3::timestamp IS NULL OR ce.created_at < $3)
+        ORDER BY ce.id
+        LIMIT $4",
+    )
+    .bind::<BigInt, _>(after)
+    .bind::<Nullable<Timestamp>, _>(from)
+    .bind::<Nullable<Timestamp>, _>(to)
+    .bind::<BigInt, _>(BATCH_SIZE)
+    .load::<ContactEventRow>(&conn)?;
+    Ok(rows)
+}
+
+// Spreadsheets take cells starting with these for formulas
+const FORMULA_PREFIXES: &[char] = &['=', '+', '-', '@', '\t', '\r'];
+
+// The CSV cells of a row, where text a spreadsheet would run as a formula starts with '
+fn cells<T: ExportRow>(row: &T) -> Result<Vec<String>, serde_json::Error> {
+    let value = serde_json::to_value(row)?;
+    Ok(T::COLUMNS
+        .iter()
+        .map(|column| match &value[*column] {
+            Value::Null => String::new(),
+            Value::String(text) if text.starts_with(FORMULA_PREFIXES) => format!("'{}", text),
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        })
+        .collect())
+}
+
+fn encode<T: ExportRow>(format: Format, rows: &[T], header: bool) -> Result<Bytes, CustomError> {
+    let failed = |err: &dyn std::fmt::Display| {
+        CustomError::new(500, format!("Failed to write export: {}", err))
+    };
+    let mut data = Vec::new();
+    match format {
+        Format::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(&mut data);
+            if header {
+                writer
+                    .write_record(T::COLUMNS)
+                    .map_err(|err| failed(&err))?;
+            }
+            for row in rows {
+                let cells = cells(row).map_err(|err| failed(&err))?;
+                writer.write_record(&cells).map_err(|err| failed(&err))?;
+            }
+            writer.flush().map_err(|err| failed(&err))?;
+        }
+        Format::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut data, row).map_err(|err| failed(&err))?;
+                data.push(b'\n');
+            }
+        }
+    }
+    Ok(Bytes::from(data))
+}
+
+// One chunk per batch, starting with the CSV header even when there are no rows
+pub fn stream<T, F>(format: Format, fetch: F) -> impl Stream<Item = Result<Bytes, CustomError>>
+where
+    T: ExportRow + Send + 'static,
+    F: Fn(i64) -> Result<Vec<T>, CustomError> + Clone + Send + 'static,
+{
+    futures::stream::unfold(Some((0, true)), move |state| {
+        let fetch = fetch.clone();
+        async move {
+            let (after, first) = state?;
+            match db::run(move || fetch(after)).await {
+                Err(err) => Some((Err(err), None)),
+                Ok(rows) if rows.is_empty() && !first => None,
+                Ok(rows) => {
+                    let next = rows.last().map(|row| (row.key(), false));
+                    Some((encode(format, &rows, first), next))
+                }
+            }
+        }
+    })
+}
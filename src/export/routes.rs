@@ -0,0 +1,153 @@
+use crate::error_handler::CustomError;
+use crate::export::{self, Format};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ContactEventsQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+// A timestamp, or a date standing for its start, or for its end when it ends a range
+fn parse_time(value: &str, end: bool) -> Result<NaiveDateTime, CustomError> {
+    if let Ok(time) = value.parse::<NaiveDateTime>() {
+        return Ok(time);
+    }
+    match value.parse::<NaiveDate>() {
+        Ok(date) if end => Ok(date.succ_opt().unwrap_or(date).and_hms_opt(0, 0, 0).unwrap()),
+        Ok(date) => Ok(date.and_hms_opt(0, 0, 0).unwrap()),
+        Err(_) => Err(CustomError::new(
+            400,
+            format!(
+                "Invalid time '{}', expected a date such as 2021-04-30 or a time such as 2021-04-30T12:00:00",
+                value
+            ),
+        )),
+    }
+}
+
+fn respond<S>(format: Format, filename: &str, stream: S) -> HttpResponse
+where
+    S: futures::Stream<Item = Result<web::Bytes, CustomError>> + 'static,
+{
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(String::from(filename))],
+        })
+        .streaming(Box::pin(stream))
+}
+
+fn export_assets(format: Format, filename: &str) -> HttpResponse {
+    respond(
+        format,
+        filename,
+        export::stream(format, export::find_assets),
+    )
+}
+
+fn export_contact_events(
+    format: Format,
+    filename: &str,
+    query: ContactEventsQuery,
+) -> Result<HttpResponse, CustomError> {
+    let from = query
+        .from
+        .as_deref()
+        .map(|from| parse_time(from, false))
+        .transpose()?;
+    let to = query
+        .to
+        .as_deref()
+        .map(|to| parse_time(to, true))
+        .transpose()?;
+    let stream = export::stream(format, move |after| {
+        export::find_contact_events(after, from, to)
+    });
+    Ok(respond(format, filename, stream))
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/assets.csv",
+    responses(
+        (status = 200, description = "Every asset with its tag as CSV", content_type = "text/csv"),
+    )
+)]
+#[get("/export/assets.csv")]
+async fn assets_csv() -> Result<HttpResponse, CustomError> {
+    log::trace!("GET /export/assets.csv");
+    Ok(export_assets(Format::Csv, "assets.csv"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/assets.ndjson",
+    responses(
+        (status = 200, description = "Every asset with its tag as newline-delimited JSON", content_type = "application/x-ndjson"),
+    )
+)]
+#[get("/export/assets.ndjson")]
+async fn assets_ndjson() -> Result<HttpResponse, CustomError> {
+    log::trace!("GET /export/assets.ndjson");
+    Ok(export_assets(Format::Ndjson, "assets.ndjson"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/contact_events.csv",
+    params(
+        ContactEventsQuery,
+    ),
+    responses(
+        (status = 200, description = "The contact events in the range as CSV", content_type = "text/csv"),
+    )
+)]
+#[get("/export/contact_events.csv")]
+async fn contact_events_csv(
+    query: web::Query<ContactEventsQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /export/contact_events.csv {:?}", &query);
+    export_contact_events(Format::Csv, "contact_events.csv", query)
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/contact_events.ndjson",
+    params(
+        ContactEventsQuery,
+    ),
+    responses(
+        (status = 200, description = "The contact events in the range as newline-delimited JSON", content_type = "application/x-ndjson"),
+    )
+)]
+#[get("/export/contact_events.ndjson")]
+async fn contact_events_ndjson(
+    query: web::Query<ContactEventsQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /export/contact_events.ndjson {:?}", &query);
+    export_contact_events(Format::Ndjson, "contact_events.ndjson", query)
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(assets_csv, assets_ndjson, contact_events_csv, contact_events_ndjson))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(assets_csv);
+    comfig.service(assets_ndjson);
+    comfig.service(contact_events_csv);
+    comfig.service(contact_events_ndjson);
+}
@@ -0,0 +1,62 @@
+use crate::error_handler::CustomError;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/*
+ * Minimal GeoJSON (RFC 7946) rendering for dropping our data onto a map
+ *
+ * Positions are [longitude, latitude] in that order, as GeoJSON requires.
+ */
+
+pub const CONTENT_TYPE: &str = "application/geo+json";
+
+// Widening f32 to f64 would render 52.370216 as 52.37021636962891, so go through its shortest form
+fn coordinate(value: f32) -> f64 {
+    value
+        .to_string()
+        .parse()
+        .unwrap_or_else(|_| f64::from(value))
+}
+
+pub fn point(latitude: f32, longitude: f32) -> Value {
+    json!({
+        "type": "Point",
+        "coordinates": [coordinate(longitude), coordinate(latitude)],
+    })
+}
+
+pub fn feature(id: i64, geometry: Value, properties: Map<String, Value>) -> Value {
+    json!({
+        "type": "Feature",
+        "id": id,
+        "geometry": geometry,
+        "properties": properties,
+    })
+}
+
+pub fn feature_collection(features: Vec<Value>) -> Value {
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+// The fields of a serialized value as feature properties, leaving out the excluded ones
+pub fn properties<T: Serialize>(
+    value: &T,
+    exclude: &[&str],
+) -> Result<Map<String, Value>, CustomError> {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(mut properties)) => {
+            for key in exclude {
+                properties.remove(*key);
+            }
+            Ok(properties)
+        }
+        Ok(_) => Ok(Map::new()),
+        Err(err) => Err(CustomError::new(
+            500,
+            format!("Failed to serialize feature properties: {}", err),
+        )),
+    }
+}
@@ -0,0 +1,219 @@
+use crate::alerts::Alert;
+use crate::asset_tags::AssetTag;
+use crate::assets::Asset;
+use crate::comments::Comment;
+use crate::contact_events::ContactEvent;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::locations::Location;
+use crate::rooms::Room;
+use crate::schema::{
+    alerts, asset_tags, assets, comments, contact_events, locations, rooms, users,
+};
+use crate::users::User;
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{Context, Request, Result, ResultExt};
+use diesel::prelude::*;
+use std::collections::HashMap;
+
+/*
+ * Batching the lookups of related resources
+ *
+ * A resolver asks its loader for the rows related to one resource, and the
+ * loader gathers what every resolver of the same field asked for before
+ * looking them all up in one query. Each request gets its own loaders, so
+ * nothing is cached across requests.
+ */
+
+// Rows of a table by id, leaving out soft-deleted ones
+macro_rules! by_id {
+    ($loader:ident, $table:ident, $model:ty) => {
+        pub struct $loader;
+
+        impl Loader<i64> for $loader {
+            type Value = $model;
+            type Error = CustomError;
+
+            async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, $model>, CustomError> {
+                let keys = keys.to_vec();
+                let rows = db::run(move || {
+                    let conn = db::connection()?;
+                    let rows = $table::table
+                        .filter($table::id.eq_any(keys))
+                        .filter($table::deleted.eq(false))
+                        .load::<$model>(&conn)?;
+                    Ok(rows)
+                })
+                .await?;
+                Ok(rows.into_iter().map(|row| (row.id, row)).collect())
+            }
+        }
+    };
+}
+
+// Rows of a table grouped by a foreign key, leaving out soft-deleted ones
+macro_rules! by_foreign_key {
+    ($loader:ident, $table:ident, $column:ident, $model:ty, $order:expr) => {
+        pub struct $loader;
+
+        impl Loader<i64> for $loader {
+            type Value = Vec<$model>;
+            type Error = CustomError;
+
+            async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, Vec<$model>>, CustomError> {
+                let keys = keys.to_vec();
+                let rows = db::run(move || {
+                    let conn = db::connection()?;
+                    let rows = $table::table
+                        .filter($table::$column.eq_any(keys))
+                        .filter($table::deleted.eq(false))
+                        .order($order)
+                        .load::<$model>(&conn)?;
+                    Ok(rows)
+                })
+                .await?;
+                let mut groups: HashMap<i64, Vec<$model>> = HashMap::new();
+                for row in rows {
+                    if let Some(key) = Option::<i64>::from(row.$column) {
+                        groups.entry(key).or_default().push(row);
+                    }
+                }
+                Ok(groups)
+            }
+        }
+    };
+}
+
+by_id!(AlertById, alerts, Alert);
+by_id!(AssetById, assets, Asset);
+by_id!(AssetTagById, asset_tags, AssetTag);
+by_id!(CommentById, comments, Comment);
+by_id!(LocationById, locations, Location);
+by_id!(UserById, users, User);
+
+by_foreign_key!(AlertsByUser, alerts, user_id, Alert, alerts::id);
+by_foreign_key!(AssetsByAssetTag, assets, asset_tag_id, Asset, assets::id);
+by_foreign_key!(
+    AssetTagsByAsset,
+    asset_tags,
+    asset_id,
+    AssetTag,
+    asset_tags::id
+);
+by_foreign_key!(
+    CommentsByAlert,
+    comments,
+    alert_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    CommentsByAsset,
+    comments,
+    asset_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    CommentsByAssetTag,
+    comments,
+    asset_tag_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    CommentsByLocation,
+    comments,
+    location_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    CommentsByParent,
+    comments,
+    parent_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    CommentsByUser,
+    comments,
+    user_id,
+    Comment,
+    (comments::created_at, comments::id)
+);
+by_foreign_key!(
+    ContactEventsByAlert,
+    contact_events,
+    alert_id,
+    ContactEvent,
+    (contact_events::created_at.desc(), contact_events::id.desc())
+);
+by_foreign_key!(
+    ContactEventsByAssetTag,
+    contact_events,
+    asset_tag_id,
+    ContactEvent,
+    (contact_events::created_at.desc(), contact_events::id.desc())
+);
+by_foreign_key!(
+    ContactEventsByLocation,
+    contact_events,
+    location_id,
+    ContactEvent,
+    (contact_events::created_at.desc(), contact_events::id.desc())
+);
+by_foreign_key!(
+    LocationsByParent,
+    locations,
+    parent_id,
+    Location,
+    locations::id
+);
+by_foreign_key!(RoomsByLocation, rooms, location_id, Room, rooms::id);
+
+// Give a request a fresh set of loaders
+pub fn register(request: Request) -> Request {
+    request
+        .data(DataLoader::new(AlertById, actix_rt::spawn))
+        .data(DataLoader::new(AssetById, actix_rt::spawn))
+        .data(DataLoader::new(AssetTagById, actix_rt::spawn))
+        .data(DataLoader::new(CommentById, actix_rt::spawn))
+        .data(DataLoader::new(LocationById, actix_rt::spawn))
+        .data(DataLoader::new(UserById, actix_rt::spawn))
+        .data(DataLoader::new(AlertsByUser, actix_rt::spawn))
+        .data(DataLoader::new(AssetsByAssetTag, actix_rt::spawn))
+        .data(DataLoader::new(AssetTagsByAsset, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByAlert, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByAsset, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByAssetTag, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByLocation, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByParent, actix_rt::spawn))
+        .data(DataLoader::new(CommentsByUser, actix_rt::spawn))
+        .data(DataLoader::new(ContactEventsByAlert, actix_rt::spawn))
+        .data(DataLoader::new(ContactEventsByAssetTag, actix_rt::spawn))
+        .data(DataLoader::new(ContactEventsByLocation, actix_rt::spawn))
+        .data(DataLoader::new(LocationsByParent, actix_rt::spawn))
+        .data(DataLoader::new(RoomsByLocation, actix_rt::spawn))
+}
+
+// The row a resource references, if any
+pub async fn one<L>(ctx: &Context<'_>, id: Option<i64>) -> Result<Option<L::Value>>
+where
+    L: Loader<i64, Error = CustomError>,
+{
+    match id {
+        Some(id) => ctx.data::<DataLoader<L>>()?.load_one(id).await.extend(),
+        None => Ok(None),
+    }
+}
+
+// The rows that reference a resource
+pub async fn many<L, T>(ctx: &Context<'_>, id: i64) -> Result<Vec<T>>
+where
+    L: Loader<i64, Value = Vec<T>, Error = CustomError>,
+    T: Send + Sync + Clone + 'static,
+{
+    let rows = ctx.data::<DataLoader<L>>()?.load_one(id).await.extend()?;
+    Ok(rows.unwrap_or_default())
+}
@@ -0,0 +1,7 @@
+mod loader;
+mod model;
+mod mutation;
+mod query;
+mod routes;
+
+pub use routes::{init_routes, openapi};
@@ -0,0 +1,693 @@
+use super::loader::{self, *};
+use crate::pagination::{PageQuery, DEFAULT_LIMIT, MAX_LIMIT};
+use crate::{alerts, asset_tags, assets, comments, contact_events, locations, rooms, users};
+use async_graphql::{scalar, Context, Object, Result, ResultExt};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/*
+ * The types of the GraphQL schema
+ *
+ * Each type wraps a model and has its columns as fields, apart from secrets
+ * like a user's token, and its associations as fields that go through the
+ * loaders. Soft-deleted resources are left out of associations, as they are
+ * left out of the REST routes.
+ */
+
+// Timestamps as the REST routes serialize them, as in 2021-01-31T12:00:00.123456
+#[derive(Serialize, Deserialize)]
+pub struct Timestamp(NaiveDateTime);
+
+scalar!(Timestamp);
+
+impl From<NaiveDateTime> for Timestamp {
+    fn from(timestamp: NaiveDateTime) -> Self {
+        Timestamp(timestamp)
+    }
+}
+
+// Wrap the models a query returns in their GraphQL types
+pub fn wrap<T, U: From<T>>(rows: Vec<T>) -> Vec<U> {
+    rows.into_iter().map(U::from).collect()
+}
+
+// A page of an association, picked out by limit and offset as a REST page is
+pub fn page<T, U: From<T>>(
+    rows: Vec<T>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Vec<U>> {
+    let (limit, offset) = PageQuery { limit, offset }.bounds().extend()?;
+    Ok(wrap(
+        rows.into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect(),
+    ))
+}
+
+// A list counts as many times as it can have items towards the complexity limit,
+// so that nesting lists multiplies what a query may cost
+pub fn page_complexity(limit: Option<i64>, child_complexity: usize) -> usize {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT) as usize * child_complexity
+}
+
+pub struct Asset(pub assets::Asset);
+
+impl From<assets::Asset> for Asset {
+    fn from(asset: assets::Asset) -> Self {
+        Asset(asset)
+    }
+}
+
+#[Object]
+impl Asset {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn asset_tag_id(&self) -> Option<i64> {
+        self.0.asset_tag_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn asset_tag(&self, ctx: &Context<'_>) -> Result<Option<AssetTag>> {
+        let asset_tag = loader::one::<AssetTagById>(ctx, self.0.asset_tag_id).await?;
+        Ok(asset_tag.map(AssetTag))
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn asset_tags(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<AssetTag>> {
+        page(
+            loader::many::<AssetTagsByAsset, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByAsset, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+pub struct AssetTag(pub asset_tags::AssetTag);
+
+impl From<asset_tags::AssetTag> for AssetTag {
+    fn from(asset_tag: asset_tags::AssetTag) -> Self {
+        AssetTag(asset_tag)
+    }
+}
+
+#[Object]
+impl AssetTag {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn serial_number(&self) -> &str {
+        &self.0.serial_number
+    }
+
+    async fn asset_id(&self) -> Option<i64> {
+        self.0.asset_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn asset(&self, ctx: &Context<'_>) -> Result<Option<Asset>> {
+        let asset = loader::one::<AssetById>(ctx, self.0.asset_id).await?;
+        Ok(asset.map(Asset))
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn assets(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Asset>> {
+        page(
+            loader::many::<AssetsByAssetTag, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn contact_events(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<ContactEvent>> {
+        page(
+            loader::many::<ContactEventsByAssetTag, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByAssetTag, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+pub struct ContactEvent(pub contact_events::ContactEvent);
+
+impl From<contact_events::ContactEvent> for ContactEvent {
+    fn from(contact_event: contact_events::ContactEvent) -> Self {
+        ContactEvent(contact_event)
+    }
+}
+
+#[Object]
+impl ContactEvent {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn asset_tag_id(&self) -> i64 {
+        self.0.asset_tag_id
+    }
+
+    async fn location_id(&self) -> i64 {
+        self.0.location_id
+    }
+
+    async fn alert_id(&self) -> Option<i64> {
+        self.0.alert_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn asset_tag(&self, ctx: &Context<'_>) -> Result<Option<AssetTag>> {
+        let asset_tag = loader::one::<AssetTagById>(ctx, Some(self.0.asset_tag_id)).await?;
+        Ok(asset_tag.map(AssetTag))
+    }
+
+    async fn location(&self, ctx: &Context<'_>) -> Result<Option<Location>> {
+        let location = loader::one::<LocationById>(ctx, Some(self.0.location_id)).await?;
+        Ok(location.map(Location))
+    }
+
+    async fn alert(&self, ctx: &Context<'_>) -> Result<Option<Alert>> {
+        let alert = loader::one::<AlertById>(ctx, self.0.alert_id).await?;
+        Ok(alert.map(Alert))
+    }
+}
+
+pub struct Location(pub locations::Location);
+
+impl From<locations::Location> for Location {
+    fn from(location: locations::Location) -> Self {
+        Location(location)
+    }
+}
+
+#[Object]
+impl Location {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> Option<&str> {
+        self.0.name.as_deref()
+    }
+
+    async fn latitude(&self) -> f32 {
+        self.0.latitude
+    }
+
+    async fn longitude(&self) -> f32 {
+        self.0.longitude
+    }
+
+    async fn ip(&self) -> Option<String> {
+        self.0.ip.map(|ip| ip.to_string())
+    }
+
+    async fn parent_id(&self) -> Option<i64> {
+        self.0.parent_id
+    }
+
+    async fn kind(&self) -> &str {
+        &self.0.kind
+    }
+
+    async fn footprint(&self) -> Option<&serde_json::Value> {
+        self.0.footprint.as_ref()
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Location>> {
+        let parent = loader::one::<LocationById>(ctx, self.0.parent_id).await?;
+        Ok(parent.map(Location))
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn children(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Location>> {
+        page(
+            loader::many::<LocationsByParent, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn rooms(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Room>> {
+        page(
+            loader::many::<RoomsByLocation, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn contact_events(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<ContactEvent>> {
+        page(
+            loader::many::<ContactEventsByLocation, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByLocation, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+pub struct Room(pub rooms::Room);
+
+impl From<rooms::Room> for Room {
+    fn from(room: rooms::Room) -> Self {
+        Room(room)
+    }
+}
+
+#[Object]
+impl Room {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn location_id(&self) -> i64 {
+        self.0.location_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn location(&self, ctx: &Context<'_>) -> Result<Option<Location>> {
+        let location = loader::one::<LocationById>(ctx, Some(self.0.location_id)).await?;
+        Ok(location.map(Location))
+    }
+}
+
+pub struct Alert(pub alerts::Alert);
+
+impl From<alerts::Alert> for Alert {
+    fn from(alert: alerts::Alert) -> Self {
+        Alert(alert)
+    }
+}
+
+#[Object]
+impl Alert {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn message(&self) -> Option<&str> {
+        self.0.message.as_deref()
+    }
+
+    async fn reason(&self) -> &str {
+        &self.0.reason
+    }
+
+    async fn user_id(&self) -> i64 {
+        self.0.user_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let user = loader::one::<UserById>(ctx, Some(self.0.user_id)).await?;
+        Ok(user.map(User))
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn contact_events(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<ContactEvent>> {
+        page(
+            loader::many::<ContactEventsByAlert, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByAlert, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+pub struct Comment(pub comments::Comment);
+
+impl From<comments::Comment> for Comment {
+    fn from(comment: comments::Comment) -> Self {
+        Comment(comment)
+    }
+}
+
+#[Object]
+impl Comment {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn content(&self) -> &str {
+        &self.0.content
+    }
+
+    async fn user_id(&self) -> i64 {
+        self.0.user_id
+    }
+
+    async fn asset_tag_id(&self) -> Option<i64> {
+        self.0.asset_tag_id
+    }
+
+    async fn asset_id(&self) -> Option<i64> {
+        self.0.asset_id
+    }
+
+    async fn location_id(&self) -> Option<i64> {
+        self.0.location_id
+    }
+
+    async fn alert_id(&self) -> Option<i64> {
+        self.0.alert_id
+    }
+
+    async fn parent_id(&self) -> Option<i64> {
+        self.0.parent_id
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    async fn user(&self, ctx: &Context<'_>) -> Result<Option<User>> {
+        let user = loader::one::<UserById>(ctx, Some(self.0.user_id)).await?;
+        Ok(user.map(User))
+    }
+
+    async fn asset_tag(&self, ctx: &Context<'_>) -> Result<Option<AssetTag>> {
+        let asset_tag = loader::one::<AssetTagById>(ctx, self.0.asset_tag_id).await?;
+        Ok(asset_tag.map(AssetTag))
+    }
+
+    async fn asset(&self, ctx: &Context<'_>) -> Result<Option<Asset>> {
+        let asset = loader::one::<AssetById>(ctx, self.0.asset_id).await?;
+        Ok(asset.map(Asset))
+    }
+
+    async fn location(&self, ctx: &Context<'_>) -> Result<Option<Location>> {
+        let location = loader::one::<LocationById>(ctx, self.0.location_id).await?;
+        Ok(location.map(Location))
+    }
+
+    async fn alert(&self, ctx: &Context<'_>) -> Result<Option<Alert>> {
+        let alert = loader::one::<AlertById>(ctx, self.0.alert_id).await?;
+        Ok(alert.map(Alert))
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<Comment>> {
+        let parent = loader::one::<CommentById>(ctx, self.0.parent_id).await?;
+        Ok(parent.map(Comment))
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn replies(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByParent, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+pub struct User(pub users::User);
+
+impl From<users::User> for User {
+    fn from(user: users::User) -> Self {
+        User(user)
+    }
+}
+
+#[Object]
+impl User {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn username(&self) -> &str {
+        &self.0.username
+    }
+
+    async fn created_at(&self) -> Timestamp {
+        self.0.created_at.into()
+    }
+
+    async fn updated_at(&self) -> Timestamp {
+        self.0.updated_at.into()
+    }
+
+    async fn deleted(&self) -> bool {
+        self.0.deleted
+    }
+
+    async fn deleted_at(&self) -> Option<Timestamp> {
+        self.0.deleted_at.map(Timestamp)
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn alerts(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Alert>> {
+        page(
+            loader::many::<AlertsByUser, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<Comment>> {
+        page(
+            loader::many::<CommentsByUser, _>(ctx, self.0.id).await?,
+            limit,
+            offset,
+        )
+    }
+}
+
+// The token a user authenticates with, as POST /users and PUT /users/{id} give it
+pub struct AuthUser(pub users::AuthUser);
+
+#[Object]
+impl AuthUser {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn token(&self) -> &str {
+        &self.0.token
+    }
+}
@@ -0,0 +1,470 @@
+use super::model::*;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::soft_delete;
+use crate::validation;
+use crate::{alerts, asset_tags, assets, comments, contact_events, locations, rooms, users};
+use async_graphql::{Context, ErrorExtensions, InputObject, Object, Result, ResultExt};
+use diesel::pg::PgConnection;
+use ipnetwork::IpNetwork;
+use std::convert::{TryFrom, TryInto};
+use validator::ValidateArgs;
+
+/*
+ * Mutations mirror POST, PUT and DELETE, and are allowed to the same users.
+ * Deleting soft-deletes unless purge is set, which only admins may do.
+ */
+
+#[derive(InputObject)]
+pub struct AssetInput {
+    pub asset_tag_id: Option<i64>,
+}
+
+impl From<AssetInput> for assets::MaybeAsset {
+    fn from(input: AssetInput) -> Self {
+        assets::MaybeAsset {
+            asset_tag_id: input.asset_tag_id,
+            deleted: false,
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct AssetTagInput {
+    pub name: String,
+    pub description: Option<String>,
+    pub serial_number: String,
+    pub asset_id: Option<i64>,
+}
+
+impl From<AssetTagInput> for asset_tags::MaybeAssetTag {
+    fn from(input: AssetTagInput) -> Self {
+        asset_tags::MaybeAssetTag {
+            name: input.name,
+            description: input.description,
+            serial_number: input.serial_number,
+            asset_id: input.asset_id,
+            deleted: false,
+        }
+    }
+}
+
+// Unlike POST /contact_events, the location is never resolved from the source IP
+#[derive(InputObject)]
+pub struct ContactEventInput {
+    pub asset_tag_id: i64,
+    pub location_id: i64,
+    pub alert_id: Option<i64>,
+}
+
+impl From<ContactEventInput> for contact_events::MaybeContactEvent {
+    fn from(input: ContactEventInput) -> Self {
+        contact_events::MaybeContactEvent {
+            asset_tag_id: input.asset_tag_id,
+            location_id: Some(input.location_id),
+            alert_id: input.alert_id,
+            deleted: false,
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct LocationInput {
+    pub name: Option<String>,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub ip: Option<String>,
+    pub parent_id: Option<i64>,
+    #[graphql(default_with = "locations::default_kind()")]
+    pub kind: String,
+    pub footprint: Option<serde_json::Value>,
+}
+
+impl TryFrom<LocationInput> for locations::MaybeLocation {
+    type Error = CustomError;
+
+    fn try_from(input: LocationInput) -> Result<Self, CustomError> {
+        let ip = match input.ip {
+            Some(ip) => Some(
+                ip.parse::<IpNetwork>()
+                    .map_err(|err| CustomError::new(400, format!("Invalid ip: {}", err)))?,
+            ),
+            None => None,
+        };
+        Ok(locations::MaybeLocation {
+            name: input.name,
+            latitude: input.latitude,
+            longitude: input.longitude,
+            ip,
+            parent_id: input.parent_id,
+            kind: input.kind,
+            footprint: input.footprint,
+        })
+    }
+}
+
+#[derive(InputObject)]
+pub struct RoomInput {
+    pub name: String,
+    pub location_id: i64,
+}
+
+impl From<RoomInput> for rooms::MaybeRoom {
+    fn from(input: RoomInput) -> Self {
+        rooms::MaybeRoom {
+            name: input.name,
+            location_id: input.location_id,
+        }
+    }
+}
+
+#[derive(InputObject)]
+pub struct AlertInput {
+    pub message: Option<String>,
+    pub reason: String,
+    pub user_id: i64,
+}
+
+impl From<AlertInput> for alerts::MaybeAlert {
+    fn from(input: AlertInput) -> Self {
+        alerts::MaybeAlert {
+            message: input.message,
+            reason: input.reason,
+            user_id: input.user_id,
+        }
+    }
+}
+
+// Comments are always written by the user the request authenticated as
+#[derive(InputObject)]
+pub struct CommentInput {
+    pub content: String,
+    pub asset_tag_id: Option<i64>,
+    pub asset_id: Option<i64>,
+    pub location_id: Option<i64>,
+    pub alert_id: Option<i64>,
+    pub parent_id: Option<i64>,
+}
+
+#[derive(InputObject)]
+pub struct UserInput {
+    pub username: String,
+    pub password: String,
+}
+
+impl From<UserInput> for users::MaybeUser {
+    fn from(input: UserInput) -> Self {
+        users::MaybeUser {
+            username: input.username,
+            password: input.password,
+        }
+    }
+}
+
+// Inputs are validated like the bodies of the routes they mirror
+async fn valid<T>(input: T) -> Result<T>
+where
+    T: for<'a> ValidateArgs<'a, Args = &'a PgConnection> + Send + 'static,
+{
+    validation::validated(input).await.extend()
+}
+
+fn current_user<'a>(ctx: &Context<'a>) -> Result<&'a users::User> {
+    ctx.data::<users::User>()
+}
+
+// Soft-delete, or purge for an admin
+async fn delete<T, U>(
+    ctx: &Context<'_>,
+    id: i64,
+    purge: bool,
+    delete_row: fn(i64) -> Result<T, CustomError>,
+    purge_row: fn(i64) -> Result<T, CustomError>,
+) -> Result<U>
+where
+    T: Send + 'static,
+    U: From<T>,
+{
+    let user = current_user(ctx)?.clone();
+    db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            purge_row(id)
+        } else {
+            delete_row(id)
+        }
+    })
+    .await
+    .map(U::from)
+    .extend()
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn create_asset(&self, input: AssetInput) -> Result<Asset> {
+        let input = valid(input.into()).await?;
+        db::run(move || assets::Asset::create(input))
+            .await
+            .map(Asset)
+            .extend()
+    }
+
+    async fn update_asset(&self, id: i64, input: AssetInput) -> Result<Asset> {
+        let input = valid(input.into()).await?;
+        db::run(move || assets::Asset::update(id, input))
+            .await
+            .map(Asset)
+            .extend()
+    }
+
+    async fn delete_asset(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<Asset> {
+        delete(ctx, id, purge, assets::Asset::delete, assets::Asset::purge).await
+    }
+
+    async fn create_asset_tag(&self, input: AssetTagInput) -> Result<AssetTag> {
+        let input = valid(input.into()).await?;
+        db::run(move || asset_tags::AssetTag::create(input))
+            .await
+            .map(AssetTag)
+            .extend()
+    }
+
+    async fn update_asset_tag(&self, id: i64, input: AssetTagInput) -> Result<AssetTag> {
+        let input = valid(input.into()).await?;
+        db::run(move || asset_tags::AssetTag::update(id, input))
+            .await
+            .map(AssetTag)
+            .extend()
+    }
+
+    async fn delete_asset_tag(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<AssetTag> {
+        delete(
+            ctx,
+            id,
+            purge,
+            asset_tags::AssetTag::delete,
+            asset_tags::AssetTag::purge,
+        )
+        .await
+    }
+
+    async fn create_contact_event(&self, input: ContactEventInput) -> Result<ContactEvent> {
+        let input = valid(input.into()).await?;
+        db::run(move || contact_events::ContactEvent::create(input))
+            .await
+            .map(ContactEvent)
+            .extend()
+    }
+
+    async fn update_contact_event(
+        &self,
+        id: i64,
+        input: ContactEventInput,
+    ) -> Result<ContactEvent> {
+        let input = valid(input.into()).await?;
+        db::run(move || contact_events::ContactEvent::update(id, input))
+            .await
+            .map(ContactEvent)
+            .extend()
+    }
+
+    async fn delete_contact_event(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<ContactEvent> {
+        delete(
+            ctx,
+            id,
+            purge,
+            contact_events::ContactEvent::delete,
+            contact_events::ContactEvent::purge,
+        )
+        .await
+    }
+
+    async fn create_location(&self, input: LocationInput) -> Result<Location> {
+        let location = valid(input.try_into().extend()?).await?;
+        db::run(move || locations::Location::create(location))
+            .await
+            .map(Location)
+            .extend()
+    }
+
+    async fn update_location(&self, id: i64, input: LocationInput) -> Result<Location> {
+        let location = valid(input.try_into().extend()?).await?;
+        db::run(move || locations::Location::update(id, location))
+            .await
+            .map(Location)
+            .extend()
+    }
+
+    async fn delete_location(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<Location> {
+        delete(
+            ctx,
+            id,
+            purge,
+            locations::Location::delete,
+            locations::Location::purge,
+        )
+        .await
+    }
+
+    async fn create_room(&self, input: RoomInput) -> Result<Room> {
+        let input = valid(input.into()).await?;
+        db::run(move || rooms::Room::create(input))
+            .await
+            .map(Room)
+            .extend()
+    }
+
+    async fn update_room(&self, id: i64, input: RoomInput) -> Result<Room> {
+        let input = valid(input.into()).await?;
+        db::run(move || rooms::Room::update(id, input))
+            .await
+            .map(Room)
+            .extend()
+    }
+
+    async fn delete_room(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<Room> {
+        delete(ctx, id, purge, rooms::Room::delete, rooms::Room::purge).await
+    }
+
+    async fn create_alert(&self, input: AlertInput) -> Result<Alert> {
+        let input = valid(input.into()).await?;
+        db::run(move || alerts::Alert::create(input))
+            .await
+            .map(Alert)
+            .extend()
+    }
+
+    async fn update_alert(&self, id: i64, input: AlertInput) -> Result<Alert> {
+        let input = valid(input.into()).await?;
+        db::run(move || alerts::Alert::update(id, input))
+            .await
+            .map(Alert)
+            .extend()
+    }
+
+    async fn delete_alert(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<Alert> {
+        delete(ctx, id, purge, alerts::Alert::delete, alerts::Alert::purge).await
+    }
+
+    async fn create_comment(&self, ctx: &Context<'_>, input: CommentInput) -> Result<Comment> {
+        let comment = comments::MaybeComment {
+            content: input.content,
+            user_id: current_user(ctx)?.id,
+            asset_tag_id: input.asset_tag_id,
+            asset_id: input.asset_id,
+            location_id: input.location_id,
+            alert_id: input.alert_id,
+            parent_id: input.parent_id,
+        };
+        let comment = valid(comment).await?;
+        db::run(move || comments::Comment::create(comment))
+            .await
+            .map(Comment)
+            .extend()
+    }
+
+    async fn update_comment(&self, ctx: &Context<'_>, id: i64, content: String) -> Result<Comment> {
+        let user = current_user(ctx)?.clone();
+        let edit = valid(comments::CommentEdit { content }).await?;
+        db::run(move || {
+            comments::Comment::find_by_id(id)?.authorize(&user)?;
+            comments::Comment::update(id, user.id, edit)
+        })
+        .await
+        .map(Comment)
+        .extend()
+    }
+
+    async fn delete_comment(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<Comment> {
+        let user = current_user(ctx)?.clone();
+        db::run(move || {
+            if purge {
+                soft_delete::authorize_purge(&user)?;
+                comments::Comment::purge(id)
+            } else {
+                comments::Comment::find_by_id(id)?.authorize(&user)?;
+                comments::Comment::delete(id)
+            }
+        })
+        .await
+        .map(Comment)
+        .extend()
+    }
+
+    async fn create_user(&self, input: UserInput) -> Result<AuthUser> {
+        let input = valid(input.into()).await?;
+        let user = db::run(move || users::User::create(input)).await.extend()?;
+        let auth_user: users::AuthUser = user.try_into().extend()?;
+        Ok(AuthUser(auth_user))
+    }
+
+    async fn update_user(&self, ctx: &Context<'_>, id: i64, input: UserInput) -> Result<AuthUser> {
+        if current_user(ctx)?.id != id {
+            return Err(CustomError::new(403, String::from("Forbidden")).extend());
+        }
+        let input = valid(input.into()).await?;
+        let user = db::run(move || users::User::update(id, input))
+            .await
+            .extend()?;
+        let auth_user: users::AuthUser = user.try_into().extend()?;
+        Ok(AuthUser(auth_user))
+    }
+
+    async fn delete_user(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        #[graphql(default)] purge: bool,
+    ) -> Result<User> {
+        let user = current_user(ctx)?.clone();
+        db::run(move || {
+            if purge {
+                soft_delete::authorize_purge(&user)?;
+                users::User::purge(id)
+            } else {
+                user.authorize(id)?;
+                users::User::delete(id)
+            }
+        })
+        .await
+        .map(User)
+        .extend()
+    }
+}
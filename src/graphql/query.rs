@@ -0,0 +1,149 @@
+use super::loader::{self, UserById};
+use super::model::*;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::pagination::PageQuery;
+use crate::{alerts, asset_tags, assets, comments, contact_events, locations, rooms, users};
+use async_graphql::{Context, ErrorExtensions, Object, Result, ResultExt};
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn assets(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Asset>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || assets::Asset::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn asset(&self, id: i64) -> Result<Asset> {
+        db::run(move || assets::Asset::find_by_id(id))
+            .await
+            .map(Asset)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn asset_tags(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<AssetTag>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || asset_tags::AssetTag::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn asset_tag(&self, id: i64) -> Result<AssetTag> {
+        db::run(move || asset_tags::AssetTag::find_by_id(id))
+            .await
+            .map(AssetTag)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn contact_events(
+        &self,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<ContactEvent>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || contact_events::ContactEvent::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn contact_event(&self, id: i64) -> Result<ContactEvent> {
+        db::run(move || contact_events::ContactEvent::find_by_id(id))
+            .await
+            .map(ContactEvent)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn locations(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Location>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || locations::Location::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn location(&self, id: i64) -> Result<Location> {
+        db::run(move || locations::Location::find_by_id(id))
+            .await
+            .map(Location)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn rooms(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Room>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || rooms::Room::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn room(&self, id: i64) -> Result<Room> {
+        db::run(move || rooms::Room::find_by_id(id))
+            .await
+            .map(Room)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn alerts(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Alert>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || alerts::Alert::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn alert(&self, id: i64) -> Result<Alert> {
+        db::run(move || alerts::Alert::find_by_id(id))
+            .await
+            .map(Alert)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn comments(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<Comment>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || comments::Comment::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn comment(&self, id: i64) -> Result<Comment> {
+        db::run(move || comments::Comment::find_by_id(id))
+            .await
+            .map(Comment)
+            .extend()
+    }
+
+    #[graphql(complexity = "page_complexity(limit, child_complexity)")]
+    async fn users(&self, limit: Option<i64>, offset: Option<i64>) -> Result<Vec<User>> {
+        let page = PageQuery { limit, offset };
+        let page = db::run(move || users::User::find_page(&page))
+            .await
+            .extend()?;
+        Ok(wrap(page.data))
+    }
+
+    async fn user(&self, ctx: &Context<'_>, id: i64) -> Result<User> {
+        match loader::one::<UserById>(ctx, Some(id)).await? {
+            Some(user) => Ok(User(user)),
+            None => Err(CustomError::new(404, String::from("The record is not found")).extend()),
+        }
+    }
+
+    // The user the request authenticated as
+    async fn me(&self, ctx: &Context<'_>) -> Result<User> {
+        Ok(User(ctx.data::<users::User>()?.clone()))
+    }
+}
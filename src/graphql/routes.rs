@@ -0,0 +1,77 @@
+use crate::error_handler::{self, CustomError, RequestId};
+use crate::graphql::loader;
+use crate::graphql::mutation::Mutation;
+use crate::graphql::query::Query;
+use crate::users::User;
+use actix_web::{get, post, web, HttpResponse};
+use async_graphql::{EmptySubscription, Request, Schema};
+use lazy_static::lazy_static;
+use utoipa::OpenApi;
+
+// Associations can nest forever, so queries can't
+const MAX_DEPTH: usize = 12;
+// Every field counts once and every list as many times as its limit, so a query
+// can't multiply nested lists into the whole database
+const MAX_COMPLEXITY: usize = 10_000;
+
+lazy_static! {
+    static ref SCHEMA: Schema<Query, Mutation, EmptySubscription> =
+        Schema::build(Query, Mutation, EmptySubscription)
+            .limit_depth(MAX_DEPTH)
+            .limit_complexity(MAX_COMPLEXITY)
+            .finish();
+}
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    request_body = serde_json::Value,
+    responses(
+        (status = 200, description = "The GraphQL response", body = serde_json::Value),
+    )
+)]
+#[post("/graphql")]
+async fn execute(
+    user: User,
+    request_id: web::ReqData<RequestId>,
+    request: web::Json<Request>,
+) -> Result<HttpResponse, CustomError> {
+    let request = request.into_inner();
+    log::trace!("POST /graphql {:?}", &request.operation_name);
+    let request = loader::register(request).data(user);
+    let response = SCHEMA.execute(request).await;
+    // Errors are returned with a 200, so the middleware never sees them to log
+    for error in &response.errors {
+        if let Some(error) = error.source::<CustomError>() {
+            error_handler::log_server_error(error, &request_id.0);
+        }
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql/schema",
+    responses(
+        (status = 200, description = "The GraphQL schema in SDL", content_type = "text/plain"),
+    )
+)]
+#[get("/graphql/schema")]
+async fn find_schema() -> Result<HttpResponse, CustomError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(SCHEMA.sdl()))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(execute, find_schema))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(execute);
+    comfig.service(find_schema);
+}
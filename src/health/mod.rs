@@ -1,3 +1,3 @@
 mod routes;
 
-pub use routes::init_routes;
+pub use routes::{init_routes, openapi};
@@ -1,11 +1,27 @@
 use crate::error_handler::CustomError;
 use actix_web::{get, web, HttpResponse};
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "The service is up", body = serde_json::Value),
+    )
+)]
 #[get("/health")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
     Ok(HttpResponse::Ok().json({}))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(find_all))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
 }
@@ -0,0 +1,264 @@
+use crate::asset_tags::{AssetTag, MaybeAssetTag};
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::schema::{asset_tags, assets};
+use crate::validation;
+use calamine::{Reader, Xlsx};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use utoipa::ToSchema;
+
+/*
+ * Bulk import of assets from a CSV or XLSX sheet
+ *
+ * Every row becomes an asset along with its asset tag. Rows are validated up
+ * front and either all of them are imported in one transaction or none are.
+ */
+
+pub const XLSX_CONTENT_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+pub const MAX_BYTES: usize = 10 * 1024 * 1024;
+pub const MAX_ROWS: usize = 5000;
+
+fn default_name_column() -> String {
+    String::from("name")
+}
+
+fn default_serial_number_column() -> String {
+    String::from("serial_number")
+}
+
+// The header of the column holding each field, the description being optional
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    #[serde(default = "default_name_column")]
+    pub name: String,
+    #[serde(default = "default_serial_number_column")]
+    pub serial_number: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RowError {
+    pub row: usize,
+    pub column: Option<String>,
+    pub message: String,
+}
+
+// Rows are numbered as in a spreadsheet, so the header is row 1
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: usize,
+    pub errors: Vec<RowError>,
+    pub asset_tags: Vec<AssetTag>,
+}
+
+struct Sheet {
+    headers: Vec<String>,
+    rows: Vec<(usize, Vec<String>)>,
+}
+
+fn read_csv(data: &[u8]) -> Result<Sheet, CustomError> {
+    let invalid = |err: csv::Error| CustomError::new(400, format!("Invalid CSV: {}", err));
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(invalid)?
+        .iter()
+        .map(String::from)
+        .collect();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(invalid)?;
+        let row = record
+            .position()
+            .map_or(rows.len() + 2, |p| p.line() as usize);
+        rows.push((row, record.iter().map(String::from).collect()));
+    }
+    Ok(Sheet { headers, rows })
+}
+
+// Only the first worksheet is read
+fn read_xlsx(data: &[u8]) -> Result<Sheet, CustomError> {
+    let invalid =
+        |err: calamine::XlsxError| CustomError::new(400, format!("Invalid XLSX: {}", err));
+    let mut workbook: Xlsx<_> = Xlsx::new(Cursor::new(data)).map_err(invalid)?;
+    let range = match workbook.worksheet_range_at(0) {
+        Some(range) => range.map_err(invalid)?,
+        None => {
+            return Err(CustomError::new(
+                400,
+                String::from("The XLSX has no worksheets"),
+            ))
+        }
+    };
+    let first_row = range.start().map_or(0, |(row, _)| row as usize) + 1;
+    let mut rows = range.rows().enumerate().map(|(i, cells)| {
+        let cells = cells
+            .iter()
+            .map(|cell| cell.to_string().trim().to_string())
+            .collect();
+        (first_row + i, cells)
+    });
+    let headers = rows.next().map(|(_, cells)| cells).unwrap_or_default();
+    Ok(Sheet {
+        headers,
+        rows: rows.collect(),
+    })
+}
+
+fn column(sheet: &Sheet, header: &str) -> Option<usize> {
+    sheet
+        .headers
+        .iter()
+        .position(|column| column.eq_ignore_ascii_case(header.trim()))
+}
+
+fn required_column(sheet: &Sheet, field: &str, header: &str) -> Result<usize, CustomError> {
+    column(sheet, header).ok_or_else(|| {
+        CustomError::new(
+            400,
+            format!("No column '{}' was found for the {}", header, field),
+        )
+    })
+}
+
+pub fn import_assets(
+    data: &[u8],
+    xlsx: bool,
+    mapping: &ColumnMapping,
+    dry_run: bool,
+) -> Result<ImportReport, CustomError> {
+    let sheet = match xlsx {
+        true => read_xlsx(data)?,
+        false => read_csv(data)?,
+    };
+    let name_column = required_column(&sheet, "name", &mapping.name)?;
+    let serial_number_column = required_column(&sheet, "serial_number", &mapping.serial_number)?;
+    let description_column = match &mapping.description {
+        Some(header) => Some(required_column(&sheet, "description", header)?),
+        None => column(&sheet, "description"),
+    };
+
+    let cell = |cells: &[String], column: usize| cells.get(column).cloned().unwrap_or_default();
+    let rows: Vec<(usize, MaybeAssetTag)> = sheet
+        .rows
+        .into_iter()
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell.is_empty()))
+        .map(|(row, cells)| {
+            let description = description_column
+                .map(|column| cell(&cells, column))
+                .filter(|description| !description.is_empty());
+            let asset_tag = MaybeAssetTag {
+                name: cell(&cells, name_column),
+                description,
+                serial_number: cell(&cells, serial_number_column),
+                asset_id: None,
+                deleted: false,
+            };
+            (row, asset_tag)
+        })
+        .collect();
+    if rows.len() > MAX_ROWS {
+        return Err(CustomError::new(
+            400,
+            format!("Imports are limited to {} rows", MAX_ROWS),
+        ));
+    }
+
+    let conn = db::connection()?;
+    let mut errors = Vec::new();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    for (row, asset_tag) in &rows {
+        let mut error = |column: &str, message: String| {
+            errors.push(RowError {
+                row: *row,
+                column: Some(String::from(column)),
+                message,
+            })
+        };
+        if asset_tag.name.is_empty() {
+            error(&mapping.name, String::from("The name is required"));
+        }
+        if asset_tag.serial_number.is_empty() {
+            error(
+                &mapping.serial_number,
+                String::from("The serial_number is required"),
+            );
+        } else if let Some(first) = seen.get(asset_tag.serial_number.as_str()) {
+            error(
+                &mapping.serial_number,
+                format!(
+                    "Duplicate serial_number '{}', first seen on row {}",
+                    asset_tag.serial_number, first
+                ),
+            );
+        } else {
+            seen.insert(&asset_tag.serial_number, *row);
+        }
+        // Held to the same limits as a POST /asset_tags body, leaving out the empty cells above
+        if let Err(invalid) = validation::validate(asset_tag, &conn) {
+            for detail in invalid.details {
+                let column = match detail.field.as_str() {
+                    "name" if !asset_tag.name.is_empty() => mapping.name.as_str(),
+                    "serial_number" if !asset_tag.serial_number.is_empty() => {
+                        mapping.serial_number.as_str()
+                    }
+                    "description" => mapping.description.as_deref().unwrap_or("description"),
+                    _ => continue,
+                };
+                error(column, format!("The {} {}", detail.field, detail.message));
+            }
+        }
+    }
+
+    // Deleted asset tags still hold on to their serial numbers
+    let existing: Vec<(i64, String)> = asset_tags::table
+        .select((asset_tags::id, asset_tags::serial_number))
+        .filter(asset_tags::serial_number.eq_any(seen.keys()))
+        .load(&conn)?;
+    for (id, serial_number) in existing {
+        errors.push(RowError {
+            row: seen[serial_number.as_str()],
+            column: Some(mapping.serial_number.clone()),
+            message: format!(
+                "The serial_number '{}' already belongs to asset tag {}",
+                serial_number, id
+            ),
+        });
+    }
+    errors.sort_by_key(|error| error.row);
+
+    let mut report = ImportReport {
+        dry_run,
+        rows: rows.len(),
+        errors,
+        asset_tags: Vec::new(),
+    };
+    if dry_run || !report.errors.is_empty() {
+        return Ok(report);
+    }
+
+    report.asset_tags = conn.transaction::<_, CustomError, _>(|| {
+        let mut imported = Vec::with_capacity(rows.len());
+        for (_, mut asset_tag) in rows {
+            let asset_id: i64 = diesel::insert_into(assets::table)
+                .values(assets::deleted.eq(false))
+                .returning(assets::id)
+                .get_result(&conn)?;
+            asset_tag.asset_id = Some(asset_id);
+            let asset_tag: AssetTag = diesel::insert_into(asset_tags::table)
+                .values(asset_tag)
+                .get_result(&conn)?;
+            imported.push(asset_tag);
+        }
+        Ok(imported)
+    })?;
+    Ok(report)
+}
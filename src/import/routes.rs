@@ -0,0 +1,83 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::import::{self, ColumnMapping, ImportReport};
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use futures::StreamExt;
+use serde::Deserialize;
+use utoipa::OpenApi;
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    dry_run: Option<bool>,
+    #[serde(flatten)]
+    mapping: ColumnMapping,
+}
+
+// Read the whole body, refusing to buffer more than the size limit
+async fn read_body(mut payload: web::Payload) -> Result<Vec<u8>, CustomError> {
+    let mut data = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk =
+            chunk.map_err(|err| CustomError::new(400, format!("Invalid upload: {}", err)))?;
+        if data.len() + chunk.len() > import::MAX_BYTES {
+            return Err(CustomError::new(
+                413,
+                format!("Imports are limited to {} bytes", import::MAX_BYTES),
+            ));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+// The body is the CSV or XLSX file, with columns mapped by query parameters such as
+// ?serial_number=Serial%20No, and nothing is imported if any row is invalid
+#[utoipa::path(
+    post,
+    path = "/import/assets",
+    params(
+        ("dry_run" = Option<bool>, Query, description = "Validate the sheet without importing it"),
+        ("name" = Option<String>, Query, description = "Header of the name column"),
+        ("serial_number" = Option<String>, Query, description = "Header of the serial number column"),
+        ("description" = Option<String>, Query, description = "Header of the description column"),
+    ),
+    request_body(content = String, content_type = "text/csv", description = "A CSV sheet, or an XLSX one"),
+    responses(
+        (status = 200, description = "The rows were imported, or would be on a dry run", body = ImportReport),
+        (status = 400, description = "No rows were imported because some are invalid", body = ImportReport),
+    )
+)]
+#[post("/import/assets")]
+async fn import_assets(
+    req: HttpRequest,
+    query: web::Query<ImportQuery>,
+    payload: web::Payload,
+) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("POST /import/assets {:?}", &query);
+    let data = read_body(payload).await?;
+    let xlsx = req
+        .headers()
+        .get("content-type")
+        .is_some_and(|content_type| content_type == import::XLSX_CONTENT_TYPE)
+        || data.starts_with(b"PK\x03\x04");
+    let dry_run = query.dry_run.unwrap_or(false);
+    let report =
+        db::run(move || import::import_assets(&data, xlsx, &query.mapping, dry_run)).await?;
+    match report.errors.is_empty() || dry_run {
+        true => Ok(HttpResponse::Ok().json(report)),
+        false => Ok(HttpResponse::BadRequest().json(report)),
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(import_assets))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(import_assets);
+}
@@ -0,0 +1,657 @@
+use crate::alerts::Alert;
+use crate::asset_tags::AssetTag;
+use crate::assets::Asset;
+use crate::attachments::Attachment;
+use crate::comments::Comment;
+use crate::contact_events::ContactEvent;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::loans::Loan;
+use crate::locations::{Location, LocationSubnet};
+use crate::maintenance_plans::MaintenancePlan;
+use crate::maintenance_records::MaintenanceRecord;
+use crate::reservations::Reservation;
+use crate::rooms::Room;
+use crate::schema::{
+    alerts, asset_tags, assets, comments, contact_events, locations, rooms, users,
+};
+use crate::users::User;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::IntoParams;
+
+/*
+ * Embedding related resources with ?include=
+ *
+ * The parameter is a comma separated list of associations, and a dot includes
+ * the associations of an included resource, as in
+ * include=asset_tags,comments,last_contact_event.location. Each association is
+ * loaded with one query for all the resources being expanded, however many of
+ * them there are, and the results are added to each resource's JSON under the
+ * association's name.
+ */
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IncludeQuery {
+    #[serde(default)]
+    pub include: String,
+}
+
+// A resource with its included associations alongside its own fields
+#[derive(Debug, Serialize)]
+pub struct Expanded<T> {
+    #[serde(flatten)]
+    pub resource: T,
+    #[serde(flatten)]
+    pub included: Map<String, Value>,
+}
+
+#[derive(Debug, Default)]
+struct Include(BTreeMap<String, Include>);
+
+impl Include {
+    fn parse(spec: &str) -> Include {
+        let mut include = Include::default();
+        for path in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+        {
+            let mut node = &mut include;
+            for name in path.split('.') {
+                node = node.0.entry(name.to_string()).or_default();
+            }
+        }
+        include
+    }
+
+    // Only the listed associations can be included on a resource
+    fn check(&self, resource: &str, associations: &[&str]) -> Result<(), CustomError> {
+        match self
+            .0
+            .keys()
+            .find(|name| !associations.contains(&name.as_str()))
+        {
+            Some(name) if associations.is_empty() => Err(CustomError::new(
+                400,
+                format!(
+                    "Cannot include {} on {}, which has nothing to include",
+                    name, resource
+                ),
+            )),
+            Some(name) => Err(CustomError::new(
+                400,
+                format!(
+                    "Cannot include {} on {}, only {}",
+                    name,
+                    resource,
+                    associations.join(", ")
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Include> {
+        self.0.get(name)
+    }
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, CustomError> {
+    serde_json::to_value(value)
+        .map_err(|err| CustomError::new(500, format!("Failed to serialize: {}", err)))
+}
+
+fn expanded<T>(resources: Vec<T>, included: Vec<Map<String, Value>>) -> Vec<Expanded<T>> {
+    resources
+        .into_iter()
+        .zip(included)
+        .map(|(resource, included)| Expanded { resource, included })
+        .collect()
+}
+
+// Add a list of related resources to each resource, expanding them all at once
+fn many<C, E, F>(
+    included: &mut [Map<String, Value>],
+    name: &str,
+    groups: Vec<Vec<C>>,
+    expand: F,
+) -> Result<(), CustomError>
+where
+    E: Serialize,
+    F: FnOnce(Vec<C>) -> Result<Vec<E>, CustomError>,
+{
+    let counts: Vec<usize> = groups.iter().map(Vec::len).collect();
+    let mut children = expand(groups.into_iter().flatten().collect())?.into_iter();
+    for (map, count) in included.iter_mut().zip(counts) {
+        let group: Vec<E> = children.by_ref().take(count).collect();
+        map.insert(name.to_string(), to_value(group)?);
+    }
+    Ok(())
+}
+
+// Add a single related resource, or null, to each resource
+fn one<C, E, F>(
+    included: &mut [Map<String, Value>],
+    name: &str,
+    children: Vec<Option<C>>,
+    expand: F,
+) -> Result<(), CustomError>
+where
+    E: Serialize,
+    F: FnOnce(Vec<C>) -> Result<Vec<E>, CustomError>,
+{
+    let present: Vec<bool> = children.iter().map(Option::is_some).collect();
+    let mut children = expand(children.into_iter().flatten().collect())?.into_iter();
+    for (map, present) in included.iter_mut().zip(present) {
+        let child = if present { children.next() } else { None };
+        map.insert(name.to_string(), to_value(child)?);
+    }
+    Ok(())
+}
+
+// Add the resource each resource references by id, which several may share
+fn referenced<C, E, F>(
+    included: &mut [Map<String, Value>],
+    name: &str,
+    keys: &[Option<i64>],
+    children: Vec<C>,
+    id: fn(&C) -> i64,
+    expand: F,
+) -> Result<(), CustomError>
+where
+    E: Serialize,
+    F: FnOnce(Vec<C>) -> Result<Vec<E>, CustomError>,
+{
+    let ids: Vec<i64> = children.iter().map(id).collect();
+    let mut values = HashMap::new();
+    for (id, child) in ids.into_iter().zip(expand(children)?) {
+        values.insert(id, to_value(child)?);
+    }
+    for (map, key) in included.iter_mut().zip(keys) {
+        let value = key
+            .and_then(|key| values.get(&key).cloned())
+            .unwrap_or(Value::Null);
+        map.insert(name.to_string(), value);
+    }
+    Ok(())
+}
+
+// Related resources that have nothing of their own to include
+fn leaves<T>(resources: Vec<T>, resource: &str, include: &Include) -> Result<Vec<T>, CustomError> {
+    include.check(resource, &[])?;
+    Ok(resources)
+}
+
+fn expand_assets(
+    conn: &PgConnection,
+    assets: Vec<Asset>,
+    include: &Include,
+) -> Result<Vec<Expanded<Asset>>, CustomError> {
+    include.check(
+        "assets",
+        &[
+            "asset_tags",
+            "attachments",
+            "comments",
+            "last_contact_event",
+            "loans",
+            "maintenance_plans",
+            "maintenance_records",
+            "reservations",
+        ],
+    )?;
+    let mut included = vec![Map::new(); assets.len()];
+    if let Some(nested) = include.get("asset_tags") {
+        let groups = AssetTag::belonging_to(&assets)
+            .filter(asset_tags::deleted.eq(false))
+            .order(asset_tags::id)
+            .load::<AssetTag>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "asset_tags", groups, |children| {
+            expand_asset_tags(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("attachments") {
+        let groups = Attachment::belonging_to(&assets)
+            .load::<Attachment>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "attachments", groups, |children| {
+            leaves(children, "attachments", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("comments") {
+        let groups = Comment::belonging_to(&assets)
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "comments", groups, |children| {
+            expand_comments(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("last_contact_event") {
+        let ids: Vec<i64> = assets.iter().map(|asset| asset.id).collect();
+        let mut latest: HashMap<i64, ContactEvent> = contact_events::table
+            .inner_join(asset_tags::table)
+            .filter(asset_tags::asset_id.eq_any(ids))
+            .filter(asset_tags::deleted.eq(false))
+            .filter(contact_events::deleted.eq(false))
+            .distinct_on(asset_tags::asset_id)
+            .order((
+                asset_tags::asset_id,
+                contact_events::created_at.desc(),
+                contact_events::id.desc(),
+            ))
+            .select((asset_tags::asset_id, contact_events::all_columns))
+            .load::<(Option<i64>, ContactEvent)>(conn)?
+            .into_iter()
+            .filter_map(|(asset_id, contact_event)| asset_id.map(|id| (id, contact_event)))
+            .collect();
+        let children = assets
+            .iter()
+            .map(|asset| latest.remove(&asset.id))
+            .collect();
+        one(&mut included, "last_contact_event", children, |children| {
+            expand_contact_events(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("loans") {
+        let groups = Loan::belonging_to(&assets)
+            .load::<Loan>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "loans", groups, |children| {
+            leaves(children, "loans", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("maintenance_plans") {
+        let groups = MaintenancePlan::belonging_to(&assets)
+            .load::<MaintenancePlan>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "maintenance_plans", groups, |children| {
+            leaves(children, "maintenance_plans", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("maintenance_records") {
+        let groups = MaintenanceRecord::belonging_to(&assets)
+            .load::<MaintenanceRecord>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "maintenance_records", groups, |children| {
+            leaves(children, "maintenance_records", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("reservations") {
+        let groups = Reservation::belonging_to(&assets)
+            .load::<Reservation>(conn)?
+            .grouped_by(&assets);
+        many(&mut included, "reservations", groups, |children| {
+            leaves(children, "reservations", nested)
+        })?;
+    }
+    Ok(expanded(assets, included))
+}
+
+fn expand_asset_tags(
+    conn: &PgConnection,
+    asset_tags: Vec<AssetTag>,
+    include: &Include,
+) -> Result<Vec<Expanded<AssetTag>>, CustomError> {
+    include.check(
+        "asset_tags",
+        &["asset", "comments", "contact_events", "last_contact_event"],
+    )?;
+    let mut included = vec![Map::new(); asset_tags.len()];
+    if let Some(nested) = include.get("asset") {
+        let keys: Vec<Option<i64>> = asset_tags.iter().map(|tag| tag.asset_id).collect();
+        let children = assets::table
+            .filter(assets::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(assets::deleted.eq(false))
+            .load::<Asset>(conn)?;
+        referenced(
+            &mut included,
+            "asset",
+            &keys,
+            children,
+            |asset| asset.id,
+            |children| expand_assets(conn, children, nested),
+        )?;
+    }
+    if let Some(nested) = include.get("comments") {
+        let groups = Comment::belonging_to(&asset_tags)
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(conn)?
+            .grouped_by(&asset_tags);
+        many(&mut included, "comments", groups, |children| {
+            expand_comments(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("contact_events") {
+        let groups = ContactEvent::belonging_to(&asset_tags)
+            .filter(contact_events::deleted.eq(false))
+            .order((contact_events::created_at.desc(), contact_events::id.desc()))
+            .load::<ContactEvent>(conn)?
+            .grouped_by(&asset_tags);
+        many(&mut included, "contact_events", groups, |children| {
+            expand_contact_events(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("last_contact_event") {
+        let mut latest: HashMap<i64, ContactEvent> = ContactEvent::belonging_to(&asset_tags)
+            .filter(contact_events::deleted.eq(false))
+            .distinct_on(contact_events::asset_tag_id)
+            .order((
+                contact_events::asset_tag_id,
+                contact_events::created_at.desc(),
+                contact_events::id.desc(),
+            ))
+            .load::<ContactEvent>(conn)?
+            .into_iter()
+            .map(|contact_event| (contact_event.asset_tag_id, contact_event))
+            .collect();
+        let children = asset_tags
+            .iter()
+            .map(|tag| latest.remove(&tag.id))
+            .collect();
+        one(&mut included, "last_contact_event", children, |children| {
+            expand_contact_events(conn, children, nested)
+        })?;
+    }
+    Ok(expanded(asset_tags, included))
+}
+
+fn expand_contact_events(
+    conn: &PgConnection,
+    contact_events: Vec<ContactEvent>,
+    include: &Include,
+) -> Result<Vec<Expanded<ContactEvent>>, CustomError> {
+    include.check("contact_events", &["alert", "asset_tag", "location"])?;
+    let mut included = vec![Map::new(); contact_events.len()];
+    if let Some(nested) = include.get("alert") {
+        let keys: Vec<Option<i64>> = contact_events.iter().map(|event| event.alert_id).collect();
+        let children = alerts::table
+            .filter(alerts::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(alerts::deleted.eq(false))
+            .load::<Alert>(conn)?;
+        referenced(
+            &mut included,
+            "alert",
+            &keys,
+            children,
+            |alert| alert.id,
+            |children| expand_alerts(conn, children, nested),
+        )?;
+    }
+    if let Some(nested) = include.get("asset_tag") {
+        let keys: Vec<Option<i64>> = contact_events
+            .iter()
+            .map(|event| Some(event.asset_tag_id))
+            .collect();
+        let children = asset_tags::table
+            .filter(asset_tags::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(asset_tags::deleted.eq(false))
+            .load::<AssetTag>(conn)?;
+        referenced(
+            &mut included,
+            "asset_tag",
+            &keys,
+            children,
+            |tag| tag.id,
+            |children| expand_asset_tags(conn, children, nested),
+        )?;
+    }
+    if let Some(nested) = include.get("location") {
+        let keys: Vec<Option<i64>> = contact_events
+            .iter()
+            .map(|event| Some(event.location_id))
+            .collect();
+        let children = locations::table
+            .filter(locations::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(locations::deleted.eq(false))
+            .load::<Location>(conn)?;
+        referenced(
+            &mut included,
+            "location",
+            &keys,
+            children,
+            |location| location.id,
+            |children| expand_locations(conn, children, nested),
+        )?;
+    }
+    Ok(expanded(contact_events, included))
+}
+
+fn expand_locations(
+    conn: &PgConnection,
+    locations: Vec<Location>,
+    include: &Include,
+) -> Result<Vec<Expanded<Location>>, CustomError> {
+    include.check(
+        "locations",
+        &[
+            "children",
+            "comments",
+            "contact_events",
+            "parent",
+            "rooms",
+            "subnets",
+        ],
+    )?;
+    let mut included = vec![Map::new(); locations.len()];
+    if let Some(nested) = include.get("children") {
+        let ids: Vec<i64> = locations.iter().map(|location| location.id).collect();
+        let mut children: HashMap<i64, Vec<Location>> = HashMap::new();
+        for child in locations::table
+            .filter(locations::parent_id.eq_any(ids))
+            .filter(locations::deleted.eq(false))
+            .order(locations::id)
+            .load::<Location>(conn)?
+        {
+            if let Some(parent_id) = child.parent_id {
+                children.entry(parent_id).or_default().push(child);
+            }
+        }
+        let groups = locations
+            .iter()
+            .map(|location| children.remove(&location.id).unwrap_or_default())
+            .collect();
+        many(&mut included, "children", groups, |children| {
+            expand_locations(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("comments") {
+        let groups = Comment::belonging_to(&locations)
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(conn)?
+            .grouped_by(&locations);
+        many(&mut included, "comments", groups, |children| {
+            expand_comments(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("contact_events") {
+        let groups = ContactEvent::belonging_to(&locations)
+            .filter(contact_events::deleted.eq(false))
+            .order((contact_events::created_at.desc(), contact_events::id.desc()))
+            .load::<ContactEvent>(conn)?
+            .grouped_by(&locations);
+        many(&mut included, "contact_events", groups, |children| {
+            expand_contact_events(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("parent") {
+        let keys: Vec<Option<i64>> = locations
+            .iter()
+            .map(|location| location.parent_id)
+            .collect();
+        let children = locations::table
+            .filter(locations::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(locations::deleted.eq(false))
+            .load::<Location>(conn)?;
+        referenced(
+            &mut included,
+            "parent",
+            &keys,
+            children,
+            |location| location.id,
+            |children| expand_locations(conn, children, nested),
+        )?;
+    }
+    if let Some(nested) = include.get("rooms") {
+        let groups = Room::belonging_to(&locations)
+            .filter(rooms::deleted.eq(false))
+            .order(rooms::id)
+            .load::<Room>(conn)?
+            .grouped_by(&locations);
+        many(&mut included, "rooms", groups, |children| {
+            leaves(children, "rooms", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("subnets") {
+        let groups = LocationSubnet::belonging_to(&locations)
+            .load::<LocationSubnet>(conn)?
+            .grouped_by(&locations);
+        many(&mut included, "subnets", groups, |children| {
+            leaves(children, "subnets", nested)
+        })?;
+    }
+    Ok(expanded(locations, included))
+}
+
+fn expand_alerts(
+    conn: &PgConnection,
+    alerts: Vec<Alert>,
+    include: &Include,
+) -> Result<Vec<Expanded<Alert>>, CustomError> {
+    include.check(
+        "alerts",
+        &[
+            "comments",
+            "contact_events",
+            "loans",
+            "maintenance_plans",
+            "user",
+        ],
+    )?;
+    let mut included = vec![Map::new(); alerts.len()];
+    if let Some(nested) = include.get("comments") {
+        let groups = Comment::belonging_to(&alerts)
+            .filter(comments::deleted.eq(false))
+            .order((comments::created_at, comments::id))
+            .load::<Comment>(conn)?
+            .grouped_by(&alerts);
+        many(&mut included, "comments", groups, |children| {
+            expand_comments(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("contact_events") {
+        let groups = ContactEvent::belonging_to(&alerts)
+            .filter(contact_events::deleted.eq(false))
+            .order((contact_events::created_at.desc(), contact_events::id.desc()))
+            .load::<ContactEvent>(conn)?
+            .grouped_by(&alerts);
+        many(&mut included, "contact_events", groups, |children| {
+            expand_contact_events(conn, children, nested)
+        })?;
+    }
+    if let Some(nested) = include.get("loans") {
+        let groups = Loan::belonging_to(&alerts)
+            .load::<Loan>(conn)?
+            .grouped_by(&alerts);
+        many(&mut included, "loans", groups, |children| {
+            leaves(children, "loans", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("maintenance_plans") {
+        let groups = MaintenancePlan::belonging_to(&alerts)
+            .load::<MaintenancePlan>(conn)?
+            .grouped_by(&alerts);
+        many(&mut included, "maintenance_plans", groups, |children| {
+            leaves(children, "maintenance_plans", nested)
+        })?;
+    }
+    if let Some(nested) = include.get("user") {
+        let keys: Vec<Option<i64>> = alerts.iter().map(|alert| Some(alert.user_id)).collect();
+        let children = users::table
+            .filter(users::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(users::deleted.eq(false))
+            .load::<User>(conn)?;
+        referenced(
+            &mut included,
+            "user",
+            &keys,
+            children,
+            |user| user.id,
+            |children| leaves(children, "user", nested),
+        )?;
+    }
+    Ok(expanded(alerts, included))
+}
+
+fn expand_comments(
+    conn: &PgConnection,
+    comments: Vec<Comment>,
+    include: &Include,
+) -> Result<Vec<Expanded<Comment>>, CustomError> {
+    include.check("comments", &["user"])?;
+    let mut included = vec![Map::new(); comments.len()];
+    if let Some(nested) = include.get("user") {
+        let keys: Vec<Option<i64>> = comments
+            .iter()
+            .map(|comment| Some(comment.user_id))
+            .collect();
+        let children = users::table
+            .filter(users::id.eq_any(keys.iter().flatten().collect::<Vec<&i64>>()))
+            .filter(users::deleted.eq(false))
+            .load::<User>(conn)?;
+        referenced(
+            &mut included,
+            "user",
+            &keys,
+            children,
+            |user| user.id,
+            |children| leaves(children, "user", nested),
+        )?;
+    }
+    Ok(expanded(comments, included))
+}
+
+pub fn assets(assets: Vec<Asset>, include: &str) -> Result<Vec<Expanded<Asset>>, CustomError> {
+    let conn = db::connection()?;
+    expand_assets(&conn, assets, &Include::parse(include))
+}
+
+pub fn asset_tags(
+    asset_tags: Vec<AssetTag>,
+    include: &str,
+) -> Result<Vec<Expanded<AssetTag>>, CustomError> {
+    let conn = db::connection()?;
+    expand_asset_tags(&conn, asset_tags, &Include::parse(include))
+}
+
+pub fn locations(
+    locations: Vec<Location>,
+    include: &str,
+) -> Result<Vec<Expanded<Location>>, CustomError> {
+    let conn = db::connection()?;
+    expand_locations(&conn, locations, &Include::parse(include))
+}
+
+pub fn alerts(alerts: Vec<Alert>, include: &str) -> Result<Vec<Expanded<Alert>>, CustomError> {
+    let conn = db::connection()?;
+    expand_alerts(&conn, alerts, &Include::parse(include))
+}
+
+// Expand a single resource with one of the functions above
+pub fn single<T, F>(resource: T, include: &str, expand: F) -> Result<Expanded<T>, CustomError>
+where
+    F: FnOnce(Vec<T>, &str) -> Result<Vec<Expanded<T>>, CustomError>,
+{
+    expand(vec![resource], include)?
+        .pop()
+        .ok_or_else(|| CustomError::new(500, String::from("Failed to expand resource")))
+}
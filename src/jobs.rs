@@ -0,0 +1,96 @@
+use chrono::Utc;
+use std::env;
+use std::time::Duration;
+
+use super::{
+    alerts, asset_scanners, asset_tags, assets, comments, contact_events, db, loans, locations,
+    maintenance_plans, roles, rooms, users,
+};
+
+/*
+ * Periodic background work that runs alongside the HttpServer
+ *
+ * The interval defaults to once a minute and can be configured with
+ * JOBS_INTERVAL_SECONDS in the environment. Soft-deleted rows are purged
+ * RETENTION_DAYS after their deletion, and kept forever when it is unset.
+ */
+
+pub fn init() {
+    let seconds = env::var("JOBS_INTERVAL_SECONDS")
+        .ok()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .unwrap_or(60);
+    log::info!("Running background jobs every {} seconds", seconds);
+
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(seconds));
+        loop {
+            interval.tick().await;
+            // The jobs query with diesel, so they run on the blocking thread pool
+            if let Err(err) = db::run(|| {
+                run();
+                Ok(())
+            })
+            .await
+            {
+                log::error!("Failed to run background jobs: {}", err);
+            }
+        }
+    });
+}
+
+pub fn run() {
+    match loans::Loan::raise_overdue_alerts() {
+        Ok(loans) if !loans.is_empty() => {
+            log::info!("Raised alerts for {} overdue loans", loans.len())
+        }
+        Ok(_) => {}
+        Err(err) => log::error!("Failed to raise alerts for overdue loans: {}", err),
+    }
+
+    match maintenance_plans::MaintenancePlan::raise_overdue_alerts() {
+        Ok(maintenance_plans) if !maintenance_plans.is_empty() => log::info!(
+            "Raised alerts for {} overdue maintenance plans",
+            maintenance_plans.len()
+        ),
+        Ok(_) => {}
+        Err(err) => log::error!("Failed to raise alerts for overdue maintenance: {}", err),
+    }
+
+    if let Some(days) = env::var("RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.parse::<i64>().ok())
+    {
+        purge_expired(days);
+    }
+}
+
+// Tables go before the tables they reference, so one run can purge a whole cascade
+fn purge_expired(days: i64) {
+    let before = Utc::now().naive_utc() - chrono::Duration::days(days);
+    let purges: [(&str, fn(_) -> _); 10] = [
+        (
+            "contact events",
+            contact_events::ContactEvent::purge_expired,
+        ),
+        ("comments", comments::Comment::purge_expired),
+        ("roles", roles::Role::purge_expired),
+        ("rooms", rooms::Room::purge_expired),
+        ("asset tags", asset_tags::AssetTag::purge_expired),
+        ("assets", assets::Asset::purge_expired),
+        ("alerts", alerts::Alert::purge_expired),
+        ("locations", locations::Location::purge_expired),
+        (
+            "asset scanners",
+            asset_scanners::AssetScanner::purge_expired,
+        ),
+        ("users", users::User::purge_expired),
+    ];
+    for (name, purge) in purges.iter() {
+        match purge(before) {
+            Ok(purged) if purged > 0 => log::info!("Purged {} deleted {}", purged, name),
+            Ok(_) => {}
+            Err(err) => log::error!("Failed to purge deleted {}: {}", name, err),
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use crate::asset_tags::AssetTag;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::schema::asset_tags;
+use diesel::prelude::*;
+use image::{DynamicImage, GrayImage, ImageOutputFormat, Luma};
+use lazy_static::lazy_static;
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+use qrcode::{Color, EcLevel, QrCode};
+use std::convert::TryFrom;
+use std::env;
+
+/*
+ * Printable labels for asset tags
+ *
+ * Each label carries a QR code of the tag's scan URL when LABELS_BASE_URL is
+ * set, or of its serial number otherwise, so GET /scan/{code} understands
+ * labels printed either way. Sheets follow the Avery layouts of the same name.
+ */
+
+pub const PNG_CONTENT_TYPE: &str = "image/png";
+pub const PDF_CONTENT_TYPE: &str = "application/pdf";
+pub const DEFAULT_PNG_SIZE: u32 = 300;
+pub const MAX_PNG_SIZE: u32 = 2000;
+pub const MAX_LABELS: usize = 1000;
+
+// Modules of blank space around a QR code that scanners need to find it
+const QUIET_ZONE: usize = 4;
+const PADDING_MM: f64 = 2.0;
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+const FONT: Name = Name(b"F1");
+
+lazy_static! {
+    static ref BASE_URL: Option<String> = env::var("LABELS_BASE_URL")
+        .ok()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty());
+}
+
+// Positions are measured from the top left corner of the page
+#[derive(Debug)]
+pub struct Sheet {
+    pub name: &'static str,
+    pub page_width_mm: f64,
+    pub page_height_mm: f64,
+    pub columns: usize,
+    pub rows: usize,
+    pub label_width_mm: f64,
+    pub label_height_mm: f64,
+    pub left_mm: f64,
+    pub top_mm: f64,
+    pub column_pitch_mm: f64,
+    pub row_pitch_mm: f64,
+}
+
+pub const DEFAULT_SHEET: &str = "avery-5160";
+
+pub const SHEETS: [Sheet; 3] = [
+    // US Letter, 30 address labels
+    Sheet {
+        name: "avery-5160",
+        page_width_mm: 215.9,
+        page_height_mm: 279.4,
+        columns: 3,
+        rows: 10,
+        label_width_mm: 66.675,
+        label_height_mm: 25.4,
+        left_mm: 4.7625,
+        top_mm: 12.7,
+        column_pitch_mm: 69.85,
+        row_pitch_mm: 25.4,
+    },
+    // US Letter, 10 shipping labels
+    Sheet {
+        name: "avery-5163",
+        page_width_mm: 215.9,
+        page_height_mm: 279.4,
+        columns: 2,
+        rows: 5,
+        label_width_mm: 101.6,
+        label_height_mm: 50.8,
+        left_mm: 3.96875,
+        top_mm: 12.7,
+        column_pitch_mm: 104.775,
+        row_pitch_mm: 50.8,
+    },
+    // A4, 21 address labels
+    Sheet {
+        name: "avery-l7160",
+        page_width_mm: 210.0,
+        page_height_mm: 297.0,
+        columns: 3,
+        rows: 7,
+        label_width_mm: 63.5,
+        label_height_mm: 38.1,
+        left_mm: 7.2,
+        top_mm: 15.15,
+        column_pitch_mm: 66.04,
+        row_pitch_mm: 38.1,
+    },
+];
+
+impl Sheet {
+    pub fn find(name: &str) -> Result<&'static Sheet, CustomError> {
+        SHEETS
+            .iter()
+            .find(|sheet| sheet.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                let names: Vec<&str> = SHEETS.iter().map(|sheet| sheet.name).collect();
+                CustomError::new(
+                    400,
+                    format!(
+                        "Unknown label sheet '{}', expected one of {}",
+                        name,
+                        names.join(", ")
+                    ),
+                )
+            })
+    }
+
+    // A page per label for label printers
+    pub fn single(width_mm: f64, height_mm: f64) -> Result<Sheet, CustomError> {
+        if !(10.0..=300.0).contains(&width_mm) || !(10.0..=300.0).contains(&height_mm) {
+            return Err(CustomError::new(
+                400,
+                String::from("Labels must be between 10 and 300 mm wide and high"),
+            ));
+        }
+        Ok(Sheet {
+            name: "single",
+            page_width_mm: width_mm,
+            page_height_mm: height_mm,
+            columns: 1,
+            rows: 1,
+            label_width_mm: width_mm,
+            label_height_mm: height_mm,
+            left_mm: 0.0,
+            top_mm: 0.0,
+            column_pitch_mm: width_mm,
+            row_pitch_mm: height_mm,
+        })
+    }
+
+    fn labels_per_page(&self) -> usize {
+        self.columns * self.rows
+    }
+}
+
+// Serial numbers are percent-encoded so any of them makes a valid URL
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+// What the QR code of an asset tag encodes
+pub fn payload(asset_tag: &AssetTag) -> String {
+    match BASE_URL.as_ref() {
+        Some(base_url) => format!(
+            "{}/scan/{}",
+            base_url,
+            percent_encode(&asset_tag.serial_number)
+        ),
+        None => asset_tag.serial_number.clone(),
+    }
+}
+
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match (bytes[i], value.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// The serial number in a scanned QR code payload, which is either a scan link or a bare serial
+pub fn serial_number(code: &str) -> String {
+    let code = code.trim();
+    match code.rfind("/scan/") {
+        Some(start) => {
+            let path = &code[start + "/scan/".len()..];
+            let end = path.find(['?', '#']).unwrap_or(path.len());
+            percent_decode(&path[..end])
+        }
+        None => code.to_string(),
+    }
+}
+
+fn qr_code(asset_tag: &AssetTag) -> Result<QrCode, CustomError> {
+    QrCode::with_error_correction_level(payload(asset_tag), EcLevel::M).map_err(|err| {
+        CustomError::new(
+            400,
+            format!(
+                "Asset tag {} cannot be encoded as a QR code: {}",
+                asset_tag.id, err
+            ),
+        )
+    })
+}
+
+// The QR code alone, scaled to whole pixels per module
+pub fn png(asset_tag: &AssetTag, size: u32) -> Result<Vec<u8>, CustomError> {
+    if !(1..=MAX_PNG_SIZE).contains(&size) {
+        return Err(CustomError::new(
+            400,
+            format!("Label images are limited to {} pixels", MAX_PNG_SIZE),
+        ));
+    }
+    let code = qr_code(asset_tag)?;
+    let colors = code.to_colors();
+    let width = code.width();
+    let modules = width + 2 * QUIET_ZONE;
+    let scale = (size as usize / modules).max(1);
+    let side = (modules * scale) as u32;
+    let image = GrayImage::from_fn(side, side, |x, y| {
+        let (x, y) = (x as usize / scale, y as usize / scale);
+        let dark = x >= QUIET_ZONE
+            && y >= QUIET_ZONE
+            && x < width + QUIET_ZONE
+            && y < width + QUIET_ZONE
+            && colors[(y - QUIET_ZONE) * width + x - QUIET_ZONE] == Color::Dark;
+        match dark {
+            true => Luma([0]),
+            false => Luma([255]),
+        }
+    });
+    let mut data = Vec::new();
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut data, ImageOutputFormat::Png)
+        .map_err(|err| CustomError::new(500, format!("Failed to encode label: {}", err)))?;
+    Ok(data)
+}
+
+// Asset tags in the order asked for, as they will be printed
+pub fn find_asset_tags(ids: &[i64]) -> Result<Vec<AssetTag>, CustomError> {
+    if ids.is_empty() || ids.len() > MAX_LABELS {
+        return Err(CustomError::new(
+            400,
+            format!("Between 1 and {} asset tag ids are required", MAX_LABELS),
+        ));
+    }
+    let conn = db::connection()?;
+    let found = asset_tags::table
+        .filter(asset_tags::id.eq_any(ids))
+        .filter(asset_tags::deleted.eq(false))
+        .load::<AssetTag>(&conn)?;
+    ids.iter()
+        .map(|id| {
+            found
+                .iter()
+                .find(|asset_tag| asset_tag.id == *id)
+                .cloned()
+                .ok_or_else(|| CustomError::new(404, format!("Asset tag {} is not found", id)))
+        })
+        .collect()
+}
+
+// PDF measures in points from the bottom left corner of the page
+fn rectangle(content: &mut Content, x: f64, y: f64, width: f64, height: f64) {
+    content.rect(
+        (x * POINTS_PER_MM) as f32,
+        (y * POINTS_PER_MM) as f32,
+        (width * POINTS_PER_MM) as f32,
+        (height * POINTS_PER_MM) as f32,
+    );
+}
+
+// Helvetica is WinAnsi encoded, which covers Latin-1 but nothing beyond it
+fn text(content: &mut Content, value: &str, font_size: f64, x: f64, y: f64) {
+    let bytes: Vec<u8> = value
+        .chars()
+        .map(|c| match u8::try_from(u32::from(c)) {
+            Ok(byte) if !(0x80..0xa0).contains(&byte) => byte,
+            _ => b'?',
+        })
+        .collect();
+    content
+        .begin_text()
+        .set_font(FONT, font_size as f32)
+        .next_line((x * POINTS_PER_MM) as f32, (y * POINTS_PER_MM) as f32)
+        .show(Str(&bytes))
+        .end_text();
+}
+
+// Cut text that would run past the label, assuming Helvetica's average glyph width
+fn fit(value: &str, font_size: f64, width_mm: f64) -> String {
+    let max_chars = (width_mm * POINTS_PER_MM / (font_size * 0.55)) as usize;
+    match value.chars().count() > max_chars {
+        true => {
+            let cut: String = value.chars().take(max_chars.saturating_sub(3)).collect();
+            format!("{}...", cut)
+        }
+        false => String::from(value),
+    }
+}
+
+// x and y are the bottom left corner of the label in millimeters
+fn draw_label(
+    content: &mut Content,
+    sheet: &Sheet,
+    asset_tag: &AssetTag,
+    x: f64,
+    y: f64,
+) -> Result<(), CustomError> {
+    let code = qr_code(asset_tag)?;
+    let colors = code.to_colors();
+    let width = code.width();
+    let side = (sheet.label_height_mm - 2.0 * PADDING_MM).min(sheet.label_width_mm / 2.0);
+    let module = side / (width + QUIET_ZONE) as f64;
+    let (qr_x, qr_y) = (x + PADDING_MM, y + (sheet.label_height_mm - side) / 2.0);
+    for row in 0..width {
+        // Runs of dark modules make one rectangle each, which keeps the file small
+        let mut column = 0;
+        while column < width {
+            if colors[row * width + column] == Color::Light {
+                column += 1;
+                continue;
+            }
+            let start = column;
+            while column < width && colors[row * width + column] == Color::Dark {
+                column += 1;
+            }
+            rectangle(
+                content,
+                qr_x + (start + QUIET_ZONE / 2) as f64 * module,
+                qr_y + side - (row + 1 + QUIET_ZONE / 2) as f64 * module,
+                (column - start) as f64 * module,
+                module,
+            );
+        }
+    }
+    content.fill_nonzero();
+
+    let text_x = qr_x + side + PADDING_MM;
+    let text_width = x + sheet.label_width_mm - PADDING_MM - text_x;
+    let name_size = (sheet.label_height_mm * 0.4).clamp(7.0, 14.0);
+    let serial_size = name_size * 0.8;
+    let middle = y + sheet.label_height_mm / 2.0;
+    text(
+        content,
+        &fit(&asset_tag.name, name_size, text_width),
+        name_size,
+        text_x,
+        middle + 1.0,
+    );
+    text(
+        content,
+        &fit(&asset_tag.serial_number, serial_size, text_width),
+        serial_size,
+        text_x,
+        middle - serial_size / POINTS_PER_MM - 1.0,
+    );
+    Ok(())
+}
+
+// Skipping the first labels lets a partly used sheet be printed on again
+pub fn pdf(asset_tags: &[AssetTag], sheet: &Sheet, skip: usize) -> Result<Vec<u8>, CustomError> {
+    let per_page = sheet.labels_per_page();
+    if skip >= per_page {
+        return Err(CustomError::new(
+            400,
+            format!("A {} sheet only has {} labels", sheet.name, per_page),
+        ));
+    }
+
+    let mut pages: Vec<Content> = Vec::new();
+    for (i, asset_tag) in asset_tags.iter().enumerate() {
+        let slot = (skip + i) % per_page;
+        if i == 0 || slot == 0 {
+            pages.push(Content::new());
+        }
+        let (column, row) = (slot % sheet.columns, slot / sheet.columns);
+        let x = sheet.left_mm + column as f64 * sheet.column_pitch_mm;
+        let y = sheet.page_height_mm
+            - sheet.top_mm
+            - row as f64 * sheet.row_pitch_mm
+            - sheet.label_height_mm;
+        if let Some(content) = pages.last_mut() {
+            draw_label(content, sheet, asset_tag, x, y)?;
+        }
+    }
+
+    let mut pdf = Pdf::new();
+    let (catalog_id, pages_id, font_id) = (Ref::new(1), Ref::new(2), Ref::new(3));
+    let media_box = Rect::new(
+        0.0,
+        0.0,
+        (sheet.page_width_mm * POINTS_PER_MM) as f32,
+        (sheet.page_height_mm * POINTS_PER_MM) as f32,
+    );
+    let mut page_ids = Vec::with_capacity(pages.len());
+    for (i, content) in pages.into_iter().enumerate() {
+        let page_id = Ref::new(4 + 2 * i as i32);
+        let content_id = Ref::new(5 + 2 * i as i32);
+        let mut page = pdf.page(page_id);
+        page.media_box(media_box)
+            .parent(pages_id)
+            .contents(content_id);
+        page.resources().fonts().pair(FONT, font_id);
+        page.finish();
+        pdf.stream(content_id, &content.finish());
+        page_ids.push(page_id);
+    }
+    let count = page_ids.len() as i32;
+    pdf.pages(pages_id).kids(page_ids).count(count);
+    pdf.catalog(catalog_id).pages(pages_id);
+    pdf.type1_font(font_id)
+        .base_font(Name(b"Helvetica"))
+        .encoding_predefined(Name(b"WinAnsiEncoding"));
+    Ok(pdf.finish())
+}
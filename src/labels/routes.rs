@@ -0,0 +1,114 @@
+use crate::asset_tags::AssetTag;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::labels::{self, Sheet};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PngQuery {
+    size: Option<u32>,
+}
+
+// Either a named sheet, or a width and height for one label per page
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PdfQuery {
+    ids: String,
+    sheet: Option<String>,
+    width_mm: Option<f64>,
+    height_mm: Option<f64>,
+    skip: Option<usize>,
+}
+
+fn parse_ids(ids: &str) -> Result<Vec<i64>, CustomError> {
+    ids.split(',')
+        .map(|id| {
+            id.trim()
+                .parse::<i64>()
+                .map_err(|_| CustomError::new(400, format!("Invalid asset tag id '{}'", id.trim())))
+        })
+        .collect()
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_tags/{id}/label.png",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+        PngQuery,
+    ),
+    responses(
+        (status = 200, description = "The label with its QR code", content_type = "image/png"),
+    )
+)]
+#[get("/asset_tags/{id}/label.png")]
+async fn label_png(
+    id: web::Path<i64>,
+    query: web::Query<PngQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /asset_tags/{}/label.png {:?}", &id, &query);
+    let asset_tag = db::run(move || AssetTag::find_by_id(id)).await?;
+    let data = labels::png(&asset_tag, query.size.unwrap_or(labels::DEFAULT_PNG_SIZE))?;
+    Ok(HttpResponse::Ok()
+        .content_type(labels::PNG_CONTENT_TYPE)
+        .body(data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_tags/labels.pdf",
+    params(
+        PdfQuery,
+    ),
+    responses(
+        (status = 200, description = "A sheet of labels", content_type = "application/pdf"),
+    )
+)]
+#[get("/asset_tags/labels.pdf")]
+async fn labels_pdf(query: web::Query<PdfQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /asset_tags/labels.pdf {:?}", &query);
+    let single;
+    let sheet = match (&query.sheet, query.width_mm, query.height_mm) {
+        (Some(name), None, None) => Sheet::find(name)?,
+        (None, Some(width_mm), Some(height_mm)) => {
+            single = Sheet::single(width_mm, height_mm)?;
+            &single
+        }
+        (None, None, None) => Sheet::find(labels::DEFAULT_SHEET)?,
+        _ => {
+            return Err(CustomError::new(
+                400,
+                String::from("Choose either a sheet or a width_mm and height_mm"),
+            ))
+        }
+    };
+    let ids = parse_ids(&query.ids)?;
+    let asset_tags = db::run(move || labels::find_asset_tags(&ids)).await?;
+    let data = labels::pdf(&asset_tags, sheet, query.skip.unwrap_or(0))?;
+    Ok(HttpResponse::Ok()
+        .content_type(labels::PDF_CONTENT_TYPE)
+        .set(ContentDisposition {
+            disposition: DispositionType::Inline,
+            parameters: vec![DispositionParam::Filename(String::from("labels.pdf"))],
+        })
+        .body(data))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(label_png, labels_pdf))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(label_png);
+    comfig.service(labels_pdf);
+}
@@ -0,0 +1,250 @@
+use crate::alerts::{Alert, MaybeAlert};
+use crate::assets::Asset;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::locations::Location;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::rooms::Room;
+use crate::schema::{alerts, loans};
+use crate::users::User;
+use crate::validation;
+use chrono::NaiveDateTime;
+use diesel::dsl::now;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const OVERDUE_REASON: &str = "overdue_loan";
+
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
+)]
+#[belongs_to(Alert)]
+#[belongs_to(Asset)]
+#[belongs_to(Location)]
+#[belongs_to(Room)]
+#[belongs_to(User)]
+#[table_name = "loans"]
+pub struct Loan {
+    pub id: i64,
+    pub asset_id: i64,
+    pub user_id: i64,
+    pub location_id: Option<i64>,
+    pub room_id: Option<i64>,
+    pub alert_id: Option<i64>,
+    pub due_at: NaiveDateTime,
+    pub returned_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
+#[table_name = "loans"]
+#[validate(context = PgConnection)]
+pub struct MaybeLoan {
+    #[validate(range(min = 1))]
+    pub asset_id: i64,
+    #[validate(custom(function = "validation::user_exists", use_context))]
+    pub user_id: i64,
+    #[validate(custom(function = "validation::location_exists", use_context))]
+    pub location_id: Option<i64>,
+    #[validate(range(min = 1))]
+    pub room_id: Option<i64>,
+    pub due_at: NaiveDateTime,
+}
+
+impl Loan {
+    pub fn find_all() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = loans::table.load::<Loan>(&conn)?;
+        Ok(loans)
+    }
+
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(loans::table, loans::id, page, &conn)
+    }
+
+    pub fn find_open() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = loans::table
+            .filter(loans::returned_at.is_null())
+            .load::<Loan>(&conn)?;
+        Ok(loans)
+    }
+
+    pub fn find_overdue() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = loans::table
+            .filter(loans::returned_at.is_null())
+            .filter(loans::due_at.lt(now))
+            .load::<Loan>(&conn)?;
+        Ok(loans)
+    }
+
+    pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let loan = loans::table.filter(loans::id.eq(id)).first(&conn)?;
+        Ok(loan)
+    }
+
+    // Current and past loans, most recent first
+    pub fn find_by_user(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = loans::table
+            .filter(loans::user_id.eq(id))
+            .order(loans::created_at.desc())
+            .load::<Loan>(&conn)?;
+        Ok(loans)
+    }
+
+    // Current and past loans, most recent first
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = loans::table
+            .filter(loans::asset_id.eq(id))
+            .order(loans::created_at.desc())
+            .load::<Loan>(&conn)?;
+        Ok(loans)
+    }
+
+    // Check out an asset, which fails with a conflict if the asset is already on loan
+    pub fn create(loan: MaybeLoan) -> Result<Self, CustomError> {
+        Asset::find_by_id(loan.asset_id)?;
+        let conn = db::connection()?;
+        let open_loans: i64 = loans::table
+            .filter(loans::asset_id.eq(loan.asset_id))
+            .filter(loans::returned_at.is_null())
+            .count()
+            .get_result(&conn)?;
+        if open_loans > 0 {
+            return Err(CustomError::new(
+                409,
+                format!("Asset {} is already on loan", loan.asset_id),
+            ));
+        }
+        let loan = diesel::insert_into(loans::table)
+            .values(loan)
+            .get_result(&conn)?;
+        Ok(loan)
+    }
+
+    // The loan, locked until the transaction on conn ends, so what a write
+    // checks of it still holds when the write commits
+    fn lock(conn: &PgConnection, id: i64) -> Result<Self, CustomError> {
+        let loan = loans::table.find(id).for_update().first(conn)?;
+        Ok(loan)
+    }
+
+    pub fn update(id: i64, loan: MaybeLoan) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(loan))
+    }
+
+    // Update with what changes makes of the loan as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeLoan, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            let loan = changes(&conn, &Self::lock(&conn, id)?)?;
+            let loan = diesel::update(loans::table.find(id))
+                .set(loan)
+                .get_result(&conn)?;
+            Ok(loan)
+        })
+    }
+
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeLoan, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            let loan = changes(&conn, &Self::lock(&conn, id)?)?;
+            let loan = diesel::update(loans::table.find(id))
+                .set((
+                    loans::asset_id.eq(loan.asset_id),
+                    loans::user_id.eq(loan.user_id),
+                    loans::location_id.eq(loan.location_id),
+                    loans::room_id.eq(loan.room_id),
+                    loans::due_at.eq(loan.due_at),
+                ))
+                .get_result(&conn)?;
+            Ok(loan)
+        })
+    }
+
+    // Check in an asset, which only applies to loans that have not been returned
+    pub fn check_in(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let loan = diesel::update(loans::table)
+            .filter(loans::id.eq(id))
+            .filter(loans::returned_at.is_null())
+            .set(loans::returned_at.eq(now.nullable()))
+            .get_result(&conn)?;
+        Ok(loan)
+    }
+
+    pub fn delete(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(loans::table.filter(loans::id.eq(id))).execute(&conn)?;
+        Ok(res)
+    }
+
+    // Delete, unless check refuses the loan as it is once locked
+    pub fn delete_checked<F>(id: i64, check: F) -> Result<usize, CustomError>
+    where
+        F: FnOnce(&Self) -> Result<(), CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            check(&Self::lock(&conn, id)?)?;
+            let res = diesel::delete(loans::table.find(id)).execute(&conn)?;
+            Ok(res)
+        })
+    }
+
+    // Raise an alert for the borrower of each overdue loan that does not have one yet
+    pub fn raise_overdue_alerts() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let loans = conn.transaction::<_, CustomError, _>(|| {
+            let overdue = loans::table
+                .filter(loans::returned_at.is_null())
+                .filter(loans::alert_id.is_null())
+                .filter(loans::due_at.lt(now))
+                .load::<Loan>(&conn)?;
+
+            let mut loans = Vec::with_capacity(overdue.len());
+            for loan in overdue {
+                let alert: Alert = diesel::insert_into(alerts::table)
+                    .values(MaybeAlert {
+                        message: Some(format!(
+                            "Asset {} was due back at {}",
+                            loan.asset_id, loan.due_at
+                        )),
+                        reason: String::from(OVERDUE_REASON),
+                        user_id: loan.user_id,
+                    })
+                    .get_result(&conn)?;
+                let loan = diesel::update(&loan)
+                    .set(loans::alert_id.eq(alert.id))
+                    .get_result(&conn)?;
+                loans.push(loan);
+            }
+            Ok(loans)
+        })?;
+        Ok(loans)
+    }
+}
@@ -0,0 +1,256 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::loans::{Loan, MaybeLoan};
+use crate::merge_patch;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use utoipa::OpenApi;
+
+#[utoipa::path(
+    get,
+    path = "/loans",
+    responses(
+        (status = 200, description = "The loans", body = [Loan]),
+    )
+)]
+#[get("/loans")]
+async fn find_all() -> Result<HttpResponse, CustomError> {
+    let loans = db::run(Loan::find_all).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/open",
+    responses(
+        (status = 200, description = "The loans not checked in yet", body = [Loan]),
+    )
+)]
+#[get("/loans/open")]
+async fn find_open() -> Result<HttpResponse, CustomError> {
+    let loans = db::run(Loan::find_open).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/overdue",
+    responses(
+        (status = 200, description = "The open loans past their due date", body = [Loan]),
+    )
+)]
+#[get("/loans/overdue")]
+async fn find_overdue() -> Result<HttpResponse, CustomError> {
+    let loans = db::run(Loan::find_overdue).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the loan"),
+    ),
+    responses(
+        (status = 200, description = "The loan", body = Loan),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/loans/id/{id}")]
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /loans/id/{}", &id);
+    let loan = db::run(move || Loan::find_by_id(id)).await?;
+    etag::respond(&req, &loan)
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/loans",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The loans of the user", body = [Loan]),
+    )
+)]
+#[get("/users/{id}/loans")]
+async fn find_by_user(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /users/{}/loans", &id);
+    let loans = db::run(move || Loan::find_by_user(id)).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/{id}/loans",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The loans of the asset", body = [Loan]),
+    )
+)]
+#[get("/assets/{id}/loans")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /assets/{}/loans", &id);
+    let loans = db::run(move || Loan::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    post,
+    path = "/loans",
+    request_body = MaybeLoan,
+    responses(
+        (status = 200, description = "The created loan", body = Loan),
+    )
+)]
+#[post("/loans")]
+async fn create(loan: web::Json<MaybeLoan>) -> Result<HttpResponse, CustomError> {
+    let loan = loan.into_inner();
+    log::trace!("POST /loans/ {:?}", &loan);
+    let loan = validation::validated(loan).await?;
+    let loan = db::run(move || Loan::create(loan)).await?;
+    Ok(HttpResponse::Ok().json(loan))
+}
+
+#[utoipa::path(
+    post,
+    path = "/loans/{id}/checkin",
+    params(
+        ("id" = i64, Path, description = "Id of the loan"),
+    ),
+    responses(
+        (status = 200, description = "The checked in loan", body = Loan),
+    )
+)]
+#[post("/loans/{id}/checkin")]
+async fn check_in(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /loans/{}/checkin", &id);
+    let loan = db::run(move || Loan::check_in(id)).await?;
+    Ok(HttpResponse::Ok().json(loan))
+}
+
+#[utoipa::path(
+    put,
+    path = "/loans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the loan"),
+    ),
+    request_body = MaybeLoan,
+    responses(
+        (status = 200, description = "The updated loan", body = Loan),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[put("/loans/{id}")]
+async fn update(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    loan: web::Json<MaybeLoan>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let loan = loan.into_inner();
+    log::trace!("PUT /loans/{} {:?}", &id, &loan);
+    let loan = validation::validated(loan).await?;
+    let precondition = etag::precondition(&req)?;
+    let loan = db::run(move || {
+        Loan::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(loan)
+        })
+    })
+    .await?;
+    etag::ok(&loan)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/loans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the loan"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated loan", body = Loan),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/loans/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /loans/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let loan = db::run(move || {
+        Loan::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&loan)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/loans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the loan"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted loans", body = usize),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[delete("/loans/{id}")]
+async fn delete(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /loans/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let res =
+        db::run(move || Loan::delete_checked(id, |current| precondition.check(current))).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_open,
+    find_overdue,
+    find_by_id,
+    find_by_user,
+    find_by_asset,
+    create,
+    check_in,
+    update,
+    patch,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+    comfig.service(find_open);
+    comfig.service(find_overdue);
+    comfig.service(find_by_id);
+    comfig.service(find_by_user);
+    comfig.service(find_by_asset);
+    comfig.service(create);
+    comfig.service(check_in);
+    comfig.service(update);
+    comfig.service(patch);
+    comfig.service(delete);
+}
@@ -0,0 +1,94 @@
+use crate::error_handler::CustomError;
+
+/*
+ * Geometry on latitude/longitude coordinates in degrees
+ *
+ * Distances are great-circle distances on a spherical earth, which is within
+ * half a percent of the ellipsoid and plenty for finding nearby locations.
+ * Footprints are small enough that they are treated as planar polygons.
+ */
+
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+pub const METERS_PER_DEGREE_LATITUDE: f64 = EARTH_RADIUS_M * std::f64::consts::PI / 180.0;
+
+pub fn validate_coordinates(latitude: f64, longitude: f64) -> Result<(), CustomError> {
+    if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+        return Err(CustomError::new(
+            400,
+            format!(
+                "Invalid coordinates ({}, {}), latitude must be within ±90 and longitude within ±180",
+                latitude, longitude
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// Haversine distance in meters
+pub fn distance_m(latitude: f64, longitude: f64, other_latitude: f64, other_longitude: f64) -> f64 {
+    let (phi, other_phi) = (latitude.to_radians(), other_latitude.to_radians());
+    let delta_phi = other_phi - phi;
+    let delta_lambda = (other_longitude - longitude).to_radians();
+    let a = (delta_phi / 2.0).sin().powi(2)
+        + phi.cos() * other_phi.cos() * (delta_lambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+}
+
+// Parse a footprint, a ring of at least three [longitude, latitude] points
+pub fn parse_footprint(footprint: &serde_json::Value) -> Result<Vec<[f64; 2]>, CustomError> {
+    let invalid = |reason: &str| {
+        CustomError::new(
+            400,
+            format!(
+                "Invalid footprint, expected a ring of [longitude, latitude] points: {}",
+                reason
+            ),
+        )
+    };
+    let ring: Vec<[f64; 2]> =
+        serde_json::from_value(footprint.clone()).map_err(|err| invalid(&err.to_string()))?;
+    let distinct = match (ring.first(), ring.last()) {
+        (Some(first), Some(last)) if first == last => ring.len() - 1,
+        _ => ring.len(),
+    };
+    if distinct < 3 {
+        return Err(invalid("at least three points are required"));
+    }
+    for [longitude, latitude] in &ring {
+        validate_coordinates(*latitude, *longitude)?;
+    }
+    Ok(ring)
+}
+
+// Even-odd rule, so the ring may or may not repeat its first point at the end
+pub fn contains(ring: &[[f64; 2]], latitude: f64, longitude: f64) -> bool {
+    if ring.is_empty() {
+        return false;
+    }
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let ([xi, yi], [xj, yj]) = (ring[i], ring[j]);
+        if (yi > latitude) != (yj > latitude)
+            && longitude < (xj - xi) * (latitude - yi) / (yj - yi) + xi
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+// Planar area in square degrees, only meaningful for comparing nearby footprints
+pub fn area(ring: &[[f64; 2]]) -> f64 {
+    if ring.is_empty() {
+        return 0.0;
+    }
+    let mut area = 0.0;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        area += (ring[j][0] + ring[i][0]) * (ring[j][1] - ring[i][1]);
+        j = i;
+    }
+    (area / 2.0).abs()
+}
@@ -1,5 +1,6 @@
+pub mod geo;
 mod model;
 mod routes;
 
 pub use model::*;
-pub use routes::init_routes;
+pub use routes::{init, init_routes, openapi, source_ip};
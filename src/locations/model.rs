@@ -1,12 +1,53 @@
+use crate::assets::Asset;
 use crate::db;
 use crate::error_handler::CustomError;
-use crate::schema::locations;
+use crate::locations::geo;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::rooms::Room;
+use crate::schema::{assets, location_subnets, locations, rooms};
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation::MAX_NAME;
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Inet};
 use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
 
-#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable)]
+/*
+ * Locations form a tree such as campus -> building -> floor, with rooms as its leaves
+ *
+ * Locations without a parent are the roots of the tree. Names are unique among
+ * the children of a location, so every building can have its own "Room 101".
+ */
+
+pub const KINDS: [&str; 3] = ["campus", "building", "floor"];
+
+pub fn default_kind() -> String {
+    String::from("building")
+}
+
+fn validate_kind(kind: &str) -> Result<(), ValidationError> {
+    match KINDS.contains(&kind) {
+        true => Ok(()),
+        false => Err(ValidationError::new("kind")
+            .with_message(format!("must be one of {}", KINDS.join(", ")).into())),
+    }
+}
+
+fn validate_footprint(footprint: &serde_json::Value) -> Result<(), ValidationError> {
+    match geo::parse_footprint(footprint) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(ValidationError::new("footprint").with_message(err.error_message.into())),
+    }
+}
+
+#[derive(
+    Debug, Clone, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, ToSchema,
+)]
 #[table_name = "locations"]
 pub struct Location {
     pub id: i64,
@@ -15,28 +56,215 @@ pub struct Location {
     pub longitude: f32,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    #[schema(value_type = Option<String>)]
     pub ip: Option<IpNetwork>,
+    pub parent_id: Option<i64>,
+    pub kind: String,
+    pub footprint: Option<serde_json::Value>,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "locations"]
+#[validate(context = PgConnection)]
 pub struct MaybeLocation {
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub name: Option<String>,
+    #[validate(range(min = -90.0, max = 90.0))]
     pub latitude: f32,
+    #[validate(range(min = -180.0, max = 180.0))]
     pub longitude: f32,
+    #[schema(value_type = Option<String>)]
     pub ip: Option<IpNetwork>,
+    #[validate(range(min = 1))]
+    pub parent_id: Option<i64>,
+    #[serde(default = "default_kind")]
+    #[validate(custom(function = "validate_kind"))]
+    pub kind: String,
+    #[serde(default)]
+    #[validate(custom(function = "validate_footprint"))]
+    pub footprint: Option<serde_json::Value>,
+}
+
+// A location along with its great-circle distance from a point
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NearbyLocation {
+    #[serde(flatten)]
+    pub location: Location,
+    pub distance_m: f64,
+}
+
+// A subnet whose addresses are at a location, such as the wifi of a floor
+#[derive(Debug, Serialize, Deserialize, Identifiable, Queryable, Associations, ToSchema)]
+#[belongs_to(Location)]
+#[table_name = "location_subnets"]
+pub struct LocationSubnet {
+    pub id: i64,
+    pub location_id: i64,
+    #[schema(value_type = String)]
+    pub subnet: IpNetwork,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MaybeLocationSubnet {
+    #[schema(value_type = String)]
+    pub subnet: IpNetwork,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "location_subnets"]
+struct NewLocationSubnet {
+    location_id: i64,
+    subnet: IpNetwork,
+}
+
+// A location along with the most specific subnet containing an address
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ResolvedLocation {
+    #[serde(flatten)]
+    pub location: Location,
+    #[schema(value_type = String)]
+    pub subnet: IpNetwork,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Descendants {
+    pub locations: Vec<Location>,
+    pub rooms: Vec<Room>,
 }
 
+// Dual-stack sockets report IPv4 clients as ::ffff:a.b.c.d
+pub fn unmapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[derive(QueryableByName)]
+struct Subnet {
+    #[sql_type = "BigInt"]
+    location_id: i64,
+    #[sql_type = "Inet"]
+    subnet: IpNetwork,
+}
+
+#[derive(QueryableByName)]
+struct Id {
+    #[sql_type = "BigInt"]
+    id: i64,
+}
+
+// Ids of every location below a location, nearest first
+fn descendant_ids(conn: &PgConnection, id: i64) -> Result<Vec<i64>, CustomError> {
+    let ids: Vec<Id> = diesel::sql_query(
+        "WITH RECURSIVE tree (id, path) AS (
+            SELECT id, ARRAY[parent_id, id] FROM locations WHERE parent_id = $1 AND NOT deleted
+            UNION ALL
+            SELECT l.id, t.path || l.id FROM locations l JOIN tree t ON l.parent_id = t.id
+            WHERE NOT l.deleted AND l.id <> ALL(t.path)
+        )
+        SELECT id FROM tree ORDER BY array_length(path, 1), id",
+    )
+    .bind::<BigInt, _>(id)
+    .load(conn)?;
+    Ok(ids.into_iter().map(|row| row.id).collect())
+}
+
+impl MaybeLocation {
+    fn validate(&self, conn: &PgConnection, id: Option<i64>) -> Result<(), CustomError> {
+        geo::validate_coordinates(f64::from(self.latitude), f64::from(self.longitude))?;
+        if let Some(footprint) = &self.footprint {
+            geo::parse_footprint(footprint)?;
+        }
+        if !KINDS.contains(&self.kind.as_str()) {
+            return Err(CustomError::new(
+                400,
+                format!(
+                    "Invalid location kind '{}', expected one of {}",
+                    self.kind,
+                    KINDS.join(", ")
+                ),
+            ));
+        }
+        if let Some(parent_id) = self.parent_id {
+            let parent_exists = diesel::select(diesel::dsl::exists(
+                locations::table
+                    .filter(locations::id.eq(parent_id))
+                    .filter(locations::deleted.eq(false)),
+            ))
+            .get_result::<bool>(conn)?;
+            if !parent_exists {
+                return Err(CustomError::new(
+                    400,
+                    format!("Parent location {} does not exist", parent_id),
+                ));
+            }
+            if let Some(id) = id {
+                if parent_id == id || descendant_ids(conn, id)?.contains(&parent_id) {
+                    return Err(CustomError::new(
+                        400,
+                        format!("Location {} cannot be moved below itself", id),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Foreign keys that keep a location from being purged
+const REFERENCES: &[Reference] = &[
+    Reference {
+        table: "comments",
+        column: "location_id",
+    },
+    Reference {
+        table: "contact_events",
+        column: "location_id",
+    },
+    Reference {
+        table: "loans",
+        column: "location_id",
+    },
+    Reference {
+        table: "locations",
+        column: "parent_id",
+    },
+    Reference {
+        table: "rooms",
+        column: "location_id",
+    },
+];
+
 impl Location {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let locations = locations::table.load::<Location>(&conn)?;
+        let locations = locations::table
+            .filter(locations::deleted.eq(false))
+            .load::<Location>(&conn)?;
         Ok(locations)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            locations::table.filter(locations::deleted.eq(false)),
+            locations::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let location = locations::table.filter(locations::id.eq(id)).first(&conn)?;
+        let location = locations::table
+            .filter(locations::id.eq(id))
+            .filter(locations::deleted.eq(false))
+            .first(&conn)?;
         Ok(location)
     }
 
@@ -44,18 +272,274 @@ impl Location {
         let conn = db::connection()?;
         let location = locations::table
             .filter(locations::name.eq(name))
+            .filter(locations::deleted.eq(false))
             .first(&conn)?;
         Ok(location)
     }
 
     pub fn find_by_ip(ip: IpNetwork) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let location = locations::table.filter(locations::ip.eq(ip)).first(&conn)?;
+        let location = locations::table
+            .filter(locations::ip.eq(ip))
+            .filter(locations::deleted.eq(false))
+            .first(&conn)?;
         Ok(location)
     }
 
+    // The location whose subnet most specifically contains the address, counting the ip of
+    // each location as one of its subnets, with ties going to the oldest location
+    pub fn resolve(ip: IpAddr) -> Result<ResolvedLocation, CustomError> {
+        let ip = unmapped(ip);
+        let conn = db::connection()?;
+        let resolved: Option<Subnet> = diesel::sql_query(
+            "SELECT location_id, subnet FROM (
+                SELECT s.location_id, s.subnet FROM location_subnets s
+                JOIN locations l ON l.id = s.location_id
+                WHERE s.subnet >>= $1 AND NOT l.deleted
+                UNION ALL
+                SELECT id, ip FROM locations WHERE ip >>= $1 AND NOT deleted
+            ) subnets
+            ORDER BY masklen(subnet) DESC, location_id
+            LIMIT 1",
+        )
+        .bind::<Inet, _>(IpNetwork::from(ip))
+        .get_result(&conn)
+        .optional()?;
+        let Subnet {
+            location_id,
+            subnet,
+        } = resolved
+            .ok_or_else(|| CustomError::new(404, format!("No location subnet contains {}", ip)))?;
+        let location = locations::table
+            .filter(locations::id.eq(location_id))
+            .filter(locations::deleted.eq(false))
+            .first(&conn)?;
+        Ok(ResolvedLocation { location, subnet })
+    }
+
+    pub fn find_subnets(id: i64) -> Result<Vec<LocationSubnet>, CustomError> {
+        let conn = db::connection()?;
+        let subnets = location_subnets::table
+            .filter(location_subnets::location_id.eq(id))
+            .order(location_subnets::id)
+            .load::<LocationSubnet>(&conn)?;
+        Ok(subnets)
+    }
+
+    // Subnets are stored by their network address, so 10.1.2.3/16 becomes 10.1.0.0/16
+    pub fn add_subnet(id: i64, subnet: MaybeLocationSubnet) -> Result<LocationSubnet, CustomError> {
+        let subnet = IpNetwork::new(subnet.subnet.network(), subnet.subnet.prefix())
+            .map_err(|err| CustomError::new(400, format!("Invalid subnet: {}", err)))?;
+        let conn = db::connection()?;
+        locations::table
+            .filter(locations::id.eq(id))
+            .filter(locations::deleted.eq(false))
+            .first::<Location>(&conn)?;
+        let assigned = location_subnets::table
+            .select(location_subnets::location_id)
+            .filter(location_subnets::subnet.eq(subnet))
+            .first::<i64>(&conn)
+            .optional()?;
+        if let Some(location_id) = assigned {
+            return Err(CustomError::new(
+                409,
+                format!(
+                    "Subnet {} is already assigned to location {}",
+                    subnet, location_id
+                ),
+            ));
+        }
+        let subnet = diesel::insert_into(location_subnets::table)
+            .values(NewLocationSubnet {
+                location_id: id,
+                subnet,
+            })
+            .get_result(&conn)?;
+        Ok(subnet)
+    }
+
+    pub fn delete_subnet(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(location_subnets::table.filter(location_subnets::id.eq(id)))
+            .execute(&conn)?;
+        Ok(res)
+    }
+
+    // Locations within the radius of a point, nearest first
+    pub fn find_near(
+        latitude: f64,
+        longitude: f64,
+        radius_m: f64,
+    ) -> Result<Vec<NearbyLocation>, CustomError> {
+        geo::validate_coordinates(latitude, longitude)?;
+        if radius_m.is_nan() || radius_m <= 0.0 {
+            return Err(CustomError::new(
+                400,
+                String::from("The radius must be a positive number of meters"),
+            ));
+        }
+        // Only the latitude band can be narrowed down safely near the poles and the antimeridian
+        let delta = radius_m / geo::METERS_PER_DEGREE_LATITUDE;
+        let conn = db::connection()?;
+        let candidates = locations::table
+            .filter(locations::latitude.ge((latitude - delta).max(-90.0) as f32))
+            .filter(locations::deleted.eq(false))
+            .filter(locations::latitude.le((latitude + delta).min(90.0) as f32))
+            .load::<Location>(&conn)?;
+
+        let mut locations: Vec<NearbyLocation> = candidates
+            .into_iter()
+            .map(|location| NearbyLocation {
+                distance_m: geo::distance_m(
+                    latitude,
+                    longitude,
+                    f64::from(location.latitude),
+                    f64::from(location.longitude),
+                ),
+                location,
+            })
+            .filter(|nearby| nearby.distance_m <= radius_m)
+            .collect();
+        locations.sort_by(|a, b| a.distance_m.total_cmp(&b.distance_m));
+        Ok(locations)
+    }
+
+    // Locations inside a bounding box, which crosses the antimeridian when min_lon > max_lon
+    pub fn find_within(
+        min_lat: f64,
+        min_lon: f64,
+        max_lat: f64,
+        max_lon: f64,
+    ) -> Result<Vec<Self>, CustomError> {
+        geo::validate_coordinates(min_lat, min_lon)?;
+        geo::validate_coordinates(max_lat, max_lon)?;
+        if min_lat > max_lat {
+            return Err(CustomError::new(
+                400,
+                String::from("The minimum latitude must not exceed the maximum latitude"),
+            ));
+        }
+        let conn = db::connection()?;
+        let query = locations::table
+            .filter(locations::latitude.between(min_lat as f32, max_lat as f32))
+            .filter(locations::deleted.eq(false))
+            .order(locations::id)
+            .into_boxed();
+        let query = match min_lon <= max_lon {
+            true => query.filter(locations::longitude.between(min_lon as f32, max_lon as f32)),
+            false => query.filter(
+                locations::longitude
+                    .ge(min_lon as f32)
+                    .or(locations::longitude.le(max_lon as f32)),
+            ),
+        };
+        let locations = query.load::<Location>(&conn)?;
+        Ok(locations)
+    }
+
+    // The location with the smallest footprint containing a point, such as a GPS sighting
+    pub fn snap(latitude: f64, longitude: f64) -> Result<Self, CustomError> {
+        geo::validate_coordinates(latitude, longitude)?;
+        let conn = db::connection()?;
+        let candidates = locations::table
+            .filter(locations::footprint.is_not_null())
+            .filter(locations::deleted.eq(false))
+            .load::<Location>(&conn)?;
+        candidates
+            .into_iter()
+            .filter_map(|location| {
+                let ring = geo::parse_footprint(location.footprint.as_ref()?).ok()?;
+                match geo::contains(&ring, latitude, longitude) {
+                    true => Some((geo::area(&ring), location)),
+                    false => None,
+                }
+            })
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, location)| location)
+            .ok_or_else(|| {
+                CustomError::new(
+                    404,
+                    format!("No location contains ({}, {})", latitude, longitude),
+                )
+            })
+    }
+
+    pub fn find_children(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let locations = locations::table
+            .filter(locations::parent_id.eq(id))
+            .filter(locations::deleted.eq(false))
+            .order(locations::name)
+            .load::<Location>(&conn)?;
+        Ok(locations)
+    }
+
+    // Every location and room below a location
+    pub fn find_descendants(id: i64) -> Result<Descendants, CustomError> {
+        let conn = db::connection()?;
+        locations::table
+            .filter(locations::id.eq(id))
+            .filter(locations::deleted.eq(false))
+            .first::<Location>(&conn)?;
+        let ids = descendant_ids(&conn, id)?;
+        let mut locations = locations::table
+            .filter(locations::id.eq_any(&ids))
+            .filter(locations::deleted.eq(false))
+            .load::<Location>(&conn)?;
+        locations.sort_by_key(|location| ids.iter().position(|id| *id == location.id));
+        let rooms = rooms::table
+            .filter(
+                rooms::location_id
+                    .eq(id)
+                    .or(rooms::location_id.eq_any(&ids)),
+            )
+            .filter(rooms::deleted.eq(false))
+            .order((rooms::location_id, rooms::name))
+            .load::<Room>(&conn)?;
+        Ok(Descendants { locations, rooms })
+    }
+
+    // The location along with every location below it when recursive
+    pub fn find_ids(id: i64, recursive: bool) -> Result<Vec<i64>, CustomError> {
+        let conn = db::connection()?;
+        locations::table
+            .filter(locations::id.eq(id))
+            .filter(locations::deleted.eq(false))
+            .first::<Location>(&conn)?;
+        let mut ids = vec![id];
+        if recursive {
+            ids.extend(descendant_ids(&conn, id)?);
+        }
+        Ok(ids)
+    }
+
+    // Assets whose most recent contact event is at the location, or below it when recursive
+    pub fn find_assets(id: i64, recursive: bool) -> Result<Vec<Asset>, CustomError> {
+        let location_ids = Self::find_ids(id, recursive)?;
+        let conn = db::connection()?;
+        let asset_ids: Vec<Id> = diesel::sql_query(
+            "SELECT DISTINCT t.asset_id AS id
+            FROM (
+                SELECT DISTINCT ON (asset_tag_id) asset_tag_id, location_id FROM contact_events
+                WHERE NOT deleted
+                ORDER BY asset_tag_id, created_at DESC, id DESC
+            ) ce
+            JOIN asset_tags t ON t.id = ce.asset_tag_id
+            WHERE ce.location_id = ANY($1) AND t.asset_id IS NOT NULL",
+        )
+        .bind::<Array<BigInt>, _>(&location_ids)
+        .load(&conn)?;
+        let assets = assets::table
+            .filter(assets::id.eq_any(asset_ids.into_iter().map(|row| row.id)))
+            .filter(assets::deleted.eq(false))
+            .order(assets::id)
+            .load::<Asset>(&conn)?;
+        Ok(assets)
+    }
+
     pub fn create(location: MaybeLocation) -> Result<Self, CustomError> {
         let conn = db::connection()?;
+        location.validate(&conn, None)?;
         let location = diesel::insert_into(locations::table)
             .values(location)
             .get_result(&conn)?;
@@ -63,17 +547,48 @@ impl Location {
     }
 
     pub fn update(id: i64, location: MaybeLocation) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(location))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeLocation, CustomError>,
+    {
         let conn = db::connection()?;
-        let location = diesel::update(locations::table)
-            .filter(locations::id.eq(id))
-            .set(location)
-            .get_result(&conn)?;
-        Ok(location)
+        conn.transaction(|| {
+            let location = changes(&conn, &Self::lock(&conn, id)?)?;
+            location.validate(&conn, Some(id))?;
+            let location = diesel::update(locations::table.find(id))
+                .set(location)
+                .get_result(&conn)?;
+            Ok(location)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeLocation, CustomError>,
+    {
         let conn = db::connection()?;
-        let res = diesel::delete(locations::table.filter(locations::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        conn.transaction(|| {
+            let location = changes(&conn, &Self::lock(&conn, id)?)?;
+            location.validate(&conn, Some(id))?;
+            let location = diesel::update(locations::table.find(id))
+                .set((
+                    locations::name.eq(location.name),
+                    locations::latitude.eq(location.latitude),
+                    locations::longitude.eq(location.longitude),
+                    locations::ip.eq(location.ip),
+                    locations::parent_id.eq(location.parent_id),
+                    locations::kind.eq(location.kind),
+                    locations::footprint.eq(location.footprint),
+                ))
+                .get_result(&conn)?;
+            Ok(location)
+        })
     }
+
+    soft_delete_methods!(locations, Location, "Location", REFERENCES);
 }
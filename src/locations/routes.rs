@@ -1,73 +1,711 @@
+use crate::assets::Asset;
+use crate::db;
 use crate::error_handler::CustomError;
-use crate::locations::{Location, MaybeLocation};
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::etag;
+use crate::geojson;
+use crate::include::{self, IncludeQuery};
+use crate::locations::{
+    unmapped, Descendants, Location, LocationSubnet, MaybeLocation, MaybeLocationSubnet,
+    NearbyLocation, ResolvedLocation,
+};
+use crate::merge_patch;
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::http::header;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use ipnetwork::IpNetwork;
+use lazy_static::lazy_static;
 use log;
+use serde::Deserialize;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use utoipa::{IntoParams, OpenApi};
 
+lazy_static! {
+    // Proxies whose Forwarded and X-Forwarded-For headers are believed
+    static ref TRUSTED_PROXIES: Vec<IpNetwork> = match env::var("TRUSTED_PROXIES") {
+        Ok(proxies) => proxies
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .map(|proxy| proxy.parse().expect("Invalid address in TRUSTED_PROXIES"))
+            .collect(),
+        Err(_) if cfg!(test) => vec![IpNetwork::from(IpAddr::from([127, 0, 0, 1]))],
+        Err(_) => Vec::new(),
+    };
+}
+
+pub fn init() {
+    lazy_static::initialize(&TRUSTED_PROXIES);
+}
+
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim().trim_matches('"');
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+        .map(unmapped)
+}
+
+// The addresses a request was forwarded for, from the client through each proxy but the last
+fn forwarded_for(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, addr)| parse_addr(addr))
+            })
+            .collect();
+    }
+    values(header::HeaderName::from_static("x-forwarded-for"))
+        .into_iter()
+        .map(parse_addr)
+        .collect()
+}
+
+// The client address. Forwarding headers only count when a trusted proxy sent them, and are
+// followed back through the trusted proxies to the first address that is not one of them
+pub fn source_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let mut ip = unmapped(req.peer_addr()?.ip());
+    for hop in forwarded_for(req).into_iter().rev() {
+        if !TRUSTED_PROXIES.iter().any(|proxy| proxy.contains(ip)) {
+            break;
+        }
+        ip = hop?;
+    }
+    Some(ip)
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AssetsQuery {
+    recursive: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct NearQuery {
+    lat: f64,
+    lon: f64,
+    radius_m: f64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct WithinQuery {
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+}
+
+// Either the filter of /locations/near or that of /locations/within, or neither
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct GeoJsonQuery {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius_m: Option<f64>,
+    min_lat: Option<f64>,
+    min_lon: Option<f64>,
+    max_lat: Option<f64>,
+    max_lon: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ResolveQuery {
+    #[param(value_type = Option<String>)]
+    ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct PointQuery {
+    lat: f64,
+    lon: f64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations",
+    params(
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The locations", body = [Location]),
+    )
+)]
 #[get("/locations")]
-async fn find_all() -> Result<HttpResponse, CustomError> {
-    let locations = Location::find_all()?;
+async fn find_all(query: web::Query<IncludeQuery>) -> Result<HttpResponse, CustomError> {
+    let locations =
+        db::run(move || include::locations(Location::find_all()?, &query.include)).await?;
     Ok(HttpResponse::Ok().json(locations))
 }
 
+#[utoipa::path(
+    get,
+    path = "/locations/all",
+    responses(
+        (status = 200, description = "The locations, deleted or not", body = [Location]),
+    )
+)]
+#[get("/locations/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let locations = db::run(Location::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/deleted",
+    responses(
+        (status = 200, description = "The deleted locations", body = [Location]),
+    )
+)]
+#[get("/locations/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let locations = db::run(Location::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations.geojson",
+    params(
+        GeoJsonQuery,
+    ),
+    responses(
+        (status = 200, description = "The locations as a GeoJSON FeatureCollection", content_type = "application/geo+json"),
+    )
+)]
+#[get("/locations.geojson")]
+async fn find_all_geojson(query: web::Query<GeoJsonQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /locations.geojson {:?}", &query);
+    let near = (query.lat, query.lon, query.radius_m);
+    let within = (query.min_lat, query.min_lon, query.max_lat, query.max_lon);
+    let locations: Vec<(Location, Option<f64>)> = db::run(move || match (near, within) {
+        ((None, None, None), (None, None, None, None)) => Ok(Location::find_all()?
+            .into_iter()
+            .map(|location| (location, None))
+            .collect()),
+        ((Some(lat), Some(lon), Some(radius_m)), (None, None, None, None)) => {
+            Ok(Location::find_near(lat, lon, radius_m)?
+                .into_iter()
+                .map(|nearby| (nearby.location, Some(nearby.distance_m)))
+                .collect())
+        }
+        ((None, None, None), (Some(min_lat), Some(min_lon), Some(max_lat), Some(max_lon))) => {
+            Ok(Location::find_within(min_lat, min_lon, max_lat, max_lon)?
+                .into_iter()
+                .map(|location| (location, None))
+                .collect())
+        }
+        _ => Err(CustomError::new(
+            400,
+            String::from(
+                "Filter by either lat, lon and radius_m or min_lat, min_lon, max_lat and max_lon",
+            ),
+        )),
+    })
+    .await?;
+
+    let mut features = Vec::with_capacity(locations.len());
+    for (location, distance_m) in locations {
+        let mut properties = geojson::properties(&location, &["id", "latitude", "longitude"])?;
+        if let Some(distance_m) = distance_m {
+            properties.insert(String::from("distance_m"), distance_m.into());
+        }
+        let geometry = geojson::point(location.latitude, location.longitude);
+        features.push(geojson::feature(location.id, geometry, properties));
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(geojson::CONTENT_TYPE)
+        .json(geojson::feature_collection(features)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The location", body = Location),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/locations/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("GET /locations/id/{}", &id);
-    let location = Location::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(location))
+    log::trace!("GET /locations/id/{} {:?}", &id, &query);
+    let location = db::run(move || {
+        include::single(
+            Location::find_by_id(id)?,
+            &query.include,
+            include::locations,
+        )
+    })
+    .await?;
+    etag::respond(&req, &location)
 }
 
+#[utoipa::path(
+    get,
+    path = "/locations/name/{name}",
+    params(
+        ("name" = String, Path, description = "Name of the location"),
+    ),
+    responses(
+        (status = 200, description = "The location with the name", body = Location),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/locations/name/{name}")]
-async fn find_by_name(name: web::Path<String>) -> Result<HttpResponse, CustomError> {
+async fn find_by_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
     let name = name.into_inner();
     log::trace!("GET /locations/name/{}", &name);
-    let location = Location::find_by_name(name)?;
-    Ok(HttpResponse::Ok().json(location))
+    let location = db::run(move || Location::find_by_name(name)).await?;
+    etag::respond(&req, &location)
 }
 
+#[utoipa::path(
+    get,
+    path = "/locations/ip/{ip}",
+    params(
+        ("ip" = String, Path, description = "An IP address or network"),
+    ),
+    responses(
+        (status = 200, description = "The location of the address", body = Location),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/locations/ip/{ip}")]
-async fn find_by_ip(ip: web::Path<IpNetwork>) -> Result<HttpResponse, CustomError> {
+async fn find_by_ip(
+    req: HttpRequest,
+    ip: web::Path<IpNetwork>,
+) -> Result<HttpResponse, CustomError> {
     let ip = ip.into_inner();
     log::trace!("GET /locations/ip/{}", &ip);
-    let location = Location::find_by_ip(ip)?;
+    let location = db::run(move || Location::find_by_ip(ip)).await?;
+    etag::respond(&req, &location)
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/near",
+    params(
+        NearQuery,
+    ),
+    responses(
+        (status = 200, description = "The locations within the radius, nearest first", body = [NearbyLocation]),
+    )
+)]
+#[get("/locations/near")]
+async fn find_near(query: web::Query<NearQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /locations/near {:?}", &query);
+    let locations =
+        db::run(move || Location::find_near(query.lat, query.lon, query.radius_m)).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/within",
+    params(
+        WithinQuery,
+    ),
+    responses(
+        (status = 200, description = "The locations within the bounding box", body = [Location]),
+    )
+)]
+#[get("/locations/within")]
+async fn find_within(query: web::Query<WithinQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /locations/within {:?}", &query);
+    let locations = db::run(move || {
+        Location::find_within(query.min_lat, query.min_lon, query.max_lat, query.max_lon)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/snap",
+    params(
+        PointQuery,
+    ),
+    responses(
+        (status = 200, description = "The location nearest to the point", body = Location),
+    )
+)]
+#[get("/locations/snap")]
+async fn snap(query: web::Query<PointQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /locations/snap {:?}", &query);
+    let location = db::run(move || Location::snap(query.lat, query.lon)).await?;
+    Ok(HttpResponse::Ok().json(location))
+}
+
+// Defaults to the address of the caller, to check what a scanner will resolve to
+#[utoipa::path(
+    get,
+    path = "/locations/resolve",
+    params(
+        ResolveQuery,
+    ),
+    responses(
+        (status = 200, description = "The location of the address, or of the client when none is given", body = ResolvedLocation),
+    )
+)]
+#[get("/locations/resolve")]
+async fn resolve(
+    req: HttpRequest,
+    query: web::Query<ResolveQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let ip = match query.ip.or_else(|| source_ip(&req)) {
+        Some(ip) => ip,
+        None => {
+            return Err(CustomError::new(
+                400,
+                String::from("No ip was given and the client address is unknown"),
+            ))
+        }
+    };
+    log::trace!("GET /locations/resolve?ip={}", &ip);
+    let location = db::run(move || Location::resolve(ip)).await?;
     Ok(HttpResponse::Ok().json(location))
 }
 
+#[utoipa::path(
+    get,
+    path = "/locations/{id}/subnets",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The subnets of the location", body = [LocationSubnet]),
+    )
+)]
+#[get("/locations/{id}/subnets")]
+async fn find_subnets(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /locations/{}/subnets", &id);
+    let subnets = db::run(move || Location::find_subnets(id)).await?;
+    Ok(HttpResponse::Ok().json(subnets))
+}
+
+#[utoipa::path(
+    post,
+    path = "/locations/{id}/subnets",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    request_body = MaybeLocationSubnet,
+    responses(
+        (status = 200, description = "The added subnet", body = LocationSubnet),
+    )
+)]
+#[post("/locations/{id}/subnets")]
+async fn add_subnet(
+    id: web::Path<i64>,
+    subnet: web::Json<MaybeLocationSubnet>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let subnet = subnet.into_inner();
+    log::trace!("POST /locations/{}/subnets {:?}", &id, &subnet);
+    let subnet = db::run(move || Location::add_subnet(id, subnet)).await?;
+    Ok(HttpResponse::Ok().json(subnet))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/locations/subnets/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the subnet"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted subnets", body = usize),
+    )
+)]
+#[delete("/locations/subnets/{id}")]
+async fn delete_subnet(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /locations/subnets/{}", &id);
+    let res = db::run(move || Location::delete_subnet(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/{id}/children",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The children of the location", body = [Location]),
+    )
+)]
+#[get("/locations/{id}/children")]
+async fn find_children(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /locations/{}/children", &id);
+    let locations = db::run(move || Location::find_children(id)).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/{id}/descendants",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The locations and rooms below the location", body = Descendants),
+    )
+)]
+#[get("/locations/{id}/descendants")]
+async fn find_descendants(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /locations/{}/descendants", &id);
+    let descendants = db::run(move || Location::find_descendants(id)).await?;
+    Ok(HttpResponse::Ok().json(descendants))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/{id}/assets",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+        AssetsQuery,
+    ),
+    responses(
+        (status = 200, description = "The assets last seen at the location", body = [Asset]),
+    )
+)]
+#[get("/locations/{id}/assets")]
+async fn find_assets(
+    id: web::Path<i64>,
+    query: web::Query<AssetsQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let recursive = query.recursive.unwrap_or(false);
+    log::trace!("GET /locations/{}/assets?recursive={}", &id, recursive);
+    let assets = db::run(move || Location::find_assets(id, recursive)).await?;
+    Ok(HttpResponse::Ok().json(assets))
+}
+
+#[utoipa::path(
+    post,
+    path = "/locations",
+    request_body = MaybeLocation,
+    responses(
+        (status = 200, description = "The created location", body = Location),
+    )
+)]
 #[post("/locations")]
 async fn create(location: web::Json<MaybeLocation>) -> Result<HttpResponse, CustomError> {
     let location = location.into_inner();
     log::trace!("POST /locations/ {:?}", &location);
-    let location = Location::create(location)?;
+    let location = validation::validated(location).await?;
+    let location = db::run(move || Location::create(location)).await?;
     Ok(HttpResponse::Ok().json(location))
 }
 
+#[utoipa::path(
+    put,
+    path = "/locations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    request_body = MaybeLocation,
+    responses(
+        (status = 200, description = "The updated location", body = Location),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/locations/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     location: web::Json<MaybeLocation>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let location = location.into_inner();
     log::trace!("PUT /locations/{} {:?}", &id, &location);
-    let location = Location::update(id, location)?;
-    Ok(HttpResponse::Ok().json(location))
+    let location = validation::validated(location).await?;
+    let precondition = etag::precondition(&req)?;
+    let location = db::run(move || {
+        Location::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(location)
+        })
+    })
+    .await?;
+    etag::ok(&location)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/locations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated location", body = Location),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/locations/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /locations/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let location = db::run(move || {
+        Location::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&location)
+}
+
+#[utoipa::path(
+    post,
+    path = "/locations/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The restored location", body = Location),
+    )
+)]
+#[post("/locations/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /locations/{}/restore", &id);
+    let res = db::run(move || Location::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/locations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted location", body = Location),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/locations/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /locations/{}", &id);
-    let res = Location::delete(id)?;
+    log::trace!("DELETE /locations/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            Location::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Location::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_all_geojson,
+    find_by_id,
+    find_by_name,
+    find_by_ip,
+    find_near,
+    find_within,
+    snap,
+    resolve,
+    find_subnets,
+    add_subnet,
+    delete_subnet,
+    find_children,
+    find_descendants,
+    find_assets,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
+    comfig.service(find_all_geojson);
     comfig.service(find_by_id);
     comfig.service(find_by_name);
     comfig.service(find_by_ip);
+    comfig.service(find_near);
+    comfig.service(find_within);
+    comfig.service(snap);
+    comfig.service(resolve);
+    comfig.service(find_subnets);
+    comfig.service(add_subnet);
+    comfig.service(delete_subnet);
+    comfig.service(find_children);
+    comfig.service(find_descendants);
+    comfig.service(find_assets);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
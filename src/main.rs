@@ -5,8 +5,9 @@ extern crate diesel_migrations;
 
 use actix_service::Service;
 use actix_web::middleware::Logger;
-use actix_web::{dev::ServiceRequest, App, HttpServer};
+use actix_web::{dev::ServiceRequest, web, App, HttpMessage, HttpServer};
 use actix_web_httpauth::middleware::HttpAuthentication;
+use futures::FutureExt;
 
 use http::header;
 
@@ -17,25 +18,83 @@ use std::env;
 mod auth;
 mod db;
 mod error_handler;
+mod etag;
+mod geojson;
+mod include;
+mod jobs;
+mod merge_patch;
+mod pagination;
 mod schema;
+mod soft_delete;
+mod validation;
+mod versioning;
 
 mod alerts;
 mod asset_scanners;
 mod asset_tags;
 mod assets;
+mod attachments;
 mod comments;
 mod contact_events;
+mod docs;
+mod export;
+mod graphql;
 mod health;
+mod import;
+mod labels;
+mod loans;
 mod locations;
+mod maintenance_plans;
+mod maintenance_records;
+mod reservations;
 mod roles;
 mod rooms;
+mod scan;
+mod search;
 mod users;
+mod v2;
+
+/*
+ * The access log. Only the path of a request is logged, as query strings can
+ * carry tokens, and each line has the request id that error_handler returns,
+ * so a client's report of a failure can be found in the logs.
+ */
+const LOG_FORMAT: &str = r#"%a "%U" %s %b "%{Referer}i" "%{User-Agent}i" %T %{x-request-id}o"#;
+
+// The routes of v1, which v2 shares unless it registered its own first
+fn init_routes(comfig: &mut web::ServiceConfig) {
+    alerts::init_routes(comfig);
+    asset_tags::init_routes(comfig);
+    asset_scanners::init_routes(comfig);
+    assets::init_routes(comfig);
+    attachments::init_routes(comfig);
+    comments::init_routes(comfig);
+    contact_events::init_routes(comfig);
+    docs::init_routes(comfig);
+    export::init_routes(comfig);
+    graphql::init_routes(comfig);
+    health::init_routes(comfig);
+    import::init_routes(comfig);
+    labels::init_routes(comfig);
+    loans::init_routes(comfig);
+    maintenance_plans::init_routes(comfig);
+    maintenance_records::init_routes(comfig);
+    reservations::init_routes(comfig);
+    roles::init_routes(comfig);
+    rooms::init_routes(comfig);
+    scan::init_routes(comfig);
+    search::init_routes(comfig);
+    users::init_routes(comfig);
+    locations::init_routes(comfig);
+}
 
 macro_rules! AppFactory {
     () => {
         || {
             App::new()
-                .wrap(Logger::default())
+                .app_data(error_handler::json_config())
+                .app_data(error_handler::query_config())
+                .app_data(error_handler::path_config())
                 .wrap(HttpAuthentication::bearer(auth::validator))
                 .wrap_fn(|req, srv| {
                     let mut req: ServiceRequest = req.into();
@@ -49,17 +108,29 @@ macro_rules! AppFactory {
 
                     srv.call(req)
                 })
-                .configure(alerts::init_routes)
-                .configure(asset_tags::init_routes)
-                .configure(asset_scanners::init_routes)
-                .configure(assets::init_routes)
-                .configure(comments::init_routes)
-                .configure(contact_events::init_routes)
-                .configure(health::init_routes)
-                .configure(roles::init_routes)
-                .configure(rooms::init_routes)
-                .configure(users::init_routes)
-                .configure(locations::init_routes)
+                .wrap_fn(|req, srv| {
+                    let request_id = error_handler::request_id(&req);
+                    req.extensions_mut()
+                        .insert(error_handler::RequestId(request_id.clone()));
+                    srv.call(req)
+                        .map(move |res| error_handler::respond(request_id, res))
+                })
+                .wrap(Logger::new(LOG_FORMAT))
+                .service(
+                    web::scope(versioning::V2)
+                        .configure(v2::init_routes)
+                        .configure(init_routes),
+                )
+                .service(
+                    web::scope(versioning::V1)
+                        .wrap(versioning::deprecation())
+                        .configure(init_routes),
+                )
+                .service(
+                    web::scope("")
+                        .wrap(versioning::deprecation())
+                        .configure(init_routes),
+                )
         }
     };
 }
@@ -70,6 +141,9 @@ async fn main() -> std::io::Result<()> {
     env_logger::init();
     db::init();
     auth::init();
+    attachments::storage::init();
+    locations::init();
+    jobs::init();
 
     let mut listenfd = ListenFd::from_env();
     let mut server = HttpServer::new(AppFactory!());
@@ -89,13 +163,17 @@ async fn main() -> std::io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use actix_web::dev::{Body, ResponseBody};
     use actix_web::{http::StatusCode, test, App};
+    use diesel::prelude::*;
+    use futures::lock::{Mutex, MutexGuard};
     use ipnetwork::IpNetwork;
     use lazy_static::lazy_static;
     use serde::{Deserialize, Serialize};
     use std::convert::TryInto;
 
     lazy_static! {
+        static ref SERIAL: Mutex<()> = Mutex::new(());
         static ref FIXTURE: () = {
             dotenv().ok();
             env_logger::init();
@@ -142,6 +220,9 @@ mod tests {
                         latitude: 1.0,
                         longitude: 1.0,
                         ip: Some(IpNetwork::V4("10.9.0.32/16".parse().unwrap())),
+                        parent_id: None,
+                        kind: String::from("building"),
+                        footprint: None,
                     })
                     .expect("Failed to create test location");
                     location.try_into().expect("Failed to create initial location")
@@ -160,12 +241,16 @@ mod tests {
                 };
     }
 
-    pub fn setup() {
+    // Every test shares the same test transaction, so the returned guard keeps
+    // tests from observing each other's records while they count them
+    pub async fn setup() -> MutexGuard<'static, ()> {
+        let serial = SERIAL.lock().await;
         lazy_static::initialize(&FIXTURE);
         lazy_static::initialize(&ADMIN_USER);
         lazy_static::initialize(&INITIAL_ASSET_TAG);
         lazy_static::initialize(&INITIAL_LOCATION);
         lazy_static::initialize(&INITIAL_ALERT);
+        serial
     }
 
     #[derive(Serialize, Deserialize)]
@@ -173,7 +258,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_health_get_without_token() {
-        setup();
+        let _serial = setup().await;
         log::info!("Token: {:?}", ADMIN_USER.token);
 
         let mut app = test::init_service(AppFactory!()()).await;
@@ -183,7 +268,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_create_and_use_user() {
-        setup();
+        let _serial = setup().await;
 
         let mut app = test::init_service(AppFactory!()()).await;
 
@@ -218,7 +303,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_create_and_login_with_user() {
-        setup();
+        let _serial = setup().await;
 
         let mut app = test::init_service(AppFactory!()()).await;
 
@@ -269,7 +354,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_user_cant_change_other_users() {
-        setup();
+        let _serial = setup().await;
 
         let mut app = test::init_service(AppFactory!()()).await;
 
@@ -375,7 +460,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_asset_tags_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all tags, there should only be the initial one
         let mut app = test::init_service(AppFactory!()()).await;
@@ -531,7 +616,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_assets_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all assets, there should be the initial one
         let mut app = test::init_service(AppFactory!()()).await;
@@ -675,7 +760,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_role_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all roles, there should be none
         let mut app = test::init_service(AppFactory!()()).await;
@@ -696,6 +781,24 @@ mod tests {
         };
         let payload = serde_json::to_string(&value).expect("Invalid value");
 
+        // Only an admin may manage roles, or anyone could make themselves admin
+        let admin_value = roles::MaybeRole {
+            name: String::from(roles::ADMIN),
+            user_id: Some(ADMIN_USER.id),
+        };
+        let req = test::TestRequest::post()
+            .uri("/roles")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(serde_json::to_string(&admin_value).expect("Invalid value"))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let admin = roles::Role::create(admin_value).expect("Failed to create admin role");
         let req = test::TestRequest::post()
             .uri("/roles")
             .header(
@@ -708,8 +811,9 @@ mod tests {
         let resp: roles::Role = test::read_response_json(&mut app, req).await;
         assert_eq!(value.name, resp.name);
         assert_eq!(value.user_id, resp.user_id);
+        let id = resp.id;
 
-        // Find all roles, it should be the one we just created
+        // Find all roles, it should be the admin role and the one we just created
         let req = test::TestRequest::get()
             .uri("/roles")
             .header(
@@ -718,12 +822,10 @@ mod tests {
             )
             .to_request();
         let resp: Vec<roles::Role> = test::read_response_json(&mut app, req).await;
-        assert_eq!(resp.len(), 1);
-        assert_eq!(value.name, resp[0].name);
-        assert_eq!(value.user_id, resp[0].user_id);
+        assert_eq!(resp.len(), 2);
+        assert!(resp.iter().any(|role| role.id == id));
 
         // Find role by id
-        let id = resp[0].id;
 
         let req = test::TestRequest::get()
             .uri(format!("/roles/id/{}", id).as_str())
@@ -778,10 +880,23 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: roles::Role = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+
+        // Restoring a role needs an admin too, or a revoked admin role could be brought back
+        roles::Role::delete(admin.id).expect("Failed to delete admin role");
+        let req = test::TestRequest::post()
+            .uri(format!("/roles/{}/restore", admin.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        roles::Role::restore(admin.id).expect("Failed to restore admin role");
 
-        // Find all roles, there should be none now
+        // Find all roles, only the admin role should be left now
         let req = test::TestRequest::get()
             .uri("/roles")
             .header(
@@ -790,12 +905,15 @@ mod tests {
             )
             .to_request();
         let resp: Vec<roles::Role> = test::read_response_json(&mut app, req).await;
-        assert_eq!(resp.len(), 0);
+        assert_eq!(resp.len(), 1);
+        assert_eq!(admin.id, resp[0].id);
+
+        roles::Role::purge(admin.id).expect("Failed to purge admin role");
     }
 
     #[actix_rt::test]
     async fn test_asset_scanner_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all scanners, there should be none
         let mut app = test::init_service(AppFactory!()()).await;
@@ -891,8 +1009,8 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: asset_scanners::AssetScanner = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
 
         // Find all scanners, there should be none now
         let req = test::TestRequest::get()
@@ -908,7 +1026,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_comment_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all comments, there should be none
         let mut app = test::init_service(AppFactory!()()).await;
@@ -926,7 +1044,11 @@ mod tests {
         let value = comments::MaybeComment {
             content: String::from("foo"),
             user_id: ADMIN_USER.id,
-            asset_tag_id: INITIAL_ASSET_TAG.id,
+            asset_tag_id: Some(INITIAL_ASSET_TAG.id),
+            asset_id: None,
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
         };
         let payload = serde_json::to_string(&value).expect("Invalid value");
 
@@ -978,7 +1100,11 @@ mod tests {
         let value_updated = comments::MaybeComment {
             content: String::from("foobar"),
             user_id: ADMIN_USER.id,
-            asset_tag_id: INITIAL_ASSET_TAG.id,
+            asset_tag_id: Some(INITIAL_ASSET_TAG.id),
+            asset_id: None,
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
         };
         let payload_updated = serde_json::to_string(&value_updated).expect("Invalid value");
 
@@ -1018,8 +1144,8 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
 
         // Find all comments, there should be none now
         let req = test::TestRequest::get()
@@ -1033,9 +1159,279 @@ mod tests {
         assert_eq!(resp.len(), 0);
     }
 
+    #[actix_rt::test]
+    async fn test_comment_threads() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let commenter: users::AuthUser = users::User::create(users::MaybeUser {
+            username: "commenter".into(),
+            password: "qsib".into(),
+        })
+        .expect("Failed to create commenter")
+        .try_into()
+        .expect("Failed to create auth user");
+
+        // A comment must have exactly one target
+        let value = comments::MaybeComment {
+            content: String::from("No target"),
+            user_id: ADMIN_USER.id,
+            asset_tag_id: None,
+            asset_id: None,
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/comments")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&value)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Comment on the INITIAL ASSET as ADMIN USER, mentioning the commenter
+        let value = comments::MaybeComment {
+            content: String::from("@commenter can you check the battery?"),
+            user_id: ADMIN_USER.id,
+            asset_tag_id: None,
+            asset_id: Some(INITIAL_ASSET.id),
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/comments")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&value)
+            .to_request();
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert_eq!(value.content, resp.content);
+        assert_eq!(value.asset_id, resp.asset_id);
+        assert_eq!(None, resp.asset_tag_id);
+        let parent_id = resp.id;
+
+        // The commenter was notified of the mention
+        let req = test::TestRequest::get()
+            .uri(format!("/alerts/user/{}", commenter.id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp: Vec<alerts::Alert> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+        assert_eq!(comments::MENTION_REASON, resp[0].reason);
+        let alert_id = resp[0].id;
+
+        // Only the author can comment as themselves
+        let req = test::TestRequest::post()
+            .uri("/comments")
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .set_json(&value)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Replies inherit the target of their parent and cannot move to another one
+        let mut reply = comments::MaybeComment {
+            content: String::from("Replaced it"),
+            user_id: commenter.id,
+            asset_tag_id: None,
+            asset_id: None,
+            location_id: None,
+            alert_id: None,
+            parent_id: Some(parent_id),
+        };
+        let req = test::TestRequest::post()
+            .uri("/comments")
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .set_json(&reply)
+            .to_request();
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert_eq!(Some(parent_id), resp.parent_id);
+        assert_eq!(Some(INITIAL_ASSET.id), resp.asset_id);
+        let reply_id = resp.id;
+
+        reply.location_id = Some(INITIAL_LOCATION.id);
+        let req = test::TestRequest::post()
+            .uri("/comments")
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .set_json(&reply)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        for uri in &[
+            format!("/comments/asset/{}", INITIAL_ASSET.id),
+            format!("/comments/{}/replies", parent_id),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<comments::Comment> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.last().map(|comment| comment.id), Some(reply_id));
+        }
+
+        // The commenter cannot edit or delete the comment of ADMIN USER
+        let edit = comments::CommentEdit {
+            content: String::from("Never mind"),
+        };
+        let req = test::TestRequest::put()
+            .uri(format!("/comments/{}", parent_id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .set_json(&edit)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::delete()
+            .uri(format!("/comments/{}", parent_id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Edits keep the previous content and only notify new mentions
+        for content in &[
+            "@commenter can you check the battery again?",
+            "@commenter @commenter can you check the fuse?",
+        ] {
+            let edit = comments::CommentEdit {
+                content: String::from(*content),
+            };
+            let req = test::TestRequest::put()
+                .uri(format!("/comments/{}", parent_id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&edit)
+                .to_request();
+            let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+            assert_eq!(edit.content, resp.content);
+        }
+        let req = test::TestRequest::get()
+            .uri(format!("/comments/{}/revisions", parent_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<comments::CommentRevision> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 2);
+        assert_eq!(value.content, resp[0].content);
+        assert_eq!(ADMIN_USER.id, resp[0].user_id);
+        assert_eq!(
+            "@commenter can you check the battery again?",
+            resp[1].content
+        );
+
+        let req = test::TestRequest::get()
+            .uri(format!("/alerts/user/{}", commenter.id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp: Vec<alerts::Alert> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+
+        // ADMIN USER can only delete the reply of the commenter with the admin role
+        let req = test::TestRequest::delete()
+            .uri(format!("/comments/{}", reply_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Nor restore it once the commenter deleted it
+        let req = test::TestRequest::delete()
+            .uri(format!("/comments/{}", reply_id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+        let restore_uri = format!("/comments/{}/restore", reply_id);
+        let req = test::TestRequest::post()
+            .uri(restore_uri.as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post()
+            .uri(restore_uri.as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert!(!resp.deleted);
+
+        let role = roles::Role::create(roles::MaybeRole {
+            name: String::from(roles::ADMIN),
+            user_id: Some(ADMIN_USER.id),
+        })
+        .expect("Failed to create admin role");
+        let req = test::TestRequest::delete()
+            .uri(format!("/comments/{}", reply_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: comments::Comment = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+
+        // Mentions in the content of a new comment do not notify its own author
+        let value = comments::MaybeComment {
+            content: String::from("Thanks @commenter, email me at commenter@example.com"),
+            user_id: commenter.id,
+            asset_tag_id: None,
+            asset_id: None,
+            location_id: Some(INITIAL_LOCATION.id),
+            alert_id: None,
+            parent_id: None,
+        };
+        assert_eq!(vec!["commenter"], comments::mentions(&value.content));
+        let comment = comments::Comment::create(value).expect("Failed to create location comment");
+        let req = test::TestRequest::get()
+            .uri(format!("/alerts/user/{}", commenter.id).as_str())
+            .header(header::AUTHORIZATION, format!("Bearer {}", commenter.token))
+            .to_request();
+        let resp: Vec<alerts::Alert> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+
+        // Purge the comments, the role, the alerts and the commenter
+        for id in &[reply_id, comment.id, parent_id] {
+            assert_eq!(
+                *id,
+                comments::Comment::purge(*id)
+                    .expect("Failed to purge comment")
+                    .id
+            );
+        }
+        assert_eq!(
+            0,
+            comments::Comment::find_revisions(parent_id)
+                .expect("Failed to find revisions")
+                .len()
+        );
+        roles::Role::purge(role.id).expect("Failed to purge admin role");
+        alerts::Alert::purge(alert_id).expect("Failed to purge alert");
+        users::User::purge(commenter.id).expect("Failed to purge commenter");
+    }
+
     #[actix_rt::test]
     async fn test_alert_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all alerts, there should only be the initial one
         let mut app = test::init_service(AppFactory!()()).await;
@@ -1145,8 +1541,8 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: alerts::Alert = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
 
         // Find all alerts, there should only be the initial one
         let req = test::TestRequest::get()
@@ -1162,7 +1558,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_location_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all locations, there should only be the initial one
         let mut app = test::init_service(AppFactory!()()).await;
@@ -1182,6 +1578,9 @@ mod tests {
             latitude: f32::from(1.1),
             longitude: f32::from(2.2),
             ip: Some(IpNetwork::V4("10.9.0.32/16".parse().unwrap())),
+            parent_id: None,
+            kind: String::from("building"),
+            footprint: None,
         };
 
         let payload = serde_json::to_string(&value).expect("Invalid value");
@@ -1239,6 +1638,9 @@ mod tests {
             latitude: f32::from(10.10),
             longitude: f32::from(12.12),
             ip: Some(IpNetwork::V4("10.0.0.00/16".parse().unwrap())),
+            parent_id: None,
+            kind: String::from("building"),
+            footprint: None,
         };
         let payload_updated = serde_json::to_string(&value_updated).expect("Invalid value");
 
@@ -1280,8 +1682,8 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: locations::Location = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
 
         // Find all locations, there should only be the initial one now
         let req = test::TestRequest::get()
@@ -1297,7 +1699,7 @@ mod tests {
 
     #[actix_rt::test]
     async fn test_room_resource() {
-        setup();
+        let _serial = setup().await;
 
         // Find all rooms, there should be none
         let mut app = test::init_service(AppFactory!()()).await;
@@ -1400,8 +1802,8 @@ mod tests {
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: usize = test::read_response_json(&mut app, req).await;
-        assert_eq!(1, resp);
+        let resp: rooms::Room = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
 
         // Find all rooms, there should be none now
         let req = test::TestRequest::get()
@@ -1416,163 +1818,3671 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn test_contact_event_resource() {
-        setup();
+    async fn test_location_tree() {
+        let _serial = setup().await;
 
-        // Find all contact_events, there should be none
         let mut app = test::init_service(AppFactory!()()).await;
+        let location = |name: &str, parent_id: Option<i64>, kind: &str| {
+            locations::Location::create(locations::MaybeLocation {
+                name: Some(String::from(name)),
+                latitude: 1.0,
+                longitude: 1.0,
+                ip: None,
+                parent_id,
+                kind: String::from(kind),
+                footprint: None,
+            })
+            .expect("Failed to create location")
+        };
+
+        // Build a campus with two buildings and a floor in the first one
+        let campus = location("campus", None, "campus");
+        let east = location("east", Some(campus.id), "building");
+        let west = location("west", Some(campus.id), "building");
+        let ground = location("ground", Some(east.id), "floor");
+
+        // Both the ground floor and the west building can have a Room 101
+        let mut room_ids = Vec::new();
+        for location_id in &[ground.id, west.id] {
+            let value = rooms::MaybeRoom {
+                name: String::from("Room 101"),
+                location_id: *location_id,
+            };
+            let req = test::TestRequest::post()
+                .uri("/rooms")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&value)
+                .to_request();
+            let resp: rooms::Room = test::read_response_json(&mut app, req).await;
+            assert_eq!(value.location_id, resp.location_id);
+            room_ids.push(resp.id);
+        }
+
+        // Find the children and descendants of the campus
         let req = test::TestRequest::get()
-            .uri("/contact_events")
+            .uri(format!("/locations/{}/children", campus.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
-        assert_eq!(resp.len(), 0);
-
-        /* Create a contact_event with
-            INITIAL ASSET TAG as asset tag association
-            INITIAL LOCATION as location association
-            INITIAL ALERT as alert association
-        */
-        let value = contact_events::MaybeContactEvent {
-            asset_tag_id: INITIAL_ASSET_TAG.id,
-            location_id: INITIAL_LOCATION.id,
-            alert_id: Some(INITIAL_ALERT.id),
-            deleted: false,
-        };
-        let payload = serde_json::to_string(&value).expect("Invalid value");
+        let resp: Vec<locations::Location> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![east.id, west.id],
+            resp.iter().map(|location| location.id).collect::<Vec<_>>()
+        );
 
-        let req = test::TestRequest::post()
-            .uri("/contact_events")
+        let req = test::TestRequest::get()
+            .uri(format!("/locations/{}/descendants", campus.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_payload(payload)
             .to_request();
-        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
-        assert_eq!(value.asset_tag_id, resp.asset_tag_id);
-        assert_eq!(value.location_id, resp.location_id);
-        assert_eq!(value.alert_id, resp.alert_id);
-        assert_eq!(value.deleted, resp.deleted);
+        let resp: locations::Descendants = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![east.id, west.id, ground.id],
+            resp.locations
+                .iter()
+                .map(|location| location.id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(resp.rooms.len(), 2);
+
+        // Locations need a known kind and cannot be moved below themselves
+        for (id, parent_id, kind, status) in &[
+            (east.id, Some(campus.id), "wing", 422),
+            (campus.id, Some(ground.id), "campus", 400),
+            (east.id, Some(east.id), "building", 400),
+        ] {
+            let value = locations::MaybeLocation {
+                name: Some(String::from("moved")),
+                latitude: 1.0,
+                longitude: 1.0,
+                ip: None,
+                parent_id: *parent_id,
+                kind: String::from(*kind),
+                footprint: None,
+            };
+            let req = test::TestRequest::put()
+                .uri(format!("/locations/{}", id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&value)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
 
-        // Find all contact_events, it should be the one we just created
-        let req = test::TestRequest::get()
-            .uri("/contact_events")
+        // A PUT keeps the optional fields it leaves out, while null in a merge patch clears them
+        let req = test::TestRequest::put()
+            .uri(format!("/locations/{}", west.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
+            .set_json(&serde_json::json!({ "latitude": 2.0, "longitude": 2.0 }))
             .to_request();
-        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
-        assert_eq!(resp.len(), 1);
-        assert_eq!(value.asset_tag_id, resp[0].asset_tag_id);
-        assert_eq!(value.location_id, resp[0].location_id);
-        assert_eq!(value.alert_id, resp[0].alert_id);
-        assert_eq!(value.deleted, resp[0].deleted);
-
-        // Find contact_event by id
-        let id = resp[0].id;
+        let resp: locations::Location = test::read_response_json(&mut app, req).await;
+        assert_eq!(west.name, resp.name);
+        assert_eq!(Some(campus.id), resp.parent_id);
+        assert_eq!(2.0, resp.latitude);
 
-        let req = test::TestRequest::get()
-            .uri(format!("/contact_events/id/{}", id).as_str())
+        let req = test::TestRequest::patch()
+            .uri(format!("/locations/{}", west.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
+            .set_payload(r#"{"parent_id": null}"#)
             .to_request();
-        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
-        assert_eq!(id, resp.id);
-        assert_eq!(value.asset_tag_id, resp.asset_tag_id);
-        assert_eq!(value.location_id, resp.location_id);
-        assert_eq!(value.alert_id, resp.alert_id);
-        assert_eq!(value.deleted, resp.deleted);
+        let resp: locations::Location = test::read_response_json(&mut app, req).await;
+        assert_eq!(west.name, resp.name);
+        assert_eq!(None, resp.parent_id);
+
+        // The INITIAL ASSET was last seen on the ground floor
+        let contact_event =
+            contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                asset_tag_id: INITIAL_ASSET_TAG.id,
+                location_id: Some(ground.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .expect("Failed to create contact event");
+        for (id, recursive, expected) in &[
+            (ground.id, false, vec![INITIAL_ASSET.id]),
+            (campus.id, false, vec![]),
+            (campus.id, true, vec![INITIAL_ASSET.id]),
+            (west.id, true, vec![]),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/locations/{}/assets?recursive={}", id, recursive).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<assets::Asset> = test::read_response_json(&mut app, req).await;
+            assert_eq!(
+                *expected,
+                resp.iter().map(|asset| asset.id).collect::<Vec<_>>()
+            );
+        }
 
-        // Update contact_event by id
-        let value_updated = contact_events::MaybeContactEvent {
-            asset_tag_id: INITIAL_ASSET_TAG.id,
-            location_id: INITIAL_LOCATION.id,
-            alert_id: Some(INITIAL_ALERT.id),
-            deleted: false,
+        // Delete the contact event for good, the rooms and the locations from the leaves up
+        let conn = db::connection().expect("Failed to get db connection");
+        diesel::delete(schema::contact_events::table.find(contact_event.id))
+            .execute(&conn)
+            .expect("Failed to delete contact event");
+        drop(conn);
+        for id in room_ids {
+            rooms::Room::purge(id).expect("Failed to purge room");
+        }
+        for id in &[ground.id, west.id, east.id, campus.id] {
+            locations::Location::purge(*id).expect("Failed to purge location");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_location_geospatial() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let square = |latitude: f64, longitude: f64, half: f64| {
+            serde_json::json!([
+                [longitude - half, latitude - half],
+                [longitude + half, latitude - half],
+                [longitude + half, latitude + half],
+                [longitude - half, latitude + half],
+                [longitude - half, latitude - half],
+            ])
         };
-        let payload_updated = serde_json::to_string(&value_updated).expect("Invalid value");
 
-        let req = test::TestRequest::put()
-            .uri(format!("/contact_events/{}", id).as_str())
+        // Coordinates and footprints are validated
+        for (latitude, longitude, footprint) in &[
+            (91.0, 0.0, None),
+            (0.0, -181.0, None),
+            (0.0, 0.0, Some(serde_json::json!([[0.0, 0.0], [1.0, 1.0]]))),
+            (0.0, 0.0, Some(serde_json::json!({ "type": "Polygon" }))),
+        ] {
+            let value = locations::MaybeLocation {
+                name: Some(String::from("invalid")),
+                latitude: *latitude,
+                longitude: *longitude,
+                ip: None,
+                parent_id: None,
+                kind: String::from("building"),
+                footprint: footprint.clone(),
+            };
+            let req = test::TestRequest::post()
+                .uri("/locations")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&value)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        // A building with a lab inside its footprint, and one in the city
+        let mut ids = Vec::new();
+        for (name, latitude, longitude, footprint) in &[
+            ("tech", 42.0575, -87.6752, square(42.0575, -87.6752, 0.001)),
+            ("lab", 42.0578, -87.6755, square(42.0578, -87.6755, 0.0002)),
+            ("city", 41.8781, -87.6298, square(41.8781, -87.6298, 0.001)),
+        ] {
+            let value = locations::MaybeLocation {
+                name: Some(String::from(*name)),
+                latitude: *latitude,
+                longitude: *longitude,
+                ip: None,
+                parent_id: None,
+                kind: String::from("building"),
+                footprint: Some(footprint.clone()),
+            };
+            let req = test::TestRequest::post()
+                .uri("/locations")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&value)
+                .to_request();
+            let resp: locations::Location = test::read_response_json(&mut app, req).await;
+            assert_eq!(value.footprint, resp.footprint);
+            ids.push(resp.id);
+        }
+        let (tech_id, lab_id, city_id) = (ids[0], ids[1], ids[2]);
+
+        // Find locations near the building, nearest first with their distance
+        let req = test::TestRequest::get()
+            .uri("/locations/near?lat=42.0575&lon=-87.6752&radius_m=1000")
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
-            .header(header::CONTENT_TYPE, "application/json")
-            .set_payload(payload_updated)
             .to_request();
-        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
-        assert_eq!(value_updated.asset_tag_id, resp.asset_tag_id);
-        assert_eq!(value_updated.location_id, resp.location_id);
-        assert_eq!(value_updated.alert_id, resp.alert_id);
+        let resp: Vec<locations::NearbyLocation> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![tech_id, lab_id],
+            resp.iter()
+                .map(|nearby| nearby.location.id)
+                .collect::<Vec<_>>()
+        );
+        assert!(resp[0].distance_m < 1.0);
+        assert!(resp[1].distance_m > 30.0 && resp[1].distance_m < 50.0);
+
+        let req = test::TestRequest::get()
+            .uri("/locations/near?lat=42.0575&lon=-87.6752&radius_m=25000")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<locations::NearbyLocation> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 3);
+        assert_eq!(city_id, resp[2].location.id);
+        assert!(resp[2].distance_m > 20_000.0 && resp[2].distance_m < 21_000.0);
+
+        // Find locations within a bounding box
+        let req = test::TestRequest::get()
+            .uri("/locations/within?min_lat=42&min_lon=-88&max_lat=42.1&max_lon=-87.6")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<locations::Location> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![tech_id, lab_id],
+            resp.iter().map(|location| location.id).collect::<Vec<_>>()
+        );
+
+        for uri in &[
+            "/locations/near?lat=42.0575&lon=-87.6752&radius_m=0",
+            "/locations/near?lat=420&lon=-87.6752&radius_m=10",
+            "/locations/within?min_lat=43&min_lon=-88&max_lat=42&max_lon=-87",
+            "/locations/snap?lat=42.0575",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Snap sightings to the smallest footprint containing them
+        for (latitude, longitude, expected) in &[
+            (42.0578, -87.6755, Some(lab_id)),
+            (42.0570, -87.6750, Some(tech_id)),
+            (42.0600, -87.6700, None),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/locations/snap?lat={}&lon={}", latitude, longitude).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            match expected {
+                Some(id) => {
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let location: locations::Location =
+                        serde_json::from_slice(&test::read_body(resp).await)
+                            .expect("Invalid location");
+                    assert_eq!(*id, location.id);
+                }
+                None => assert_eq!(resp.status(), StatusCode::NOT_FOUND),
+            }
+        }
+
+        for id in &ids {
+            locations::Location::purge(*id).expect("Failed to purge location");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_geojson() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let value = locations::MaybeLocation {
+            name: Some(String::from("depot")),
+            latitude: 52.370216,
+            longitude: 4.895168,
+            ip: None,
+            parent_id: None,
+            kind: String::from("building"),
+            footprint: None,
+        };
+        let location = locations::Location::create(value).expect("Failed to create location");
+
+        // Locations are points at [longitude, latitude], filtered like /locations/near
+        let req = test::TestRequest::get()
+            .uri("/locations.geojson?lat=52.37&lon=4.89&radius_m=1000")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            "application/geo+json",
+            resp.headers()
+                .get(header::CONTENT_TYPE)
+                .expect("Missing content type")
+        );
+        let resp: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).expect("Invalid GeoJSON");
+        assert_eq!("FeatureCollection", resp["type"]);
+        let features = resp["features"].as_array().expect("Missing features");
+        assert_eq!(1, features.len());
+        assert_eq!("Feature", features[0]["type"]);
+        assert_eq!(location.id, features[0]["id"]);
+        assert_eq!(
+            serde_json::json!({ "type": "Point", "coordinates": [4.895168, 52.370216] }),
+            features[0]["geometry"]
+        );
+        assert_eq!("depot", features[0]["properties"]["name"]);
+        assert!(features[0]["properties"]["distance_m"].is_number());
+
+        for uri in &[
+            "/locations.geojson?lat=52.37&lon=4.89",
+            "/locations.geojson?lat=52.37&lon=4.89&radius_m=1000&min_lat=52",
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Assets are where their tags were last seen
+        let contact_event =
+            contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                asset_tag_id: INITIAL_ASSET_TAG.id,
+                location_id: Some(location.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .expect("Failed to create contact event");
+        let spare = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("spare"),
+            description: None,
+            serial_number: String::from("spare"),
+            asset_id: Some(INITIAL_ASSET.id),
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let spare_event = contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+            asset_tag_id: spare.id,
+            location_id: Some(location.id),
+            alert_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create contact event");
+
+        // An asset lent out to a room has it along with its position
+        let room = rooms::Room::create(rooms::MaybeRoom {
+            name: String::from("Room 7"),
+            location_id: location.id,
+        })
+        .expect("Failed to create room");
+        let loan = loans::Loan::create(loans::MaybeLoan {
+            asset_id: INITIAL_ASSET.id,
+            user_id: ADMIN_USER.id,
+            location_id: Some(location.id),
+            room_id: Some(room.id),
+            due_at: chrono::Utc::now().naive_utc() + chrono::Duration::days(1),
+        })
+        .expect("Failed to create loan");
+
+        let req = test::TestRequest::get()
+            .uri(format!("/assets/positions?location_id={}", location.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<assets::AssetPosition> = test::read_response_json(&mut app, req).await;
+        assert_eq!(2, resp.len());
+        assert_eq!(INITIAL_ASSET.id, resp[0].asset_id);
+        assert_eq!(Some(String::from("depot")), resp[0].location_name);
+        assert_eq!(Some(room.id), resp[0].room_id);
+        assert_eq!(Some(String::from("Room 7")), resp[1].room_name);
+
+        let req = test::TestRequest::get()
+            .uri("/assets/positions.geojson")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let features = resp["features"].as_array().expect("Missing features");
+        // Each tag of the asset is a feature of its own
+        assert_eq!(2, features.len());
+        assert_eq!(INITIAL_ASSET_TAG.id, features[0]["id"]);
+        assert_eq!(spare.id, features[1]["id"]);
+        assert_eq!(INITIAL_ASSET.id, features[1]["properties"]["asset_id"]);
+        assert_eq!("Room 7", features[0]["properties"]["room_name"]);
+        assert_eq!(
+            serde_json::json!([4.895168, 52.370216]),
+            features[0]["geometry"]["coordinates"]
+        );
+        assert_eq!("initial", features[0]["properties"]["asset_tag_name"]);
+        assert_eq!(location.id, features[0]["properties"]["location_id"]);
+        assert!(features[0]["properties"]["seen_at"].is_string());
+
+        let req = test::TestRequest::get()
+            .uri(
+                format!(
+                    "/assets/positions.geojson?location_id={}",
+                    INITIAL_LOCATION.id
+                )
+                .as_str(),
+            )
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(serde_json::json!([]), resp["features"]);
+
+        let conn = db::connection().expect("Failed to get db connection");
+        for id in &[contact_event.id, spare_event.id] {
+            diesel::delete(schema::contact_events::table.find(id))
+                .execute(&conn)
+                .expect("Failed to delete contact event");
+        }
+        drop(conn);
+        loans::Loan::delete(loan.id).expect("Failed to delete loan");
+        rooms::Room::purge(room.id).expect("Failed to purge room");
+        asset_tags::AssetTag::purge(spare.id).expect("Failed to purge asset tag");
+        locations::Location::purge(location.id).expect("Failed to purge location");
+    }
+
+    #[actix_rt::test]
+    async fn test_location_subnets() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let mut ids = Vec::new();
+        for name in &["wing", "lab"] {
+            let location = locations::Location::create(locations::MaybeLocation {
+                name: Some(String::from(*name)),
+                latitude: 1.0,
+                longitude: 1.0,
+                ip: None,
+                parent_id: None,
+                kind: String::from("building"),
+                footprint: None,
+            })
+            .expect("Failed to create location");
+            ids.push(location.id);
+        }
+        let (wing_id, lab_id) = (ids[0], ids[1]);
+
+        // A location can have several subnets, stored by their network address
+        let mut subnet_ids = Vec::new();
+        for (id, subnet, expected) in &[
+            (wing_id, "10.20.0.0/16", "10.20.0.0/16"),
+            (wing_id, "fd00:20::/32", "fd00:20::/32"),
+            (lab_id, "10.20.5.7/24", "10.20.5.0/24"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(format!("/locations/{}/subnets", id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&serde_json::json!({ "subnet": subnet }))
+                .to_request();
+            let resp: locations::LocationSubnet = test::read_response_json(&mut app, req).await;
+            assert_eq!(*id, resp.location_id);
+            assert_eq!(*expected, resp.subnet.to_string());
+            subnet_ids.push(resp.id);
+        }
+
+        let req = test::TestRequest::post()
+            .uri(format!("/locations/{}/subnets", lab_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({ "subnet": "10.20.0.0/16" }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        let req = test::TestRequest::get()
+            .uri(format!("/locations/{}/subnets", wing_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<locations::LocationSubnet> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            subnet_ids[..2].to_vec(),
+            resp.iter().map(|subnet| subnet.id).collect::<Vec<_>>()
+        );
+
+        // The longest prefix wins, and the ip of a location counts as a subnet
+        for (ip, expected) in &[
+            ("10.20.5.9", Some(lab_id)),
+            ("::ffff:10.20.5.9", Some(lab_id)),
+            ("10.20.9.1", Some(wing_id)),
+            ("fd00:20::1", Some(wing_id)),
+            ("10.9.1.1", Some(INITIAL_LOCATION.id)),
+            ("192.0.2.1", None),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/locations/resolve?ip={}", ip).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            match expected {
+                Some(id) => {
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let resolved: locations::ResolvedLocation =
+                        serde_json::from_slice(&test::read_body(resp).await)
+                            .expect("Invalid resolved location");
+                    assert_eq!(*id, resolved.location.id);
+                }
+                None => assert_eq!(resp.status(), StatusCode::NOT_FOUND),
+            }
+        }
+
+        // Without an ip the caller's address is resolved
+        let req = test::TestRequest::get()
+            .uri("/locations/resolve")
+            .peer_addr("10.20.5.9:4000".parse().unwrap())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: locations::ResolvedLocation = test::read_response_json(&mut app, req).await;
+        assert_eq!(lab_id, resp.location.id);
+        assert_eq!("10.20.5.0/24", resp.subnet.to_string());
+
+        // Sightings without a location are placed by their source address, which forwarding
+        // headers only stand in for when a trusted proxy sent them
+        let mut contact_event_ids = Vec::new();
+        for (peer_addr, forwarded, expected) in &[
+            ("10.20.9.1:4000", None, Some(wing_id)),
+            (
+                "127.0.0.1:4000",
+                Some(("x-forwarded-for", "10.20.5.9")),
+                Some(lab_id),
+            ),
+            (
+                "127.0.0.1:4000",
+                Some(("x-forwarded-for", "10.20.5.9, 127.0.0.1")),
+                Some(lab_id),
+            ),
+            (
+                "127.0.0.1:4000",
+                Some(("forwarded", "for=10.20.5.9;proto=http")),
+                Some(lab_id),
+            ),
+            (
+                "10.20.9.1:4000",
+                Some(("x-forwarded-for", "10.20.5.9")),
+                Some(wing_id),
+            ),
+            ("192.0.2.1:4000", None, None),
+            (
+                "192.0.2.1:4000",
+                Some(("x-forwarded-for", "10.20.5.9")),
+                None,
+            ),
+        ] {
+            let mut req = test::TestRequest::post()
+                .uri("/contact_events")
+                .peer_addr(peer_addr.parse().unwrap())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&serde_json::json!({
+                    "asset_tag_id": INITIAL_ASSET_TAG.id,
+                    "alert_id": null,
+                    "deleted": false,
+                }));
+            if let Some((name, value)) = forwarded {
+                req = req.header(*name, *value);
+            }
+            let resp = test::call_service(&mut app, req.to_request()).await;
+            match expected {
+                Some(id) => {
+                    assert_eq!(resp.status(), StatusCode::OK);
+                    let contact_event: contact_events::ContactEvent =
+                        serde_json::from_slice(&test::read_body(resp).await)
+                            .expect("Invalid contact event");
+                    assert_eq!(*id, contact_event.location_id);
+                    contact_event_ids.push(contact_event.id);
+                }
+                None => assert_eq!(resp.status(), StatusCode::BAD_REQUEST),
+            }
+        }
+
+        let req = test::TestRequest::delete()
+            .uri(format!("/locations/subnets/{}", subnet_ids[2]).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: usize = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, resp);
+        let req = test::TestRequest::get()
+            .uri("/locations/resolve?ip=10.20.5.9")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: locations::ResolvedLocation = test::read_response_json(&mut app, req).await;
+        assert_eq!(wing_id, resp.location.id);
+
+        // Deleting the locations deletes their subnets
+        let conn = db::connection().expect("Failed to get db connection");
+        diesel::delete(
+            schema::contact_events::table
+                .filter(schema::contact_events::id.eq_any(&contact_event_ids)),
+        )
+        .execute(&conn)
+        .expect("Failed to delete contact events");
+        drop(conn);
+        for id in &ids {
+            locations::Location::purge(*id).expect("Failed to purge location");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_search() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("Centrifuge rotor"),
+            description: Some(String::from("Spare for the wet lab centrifuges")),
+            serial_number: String::from("CF-9000"),
+            asset_id: Some(INITIAL_ASSET.id),
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let room = rooms::Room::create(rooms::MaybeRoom {
+            name: String::from("Centrifuge bay"),
+            location_id: INITIAL_LOCATION.id,
+        })
+        .expect("Failed to create room");
+        let comment = comments::Comment::create(comments::MaybeComment {
+            content: String::from("The centrifuge is making a grinding noise"),
+            user_id: ADMIN_USER.id,
+            asset_tag_id: None,
+            asset_id: Some(INITIAL_ASSET.id),
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
+        })
+        .expect("Failed to create comment");
+
+        // Results are ranked, typed and highlighted, with names weighing the most
+        let req = test::TestRequest::get()
+            .uri("/search?q=centrifuge")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<search::SearchResult> = test::read_response_json(&mut app, req).await;
+        assert_eq!(3, resp.len());
+        assert_eq!(
+            ("asset_tag", asset_tag.id, Some(INITIAL_ASSET.id)),
+            (resp[0].kind.as_str(), resp[0].id, resp[0].asset_id)
+        );
+        assert!(resp[0].snippet.contains("<mark>Centrifuge</mark>"));
+        assert!(resp.windows(2).all(|pair| pair[0].rank >= pair[1].rank));
+        let mut kinds: Vec<(&str, i64)> = resp
+            .iter()
+            .map(|result| (result.kind.as_str(), result.id))
+            .collect();
+        kinds.sort();
+        assert_eq!(
+            vec![
+                ("asset_tag", asset_tag.id),
+                ("comment", comment.id),
+                ("room", room.id)
+            ],
+            kinds
+        );
+
+        // Websearch syntax, serial numbers and limits
+        for (uri, expected) in &[
+            (
+                "/search?q=centrifuge%20-grinding",
+                vec![asset_tag.id, room.id],
+            ),
+            ("/search?q=%22wet%20lab%22", vec![asset_tag.id]),
+            ("/search?q=CF-9000", vec![asset_tag.id]),
+            ("/search?q=centrifuge&limit=1", vec![asset_tag.id]),
+            ("/search?q=xylophone", vec![]),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<search::SearchResult> = test::read_response_json(&mut app, req).await;
+            let mut ids: Vec<i64> = resp.iter().map(|result| result.id).collect();
+            ids.sort();
+            let mut expected = expected.clone();
+            expected.sort();
+            assert_eq!(expected, ids, "{}", uri);
+        }
+
+        for uri in &["/search?q=%20", "/search?q=centrifuge&limit=0"] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Snippets are HTML in which only the highlights are markup
+        let script = comments::Comment::create(comments::MaybeComment {
+            content: String::from("<script>alert('xylophone')</script> & \"xylophone\""),
+            user_id: ADMIN_USER.id,
+            asset_tag_id: None,
+            asset_id: Some(INITIAL_ASSET.id),
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
+        })
+        .expect("Failed to create comment");
+        let req = test::TestRequest::get()
+            .uri("/search?q=xylophone")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<search::SearchResult> = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, resp.len());
+        let snippet = &resp[0].snippet;
+        assert!(snippet.contains("<mark>xylophone</mark>"), "{}", snippet);
+        assert!(snippet.contains("script&gt;"), "{}", snippet);
+        let text = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert!(!text.contains('<') && !text.contains('>'), "{}", snippet);
+
+        comments::Comment::purge(script.id).expect("Failed to purge comment");
+        comments::Comment::purge(comment.id).expect("Failed to purge comment");
+        rooms::Room::purge(room.id).expect("Failed to purge room");
+        let conn = db::connection().expect("Failed to get db connection");
+        diesel::delete(schema::asset_tags::table.find(asset_tag.id))
+            .execute(&conn)
+            .expect("Failed to delete asset tag");
+    }
+
+    #[actix_rt::test]
+    async fn test_import_assets() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let count = asset_tags::AssetTag::find_with_deleted()
+            .expect("Failed to find asset tags")
+            .len();
+
+        // Every invalid row is reported, and nothing is imported
+        let csv = "Tag,Serial No,Notes\n\
+            Pipette,P-100,Single channel\n\
+            ,P-200,\n\
+            Pipette,P-100,Again\n\
+            Initial,initial,Taken\n";
+        for (dry_run, status) in &[(true, StatusCode::OK), (false, StatusCode::BAD_REQUEST)] {
+            let req = test::TestRequest::post()
+                .uri(
+                    format!(
+                        "/import/assets?dry_run={}&name=Tag&serial_number=Serial%20No&description=Notes",
+                        dry_run
+                    )
+                    .as_str(),
+                )
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::CONTENT_TYPE, "text/csv")
+                .set_payload(csv)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+            let report: import::ImportReport =
+                serde_json::from_slice(&test::read_body(resp).await).expect("Invalid report");
+            assert_eq!(*dry_run, report.dry_run);
+            assert_eq!(4, report.rows);
+            assert_eq!(
+                vec![(3, "Tag"), (4, "Serial No"), (5, "Serial No")],
+                report
+                    .errors
+                    .iter()
+                    .map(|error| (error.row, error.column.as_deref().unwrap_or_default()))
+                    .collect::<Vec<_>>()
+            );
+            assert!(report.asset_tags.is_empty());
+        }
+        assert_eq!(
+            count,
+            asset_tags::AssetTag::find_with_deleted()
+                .expect("Failed to find asset tags")
+                .len()
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/import/assets?serial_number=serial")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_payload("name,serial_number\nPipette,P-100\n")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // A valid sheet imports an asset for each tag
+        let req = test::TestRequest::post()
+            .uri("/import/assets")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_payload(
+                "name,serial_number,description\nPipette,P-100,Single channel\nScale,S-1,\n\n",
+            )
+            .to_request();
+        let report: import::ImportReport = test::read_response_json(&mut app, req).await;
+        assert!(!report.dry_run);
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            vec![
+                ("Pipette", "P-100", Some("Single channel")),
+                ("Scale", "S-1", None)
+            ],
+            report
+                .asset_tags
+                .iter()
+                .map(|asset_tag| (
+                    asset_tag.name.as_str(),
+                    asset_tag.serial_number.as_str(),
+                    asset_tag.description.as_deref()
+                ))
+                .collect::<Vec<_>>()
+        );
+        for asset_tag in &report.asset_tags {
+            let asset_id = asset_tag.asset_id.expect("Missing asset");
+            assert!(
+                !assets::Asset::find_by_id(asset_id)
+                    .expect("Failed to find asset")
+                    .deleted
+            );
+        }
+
+        let conn = db::connection().expect("Failed to get db connection");
+        for asset_tag in &report.asset_tags {
+            diesel::delete(schema::asset_tags::table.find(asset_tag.id))
+                .execute(&conn)
+                .expect("Failed to delete asset tag");
+            diesel::delete(schema::assets::table.find(asset_tag.asset_id.unwrap()))
+                .execute(&conn)
+                .expect("Failed to delete asset");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_export() {
+        use export::ExportRow;
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let sightings: Vec<contact_events::MaybeContactEvent> = (0..export::BATCH_SIZE + 1)
+            .map(|_| contact_events::MaybeContactEvent {
+                asset_tag_id: INITIAL_ASSET_TAG.id,
+                location_id: Some(INITIAL_LOCATION.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .collect();
+        let conn = db::connection().expect("Failed to get db connection");
+        let contact_event_ids: Vec<i64> = diesel::insert_into(schema::contact_events::table)
+            .values(sightings)
+            .returning(schema::contact_events::id)
+            .get_results(&conn)
+            .expect("Failed to create contact events");
+        drop(conn);
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        let formula = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("@SUM(A1:A2)"),
+            description: Some(String::from("=HYPERLINK(\"http://example.com\")")),
+            serial_number: String::from("-1"),
+            asset_id: Some(asset.id),
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+
+        // Assets are joined with the names of their tags and where they were last seen
+        let req = test::TestRequest::get()
+            .uri("/export/assets.csv")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            "text/csv; charset=utf-8",
+            resp.headers()
+                .get(header::CONTENT_TYPE)
+                .expect("Missing content type")
+        );
+        let body = test::read_body(resp).await;
+        let mut reader = csv::Reader::from_reader(&body[..]);
+        assert_eq!(
+            export::AssetRow::COLUMNS.to_vec(),
+            reader
+                .headers()
+                .expect("Missing header")
+                .iter()
+                .collect::<Vec<_>>()
+        );
+        let rows: Vec<export::AssetRow> = reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .expect("Invalid CSV");
+        let row = rows
+            .iter()
+            .find(|row| row.asset_id == INITIAL_ASSET.id)
+            .expect("Missing initial asset");
+        assert_eq!(Some("initial"), row.serial_number.as_deref());
+        assert_eq!(Some("initial"), row.last_seen_location.as_deref());
+        assert!(row.last_seen_at.is_some());
+
+        // Text that a spreadsheet would run as a formula is quoted in CSV
+        let row = rows
+            .iter()
+            .find(|row| row.asset_id == asset.id)
+            .expect("Missing asset");
+        assert_eq!(Some("'@SUM(A1:A2)"), row.tag_name.as_deref());
+        assert_eq!(Some("'-1"), row.serial_number.as_deref());
+        assert_eq!(
+            Some("'=HYPERLINK(\"http://example.com\")"),
+            row.description.as_deref()
+        );
+
+        // Sightings are streamed in batches, one JSON object per line
+        let req = test::TestRequest::get()
+            .uri("/export/contact_events.ndjson?from=2000-01-01")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let body = test::read_body(test::call_service(&mut app, req).await).await;
+        let rows: Vec<export::ContactEventRow> = std::str::from_utf8(&body)
+            .expect("Invalid UTF-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("Invalid JSON line"))
+            .collect();
+        assert_eq!(
+            contact_event_ids,
+            rows.iter()
+                .map(|row| row.contact_event_id)
+                .filter(|id| contact_event_ids.contains(id))
+                .collect::<Vec<_>>()
+        );
+        assert!(rows.iter().all(|row| row.serial_number == "initial"));
+
+        let req = test::TestRequest::get()
+            .uri("/export/contact_events.csv?to=2000-01-01")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let body = test::read_body(test::call_service(&mut app, req).await).await;
+        assert_eq!(
+            format!("{}\n", export::ContactEventRow::COLUMNS.join(",")),
+            std::str::from_utf8(&body).expect("Invalid UTF-8")
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/export/contact_events.csv?from=yesterday")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let conn = db::connection().expect("Failed to get db connection");
+        diesel::delete(
+            schema::contact_events::table
+                .filter(schema::contact_events::id.eq_any(&contact_event_ids)),
+        )
+        .execute(&conn)
+        .expect("Failed to delete contact events");
+        drop(conn);
+        asset_tags::AssetTag::purge(formula.id).expect("Failed to purge asset tag");
+        assets::Asset::purge(asset.id).expect("Failed to purge asset");
+    }
+
+    #[actix_rt::test]
+    async fn test_labels() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+
+        // A QR code of the serial number with a blank border
+        let req = test::TestRequest::get()
+            .uri(format!("/asset_tags/{}/label.png?size=200", INITIAL_ASSET_TAG.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            "image/png",
+            resp.headers()
+                .get(header::CONTENT_TYPE)
+                .expect("Missing content type")
+        );
+        let image = image::load_from_memory(&test::read_body(resp).await)
+            .expect("Invalid PNG")
+            .to_luma8();
+        assert_eq!(image.width(), image.height());
+        assert!(image.width() > 100 && image.width() <= 200);
+        assert_eq!(255, image.get_pixel(0, 0)[0]);
+        assert!(image.pixels().any(|pixel| pixel[0] == 0));
+        assert_eq!("initial", labels::payload(&INITIAL_ASSET_TAG));
+
+        // Sheets of labels spill over onto as many pages as needed
+        for (uri, pages) in &[
+            (format!("ids={}", INITIAL_ASSET_TAG.id), 1),
+            (
+                format!("ids={0},{0}&sheet=avery-5163&skip=9", INITIAL_ASSET_TAG.id),
+                2,
+            ),
+            (
+                format!("ids={0},{0}&width_mm=62&height_mm=29", INITIAL_ASSET_TAG.id),
+                2,
+            ),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/asset_tags/labels.pdf?{}", uri).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            assert!(body.starts_with(b"%PDF"));
+            let text = String::from_utf8_lossy(&body);
+            assert_eq!(
+                *pages,
+                text.matches("/Type /Page").count() - text.matches("/Type /Pages").count(),
+                "{}",
+                uri
+            );
+        }
+
+        for (uri, status) in &[
+            (String::from("ids=abc"), StatusCode::BAD_REQUEST),
+            (String::from("ids=0"), StatusCode::NOT_FOUND),
+            (
+                format!("ids={}&sheet=avery-1", INITIAL_ASSET_TAG.id),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("ids={}&skip=30", INITIAL_ASSET_TAG.id),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                format!("ids={}&width_mm=62", INITIAL_ASSET_TAG.id),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/asset_tags/labels.pdf?{}", uri).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status, "{}", uri);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_scan() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let loan = loans::Loan::create(loans::MaybeLoan {
+            asset_id: INITIAL_ASSET.id,
+            user_id: ADMIN_USER.id,
+            location_id: None,
+            room_id: None,
+            due_at: chrono::Utc::today().naive_utc().and_hms(12, 0, 0) - chrono::Duration::days(1),
+        })
+        .expect("Failed to create loan");
+        let alert_id = loans::Loan::raise_overdue_alerts()
+            .expect("Failed to raise alerts")
+            .into_iter()
+            .find(|overdue| overdue.id == loan.id)
+            .and_then(|overdue| overdue.alert_id)
+            .expect("Missing overdue alert");
+
+        // A bare serial from a barcode resolves to the tag, its asset and open alerts
+        let req = test::TestRequest::get()
+            .uri("/scan/initial")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: scan::ScanSummary = test::read_response_json(&mut app, req).await;
+        assert_eq!(INITIAL_ASSET_TAG.id, resp.asset_tag.id);
+        assert_eq!(Some(INITIAL_ASSET.id), resp.asset.map(|asset| asset.id));
+        assert!(resp.contact_event.is_none());
+        assert_eq!(
+            vec![alert_id],
+            resp.open_alerts
+                .iter()
+                .map(|alert| alert.id)
+                .collect::<Vec<_>>()
+        );
+
+        // Looking a label up records nothing, even when asked to
+        let req = test::TestRequest::get()
+            .uri(format!("/scan/initial?location_id={}", INITIAL_LOCATION.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: scan::ScanSummary = test::read_response_json(&mut app, req).await;
+        assert!(resp.contact_event.is_none());
+        assert!(resp.last_seen.is_none());
+
+        // A QR code link, passed whole, records a sighting at the chosen location
+        let req = test::TestRequest::post()
+            .uri("/scan/https%3A%2F%2Fassets.example.org%2Fscan%2Finitial")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({ "location_id": INITIAL_LOCATION.id }))
+            .to_request();
+        let resp: scan::ScanSummary = test::read_response_json(&mut app, req).await;
+        let contact_event = resp.contact_event.expect("Missing contact event");
+        assert_eq!(INITIAL_ASSET_TAG.id, contact_event.asset_tag_id);
+        assert_eq!(INITIAL_LOCATION.id, contact_event.location_id);
+        let last_seen = resp.last_seen.expect("Missing last seen");
+        assert_eq!(INITIAL_LOCATION.id, last_seen.location_id);
+        assert_eq!(contact_event.created_at, last_seen.seen_at);
+
+        assert_eq!(
+            "initial",
+            labels::serial_number("https://x/scan/initial?a=b")
+        );
+        assert_eq!("a b/c", labels::serial_number("https://x/scan/a%20b%2Fc"));
+        assert_eq!("initial", labels::serial_number(" initial\n"));
+
+        // Codes are decoded once, so a serial number can hold what looks like an escape
+        let percent = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("percent"),
+            description: None,
+            serial_number: String::from("A%25B"),
+            asset_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        for uri in &["/scan/A%2525B", "/scan/https%3A%2F%2Fx%2Fscan%2FA%252525B"] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: scan::ScanSummary = test::read_response_json(&mut app, req).await;
+            assert_eq!(percent.id, resp.asset_tag.id, "{}", uri);
+        }
+        asset_tags::AssetTag::purge(percent.id).expect("Failed to purge asset tag");
+
+        for (uri, location_id, status) in &[
+            ("/scan/missing", INITIAL_LOCATION.id, StatusCode::NOT_FOUND),
+            ("/scan/initial", 0, StatusCode::BAD_REQUEST),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&serde_json::json!({ "location_id": location_id }))
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
+
+        let conn = db::connection().expect("Failed to get db connection");
+        diesel::delete(schema::contact_events::table.find(contact_event.id))
+            .execute(&conn)
+            .expect("Failed to delete contact event");
+        diesel::delete(schema::loans::table.find(loan.id))
+            .execute(&conn)
+            .expect("Failed to delete loan");
+        diesel::delete(schema::alerts::table.find(alert_id))
+            .execute(&conn)
+            .expect("Failed to delete alert");
+    }
+
+    #[actix_rt::test]
+    async fn test_restore_and_purge() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("purged"),
+            description: None,
+            serial_number: String::from("purged"),
+            asset_id: Some(asset.id),
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let contact_event =
+            contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                asset_tag_id: asset_tag.id,
+                location_id: Some(INITIAL_LOCATION.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .expect("Failed to create contact event");
+
+        // A soft-deleted asset can be restored, but only once
+        let req = test::TestRequest::delete()
+            .uri(format!("/assets/{}", asset.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: assets::Asset = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+        assert!(resp.deleted_at.is_some());
+        for status in &[StatusCode::OK, StatusCode::NOT_FOUND] {
+            let req = test::TestRequest::post()
+                .uri(format!("/assets/{}/restore", asset.id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
+        let resp = assets::Asset::find_by_id(asset.id).expect("Failed to find restored asset");
+        assert!(!resp.deleted);
+        assert_eq!(None, resp.deleted_at);
+
+        // Purging is for admins only
+        let uri = format!("/contact_events/{}?purge=true", contact_event.id);
+        let req = test::TestRequest::delete()
+            .uri(uri.as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let role = roles::Role::create(roles::MaybeRole {
+            name: String::from(roles::ADMIN),
+            user_id: Some(ADMIN_USER.id),
+        })
+        .expect("Failed to create admin role");
+
+        // Rows still referenced by others cannot be purged until those are
+        for (uri, status) in &[
+            (
+                format!("/asset_tags/{}?purge=true", asset_tag.id),
+                StatusCode::CONFLICT,
+            ),
+            (uri, StatusCode::OK),
+            (
+                format!("/assets/{}?purge=true", asset.id),
+                StatusCode::CONFLICT,
+            ),
+            (
+                format!("/asset_tags/{}?purge=true", asset_tag.id),
+                StatusCode::OK,
+            ),
+            (format!("/assets/{}?purge=true", asset.id), StatusCode::OK),
+            (
+                format!("/assets/{}?purge=true", asset.id),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let req = test::TestRequest::delete()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
+        assert!(!assets::Asset::find_with_deleted()
+            .expect("Failed to find assets")
+            .iter()
+            .any(|resp| resp.id == asset.id));
+
+        // The retention job purges rows deleted before its cutoff
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        assets::Asset::delete(asset.id).expect("Failed to delete asset");
+        let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::days(30);
+        diesel::update(schema::assets::table.find(asset.id))
+            .set(schema::assets::deleted_at.eq(cutoff + chrono::Duration::seconds(1)))
+            .execute(&db::connection().expect("Failed to get db connection"))
+            .expect("Failed to backdate asset");
+        assert_eq!(
+            0,
+            assets::Asset::purge_expired(cutoff).expect("Failed to purge assets")
+        );
+        diesel::update(schema::assets::table.find(asset.id))
+            .set(schema::assets::deleted_at.eq(cutoff - chrono::Duration::seconds(1)))
+            .execute(&db::connection().expect("Failed to get db connection"))
+            .expect("Failed to backdate asset");
+        assert_eq!(
+            1,
+            assets::Asset::purge_expired(cutoff).expect("Failed to purge assets")
+        );
+
+        roles::Role::purge(role.id).expect("Failed to purge admin role");
+    }
+
+    #[actix_rt::test]
+    async fn test_cascading_delete() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("cascaded"),
+            description: None,
+            serial_number: String::from("cascaded"),
+            asset_id: Some(asset.id),
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let contact_event =
+            contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                asset_tag_id: asset_tag.id,
+                location_id: Some(INITIAL_LOCATION.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .expect("Failed to create contact event");
+        let building = locations::Location::create(locations::MaybeLocation {
+            name: Some(String::from("cascaded building")),
+            latitude: 1.0,
+            longitude: 1.0,
+            ip: None,
+            parent_id: None,
+            kind: String::from("building"),
+            footprint: None,
+        })
+        .expect("Failed to create building");
+        let floor = locations::Location::create(locations::MaybeLocation {
+            name: Some(String::from("cascaded floor")),
+            latitude: 1.0,
+            longitude: 1.0,
+            ip: None,
+            parent_id: Some(building.id),
+            kind: String::from("floor"),
+            footprint: None,
+        })
+        .expect("Failed to create floor");
+        let room = rooms::Room::create(rooms::MaybeRoom {
+            name: String::from("cascaded room"),
+            location_id: floor.id,
+        })
+        .expect("Failed to create room");
+
+        // Deleting an asset deletes its tags and their contact events
+        let req = test::TestRequest::delete()
+            .uri(format!("/assets/{}", asset.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: assets::Asset = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+        let deleted_at = resp.deleted_at;
+        let req = test::TestRequest::get()
+            .uri("/asset_tags/deleted")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<asset_tags::AssetTag> = test::read_response_json(&mut app, req).await;
+        assert!(resp
+            .iter()
+            .any(|tag| tag.id == asset_tag.id && tag.deleted_at == deleted_at));
+        assert!(contact_events::ContactEvent::find_by_id(contact_event.id).is_err());
+
+        // Restoring the asset brings them back
+        let req = test::TestRequest::post()
+            .uri(format!("/assets/{}/restore", asset.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = asset_tags::AssetTag::find_by_id(asset_tag.id).expect("Failed to find tag");
+        assert_eq!(None, resp.deleted_at);
+        contact_events::ContactEvent::find_by_id(contact_event.id)
+            .expect("Failed to find contact event");
+
+        // Deleting a location deletes the locations and rooms inside it
+        let req = test::TestRequest::delete()
+            .uri(format!("/locations/{}", building.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: locations::Location = test::read_response_json(&mut app, req).await;
+        assert!(resp.deleted);
+        for (uri, deleted) in &[
+            ("/locations/deleted", vec![building.id, floor.id]),
+            ("/locations/all", vec![building.id, floor.id]),
+            ("/locations", vec![]),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<locations::Location> = test::read_response_json(&mut app, req).await;
+            let mut ids: Vec<i64> = resp
+                .iter()
+                .map(|location| location.id)
+                .filter(|id| *id == building.id || *id == floor.id)
+                .collect();
+            ids.sort_unstable();
+            assert_eq!(*deleted, ids);
+        }
+        for (uri, len) in &[("/rooms/deleted", 1), ("/rooms", 0)] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<rooms::Room> = test::read_response_json(&mut app, req).await;
+            assert_eq!(*len, resp.iter().filter(|resp| resp.id == room.id).count());
+        }
+
+        // Restoring the building restores the floor and its room
+        let req = test::TestRequest::post()
+            .uri(format!("/locations/{}/restore", building.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: locations::Location = test::read_response_json(&mut app, req).await;
+        assert!(!resp.deleted);
+        locations::Location::find_by_id(floor.id).expect("Failed to find floor");
+        rooms::Room::find_by_id(room.id).expect("Failed to find room");
+
+        contact_events::ContactEvent::purge(contact_event.id)
+            .expect("Failed to purge contact event");
+        asset_tags::AssetTag::purge(asset_tag.id).expect("Failed to purge asset tag");
+        assets::Asset::purge(asset.id).expect("Failed to purge asset");
+        rooms::Room::purge(room.id).expect("Failed to purge room");
+        for id in &[floor.id, building.id] {
+            locations::Location::purge(*id).expect("Failed to purge location");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_conditional_requests() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("conditional"),
+            description: None,
+            serial_number: String::from("conditional"),
+            asset_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let uri = format!("/asset_tags/{}", asset_tag.id);
+
+        // Single resources come with an ETag, and are not sent again while it matches
+        let req = test::TestRequest::get()
+            .uri(format!("/asset_tags/id/{}", asset_tag.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .expect("Missing ETag")
+            .to_str()
+            .expect("Invalid ETag")
+            .to_string();
+        for (if_none_match, status) in &[
+            (etag.as_str(), StatusCode::NOT_MODIFIED),
+            ("\"stale\"", StatusCode::OK),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/asset_tags/id/{}", asset_tag.id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::IF_NONE_MATCH, *if_none_match)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
+
+        // The first edit against the ETag wins, the second one is refused
+        let mut updated_etag = String::new();
+        for (name, status) in &[
+            ("first edit", StatusCode::OK),
+            ("second edit", StatusCode::PRECONDITION_FAILED),
+        ] {
+            let req = test::TestRequest::put()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::IF_MATCH, etag.as_str())
+                .set_json(&asset_tags::MaybeAssetTag {
+                    name: String::from(*name),
+                    description: None,
+                    serial_number: String::from("conditional"),
+                    asset_id: None,
+                    deleted: false,
+                })
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+            if let Some(etag) = resp.headers().get(header::ETAG) {
+                updated_etag = etag.to_str().expect("Invalid ETag").to_string();
+            }
+        }
+        assert_ne!(etag, updated_etag);
+        let resp = asset_tags::AssetTag::find_by_id(asset_tag.id).expect("Failed to find tag");
+        assert_eq!("first edit", resp.name);
+
+        // Deletes are held to the same ETag
+        for (if_match, status) in &[
+            (etag.as_str(), StatusCode::PRECONDITION_FAILED),
+            (updated_etag.as_str(), StatusCode::OK),
+        ] {
+            let req = test::TestRequest::delete()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::IF_MATCH, *if_match)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+        }
+
+        asset_tags::AssetTag::purge(asset_tag.id).expect("Failed to purge asset tag");
+    }
+
+    #[actix_rt::test]
+    async fn test_merge_patch() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+            name: String::from("patched"),
+            description: Some(String::from("before")),
+            serial_number: String::from("patched"),
+            asset_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset tag");
+        let uri = format!("/asset_tags/{}", asset_tag.id);
+
+        // A PUT leaves optional fields it lacks as they are
+        let req = test::TestRequest::put()
+            .uri(uri.as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_payload(r#"{"name": "patched", "serial_number": "patched", "deleted": false}"#)
+            .header(header::CONTENT_TYPE, "application/json")
+            .to_request();
+        let resp: asset_tags::AssetTag = test::read_response_json(&mut app, req).await;
+        assert_eq!(asset_tag.description, resp.description);
+
+        // Fields left out of the patch keep their values, and null clears them
+        for (patch, description) in &[
+            (r#"{"description": "after"}"#, Some("after")),
+            (r#"{"description": null}"#, None),
+        ] {
+            let req = test::TestRequest::patch()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::CONTENT_TYPE, "application/merge-patch+json")
+                .set_payload(*patch)
+                .to_request();
+            let resp: asset_tags::AssetTag = test::read_response_json(&mut app, req).await;
+            assert_eq!(asset_tag.name, resp.name);
+            assert_eq!(asset_tag.serial_number, resp.serial_number);
+            assert_eq!(description.map(String::from), resp.description);
+        }
+
+        // Immutable fields and malformed patches are refused
+        for patch in &[
+            r#"{"id": 1}"#,
+            r#"{"created_at": "2021-01-01T00:00:00"}"#,
+            r#"{"deleted": true}"#,
+            r#"{"name": "#,
+        ] {
+            let req = test::TestRequest::patch()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_payload(*patch)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // As are patches that clear a required field or give one the wrong type
+        for patch in &[r#"{"name": null}"#, r#"{"name": 5}"#] {
+            let req = test::TestRequest::patch()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_payload(*patch)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let resp: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(resp["code"], "invalid_body");
+        }
+
+        // Patches honor If-Match like PUT does
+        let req = test::TestRequest::patch()
+            .uri(uri.as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::IF_MATCH, "\"stale\"")
+            .set_payload(r#"{"name": "stale"}"#)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+        let resp = asset_tags::AssetTag::find_by_id(asset_tag.id).expect("Failed to find tag");
+        assert_eq!(asset_tag.name, resp.name);
+        assert!(!resp.deleted);
+
+        // References in a patch are checked on the connection the write holds
+        let contact_event =
+            contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                asset_tag_id: asset_tag.id,
+                location_id: Some(INITIAL_LOCATION.id),
+                alert_id: None,
+                deleted: false,
+            })
+            .expect("Failed to create contact event");
+        let req = test::TestRequest::patch()
+            .uri(format!("/contact_events/{}", contact_event.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_payload(r#"{"asset_tag_id": 99999999, "location_id": 99999999}"#)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            resp["details"],
+            serde_json::json!([
+                { "field": "asset_tag_id", "message": "Asset tag 99999999 does not exist" },
+                { "field": "location_id", "message": "Location 99999999 does not exist" },
+            ])
+        );
+
+        contact_events::ContactEvent::purge(contact_event.id)
+            .expect("Failed to purge contact event");
+        asset_tags::AssetTag::purge(asset_tag.id).expect("Failed to purge asset tag");
+    }
+
+    #[actix_rt::test]
+    async fn test_include() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        let mut tag_ids = Vec::new();
+        let mut contact_event_ids = Vec::new();
+        for name in &["included", "also included"] {
+            let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+                name: String::from(*name),
+                description: None,
+                serial_number: String::from(*name),
+                asset_id: Some(asset.id),
+                deleted: false,
+            })
+            .expect("Failed to create asset tag");
+            let contact_event =
+                contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                    asset_tag_id: asset_tag.id,
+                    location_id: Some(INITIAL_LOCATION.id),
+                    alert_id: None,
+                    deleted: false,
+                })
+                .expect("Failed to create contact event");
+            tag_ids.push(asset_tag.id);
+            contact_event_ids.push(contact_event.id);
+        }
+        let comment = comments::Comment::create(comments::MaybeComment {
+            content: String::from("included"),
+            user_id: ADMIN_USER.id,
+            asset_tag_id: None,
+            asset_id: Some(asset.id),
+            location_id: None,
+            alert_id: None,
+            parent_id: None,
+        })
+        .expect("Failed to create comment");
+
+        // An asset page in one request
+        let req = test::TestRequest::get()
+            .uri(
+                format!(
+                    "/assets/id/{}?include=asset_tags,comments,last_contact_event.location",
+                    asset.id
+                )
+                .as_str(),
+            )
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(Some(asset.id), resp["id"].as_i64());
+        let ids: Vec<i64> = resp["asset_tags"]
+            .as_array()
+            .expect("Missing asset tags")
+            .iter()
+            .filter_map(|tag| tag["id"].as_i64())
+            .collect();
+        assert_eq!(tag_ids, ids);
+        assert_eq!(Some(comment.id), resp["comments"][0]["id"].as_i64());
+        assert_eq!(
+            Some(contact_event_ids[1]),
+            resp["last_contact_event"]["id"].as_i64()
+        );
+        assert_eq!(
+            Some(INITIAL_LOCATION.id),
+            resp["last_contact_event"]["location"]["id"].as_i64()
+        );
+
+        // Lists expand every resource, and nest as deep as asked
+        let req = test::TestRequest::get()
+            .uri("/asset_tags?include=asset.asset_tags,last_contact_event")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<serde_json::Value> = test::read_response_json(&mut app, req).await;
+        for resp in &resp {
+            if resp["id"].as_i64() == Some(tag_ids[0]) {
+                assert_eq!(Some(asset.id), resp["asset"]["id"].as_i64());
+                assert_eq!(
+                    2,
+                    resp["asset"]["asset_tags"].as_array().map_or(0, Vec::len)
+                );
+                assert_eq!(
+                    Some(contact_event_ids[0]),
+                    resp["last_contact_event"]["id"].as_i64()
+                );
+            } else if resp["asset_id"].is_null() {
+                assert!(resp["asset"].is_null());
+            }
+        }
+
+        let req = test::TestRequest::get()
+            .uri(format!("/alerts/id/{}?include=user", INITIAL_ALERT.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(Some(INITIAL_ALERT.user_id), resp["user"]["id"].as_i64());
+        assert!(resp["user"]["token"].is_null());
+
+        // Unknown associations are refused rather than ignored
+        for uri in &[
+            format!("/assets/id/{}?include=owner", asset.id),
+            format!("/assets/id/{}?include=comments.owner", asset.id),
+            String::from("/locations?include=rooms.location"),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        comments::Comment::purge(comment.id).expect("Failed to purge comment");
+        for id in &contact_event_ids {
+            contact_events::ContactEvent::purge(*id).expect("Failed to purge contact event");
+        }
+        for id in &tag_ids {
+            asset_tags::AssetTag::purge(*id).expect("Failed to purge asset tag");
+        }
+        assets::Asset::purge(asset.id).expect("Failed to purge asset");
+    }
+
+    #[actix_rt::test]
+    async fn test_graphql() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let asset = assets::Asset::create(assets::MaybeAsset {
+            asset_tag_id: None,
+            deleted: false,
+        })
+        .expect("Failed to create asset");
+        let mut tag_ids = Vec::new();
+        let mut contact_event_ids = Vec::new();
+        for name in &["graphed", "also graphed"] {
+            let asset_tag = asset_tags::AssetTag::create(asset_tags::MaybeAssetTag {
+                name: String::from(*name),
+                description: None,
+                serial_number: String::from(*name),
+                asset_id: Some(asset.id),
+                deleted: false,
+            })
+            .expect("Failed to create asset tag");
+            let contact_event =
+                contact_events::ContactEvent::create(contact_events::MaybeContactEvent {
+                    asset_tag_id: asset_tag.id,
+                    location_id: Some(INITIAL_LOCATION.id),
+                    alert_id: None,
+                    deleted: false,
+                })
+                .expect("Failed to create contact event");
+            tag_ids.push(asset_tag.id);
+            contact_event_ids.push(contact_event.id);
+        }
+
+        // An asset and everything around it in one query
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "query Asset($id: Int!) {
+                    asset(id: $id) {
+                        id
+                        assetTags {
+                            id
+                            asset { id }
+                            contactEvents { id location { id } }
+                        }
+                    }
+                    me { id }
+                }",
+                "variables": { "id": asset.id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["errors"].is_null());
+        let data = &resp["data"];
+        assert_eq!(Some(asset.id), data["asset"]["id"].as_i64());
+        assert_eq!(Some(ADMIN_USER.id), data["me"]["id"].as_i64());
+        let tags = data["asset"]["assetTags"]
+            .as_array()
+            .expect("Missing asset tags");
+        let ids: Vec<i64> = tags.iter().filter_map(|tag| tag["id"].as_i64()).collect();
+        assert_eq!(tag_ids, ids);
+        for (tag, contact_event_id) in tags.iter().zip(&contact_event_ids) {
+            assert_eq!(Some(asset.id), tag["asset"]["id"].as_i64());
+            assert_eq!(
+                Some(*contact_event_id),
+                tag["contactEvents"][0]["id"].as_i64()
+            );
+            assert_eq!(
+                Some(INITIAL_LOCATION.id),
+                tag["contactEvents"][0]["location"]["id"].as_i64()
+            );
+        }
+
+        // Mutations go through the same model functions and checks as REST
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "mutation Comment($id: Int!) {
+                    createComment(input: { content: \"graphed\", assetId: $id }) {
+                        id
+                        content
+                        user { id }
+                        asset { id }
+                    }
+                }",
+                "variables": { "id": asset.id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["errors"].is_null());
+        let comment = &resp["data"]["createComment"];
+        let comment_id = comment["id"].as_i64().expect("Missing comment id");
+        assert_eq!(Some("graphed"), comment["content"].as_str());
+        assert_eq!(Some(ADMIN_USER.id), comment["user"]["id"].as_i64());
+        assert_eq!(Some(asset.id), comment["asset"]["id"].as_i64());
+
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "mutation Delete($id: Int!) {
+                    deleteComment(id: $id, purge: true) { id }
+                }",
+                "variables": { "id": comment_id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            Some(403),
+            resp["errors"][0]["extensions"]["status"].as_i64()
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "mutation Delete($id: Int!) {
+                    deleteComment(id: $id) { id deleted }
+                }",
+                "variables": { "id": comment_id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["errors"].is_null());
+        assert_eq!(
+            Some(true),
+            resp["data"]["deleteComment"]["deleted"].as_bool()
+        );
+
+        // Soft-deleted resources drop out of associations
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "query Asset($id: Int!) { asset(id: $id) { comments { id } } }",
+                "variables": { "id": asset.id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            Some(0),
+            resp["data"]["asset"]["comments"].as_array().map(Vec::len)
+        );
+
+        // Lists take limit and offset like REST pages
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "query Asset($id: Int!) {
+                    asset(id: $id) { assetTags(limit: 1, offset: 1) { id } }
+                }",
+                "variables": { "id": asset.id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            Some(tag_ids[1]),
+            resp["data"]["asset"]["assetTags"][0]["id"].as_i64()
+        );
+        assert_eq!(
+            Some(1),
+            resp["data"]["asset"]["assetTags"].as_array().map(Vec::len)
+        );
+
+        // Limits past the maximum, and lists nested into too many rows, are refused
+        for (query, error) in &[
+            ("{ assets(limit: 501) { id } }", "The limit must be between"),
+            (
+                "{ assets(limit: 500) { assetTags(limit: 500) { id } } }",
+                "too complex",
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/graphql")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(&serde_json::json!({ "query": query }))
+                .to_request();
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            let message = resp["errors"][0]["message"].as_str().unwrap_or_default();
+            assert!(message.contains(error), "{}", message);
+            assert!(resp["data"]["assets"].is_null());
+        }
+
+        // Unknown fields are refused by the schema
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({ "query": "{ assets { owner } }" }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["errors"][0]["message"].is_string());
+
+        comments::Comment::purge(comment_id).expect("Failed to purge comment");
+        for id in &contact_event_ids {
+            contact_events::ContactEvent::purge(*id).expect("Failed to purge contact event");
+        }
+        for id in &tag_ids {
+            asset_tags::AssetTag::purge(*id).expect("Failed to purge asset tag");
+        }
+        assets::Asset::purge(asset.id).expect("Failed to purge asset");
+    }
+
+    #[actix_rt::test]
+    async fn test_openapi() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+
+        // The document and its UI need no token
+        let req = test::TestRequest::get().uri("/docs").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body = test::read_body(resp).await;
+        assert!(!String::from_utf8_lossy(&body).contains("unpkg"));
+
+        let req = test::TestRequest::get()
+            .uri("/docs/swagger-ui-bundle.js")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        let req = test::TestRequest::get()
+            .uri("/docs/missing.js")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 404);
+
+        let req = test::TestRequest::get().uri("/openapi.json").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        let doc: serde_json::Value = test::read_body_json(resp).await;
+        let paths = doc["paths"].as_object().expect("Failed to find paths");
+        assert_eq!(
+            doc["paths"]["/api/v1/health"]["get"]["security"],
+            serde_json::json!([])
+        );
+        assert_eq!(
+            doc["paths"]["/api/v2/alerts"]["get"]["security"],
+            serde_json::json!([{ "bearer": [] }])
+        );
+        assert_eq!(
+            doc["paths"]["/api/v1/alerts/id/{id}"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Alert"
+        );
+        assert!(doc["components"]["schemas"]["MaybeAlert"].is_object());
+
+        assert_eq!(
+            doc["paths"]["/api/v1/alerts"]["get"]["deprecated"],
+            serde_json::json!(true)
+        );
+        assert_eq!(
+            doc["paths"]["/api/v2/alerts"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Page_Alert"
+        );
+
+        // Every route registered by a module is documented under each version
+        // that serves it, and nothing else is
+        let mut routes = std::collections::BTreeSet::new();
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        for entry in std::fs::read_dir(src).expect("Failed to list modules") {
+            let dir = entry.expect("Failed to read module").path();
+            let source = match std::fs::read_to_string(dir.join("routes.rs")) {
+                Ok(source) => source,
+                Err(_) => continue,
+            };
+            let versions = if dir.ends_with("v2") {
+                vec![versioning::V2]
+            } else {
+                vec![versioning::V1, versioning::V2]
+            };
+            for line in source.lines() {
+                for method in &["get", "post", "put", "patch", "delete"] {
+                    let path = line
+                        .trim()
+                        .strip_prefix(&format!("#[{}(\"", method))
+                        .and_then(|rest| rest.strip_suffix("\")]"));
+                    if let Some(path) = path {
+                        let path = path.replace(":\\\\d+}", "}");
+                        for version in &versions {
+                            routes.insert((method.to_string(), format!("{}{}", version, path)));
+                        }
+                    }
+                }
+            }
+        }
+        let mut documented = std::collections::BTreeSet::new();
+        let mut operation_ids = std::collections::BTreeSet::new();
+        for (path, item) in paths {
+            for (method, operation) in item.as_object().unwrap() {
+                documented.insert((method.clone(), path.clone()));
+                operation_ids.insert(operation["operationId"].as_str().unwrap().to_string());
+            }
+        }
+        let undocumented: Vec<_> = routes.difference(&documented).collect();
+        assert!(undocumented.is_empty(), "Undocumented: {:?}", undocumented);
+        assert_eq!(documented, routes);
+        assert_eq!(operation_ids.len(), routes.len());
+    }
+
+    #[actix_rt::test]
+    async fn test_versioning() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+        let existing = alerts::Alert::find_all()
+            .expect("Failed to find alerts")
+            .len();
+        let mut alerts = Vec::new();
+        for reason in &["first", "second", "third"] {
+            let alert = alerts::Alert::create(alerts::MaybeAlert {
+                message: None,
+                reason: String::from(*reason),
+                user_id: ADMIN_USER.id,
+            })
+            .expect("Failed to create alert");
+            alerts.push(alert);
+        }
+
+        // v1 is served at the root and under /api/v1, and both are deprecated
+        for uri in &["/alerts", "/api/v1/alerts"] {
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), 200);
+            assert_eq!(
+                resp.headers().get("Deprecation").unwrap(),
+                versioning::DEPRECATION
+            );
+            assert_eq!(resp.headers().get("Sunset").unwrap(), versioning::SUNSET);
+            let resp: Vec<alerts::Alert> = test::read_body_json(resp).await;
+            assert_eq!(resp.len(), existing + 3);
+        }
+
+        // Public routes stay public under a version
+        let req = test::TestRequest::get().uri("/api/v1/health").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+
+        // v2 pages listings
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/v2/alerts?limit=2&offset={}", existing + 1))
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("Deprecation").is_none());
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            resp["pagination"],
+            serde_json::json!({ "limit": 2, "offset": existing + 1, "total": existing + 3 })
+        );
+        assert_eq!(resp["data"][0]["id"], alerts[1].id);
+        assert_eq!(resp["data"][1]["id"], alerts[2].id);
+
+        let req = test::TestRequest::get()
+            .uri("/api/v2/alerts?limit=0")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 400);
+
+        // and has single resources at /{resource}/{id}
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/v2/alerts/{}", alerts[0].id))
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        let resp: alerts::Alert = test::read_body_json(resp).await;
+        assert_eq!(resp.id, alerts[0].id);
+
+        // while sharing the routes it doesn't change
+        let req = test::TestRequest::get()
+            .uri("/api/v2/alerts/all")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        let resp: Vec<alerts::Alert> = test::read_body_json(resp).await;
+        assert!(alerts
+            .iter()
+            .all(|alert| resp.iter().any(|other| other.id == alert.id)));
+
+        for alert in alerts {
+            alerts::Alert::purge(alert.id).expect("Failed to purge alert");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_error_responses() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+
+        // Errors have a code, and responses return the request id they were given
+        let req = test::TestRequest::get()
+            .uri("/asset_scanners/id/0")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(error_handler::REQUEST_ID, "test-request")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 404);
+        assert_eq!(
+            resp.headers().get(error_handler::REQUEST_ID).unwrap(),
+            "test-request"
+        );
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            resp,
+            serde_json::json!({
+                "code": "not_found",
+                "message": "The record is not found",
+                "request_id": "test-request",
+            })
+        );
+
+        // Otherwise each response gets a new one
+        let req = test::TestRequest::get().uri("/health").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().contains_key(error_handler::REQUEST_ID));
+
+        // A token that doesn't decode is the client's mistake, not the server's
+        let req = test::TestRequest::get()
+            .uri("/asset_scanners")
+            .header(header::AUTHORIZATION, "Bearer not-base64!")
+            .to_request();
+        let error = app.call(req).await.expect_err("Expected an error");
+        let resp = error.as_response_error().error_response();
+        assert_eq!(resp.status(), 400);
+        let resp: serde_json::Value = match resp.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => {
+                serde_json::from_slice(bytes).expect("Invalid JSON")
+            }
+            _ => panic!("Expected a JSON body"),
+        };
+        assert_eq!(resp["code"], "malformed_token");
+        assert!(resp["request_id"].is_string());
+
+        // Database errors name the constraint they violate. They would abort the
+        // transaction the tests share, so they are made in a savepoint.
+        let conn = db::connection().expect("Failed to get db connection");
+        let violate = |sql: &str| {
+            let error = conn
+                .transaction::<_, diesel::result::Error, _>(|| {
+                    diesel::sql_query(sql).execute(&conn)
+                })
+                .expect_err("Expected a database error");
+            error_handler::CustomError::from(error).body(None)
+        };
+        let resp = violate("INSERT INTO asset_scanners (name) VALUES ('Twin'), ('Twin')");
+        assert_eq!(resp.code, "already_exists");
+        assert_eq!(resp.constraint.as_deref(), Some("asset_scanners_name_key"));
+        let resp = violate("INSERT INTO alerts (reason, user_id) VALUES ('Missing user', 0)");
+        assert_eq!(resp.code, "invalid_reference");
+        assert_eq!(resp.constraint.as_deref(), Some("alerts_user_id_fkey"));
+        let resp = violate(&format!("DELETE FROM users WHERE id = {}", ADMIN_USER.id));
+        assert_eq!(resp.code, "still_referenced");
+        drop(conn);
+
+        // Extractors reject requests with the same bodies
+        for (payload, status, code) in &[
+            ("{", 400, "malformed_json"),
+            (r#"{"name": 1}"#, 422, "invalid_body"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/asset_scanners")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_payload(*payload)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), *status);
+            let resp: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(resp["code"], *code);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/api/v2/asset_scanners?limit=many")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), 400);
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(resp["code"], "invalid_query");
+    }
+
+    #[actix_rt::test]
+    async fn test_validation() {
+        let _serial = setup().await;
+
+        let mut app = test::init_service(AppFactory!()()).await;
+
+        // Every field that is out of bounds is reported at once
+        let req = test::TestRequest::post()
+            .uri("/asset_tags")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "name": "",
+                "description": null,
+                "serial_number": "S".repeat(256),
+                "asset_id": -1,
+                "deleted": false,
+            }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(resp["code"], "validation_failed");
+        assert_eq!(
+            resp["details"],
+            serde_json::json!([
+                { "field": "asset_id", "message": "must be at least 1" },
+                { "field": "name", "message": "must be 1 to 255 characters long" },
+                { "field": "serial_number", "message": "must be 1 to 255 characters long" },
+            ])
+        );
+
+        // References are checked before the database sees them, nested fields by their path
+        for (uri, body, field, message) in &[
+            (
+                "/alerts",
+                serde_json::json!({ "reason": "Missing user", "user_id": 0 }),
+                "user_id",
+                "User 0 does not exist",
+            ),
+            (
+                "/contact_events",
+                serde_json::json!({
+                    "asset_tag_id": INITIAL_ASSET_TAG.id,
+                    "location_id": 0,
+                    "alert_id": null,
+                    "deleted": false,
+                }),
+                "location_id",
+                "Location 0 does not exist",
+            ),
+            (
+                "/reservations",
+                serde_json::json!({
+                    "asset_id": INITIAL_ASSET.id,
+                    "user_id": ADMIN_USER.id,
+                    "starts_at": "2030-01-01T09:00:00",
+                    "ends_at": "2030-01-01T10:00:00",
+                    "note": null,
+                    "recurrence": { "frequency": "daily", "count": 0 },
+                }),
+                "recurrence.count",
+                "must be between 1 and 366",
+            ),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", uri);
+            let resp: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(
+                resp["details"],
+                serde_json::json!([{ "field": field, "message": message }]),
+                "{}",
+                uri
+            );
+        }
+
+        // Patches are held to the same limits
+        let req = test::TestRequest::patch()
+            .uri(&format!("/asset_tags/{}", INITIAL_ASSET_TAG.id))
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/merge-patch+json")
+            .set_payload(r#"{"name": ""}"#)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // And so are mutations
+        let req = test::TestRequest::post()
+            .uri("/graphql")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&serde_json::json!({
+                "query": "mutation Alert($userId: Int!) { createAlert(input: { reason: \"\", userId: $userId }) { id } }",
+                "variables": { "userId": ADMIN_USER.id },
+            }))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let extensions = &resp["errors"][0]["extensions"];
+        assert_eq!(extensions["status"], 422);
+        assert_eq!(extensions["code"], "validation_failed");
+        assert_eq!(extensions["details"][0]["field"], "reason");
+    }
+
+    #[actix_rt::test]
+    async fn test_contact_event_resource() {
+        let _serial = setup().await;
+
+        // Find all contact_events, there should be none
+        let mut app = test::init_service(AppFactory!()()).await;
+        let req = test::TestRequest::get()
+            .uri("/contact_events")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        /* Create a contact_event with
+            INITIAL ASSET TAG as asset tag association
+            INITIAL LOCATION as location association
+            INITIAL ALERT as alert association
+        */
+        let value = contact_events::MaybeContactEvent {
+            asset_tag_id: INITIAL_ASSET_TAG.id,
+            location_id: Some(INITIAL_LOCATION.id),
+            alert_id: Some(INITIAL_ALERT.id),
+            deleted: false,
+        };
+        let payload = serde_json::to_string(&value).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/contact_events")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
+        assert_eq!(value.asset_tag_id, resp.asset_tag_id);
+        assert_eq!(value.location_id, Some(resp.location_id));
+        assert_eq!(value.alert_id, resp.alert_id);
+        assert_eq!(value.deleted, resp.deleted);
+
+        // Find all contact_events, it should be the one we just created
+        let req = test::TestRequest::get()
+            .uri("/contact_events")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+        assert_eq!(value.asset_tag_id, resp[0].asset_tag_id);
+        assert_eq!(value.location_id, Some(resp[0].location_id));
+        assert_eq!(value.alert_id, resp[0].alert_id);
+        assert_eq!(value.deleted, resp[0].deleted);
+
+        // Find contact_event by id
+        let id = resp[0].id;
+
+        let req = test::TestRequest::get()
+            .uri(format!("/contact_events/id/{}", id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
+        assert_eq!(id, resp.id);
+        assert_eq!(value.asset_tag_id, resp.asset_tag_id);
+        assert_eq!(value.location_id, Some(resp.location_id));
+        assert_eq!(value.alert_id, resp.alert_id);
+        assert_eq!(value.deleted, resp.deleted);
+
+        // Update contact_event by id
+        let value_updated = contact_events::MaybeContactEvent {
+            asset_tag_id: INITIAL_ASSET_TAG.id,
+            location_id: Some(INITIAL_LOCATION.id),
+            alert_id: Some(INITIAL_ALERT.id),
+            deleted: false,
+        };
+        let payload_updated = serde_json::to_string(&value_updated).expect("Invalid value");
+
+        let req = test::TestRequest::put()
+            .uri(format!("/contact_events/{}", id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload_updated)
+            .to_request();
+        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
+        assert_eq!(value_updated.asset_tag_id, resp.asset_tag_id);
+        assert_eq!(value_updated.location_id, Some(resp.location_id));
+        assert_eq!(value_updated.alert_id, resp.alert_id);
+        assert_eq!(value_updated.deleted, resp.deleted);
+
+        // Find contact_event by id, should be the updated one
+        let req = test::TestRequest::get()
+            .uri(format!("/contact_events/id/{}", id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
+        assert_eq!(value_updated.asset_tag_id, resp.asset_tag_id);
+        assert_eq!(value_updated.location_id, Some(resp.location_id));
+        assert_eq!(value_updated.alert_id, resp.alert_id);
         assert_eq!(value_updated.deleted, resp.deleted);
 
-        // Find contact_event by id, should be the updated one
-        let req = test::TestRequest::get()
-            .uri(format!("/contact_events/id/{}", id).as_str())
+        // Delete the contact_event by id
+        let req = test::TestRequest::delete()
+            .uri(format!("/contact_events/{}", id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.deleted, true);
+
+        // Find all contact_events, there should be none
+        let req = test::TestRequest::get()
+            .uri("/contact_events")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        // Find all deleted contact_events, there should be the deleted one
+        let req = test::TestRequest::get()
+            .uri("/contact_events/deleted")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+        assert_eq!(id, resp[0].id);
+        assert_eq!(resp[0].deleted, true);
+
+        // Find all contact_events including deleted ones, there should be 1
+        let req = test::TestRequest::get()
+            .uri("/contact_events/all")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_loan_resource() {
+        let _serial = setup().await;
+
+        // Find all loans, there should be none
+        let mut app = test::init_service(AppFactory!()()).await;
+        let req = test::TestRequest::get()
+            .uri("/loans")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<loans::Loan> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        /* Check out a loan with
+            INITIAL ASSET as asset association
+            ADMIN USER as user association
+            INITIAL LOCATION as destination location association
+        */
+        let value = loans::MaybeLoan {
+            asset_id: INITIAL_ASSET.id,
+            user_id: ADMIN_USER.id,
+            location_id: Some(INITIAL_LOCATION.id),
+            room_id: None,
+            due_at: chrono::Utc::today().naive_utc().and_hms(12, 0, 0) + chrono::Duration::days(7),
+        };
+        let payload = serde_json::to_string(&value).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/loans")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload.clone())
+            .to_request();
+        let resp: loans::Loan = test::read_response_json(&mut app, req).await;
+        assert_eq!(value.asset_id, resp.asset_id);
+        assert_eq!(value.user_id, resp.user_id);
+        assert_eq!(value.location_id, resp.location_id);
+        assert_eq!(value.room_id, resp.room_id);
+        assert_eq!(value.due_at, resp.due_at);
+        assert_eq!(None, resp.returned_at);
+
+        let id = resp.id;
+
+        // Fail to check out the same asset while it is on loan
+        let req = test::TestRequest::post()
+            .uri("/loans")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // Find loans by user and by asset, it should be the one we just created
+        for uri in &[
+            format!("/users/{}/loans", ADMIN_USER.id),
+            format!("/assets/{}/loans", INITIAL_ASSET.id),
+            String::from("/loans/open"),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<loans::Loan> = test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(id, resp[0].id);
+        }
+
+        // Find overdue loans, there should be none
+        let req = test::TestRequest::get()
+            .uri("/loans/overdue")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<loans::Loan> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        // Check in the loan
+        let req = test::TestRequest::post()
+            .uri(format!("/loans/{}/checkin", id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: loans::Loan = test::read_response_json(&mut app, req).await;
+        assert_eq!(id, resp.id);
+        assert!(resp.returned_at.is_some());
+
+        // Fail to check in the loan twice
+        let req = test::TestRequest::post()
+            .uri(format!("/loans/{}/checkin", id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
-        assert_eq!(value_updated.asset_tag_id, resp.asset_tag_id);
-        assert_eq!(value_updated.location_id, resp.location_id);
-        assert_eq!(value_updated.alert_id, resp.alert_id);
-        assert_eq!(value_updated.deleted, resp.deleted);
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
-        // Delete the contact_event by id
-        let req = test::TestRequest::delete()
-            .uri(format!("/contact_events/{}", id).as_str())
+        // Check out the asset again, already past its due date
+        let overdue_value = loans::MaybeLoan {
+            asset_id: INITIAL_ASSET.id,
+            user_id: ADMIN_USER.id,
+            location_id: None,
+            room_id: None,
+            due_at: chrono::Utc::today().naive_utc().and_hms(12, 0, 0) - chrono::Duration::days(1),
+        };
+        let payload = serde_json::to_string(&overdue_value).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/loans")
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
             .to_request();
-        let resp: contact_events::ContactEvent = test::read_response_json(&mut app, req).await;
-        assert_eq!(resp.deleted, true);
+        let resp: loans::Loan = test::read_response_json(&mut app, req).await;
+        let overdue_id = resp.id;
 
-        // Find all contact_events, there should be none
+        // Find loans by asset, it should be both loans
         let req = test::TestRequest::get()
-            .uri("/contact_events")
+            .uri(format!("/assets/{}/loans", INITIAL_ASSET.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        let resp: Vec<loans::Loan> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 2);
+
+        // Find overdue loans, it should be the one we just created
+        let req = test::TestRequest::get()
+            .uri("/loans/overdue")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<loans::Loan> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 1);
+        assert_eq!(overdue_id, resp[0].id);
+        assert_eq!(None, resp[0].alert_id);
+
+        // Raise an alert for the overdue loan only once
+        let resp = loans::Loan::raise_overdue_alerts().expect("Failed to raise alerts");
+        assert_eq!(resp.len(), 1);
+        assert_eq!(overdue_id, resp[0].id);
+        let alert_id = resp[0].alert_id.expect("Missing overdue alert");
+        let resp = loans::Loan::raise_overdue_alerts().expect("Failed to raise alerts");
         assert_eq!(resp.len(), 0);
 
-        // Find all deleted contact_events, there should be the deleted one
         let req = test::TestRequest::get()
-            .uri("/contact_events/deleted")
+            .uri(format!("/alerts/id/{}", alert_id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        let resp: alerts::Alert = test::read_response_json(&mut app, req).await;
+        assert_eq!(ADMIN_USER.id, resp.user_id);
+        assert_eq!(loans::OVERDUE_REASON, resp.reason);
+
+        // Delete the loans, then purge the alert
+        for uri in &[format!("/loans/{}", id), format!("/loans/{}", overdue_id)] {
+            let req = test::TestRequest::delete()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: usize = test::read_response_json(&mut app, req).await;
+            assert_eq!(1, resp);
+        }
+        alerts::Alert::purge(alert_id).expect("Failed to purge alert");
+    }
+
+    #[actix_rt::test]
+    async fn test_reservation_resource() {
+        let _serial = setup().await;
+
+        // Find all reservations, there should be none
+        let mut app = test::init_service(AppFactory!()()).await;
+        let req = test::TestRequest::get()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<reservations::Reservation> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        // Create a reservation of INITIAL ASSET for ADMIN USER
+        let starts_at = chrono::NaiveDate::from_ymd(2030, 1, 7).and_hms(9, 0, 0);
+        let value = reservations::MaybeReservation {
+            asset_id: INITIAL_ASSET.id,
+            user_id: ADMIN_USER.id,
+            starts_at,
+            ends_at: starts_at + chrono::Duration::hours(2),
+            note: Some(String::from("Calibration, then imaging")),
+        };
+        let payload = serde_json::to_string(&value).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp: Vec<reservations::Reservation> = test::read_response_json(&mut app, req).await;
         assert_eq!(resp.len(), 1);
-        assert_eq!(id, resp[0].id);
-        assert_eq!(resp[0].deleted, true);
+        assert_eq!(value.asset_id, resp[0].asset_id);
+        assert_eq!(value.user_id, resp[0].user_id);
+        assert_eq!(value.starts_at, resp[0].starts_at);
+        assert_eq!(value.ends_at, resp[0].ends_at);
+        assert_eq!(value.note, resp[0].note);
+        assert_eq!(None, resp[0].series_id);
 
-        // Find all contact_events including deleted ones, there should be 1
+        let id = resp[0].id;
+
+        // Fail to create an overlapping reservation
+        let overlapping = reservations::MaybeReservation {
+            starts_at: starts_at + chrono::Duration::hours(1),
+            ends_at: starts_at + chrono::Duration::hours(3),
+            note: None,
+            ..value.clone()
+        };
+        let payload = serde_json::to_string(&overlapping).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // Fail to create a weekly reservation whose third occurrence overlaps
+        let recurring = reservations::NewReservation {
+            reservation: reservations::MaybeReservation {
+                starts_at: starts_at - chrono::Duration::weeks(2),
+                ends_at: starts_at - chrono::Duration::weeks(2) + chrono::Duration::hours(1),
+                note: None,
+                ..value.clone()
+            },
+            recurrence: Some(reservations::Recurrence {
+                frequency: reservations::Frequency::Weekly,
+                count: 3,
+            }),
+        };
+        let payload = serde_json::to_string(&recurring).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        // A series running past the last representable date is refused
+        let late = chrono::NaiveDate::from_ymd(262_143, 12, 1).and_hms(9, 0, 0);
+        let overflowing = reservations::NewReservation {
+            reservation: reservations::MaybeReservation {
+                starts_at: late,
+                ends_at: late + chrono::Duration::hours(1),
+                ..value.clone()
+            },
+            recurrence: Some(reservations::Recurrence {
+                frequency: reservations::Frequency::Weekly,
+                count: 10,
+            }),
+        };
+        let req = test::TestRequest::post()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&overflowing)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Create a daily reservation that starts right after the first one ends
+        let recurring = reservations::NewReservation {
+            reservation: reservations::MaybeReservation {
+                starts_at: value.ends_at,
+                ends_at: value.ends_at + chrono::Duration::hours(1),
+                note: None,
+                ..value.clone()
+            },
+            recurrence: Some(reservations::Recurrence {
+                frequency: reservations::Frequency::Daily,
+                count: 3,
+            }),
+        };
+        let payload = serde_json::to_string(&recurring).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/reservations")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp: Vec<reservations::Reservation> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 3);
+        let series_id = resp[0].id;
+        for (n, reservation) in resp.iter().enumerate() {
+            let offset = chrono::Duration::days(n as i64);
+            assert_eq!(Some(series_id), reservation.series_id);
+            assert_eq!(
+                recurring.reservation.starts_at + offset,
+                reservation.starts_at
+            );
+            assert_eq!(recurring.reservation.ends_at + offset, reservation.ends_at);
+        }
+
+        // Find reservations by asset and by user, it should be all four
+        for uri in &[
+            format!("/assets/{}/reservations", INITIAL_ASSET.id),
+            format!("/users/{}/reservations", ADMIN_USER.id),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<reservations::Reservation> =
+                test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 4);
+            assert_eq!(id, resp[0].id);
+        }
+
+        // Fail to subscribe with the API token in the query string
         let req = test::TestRequest::get()
-            .uri("/contact_events/all")
+            .uri(
+                format!(
+                    "/assets/{}/reservations.ics?token={}",
+                    INITIAL_ASSET.id,
+                    ADMIN_USER
+                        .token
+                        .replace('+', "%2B")
+                        .replace('/', "%2F")
+                        .replace('=', "%3D")
+                )
+                .as_str(),
+            )
+            .to_request();
+        let err = app
+            .call(req)
+            .await
+            .expect_err("API token accepted in a feed URL");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Subscribe to the asset calendar with a feed token in the query string
+        let req = test::TestRequest::post()
+            .uri(format!("/users/{}/feed_token", ADMIN_USER.id).as_str())
             .header(
                 header::AUTHORIZATION,
                 format!("Bearer {}", ADMIN_USER.token),
             )
             .to_request();
-        let resp: Vec<contact_events::ContactEvent> = test::read_response_json(&mut app, req).await;
+        let feed_token: users::FeedToken = test::read_response_json(&mut app, req).await;
+        assert_eq!(feed_token.user_id, ADMIN_USER.id);
+        let feed_uri = format!(
+            "/assets/{}/reservations.ics?token={}",
+            INITIAL_ASSET.id, feed_token.token
+        );
+        let req = test::TestRequest::get().uri(feed_uri.as_str()).to_request();
+        let resp = test::read_response(&mut app, req).await;
+        let calendar = String::from_utf8(resp.to_vec()).expect("Invalid calendar");
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(calendar.matches("BEGIN:VEVENT").count(), 4);
+        assert!(calendar.contains(&format!("UID:reservation-{}@qsib-asset", id)));
+        assert!(calendar.contains("DTSTART:20300107T090000Z"));
+        assert!(calendar.contains("DESCRIPTION:Calibration\\, then imaging"));
+
+        // Fail to read anything else with the feed token
+        let req = test::TestRequest::get()
+            .uri(format!("/assets?token={}", feed_token.token).as_str())
+            .to_request();
+        let err = app
+            .call(req)
+            .await
+            .expect_err("Feed token accepted outside a feed");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Fail to subscribe once the feed token is revoked
+        let req = test::TestRequest::delete()
+            .uri(format!("/users/{}/feed_token", ADMIN_USER.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let revoked: usize = test::read_response_json(&mut app, req).await;
+        assert_eq!(revoked, 1);
+        let req = test::TestRequest::get().uri(feed_uri.as_str()).to_request();
+        let err = app
+            .call(req)
+            .await
+            .expect_err("Revoked feed token accepted");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Fail to read the user calendar without a token
+        let req = test::TestRequest::get()
+            .uri(format!("/users/{}/reservations.ics", ADMIN_USER.id).as_str())
+            .to_request();
+        let err = app
+            .call(req)
+            .await
+            .expect_err("Missing authentication error");
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Delete the series and the first reservation
+        for (uri, count) in &[
+            (format!("/reservations/series/{}", series_id), 3),
+            (format!("/reservations/{}", id), 1),
+        ] {
+            let req = test::TestRequest::delete()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: usize = test::read_response_json(&mut app, req).await;
+            assert_eq!(*count, resp);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_maintenance_resource() {
+        let _serial = setup().await;
+
+        // Find all maintenance_plans, there should be none
+        let mut app = test::init_service(AppFactory!()()).await;
+        let req = test::TestRequest::get()
+            .uri("/maintenance_plans")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<maintenance_plans::MaintenancePlan> =
+            test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        /* Create maintenance_plans with
+            INITIAL ASSET as asset association
+            ADMIN USER as user association
+        */
+        let today = chrono::Utc::today().naive_utc().and_hms(12, 0, 0);
+        let values = [
+            maintenance_plans::MaybeMaintenancePlan {
+                asset_id: INITIAL_ASSET.id,
+                user_id: ADMIN_USER.id,
+                name: String::from("Calibration"),
+                interval_days: 365,
+                next_due_at: today + chrono::Duration::days(10),
+            },
+            maintenance_plans::MaybeMaintenancePlan {
+                asset_id: INITIAL_ASSET.id,
+                user_id: ADMIN_USER.id,
+                name: String::from("Battery replacement"),
+                interval_days: 90,
+                next_due_at: today - chrono::Duration::days(1),
+            },
+        ];
+        let mut ids = vec![];
+        for value in values.iter() {
+            let payload = serde_json::to_string(&value).expect("Invalid value");
+
+            let req = test::TestRequest::post()
+                .uri("/maintenance_plans")
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_payload(payload)
+                .to_request();
+            let resp: maintenance_plans::MaintenancePlan =
+                test::read_response_json(&mut app, req).await;
+            assert_eq!(value.asset_id, resp.asset_id);
+            assert_eq!(value.user_id, resp.user_id);
+            assert_eq!(value.name, resp.name);
+            assert_eq!(value.interval_days, resp.interval_days);
+            assert_eq!(value.next_due_at, resp.next_due_at);
+            assert_eq!(None, resp.alert_id);
+            ids.push(resp.id);
+        }
+        let (calibration_id, battery_id) = (ids[0], ids[1]);
+
+        // Find assets due for maintenance within a window, overdue ones first
+        for (within, expected) in &[
+            ("30d", vec![battery_id, calibration_id]),
+            ("5d", vec![battery_id]),
+            ("2w", vec![battery_id, calibration_id]),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(format!("/assets/due_for_maintenance?within={}", within).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<maintenance_plans::MaintenancePlan> =
+                test::read_response_json(&mut app, req).await;
+            let resp: Vec<i64> = resp.iter().map(|plan| plan.id).collect();
+            assert_eq!(expected, &resp);
+        }
+
+        // Fail to find assets due for maintenance with an invalid or too wide window
+        for within in &["soon", "36501d", "999999999999999d", "9223372036854775807w"] {
+            let req = test::TestRequest::get()
+                .uri(format!("/assets/due_for_maintenance?within={}", within).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // Raise an alert for the overdue plan only once
+        let resp = maintenance_plans::MaintenancePlan::raise_overdue_alerts()
+            .expect("Failed to raise alerts");
         assert_eq!(resp.len(), 1);
+        assert_eq!(battery_id, resp[0].id);
+        let alert_id = resp[0].alert_id.expect("Missing overdue alert");
+        let resp = maintenance_plans::MaintenancePlan::raise_overdue_alerts()
+            .expect("Failed to raise alerts");
+        assert_eq!(resp.len(), 0);
+
+        // A record too late for the plan to fall due again is refused
+        let value = maintenance_records::MaybeMaintenanceRecord {
+            asset_id: INITIAL_ASSET.id,
+            maintenance_plan_id: Some(battery_id),
+            user_id: ADMIN_USER.id,
+            performed_at: chrono::NaiveDate::from_ymd(262_143, 12, 31).and_hms(0, 0, 0),
+            notes: None,
+            certificate: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/maintenance_records")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .set_json(&value)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Record the overdue maintenance
+        let value = maintenance_records::MaybeMaintenanceRecord {
+            asset_id: INITIAL_ASSET.id,
+            maintenance_plan_id: Some(battery_id),
+            user_id: ADMIN_USER.id,
+            performed_at: today,
+            notes: Some(String::from("Replaced both cells")),
+            certificate: Some(String::from("https://example.com/certificates/1.pdf")),
+        };
+        let payload = serde_json::to_string(&value).expect("Invalid value");
+
+        let req = test::TestRequest::post()
+            .uri("/maintenance_records")
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload)
+            .to_request();
+        let resp: maintenance_records::MaintenanceRecord =
+            test::read_response_json(&mut app, req).await;
+        assert_eq!(value.asset_id, resp.asset_id);
+        assert_eq!(value.maintenance_plan_id, resp.maintenance_plan_id);
+        assert_eq!(value.user_id, resp.user_id);
+        assert_eq!(value.performed_at, resp.performed_at);
+        assert_eq!(value.notes, resp.notes);
+        assert_eq!(value.certificate, resp.certificate);
+        let record_id = resp.id;
+
+        // The plan is due again after its interval, without an alert
+        let req = test::TestRequest::get()
+            .uri(format!("/maintenance_plans/id/{}", battery_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: maintenance_plans::MaintenancePlan =
+            test::read_response_json(&mut app, req).await;
+        assert_eq!(today + chrono::Duration::days(90), resp.next_due_at);
+        assert_eq!(None, resp.alert_id);
+
+        // Find maintenance_records by asset and by plan, it should be the one we just created
+        for uri in &[
+            format!("/maintenance_records/asset/{}", INITIAL_ASSET.id),
+            format!("/maintenance_records/maintenance_plan/{}", battery_id),
+        ] {
+            let req = test::TestRequest::get()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: Vec<maintenance_records::MaintenanceRecord> =
+                test::read_response_json(&mut app, req).await;
+            assert_eq!(resp.len(), 1);
+            assert_eq!(record_id, resp[0].id);
+        }
+
+        // Delete the record and the plans, then purge the alert
+        for uri in &[
+            format!("/maintenance_records/{}", record_id),
+            format!("/maintenance_plans/{}", calibration_id),
+            format!("/maintenance_plans/{}", battery_id),
+        ] {
+            let req = test::TestRequest::delete()
+                .uri(uri.as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: usize = test::read_response_json(&mut app, req).await;
+            assert_eq!(1, resp);
+        }
+        alerts::Alert::purge(alert_id).expect("Failed to purge alert");
+    }
+
+    const BOUNDARY: &str = "qsib-asset-test-boundary";
+
+    fn multipart_body(filename: &str, content_type: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+            BOUNDARY, filename, content_type
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    #[actix_rt::test]
+    async fn test_attachment_resource() {
+        let _serial = setup().await;
+
+        // Find attachments of the INITIAL ASSET, there should be none
+        let mut app = test::init_service(AppFactory!()()).await;
+        let req = test::TestRequest::get()
+            .uri(format!("/assets/{}/attachments", INITIAL_ASSET.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<attachments::Attachment> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 0);
+
+        // Upload a manual and a photo of the INITIAL ASSET as the ADMIN USER
+        let manual = b"Turn it off and on again".to_vec();
+        let mut photo = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::new(640, 320))
+            .write_to(&mut photo, image::ImageOutputFormat::Png)
+            .expect("Failed to encode photo");
+        let mut ids = Vec::new();
+        for (filename, content_type, data) in &[
+            ("manual.txt", "text/plain", &manual),
+            ("photo.png", "image/png", &photo),
+        ] {
+            let req = test::TestRequest::post()
+                .uri(format!("/assets/{}/attachments", INITIAL_ASSET.id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .set_payload(multipart_body(filename, content_type, data))
+                .to_request();
+            let resp: attachments::Attachment = test::read_response_json(&mut app, req).await;
+            assert_eq!(INITIAL_ASSET.id, resp.asset_id);
+            assert_eq!(ADMIN_USER.id, resp.user_id);
+            assert_eq!(*filename, resp.filename);
+            assert_eq!(*content_type, resp.content_type);
+            assert_eq!(data.len() as i64, resp.size);
+            assert_eq!(
+                format!("{:x}", <sha2::Sha256 as sha2::Digest>::digest(data)),
+                resp.sha256
+            );
+            ids.push(resp.id);
+        }
+        let (manual_id, photo_id) = (ids[0], ids[1]);
+
+        // Download the manual as it was uploaded
+        let req = test::TestRequest::get()
+            .uri(format!("/attachments/{}/download", manual_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain"
+        );
+        assert_eq!(
+            resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"manual.txt\""
+        );
+        assert_eq!(test::read_body(resp).await, manual);
+
+        // Only the photo has a thumbnail, which fits in the thumbnail size
+        let req = test::TestRequest::get()
+            .uri(format!("/attachments/{}/thumbnail", manual_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let req = test::TestRequest::get()
+            .uri(format!("/attachments/{}/thumbnail", photo_id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/png"
+        );
+        let thumbnail =
+            image::load_from_memory(&test::read_body(resp).await).expect("Invalid thumbnail");
+        assert_eq!((256, 128), image::GenericImageView::dimensions(&thumbnail));
+
+        // Uploads over the size limit are refused
+        let req = test::TestRequest::post()
+            .uri(format!("/assets/{}/attachments", INITIAL_ASSET.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .set_payload(multipart_body(
+                "huge.bin",
+                "application/octet-stream",
+                &vec![0; *attachments::storage::MAX_BYTES + 1],
+            ))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Images claiming more pixels than allowed are stored without decoding them
+        let mut bomb = b"GIF89a".to_vec();
+        bomb.extend_from_slice(&[
+            0x60, 0xEA, 0x60, 0xEA, 0x80, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF,
+        ]);
+        bomb.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x44, 1, 0, 0x3B]);
+        let req = test::TestRequest::post()
+            .uri(format!("/assets/{}/attachments", INITIAL_ASSET.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .set_payload(multipart_body("bomb.gif", "image/gif", &bomb))
+            .to_request();
+        let resp: attachments::Attachment = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.thumbnail_key, None);
+        ids.push(resp.id);
+
+        // Find attachments of the INITIAL ASSET, most recent first
+        let req = test::TestRequest::get()
+            .uri(format!("/assets/{}/attachments", INITIAL_ASSET.id).as_str())
+            .header(
+                header::AUTHORIZATION,
+                format!("Bearer {}", ADMIN_USER.token),
+            )
+            .to_request();
+        let resp: Vec<attachments::Attachment> = test::read_response_json(&mut app, req).await;
+        assert_eq!(resp.len(), 3);
+
+        // Delete the attachments along with their contents
+        for id in &ids {
+            let req = test::TestRequest::delete()
+                .uri(format!("/attachments/{}", id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp: usize = test::read_response_json(&mut app, req).await;
+            assert_eq!(1, resp);
+
+            let req = test::TestRequest::get()
+                .uri(format!("/attachments/{}/download", id).as_str())
+                .header(
+                    header::AUTHORIZATION,
+                    format!("Bearer {}", ADMIN_USER.token),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
     }
 }
@@ -0,0 +1,241 @@
+use crate::alerts::{Alert, MaybeAlert};
+use crate::assets::Asset;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::schema::{alerts, maintenance_plans};
+use crate::users::User;
+use crate::validation::{self, MAX_NAME};
+use chrono::{Duration, NaiveDateTime};
+use diesel::dsl::now;
+use diesel::pg::data_types::PgInterval;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+pub const OVERDUE_REASON: &str = "overdue_maintenance";
+// The longest maintenance interval, and the widest window for finding what is due
+pub const MAX_INTERVAL_DAYS: i32 = 36_500;
+
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
+)]
+#[belongs_to(Alert)]
+#[belongs_to(Asset)]
+#[belongs_to(User)]
+#[table_name = "maintenance_plans"]
+pub struct MaintenancePlan {
+    pub id: i64,
+    pub asset_id: i64,
+    pub user_id: i64,
+    pub alert_id: Option<i64>,
+    pub name: String,
+    pub interval_days: i32,
+    pub next_due_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
+#[table_name = "maintenance_plans"]
+#[validate(context = PgConnection)]
+pub struct MaybeMaintenancePlan {
+    #[validate(range(min = 1))]
+    pub asset_id: i64,
+    #[validate(custom(function = "validation::user_exists", use_context))]
+    pub user_id: i64,
+    #[validate(length(min = 1, max = MAX_NAME))]
+    pub name: String,
+    #[validate(range(min = 1, max = MAX_INTERVAL_DAYS))]
+    pub interval_days: i32,
+    pub next_due_at: NaiveDateTime,
+}
+
+// Parse a window such as "30d", "2w" or "12h", where a bare number is a number of days
+pub fn parse_within(within: &str) -> Result<Duration, CustomError> {
+    let invalid = || {
+        CustomError::new(
+            400,
+            format!("Invalid window '{}', expected a value like 30d", within),
+        )
+    };
+    let within = within.trim();
+    let (amount, unit) = match within.char_indices().last() {
+        Some((i, unit)) if unit.is_ascii_alphabetic() => (&within[..i], unit),
+        Some(_) => (within, 'd'),
+        None => return Err(invalid()),
+    };
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    if amount < 0 {
+        return Err(invalid());
+    }
+    let hours_per_unit = match unit {
+        'h' => 1,
+        'd' => 24,
+        'w' => 7 * 24,
+        _ => return Err(invalid()),
+    };
+    // Durations panic on overflow, so bound the window before making one
+    match amount.checked_mul(hours_per_unit) {
+        Some(hours) if hours <= i64::from(MAX_INTERVAL_DAYS) * 24 => Ok(Duration::hours(hours)),
+        _ => Err(CustomError::new(
+            400,
+            format!(
+                "Invalid window '{}', it can be at most {} days",
+                within, MAX_INTERVAL_DAYS
+            ),
+        )),
+    }
+}
+
+impl MaybeMaintenancePlan {
+    fn validate(&self) -> Result<(), CustomError> {
+        if self.interval_days <= 0 {
+            return Err(CustomError::new(
+                400,
+                String::from("A maintenance interval must be at least one day"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl MaintenancePlan {
+    pub fn find_all() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_plans = maintenance_plans::table.load::<MaintenancePlan>(&conn)?;
+        Ok(maintenance_plans)
+    }
+
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(maintenance_plans::table, maintenance_plans::id, page, &conn)
+    }
+
+    pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_plan = maintenance_plans::table
+            .filter(maintenance_plans::id.eq(id))
+            .first(&conn)?;
+        Ok(maintenance_plan)
+    }
+
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_plans = maintenance_plans::table
+            .filter(maintenance_plans::asset_id.eq(id))
+            .order(maintenance_plans::next_due_at)
+            .load::<MaintenancePlan>(&conn)?;
+        Ok(maintenance_plans)
+    }
+
+    // Plans that are overdue or become due within the window, soonest first
+    pub fn find_due_within(within: Duration) -> Result<Vec<Self>, CustomError> {
+        let within = PgInterval::from_microseconds(within.num_microseconds().unwrap_or(i64::MAX));
+        let conn = db::connection()?;
+        let maintenance_plans = maintenance_plans::table
+            .filter(maintenance_plans::next_due_at.le(now + within))
+            .order(maintenance_plans::next_due_at)
+            .load::<MaintenancePlan>(&conn)?;
+        Ok(maintenance_plans)
+    }
+
+    pub fn create(maintenance_plan: MaybeMaintenancePlan) -> Result<Self, CustomError> {
+        maintenance_plan.validate()?;
+        let conn = db::connection()?;
+        let maintenance_plan = diesel::insert_into(maintenance_plans::table)
+            .values(maintenance_plan)
+            .get_result(&conn)?;
+        Ok(maintenance_plan)
+    }
+
+    // The plan, locked until the transaction on conn ends, so what a write
+    // checks of it still holds when the write commits
+    fn lock(conn: &PgConnection, id: i64) -> Result<Self, CustomError> {
+        let maintenance_plan = maintenance_plans::table.find(id).for_update().first(conn)?;
+        Ok(maintenance_plan)
+    }
+
+    pub fn update(id: i64, maintenance_plan: MaybeMaintenancePlan) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(maintenance_plan))
+    }
+
+    // Update with what changes makes of the plan as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeMaintenancePlan, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            let maintenance_plan = changes(&conn, &Self::lock(&conn, id)?)?;
+            maintenance_plan.validate()?;
+            let maintenance_plan = diesel::update(maintenance_plans::table.find(id))
+                .set(maintenance_plan)
+                .get_result(&conn)?;
+            Ok(maintenance_plan)
+        })
+    }
+
+    pub fn delete(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(maintenance_plans::table.filter(maintenance_plans::id.eq(id)))
+            .execute(&conn)?;
+        Ok(res)
+    }
+
+    // Delete, unless check refuses the plan as it is once locked
+    pub fn delete_checked<F>(id: i64, check: F) -> Result<usize, CustomError>
+    where
+        F: FnOnce(&Self) -> Result<(), CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            check(&Self::lock(&conn, id)?)?;
+            let res = diesel::delete(maintenance_plans::table.find(id)).execute(&conn)?;
+            Ok(res)
+        })
+    }
+
+    // Raise an alert for the owner of each overdue plan that does not have one yet
+    pub fn raise_overdue_alerts() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_plans = conn.transaction::<_, CustomError, _>(|| {
+            let overdue = maintenance_plans::table
+                .filter(maintenance_plans::alert_id.is_null())
+                .filter(maintenance_plans::next_due_at.lt(now))
+                .load::<MaintenancePlan>(&conn)?;
+
+            let mut maintenance_plans = Vec::with_capacity(overdue.len());
+            for maintenance_plan in overdue {
+                let alert: Alert = diesel::insert_into(alerts::table)
+                    .values(MaybeAlert {
+                        message: Some(format!(
+                            "{} for asset {} was due at {}",
+                            maintenance_plan.name,
+                            maintenance_plan.asset_id,
+                            maintenance_plan.next_due_at
+                        )),
+                        reason: String::from(OVERDUE_REASON),
+                        user_id: maintenance_plan.user_id,
+                    })
+                    .get_result(&conn)?;
+                let maintenance_plan = diesel::update(&maintenance_plan)
+                    .set(maintenance_plans::alert_id.eq(alert.id))
+                    .get_result(&conn)?;
+                maintenance_plans.push(maintenance_plan);
+            }
+            Ok(maintenance_plans)
+        })?;
+        Ok(maintenance_plans)
+    }
+}
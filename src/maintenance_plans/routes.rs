@@ -0,0 +1,218 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::maintenance_plans::{parse_within, MaintenancePlan, MaybeMaintenancePlan};
+use crate::merge_patch;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DueQuery {
+    within: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_plans",
+    responses(
+        (status = 200, description = "The maintenance plans", body = [MaintenancePlan]),
+    )
+)]
+#[get("/maintenance_plans")]
+async fn find_all() -> Result<HttpResponse, CustomError> {
+    let maintenance_plans = db::run(MaintenancePlan::find_all).await?;
+    Ok(HttpResponse::Ok().json(maintenance_plans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_plans/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance plan"),
+    ),
+    responses(
+        (status = 200, description = "The maintenance plan", body = MaintenancePlan),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/maintenance_plans/id/{id}")]
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /maintenance_plans/id/{}", &id);
+    let maintenance_plan = db::run(move || MaintenancePlan::find_by_id(id)).await?;
+    etag::respond(&req, &maintenance_plan)
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_plans/asset/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The maintenance plans of the asset", body = [MaintenancePlan]),
+    )
+)]
+#[get("/maintenance_plans/asset/{id}")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /maintenance_plans/asset/{}", &id);
+    let maintenance_plans = db::run(move || MaintenancePlan::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_plans))
+}
+
+// The maintenance plans of any asset that is overdue or due within the window (default 30d)
+#[utoipa::path(
+    get,
+    path = "/assets/due_for_maintenance",
+    params(
+        DueQuery,
+    ),
+    responses(
+        (status = 200, description = "The maintenance plans due within the period", body = [MaintenancePlan]),
+    )
+)]
+#[get("/assets/due_for_maintenance")]
+async fn find_due(query: web::Query<DueQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /assets/due_for_maintenance {:?}", &query);
+    let within = parse_within(query.within.as_deref().unwrap_or("30d"))?;
+    let maintenance_plans = db::run(move || MaintenancePlan::find_due_within(within)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_plans))
+}
+
+#[utoipa::path(
+    post,
+    path = "/maintenance_plans",
+    request_body = MaybeMaintenancePlan,
+    responses(
+        (status = 200, description = "The created maintenance plan", body = MaintenancePlan),
+    )
+)]
+#[post("/maintenance_plans")]
+async fn create(
+    maintenance_plan: web::Json<MaybeMaintenancePlan>,
+) -> Result<HttpResponse, CustomError> {
+    let maintenance_plan = maintenance_plan.into_inner();
+    log::trace!("POST /maintenance_plans/ {:?}", &maintenance_plan);
+    let maintenance_plan = validation::validated(maintenance_plan).await?;
+    let maintenance_plan = db::run(move || MaintenancePlan::create(maintenance_plan)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_plan))
+}
+
+#[utoipa::path(
+    put,
+    path = "/maintenance_plans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance plan"),
+    ),
+    request_body = MaybeMaintenancePlan,
+    responses(
+        (status = 200, description = "The updated maintenance plan", body = MaintenancePlan),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[put("/maintenance_plans/{id}")]
+async fn update(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    maintenance_plan: web::Json<MaybeMaintenancePlan>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let maintenance_plan = maintenance_plan.into_inner();
+    log::trace!("PUT /maintenance_plans/{} {:?}", &id, &maintenance_plan);
+    let maintenance_plan = validation::validated(maintenance_plan).await?;
+    let precondition = etag::precondition(&req)?;
+    let maintenance_plan = db::run(move || {
+        MaintenancePlan::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(maintenance_plan)
+        })
+    })
+    .await?;
+    etag::ok(&maintenance_plan)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/maintenance_plans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance plan"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated maintenance plan", body = MaintenancePlan),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/maintenance_plans/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /maintenance_plans/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let maintenance_plan = db::run(move || {
+        MaintenancePlan::update_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&maintenance_plan)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/maintenance_plans/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance plan"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted maintenance plans", body = usize),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[delete("/maintenance_plans/{id}")]
+async fn delete(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /maintenance_plans/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let res =
+        db::run(move || MaintenancePlan::delete_checked(id, |current| precondition.check(current)))
+            .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_by_id,
+    find_by_asset,
+    find_due,
+    create,
+    update,
+    patch,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+    comfig.service(find_by_id);
+    comfig.service(find_by_asset);
+    comfig.service(find_due);
+    comfig.service(create);
+    comfig.service(update);
+    comfig.service(patch);
+    comfig.service(delete);
+}
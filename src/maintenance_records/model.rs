@@ -0,0 +1,222 @@
+use crate::assets::Asset;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::maintenance_plans::MaintenancePlan;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::schema::{maintenance_plans, maintenance_records};
+use crate::users::User;
+use crate::validation::{self, MAX_TEXT};
+use chrono::{Duration, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
+)]
+#[belongs_to(Asset)]
+#[belongs_to(MaintenancePlan)]
+#[belongs_to(User)]
+#[table_name = "maintenance_records"]
+pub struct MaintenanceRecord {
+    pub id: i64,
+    pub asset_id: i64,
+    pub maintenance_plan_id: Option<i64>,
+    pub user_id: i64,
+    pub performed_at: NaiveDateTime,
+    pub notes: Option<String>,
+    pub certificate: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
+#[table_name = "maintenance_records"]
+#[validate(context = PgConnection)]
+pub struct MaybeMaintenanceRecord {
+    #[validate(range(min = 1))]
+    pub asset_id: i64,
+    #[validate(range(min = 1))]
+    pub maintenance_plan_id: Option<i64>,
+    #[validate(custom(function = "validation::user_exists", use_context))]
+    pub user_id: i64,
+    pub performed_at: NaiveDateTime,
+    #[validate(length(max = MAX_TEXT))]
+    pub notes: Option<String>,
+    #[validate(length(max = MAX_TEXT))]
+    pub certificate: Option<String>,
+}
+
+impl MaintenanceRecord {
+    pub fn find_all() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_records = maintenance_records::table.load::<MaintenanceRecord>(&conn)?;
+        Ok(maintenance_records)
+    }
+
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            maintenance_records::table,
+            maintenance_records::id,
+            page,
+            &conn
+        )
+    }
+
+    pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_record = maintenance_records::table
+            .filter(maintenance_records::id.eq(id))
+            .first(&conn)?;
+        Ok(maintenance_record)
+    }
+
+    // Maintenance history, most recent first
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_records = maintenance_records::table
+            .filter(maintenance_records::asset_id.eq(id))
+            .order(maintenance_records::performed_at.desc())
+            .load::<MaintenanceRecord>(&conn)?;
+        Ok(maintenance_records)
+    }
+
+    // Maintenance history, most recent first
+    pub fn find_by_maintenance_plan(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let maintenance_records = maintenance_records::table
+            .filter(maintenance_records::maintenance_plan_id.eq(id))
+            .order(maintenance_records::performed_at.desc())
+            .load::<MaintenanceRecord>(&conn)?;
+        Ok(maintenance_records)
+    }
+
+    // Recording maintenance for a plan schedules its next due date and clears its overdue alert
+    pub fn create(maintenance_record: MaybeMaintenanceRecord) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            if let Some(plan_id) = maintenance_record.maintenance_plan_id {
+                let plan: MaintenancePlan = maintenance_plans::table
+                    .filter(maintenance_plans::id.eq(plan_id))
+                    .first(&conn)?;
+                if plan.asset_id != maintenance_record.asset_id {
+                    return Err(CustomError::new(
+                        400,
+                        format!(
+                            "Maintenance plan {} does not belong to asset {}",
+                            plan.id, maintenance_record.asset_id
+                        ),
+                    ));
+                }
+                let next_due_at = maintenance_record
+                    .performed_at
+                    .checked_add_signed(Duration::days(i64::from(plan.interval_days)))
+                    .ok_or_else(|| {
+                        CustomError::new(
+                            400,
+                            String::from(
+                                "The next due date of the maintenance plan is out of range",
+                            ),
+                        )
+                    })?;
+                diesel::update(&plan)
+                    .set((
+                        maintenance_plans::next_due_at.eq(next_due_at),
+                        maintenance_plans::alert_id.eq(None::<i64>),
+                    ))
+                    .execute(&conn)?;
+            }
+
+            let maintenance_record = diesel::insert_into(maintenance_records::table)
+                .values(maintenance_record)
+                .get_result(&conn)?;
+            Ok(maintenance_record)
+        })
+    }
+
+    // The record, locked until the transaction on conn ends, so what a write
+    // checks of it still holds when the write commits
+    fn lock(conn: &PgConnection, id: i64) -> Result<Self, CustomError> {
+        let maintenance_record = maintenance_records::table
+            .find(id)
+            .for_update()
+            .first(conn)?;
+        Ok(maintenance_record)
+    }
+
+    pub fn update(
+        id: i64,
+        maintenance_record: MaybeMaintenanceRecord,
+    ) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(maintenance_record))
+    }
+
+    // Update with what changes makes of the record as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeMaintenanceRecord, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            let maintenance_record = changes(&conn, &Self::lock(&conn, id)?)?;
+            let maintenance_record = diesel::update(maintenance_records::table.find(id))
+                .set(maintenance_record)
+                .get_result(&conn)?;
+            Ok(maintenance_record)
+        })
+    }
+
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeMaintenanceRecord, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            let maintenance_record = changes(&conn, &Self::lock(&conn, id)?)?;
+            let maintenance_record = diesel::update(maintenance_records::table.find(id))
+                .set((
+                    maintenance_records::asset_id.eq(maintenance_record.asset_id),
+                    maintenance_records::maintenance_plan_id
+                        .eq(maintenance_record.maintenance_plan_id),
+                    maintenance_records::user_id.eq(maintenance_record.user_id),
+                    maintenance_records::performed_at.eq(maintenance_record.performed_at),
+                    maintenance_records::notes.eq(maintenance_record.notes),
+                    maintenance_records::certificate.eq(maintenance_record.certificate),
+                ))
+                .get_result(&conn)?;
+            Ok(maintenance_record)
+        })
+    }
+
+    pub fn delete(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(maintenance_records::table.filter(maintenance_records::id.eq(id)))
+            .execute(&conn)?;
+        Ok(res)
+    }
+
+    // Delete, unless check refuses the record as it is once locked
+    pub fn delete_checked<F>(id: i64, check: F) -> Result<usize, CustomError>
+    where
+        F: FnOnce(&Self) -> Result<(), CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            check(&Self::lock(&conn, id)?)?;
+            let res = diesel::delete(maintenance_records::table.find(id)).execute(&conn)?;
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,211 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::maintenance_records::{MaintenanceRecord, MaybeMaintenanceRecord};
+use crate::merge_patch;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use utoipa::OpenApi;
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records",
+    responses(
+        (status = 200, description = "The maintenance records", body = [MaintenanceRecord]),
+    )
+)]
+#[get("/maintenance_records")]
+async fn find_all() -> Result<HttpResponse, CustomError> {
+    let maintenance_records = db::run(MaintenanceRecord::find_all).await?;
+    Ok(HttpResponse::Ok().json(maintenance_records))
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance record"),
+    ),
+    responses(
+        (status = 200, description = "The maintenance record", body = MaintenanceRecord),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/maintenance_records/id/{id}")]
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /maintenance_records/id/{}", &id);
+    let maintenance_record = db::run(move || MaintenanceRecord::find_by_id(id)).await?;
+    etag::respond(&req, &maintenance_record)
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records/asset/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The maintenance records of the asset", body = [MaintenanceRecord]),
+    )
+)]
+#[get("/maintenance_records/asset/{id}")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /maintenance_records/asset/{}", &id);
+    let maintenance_records = db::run(move || MaintenanceRecord::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_records))
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records/maintenance_plan/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance plan"),
+    ),
+    responses(
+        (status = 200, description = "The maintenance records of the maintenance plan", body = [MaintenanceRecord]),
+    )
+)]
+#[get("/maintenance_records/maintenance_plan/{id}")]
+async fn find_by_maintenance_plan(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /maintenance_records/maintenance_plan/{}", &id);
+    let maintenance_records =
+        db::run(move || MaintenanceRecord::find_by_maintenance_plan(id)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_records))
+}
+
+#[utoipa::path(
+    post,
+    path = "/maintenance_records",
+    request_body = MaybeMaintenanceRecord,
+    responses(
+        (status = 200, description = "The created maintenance record", body = MaintenanceRecord),
+    )
+)]
+#[post("/maintenance_records")]
+async fn create(
+    maintenance_record: web::Json<MaybeMaintenanceRecord>,
+) -> Result<HttpResponse, CustomError> {
+    let maintenance_record = maintenance_record.into_inner();
+    log::trace!("POST /maintenance_records/ {:?}", &maintenance_record);
+    let maintenance_record = validation::validated(maintenance_record).await?;
+    let maintenance_record = db::run(move || MaintenanceRecord::create(maintenance_record)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_record))
+}
+
+#[utoipa::path(
+    put,
+    path = "/maintenance_records/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance record"),
+    ),
+    request_body = MaybeMaintenanceRecord,
+    responses(
+        (status = 200, description = "The updated maintenance record", body = MaintenanceRecord),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[put("/maintenance_records/{id}")]
+async fn update(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    maintenance_record: web::Json<MaybeMaintenanceRecord>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let maintenance_record = maintenance_record.into_inner();
+    log::trace!("PUT /maintenance_records/{} {:?}", &id, &maintenance_record);
+    let maintenance_record = validation::validated(maintenance_record).await?;
+    let precondition = etag::precondition(&req)?;
+    let maintenance_record = db::run(move || {
+        MaintenanceRecord::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(maintenance_record)
+        })
+    })
+    .await?;
+    etag::ok(&maintenance_record)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/maintenance_records/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance record"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated maintenance record", body = MaintenanceRecord),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/maintenance_records/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /maintenance_records/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let maintenance_record = db::run(move || {
+        MaintenanceRecord::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&maintenance_record)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/maintenance_records/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the maintenance record"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted maintenance records", body = usize),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[delete("/maintenance_records/{id}")]
+async fn delete(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /maintenance_records/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let res = db::run(move || {
+        MaintenanceRecord::delete_checked(id, |current| precondition.check(current))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_by_id,
+    find_by_asset,
+    find_by_maintenance_plan,
+    create,
+    update,
+    patch,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+    comfig.service(find_by_id);
+    comfig.service(find_by_asset);
+    comfig.service(find_by_maintenance_plan);
+    comfig.service(create);
+    comfig.service(update);
+    comfig.service(patch);
+    comfig.service(delete);
+}
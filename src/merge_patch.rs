@@ -0,0 +1,77 @@
+use crate::error_handler::CustomError;
+use crate::validation;
+use diesel::pg::PgConnection;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use validator::ValidateArgs;
+
+/*
+ * PATCH with JSON Merge Patch (RFC 7396)
+ *
+ * The patch is merged into the JSON of the current resource and the result is
+ * read back as the body a PUT would take, so fields left out of the patch keep
+ * their values and null clears them. A patch can only change what that PUT body
+ * has, so ids, timestamps and the like are refused rather than ignored, and the
+ * result is validated like that body.
+ */
+
+// Soft-deleting goes through DELETE and restore, which cascade
+const IMMUTABLE: &[&str] = &["deleted"];
+
+pub fn merge(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(serde_json::Map::new());
+            }
+            if let Value::Object(target) = target {
+                for (key, value) in patch {
+                    if value.is_null() {
+                        target.remove(key);
+                    } else {
+                        merge(target.entry(key.as_str()).or_insert(Value::Null), value);
+                    }
+                }
+            }
+        }
+        patch => *target = patch.clone(),
+    }
+}
+
+// A patch that isn't JSON is malformed, and one that leaves a value of the wrong type is invalid
+fn invalid(err: serde_json::Error) -> CustomError {
+    match err.is_data() {
+        true => CustomError::new(422, format!("The merge patch is invalid: {}", err))
+            .with_code("invalid_body"),
+        false => CustomError::new(400, format!("Invalid merge patch: {}", err))
+            .with_code("malformed_json"),
+    }
+}
+
+// Apply a patch body to a resource, giving the update to save, validated on the write's connection
+pub fn apply<T, U>(conn: &PgConnection, current: &T, body: &[u8]) -> Result<U, CustomError>
+where
+    T: Serialize,
+    U: Serialize + DeserializeOwned + for<'a> ValidateArgs<'a, Args = &'a PgConnection>,
+{
+    let patch: Value = serde_json::from_slice(body).map_err(invalid)?;
+    let original = serde_json::to_value(current).map_err(invalid)?;
+    let mut merged = original.clone();
+    merge(&mut merged, &patch);
+    let update: U = serde_json::from_value(merged.clone()).map_err(invalid)?;
+    let updatable = serde_json::to_value(&update).map_err(invalid)?;
+    if let Value::Object(patch) = &patch {
+        for field in patch.keys() {
+            let fixed = IMMUTABLE.contains(&field.as_str()) || updatable.get(field).is_none();
+            if fixed && merged.get(field) != original.get(field) {
+                return Err(CustomError::new(
+                    400,
+                    format!("{} cannot be changed", field),
+                ));
+            }
+        }
+    }
+    validation::validate(&update, conn)?;
+    Ok(update)
+}
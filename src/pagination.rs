@@ -0,0 +1,90 @@
+use crate::error_handler::CustomError;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+/*
+ * Paginated listings, as /api/v2 returns them
+ *
+ * A page holds at most limit rows starting at offset, wrapped in an envelope
+ * that also tells how many rows there are in all, so clients know when to stop.
+ */
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+impl PageQuery {
+    // The limit and offset, refusing ones that make no page
+    pub fn bounds(&self) -> Result<(i64, i64), CustomError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(CustomError::new(
+                400,
+                format!("The limit must be between 1 and {}", MAX_LIMIT),
+            ));
+        }
+        let offset = self.offset.unwrap_or(0);
+        if offset < 0 {
+            return Err(CustomError::new(
+                400,
+                String::from("The offset must not be negative"),
+            ));
+        }
+        Ok((limit, offset))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Pagination {
+    pub limit: i64,
+    pub offset: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub pagination: Pagination,
+}
+
+impl<T> Page<T> {
+    // The same page with its rows turned into something else, such as expanded by ?include=
+    pub fn try_map<U, F>(self, f: F) -> Result<Page<U>, CustomError>
+    where
+        F: FnOnce(Vec<T>) -> Result<Vec<U>, CustomError>,
+    {
+        Ok(Page {
+            data: f(self.data)?,
+            pagination: self.pagination,
+        })
+    }
+}
+
+// Load a page of a query in the given order, counting the rows of the whole query
+macro_rules! paginate {
+    ($query:expr, $order:expr, $page:expr, $conn:expr) => {{
+        let (limit, offset) = $page.bounds()?;
+        let total = $query.count().get_result::<i64>($conn)?;
+        let data = $query
+            .order($order)
+            .limit(limit)
+            .offset(offset)
+            .load($conn)?;
+        Ok($crate::pagination::Page {
+            data,
+            pagination: $crate::pagination::Pagination {
+                limit,
+                offset,
+                total,
+            },
+        })
+    }};
+}
+
+pub(crate) use paginate;
@@ -0,0 +1,76 @@
+use crate::reservations::Reservation;
+use chrono::NaiveDateTime;
+
+/*
+ * Minimal iCalendar (RFC 5545) rendering of reservations
+ *
+ * Reservation times are stored without a time zone and are treated as UTC.
+ * Each occurrence of a recurring reservation is its own VEVENT, so edits or
+ * deletions of a single occurrence show up in subscribed calendars as is.
+ */
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+
+fn timestamp(time: &NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Content lines longer than 75 octets are folded onto continuation lines
+fn push_line(calendar: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            calendar.push_str("\r\n ");
+            width = 1;
+        }
+        calendar.push(c);
+        width += c.len_utf8();
+    }
+    calendar.push_str("\r\n");
+}
+
+pub fn render(name: &str, reservations: &[Reservation]) -> String {
+    let mut calendar = String::new();
+    push_line(&mut calendar, "BEGIN:VCALENDAR");
+    push_line(&mut calendar, "VERSION:2.0");
+    push_line(&mut calendar, "PRODID:-//QSIB//Asset Tracking//EN");
+    push_line(&mut calendar, "CALSCALE:GREGORIAN");
+    push_line(&mut calendar, &format!("X-WR-CALNAME:{}", escape(name)));
+    for reservation in reservations {
+        push_line(&mut calendar, "BEGIN:VEVENT");
+        push_line(
+            &mut calendar,
+            &format!("UID:reservation-{}@qsib-asset", reservation.id),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DTSTAMP:{}", timestamp(&reservation.updated_at)),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DTSTART:{}", timestamp(&reservation.starts_at)),
+        );
+        push_line(
+            &mut calendar,
+            &format!("DTEND:{}", timestamp(&reservation.ends_at)),
+        );
+        push_line(
+            &mut calendar,
+            &format!("SUMMARY:Asset {} reserved", reservation.asset_id),
+        );
+        if let Some(note) = &reservation.note {
+            push_line(&mut calendar, &format!("DESCRIPTION:{}", escape(note)));
+        }
+        push_line(&mut calendar, "END:VEVENT");
+    }
+    push_line(&mut calendar, "END:VCALENDAR");
+    calendar
+}
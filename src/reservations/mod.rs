@@ -0,0 +1,6 @@
+mod calendar;
+mod model;
+mod routes;
+
+pub use model::*;
+pub use routes::{init_routes, openapi};
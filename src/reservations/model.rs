@@ -0,0 +1,390 @@
+use crate::assets::Asset;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::schema::{assets, reservations};
+use crate::users::User;
+use crate::validation::{self, MAX_TEXT};
+use chrono::{Duration, NaiveDateTime};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidateArgs, ValidationErrors};
+
+// Upper bound on the occurrences created by a single recurring reservation
+pub const MAX_OCCURRENCES: u32 = 366;
+
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
+)]
+#[belongs_to(Asset)]
+#[belongs_to(User)]
+#[table_name = "reservations"]
+pub struct Reservation {
+    pub id: i64,
+    pub asset_id: i64,
+    pub user_id: i64,
+    pub series_id: Option<i64>,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+    pub note: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, Clone, ToSchema, Validate)]
+#[table_name = "reservations"]
+#[validate(context = PgConnection)]
+pub struct MaybeReservation {
+    #[validate(range(min = 1))]
+    pub asset_id: i64,
+    #[validate(custom(function = "validation::user_exists", use_context))]
+    pub user_id: i64,
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+    #[validate(length(max = MAX_TEXT))]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Frequency {
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, ToSchema, Validate)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    #[validate(range(min = 1, max = MAX_OCCURRENCES))]
+    pub count: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewReservation {
+    #[serde(flatten)]
+    pub reservation: MaybeReservation,
+    pub recurrence: Option<Recurrence>,
+}
+
+// The reservation is flattened into the body, so its fields are validated as the body's own
+impl<'a> ValidateArgs<'a> for NewReservation {
+    type Args = &'a PgConnection;
+
+    fn validate_with_args(&self, conn: &'a PgConnection) -> Result<(), ValidationErrors> {
+        let recurrence = match &self.recurrence {
+            Some(recurrence) => recurrence.validate(),
+            None => Ok(()),
+        };
+        ValidationErrors::merge(
+            self.reservation.validate_with_args(conn),
+            "recurrence",
+            recurrence,
+        )
+    }
+}
+
+impl Recurrence {
+    fn interval(&self) -> Duration {
+        match self.frequency {
+            Frequency::Daily => Duration::days(1),
+            Frequency::Weekly => Duration::weeks(1),
+        }
+    }
+}
+
+impl MaybeReservation {
+    // Expand a reservation into each of its occurrences, starting with itself
+    fn occurrences(
+        &self,
+        recurrence: Option<Recurrence>,
+    ) -> Result<Vec<MaybeReservation>, CustomError> {
+        let recurrence = match recurrence {
+            Some(recurrence) => recurrence,
+            None => return Ok(vec![self.clone()]),
+        };
+        (0..recurrence.count)
+            .map(|n| {
+                let offset = recurrence.interval() * n as i32;
+                match (
+                    self.starts_at.checked_add_signed(offset),
+                    self.ends_at.checked_add_signed(offset),
+                ) {
+                    (Some(starts_at), Some(ends_at)) => Ok(MaybeReservation {
+                        starts_at,
+                        ends_at,
+                        ..self.clone()
+                    }),
+                    _ => Err(CustomError::new(
+                        400,
+                        String::from("The recurring reservation ends out of range"),
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), CustomError> {
+        if self.starts_at >= self.ends_at {
+            return Err(CustomError::new(
+                400,
+                String::from("A reservation must start before it ends"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Reservation {
+    pub fn find_all() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let reservations = reservations::table
+            .order(reservations::starts_at)
+            .load::<Reservation>(&conn)?;
+        Ok(reservations)
+    }
+
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            reservations::table,
+            (reservations::starts_at, reservations::id),
+            page,
+            &conn
+        )
+    }
+
+    pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let reservation = reservations::table
+            .filter(reservations::id.eq(id))
+            .first(&conn)?;
+        Ok(reservation)
+    }
+
+    pub fn find_by_series(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let reservations = reservations::table
+            .filter(reservations::series_id.eq(id))
+            .order(reservations::starts_at)
+            .load::<Reservation>(&conn)?;
+        Ok(reservations)
+    }
+
+    pub fn find_by_asset(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let reservations = reservations::table
+            .filter(reservations::asset_id.eq(id))
+            .order(reservations::starts_at)
+            .load::<Reservation>(&conn)?;
+        Ok(reservations)
+    }
+
+    pub fn find_by_user(id: i64) -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let reservations = reservations::table
+            .filter(reservations::user_id.eq(id))
+            .order(reservations::starts_at)
+            .load::<Reservation>(&conn)?;
+        Ok(reservations)
+    }
+
+    // Reservations of the same asset that overlap the half-open range [starts_at, ends_at)
+    fn find_conflicts(
+        conn: &PgConnection,
+        reservation: &MaybeReservation,
+        exclude_id: Option<i64>,
+    ) -> Result<Vec<Self>, CustomError> {
+        let mut query = reservations::table
+            .filter(reservations::asset_id.eq(reservation.asset_id))
+            .filter(reservations::starts_at.lt(reservation.ends_at))
+            .filter(reservations::ends_at.gt(reservation.starts_at))
+            .into_boxed();
+        if let Some(id) = exclude_id {
+            query = query.filter(reservations::id.ne(id));
+        }
+        Ok(query.load::<Reservation>(conn)?)
+    }
+
+    // Holding the asset's row until the transaction ends makes concurrent bookings of it
+    // check for conflicts one after another, so they can't both pass
+    fn lock_asset(conn: &PgConnection, asset_id: i64) -> Result<(), CustomError> {
+        assets::table
+            .find(asset_id)
+            .select(assets::id)
+            .for_update()
+            .get_result::<i64>(conn)?;
+        Ok(())
+    }
+
+    // The reservation, locked until the transaction on conn ends, so what a write
+    // checks of it still holds when the write commits
+    fn lock(conn: &PgConnection, id: i64) -> Result<Self, CustomError> {
+        let reservation = reservations::table.find(id).for_update().first(conn)?;
+        Ok(reservation)
+    }
+
+    fn conflict(reservation: &MaybeReservation, conflicts: &[Reservation]) -> CustomError {
+        let ids: Vec<String> = conflicts.iter().map(|r| r.id.to_string()).collect();
+        CustomError::new(
+            409,
+            format!(
+                "Asset {} is already reserved from {} to {} by reservation {}",
+                reservation.asset_id,
+                reservation.starts_at,
+                reservation.ends_at,
+                ids.join(", ")
+            ),
+        )
+        .with_code("already_reserved")
+    }
+
+    // Create a reservation and any recurring occurrences, rejecting all of them if any overlap
+    pub fn create(
+        reservation: MaybeReservation,
+        recurrence: Option<Recurrence>,
+    ) -> Result<Vec<Self>, CustomError> {
+        reservation.validate()?;
+        if let Some(recurrence) = recurrence {
+            if reservation.ends_at - reservation.starts_at > recurrence.interval() {
+                return Err(CustomError::new(
+                    400,
+                    String::from("A recurring reservation cannot overlap its next occurrence"),
+                ));
+            }
+            if recurrence.count == 0 || recurrence.count > MAX_OCCURRENCES {
+                return Err(CustomError::new(
+                    400,
+                    format!(
+                        "A recurring reservation needs between 1 and {} occurrences",
+                        MAX_OCCURRENCES
+                    ),
+                ));
+            }
+        }
+        Asset::find_by_id(reservation.asset_id)?;
+
+        let occurrences = reservation.occurrences(recurrence)?;
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            Self::lock_asset(&conn, reservation.asset_id)?;
+            for occurrence in occurrences.iter() {
+                let conflicts = Self::find_conflicts(&conn, occurrence, None)?;
+                if !conflicts.is_empty() {
+                    return Err(Self::conflict(occurrence, &conflicts));
+                }
+            }
+
+            let reservations: Vec<Reservation> = diesel::insert_into(reservations::table)
+                .values(&occurrences)
+                .get_results(&conn)?;
+            if recurrence.is_none() {
+                return Ok(reservations);
+            }
+
+            // The first occurrence identifies the series, including itself
+            let ids: Vec<i64> = reservations.iter().map(|r| r.id).collect();
+            let mut reservations = diesel::update(reservations::table)
+                .filter(reservations::id.eq_any(&ids))
+                .set(reservations::series_id.eq(ids[0]))
+                .get_results::<Reservation>(&conn)?;
+            reservations.sort_by_key(|r| r.starts_at);
+            Ok(reservations)
+        })
+    }
+
+    pub fn update(id: i64, reservation: MaybeReservation) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(reservation))
+    }
+
+    // Update with what changes makes of the reservation as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeReservation, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            let reservation = changes(&conn, &Self::lock(&conn, id)?)?;
+            Self::check_rebooking(&conn, id, &reservation)?;
+            let reservation = diesel::update(reservations::table)
+                .filter(reservations::id.eq(id))
+                .set(reservation)
+                .get_result(&conn)?;
+            Ok(reservation)
+        })
+    }
+
+    // Like update_with, but a missing note is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeReservation, CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction::<_, CustomError, _>(|| {
+            let reservation = changes(&conn, &Self::lock(&conn, id)?)?;
+            Self::check_rebooking(&conn, id, &reservation)?;
+            let reservation = diesel::update(reservations::table)
+                .filter(reservations::id.eq(id))
+                .set((
+                    reservations::asset_id.eq(reservation.asset_id),
+                    reservations::user_id.eq(reservation.user_id),
+                    reservations::starts_at.eq(reservation.starts_at),
+                    reservations::ends_at.eq(reservation.ends_at),
+                    reservations::note.eq(reservation.note),
+                ))
+                .get_result(&conn)?;
+            Ok(reservation)
+        })
+    }
+
+    // Refuse to move a reservation onto a time its asset is already booked for
+    fn check_rebooking(
+        conn: &PgConnection,
+        id: i64,
+        reservation: &MaybeReservation,
+    ) -> Result<(), CustomError> {
+        reservation.validate()?;
+        Self::lock_asset(conn, reservation.asset_id)?;
+        let conflicts = Self::find_conflicts(conn, reservation, Some(id))?;
+        if !conflicts.is_empty() {
+            return Err(Self::conflict(reservation, &conflicts));
+        }
+        Ok(())
+    }
+
+    pub fn delete(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res =
+            diesel::delete(reservations::table.filter(reservations::id.eq(id))).execute(&conn)?;
+        Ok(res)
+    }
+
+    // Delete, unless check refuses the reservation as it is once locked
+    pub fn delete_checked<F>(id: i64, check: F) -> Result<usize, CustomError>
+    where
+        F: FnOnce(&Self) -> Result<(), CustomError>,
+    {
+        let conn = db::connection()?;
+        conn.transaction(|| {
+            check(&Self::lock(&conn, id)?)?;
+            let res = diesel::delete(reservations::table.find(id)).execute(&conn)?;
+            Ok(res)
+        })
+    }
+
+    pub fn delete_by_series(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(reservations::table.filter(reservations::series_id.eq(id)))
+            .execute(&conn)?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,295 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::merge_patch;
+use crate::reservations::{calendar, MaybeReservation, NewReservation, Reservation};
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
+use utoipa::OpenApi;
+
+#[utoipa::path(
+    get,
+    path = "/reservations",
+    responses(
+        (status = 200, description = "The reservations", body = [Reservation]),
+    )
+)]
+#[get("/reservations")]
+async fn find_all() -> Result<HttpResponse, CustomError> {
+    let reservations = db::run(Reservation::find_all).await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/reservations/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the reservation"),
+    ),
+    responses(
+        (status = 200, description = "The reservation", body = Reservation),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/reservations/id/{id}")]
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /reservations/id/{}", &id);
+    let reservation = db::run(move || Reservation::find_by_id(id)).await?;
+    etag::respond(&req, &reservation)
+}
+
+#[utoipa::path(
+    get,
+    path = "/reservations/series/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the series"),
+    ),
+    responses(
+        (status = 200, description = "The reservations of the series", body = [Reservation]),
+    )
+)]
+#[get("/reservations/series/{id}")]
+async fn find_by_series(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /reservations/series/{}", &id);
+    let reservations = db::run(move || Reservation::find_by_series(id)).await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/{id}/reservations",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The reservations of the asset", body = [Reservation]),
+    )
+)]
+#[get("/assets/{id}/reservations")]
+async fn find_by_asset(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /assets/{}/reservations", &id);
+    let reservations = db::run(move || Reservation::find_by_asset(id)).await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/{id}/reservations.ics",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+    ),
+    responses(
+        (status = 200, description = "The reservations of the asset as an iCalendar feed", content_type = "text/calendar"),
+    )
+)]
+#[get("/assets/{id}/reservations.ics")]
+async fn find_by_asset_ics(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /assets/{}/reservations.ics", &id);
+    let reservations = db::run(move || Reservation::find_by_asset(id)).await?;
+    let name = format!("Asset {} reservations", id);
+    Ok(HttpResponse::Ok()
+        .content_type(calendar::CONTENT_TYPE)
+        .body(calendar::render(&name, &reservations)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/reservations",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The reservations of the user", body = [Reservation]),
+    )
+)]
+#[get("/users/{id}/reservations")]
+async fn find_by_user(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /users/{}/reservations", &id);
+    let reservations = db::run(move || Reservation::find_by_user(id)).await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}/reservations.ics",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The reservations of the user as an iCalendar feed", content_type = "text/calendar"),
+    )
+)]
+#[get("/users/{id}/reservations.ics")]
+async fn find_by_user_ics(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /users/{}/reservations.ics", &id);
+    let reservations = db::run(move || Reservation::find_by_user(id)).await?;
+    let name = format!("User {} reservations", id);
+    Ok(HttpResponse::Ok()
+        .content_type(calendar::CONTENT_TYPE)
+        .body(calendar::render(&name, &reservations)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/reservations",
+    request_body = NewReservation,
+    responses(
+        (status = 200, description = "The created reservations, one per occurrence", body = [Reservation]),
+    )
+)]
+#[post("/reservations")]
+async fn create(reservation: web::Json<NewReservation>) -> Result<HttpResponse, CustomError> {
+    let reservation = reservation.into_inner();
+    log::trace!("POST /reservations/ {:?}", &reservation);
+    let reservation = validation::validated(reservation).await?;
+    let reservations =
+        db::run(move || Reservation::create(reservation.reservation, reservation.recurrence))
+            .await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    put,
+    path = "/reservations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the reservation"),
+    ),
+    request_body = MaybeReservation,
+    responses(
+        (status = 200, description = "The updated reservation", body = Reservation),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[put("/reservations/{id}")]
+async fn update(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    reservation: web::Json<MaybeReservation>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    let reservation = reservation.into_inner();
+    log::trace!("PUT /reservations/{} {:?}", &id, &reservation);
+    let reservation = validation::validated(reservation).await?;
+    let precondition = etag::precondition(&req)?;
+    let reservation = db::run(move || {
+        Reservation::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(reservation)
+        })
+    })
+    .await?;
+    etag::ok(&reservation)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/reservations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the reservation"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated reservation", body = Reservation),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/reservations/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /reservations/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let reservation = db::run(move || {
+        Reservation::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&reservation)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/reservations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the reservation"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted reservations", body = usize),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[delete("/reservations/{id}")]
+async fn delete(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /reservations/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let res =
+        db::run(move || Reservation::delete_checked(id, |current| precondition.check(current)))
+            .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/reservations/series/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the series"),
+    ),
+    responses(
+        (status = 200, description = "The number of deleted reservations", body = usize),
+    )
+)]
+#[delete("/reservations/series/{id}")]
+async fn delete_by_series(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /reservations/series/{}", &id);
+    let res = db::run(move || Reservation::delete_by_series(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_by_id,
+    find_by_series,
+    find_by_asset,
+    find_by_asset_ics,
+    find_by_user,
+    find_by_user_ics,
+    create,
+    update,
+    patch,
+    delete,
+    delete_by_series
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+    comfig.service(find_by_id);
+    comfig.service(find_by_series);
+    comfig.service(find_by_asset);
+    comfig.service(find_by_asset_ics);
+    comfig.service(find_by_user);
+    comfig.service(find_by_user_ics);
+    comfig.service(create);
+    comfig.service(update);
+    comfig.service(patch);
+    comfig.service(delete);
+    comfig.service(delete_by_series);
+}
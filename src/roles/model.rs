@@ -1,13 +1,30 @@
 use crate::db;
 use crate::error_handler::CustomError;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::roles;
+use crate::soft_delete::{soft_delete_methods, Reference};
 use crate::users::User;
+use crate::validation::{self, MAX_NAME};
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+// Users holding a role with this name may moderate content authored by others
+pub const ADMIN: &str = "admin";
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(User)]
 #[table_name = "roles"]
@@ -17,31 +34,57 @@ pub struct Role {
     pub user_id: Option<i64>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "roles"]
+#[validate(context = PgConnection)]
 pub struct MaybeRole {
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub name: String,
+    #[validate(custom(function = "validation::user_exists", use_context))]
     pub user_id: Option<i64>,
 }
 
+// Nothing references roles, so they can always be purged
+const REFERENCES: &[Reference] = &[];
+
 impl Role {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let roles = roles::table.load::<Role>(&conn)?;
+        let roles = roles::table
+            .filter(roles::deleted.eq(false))
+            .load::<Role>(&conn)?;
         Ok(roles)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            roles::table.filter(roles::deleted.eq(false)),
+            roles::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let role = roles::table.filter(roles::id.eq(id)).first(&conn)?;
+        let role = roles::table
+            .filter(roles::id.eq(id))
+            .filter(roles::deleted.eq(false))
+            .first(&conn)?;
         Ok(role)
     }
 
     pub fn find_by_name(name: String) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let role = roles::table.filter(roles::name.eq(name)).first(&conn)?;
+        let role = roles::table
+            .filter(roles::name.eq(name))
+            .filter(roles::deleted.eq(false))
+            .first(&conn)?;
         Ok(role)
     }
 
@@ -49,6 +92,7 @@ impl Role {
         let conn = db::connection()?;
         let roles = roles::table
             .filter(roles::user_id.eq(id))
+            .filter(roles::deleted.eq(false))
             .load::<Role>(&conn)?;
         Ok(roles)
     }
@@ -62,17 +106,38 @@ impl Role {
     }
 
     pub fn update(id: i64, role: MaybeRole) -> Result<Self, CustomError> {
+        Self::update_with(id, |_, _| Ok(role))
+    }
+
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeRole, CustomError>,
+    {
         let conn = db::connection()?;
-        let role = diesel::update(roles::table)
-            .filter(roles::id.eq(id))
-            .set(role)
-            .get_result(&conn)?;
-        Ok(role)
+        conn.transaction(|| {
+            let role = changes(&conn, &Self::lock(&conn, id)?)?;
+            let role = diesel::update(roles::table.find(id))
+                .set(role)
+                .get_result(&conn)?;
+            Ok(role)
+        })
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    // Like update_with, but a missing optional field is cleared, as null is in a merge patch
+    pub fn patch_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeRole, CustomError>,
+    {
         let conn = db::connection()?;
-        let res = diesel::delete(roles::table.filter(roles::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        conn.transaction(|| {
+            let role = changes(&conn, &Self::lock(&conn, id)?)?;
+            let role = diesel::update(roles::table.find(id))
+                .set((roles::name.eq(role.name), roles::user_id.eq(role.user_id)))
+                .get_result(&conn)?;
+            Ok(role)
+        })
     }
+
+    soft_delete_methods!(roles, Role, "Role", REFERENCES);
 }
@@ -1,72 +1,289 @@
+use crate::db;
 use crate::error_handler::CustomError;
+use crate::etag;
+use crate::merge_patch;
 use crate::roles::{MaybeRole, Role};
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::soft_delete::DeleteQuery;
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/roles",
+    responses(
+        (status = 200, description = "The roles", body = [Role]),
+    )
+)]
 #[get("/roles")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
-    let roles = Role::find_all()?;
+    let roles = db::run(Role::find_all).await?;
     Ok(HttpResponse::Ok().json(roles))
 }
 
+#[utoipa::path(
+    get,
+    path = "/roles/all",
+    responses(
+        (status = 200, description = "The roles, deleted or not", body = [Role]),
+    )
+)]
+#[get("/roles/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let roles = db::run(Role::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+#[utoipa::path(
+    get,
+    path = "/roles/deleted",
+    responses(
+        (status = 200, description = "The deleted roles", body = [Role]),
+    )
+)]
+#[get("/roles/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let roles = db::run(Role::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+#[utoipa::path(
+    get,
+    path = "/roles/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the role"),
+    ),
+    responses(
+        (status = 200, description = "The role", body = Role),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/roles/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /roles/id/{}", &id);
-    let role = Role::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(role))
+    let role = db::run(move || Role::find_by_id(id)).await?;
+    etag::respond(&req, &role)
 }
 
+#[utoipa::path(
+    get,
+    path = "/roles/name/{name}",
+    params(
+        ("name" = String, Path, description = "Name of the role"),
+    ),
+    responses(
+        (status = 200, description = "The role with the name", body = Role),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/roles/name/{name}")]
-async fn find_by_name(name: web::Path<String>) -> Result<HttpResponse, CustomError> {
+async fn find_by_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
     let name = name.into_inner();
     log::trace!("GET /roles/name/{}", &name);
-    let role = Role::find_by_name(name)?;
-    Ok(HttpResponse::Ok().json(role))
+    let role = db::run(move || Role::find_by_name(name)).await?;
+    etag::respond(&req, &role)
 }
 
+#[utoipa::path(
+    get,
+    path = "/roles/user/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The roles of the user", body = [Role]),
+    )
+)]
 #[get("/roles/user/{id}")]
 async fn find_by_user(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /roles/user/{}", &id);
-    let roles = Role::find_by_user(id)?;
+    let roles = db::run(move || Role::find_by_user(id)).await?;
     Ok(HttpResponse::Ok().json(roles))
 }
 
+#[utoipa::path(
+    post,
+    path = "/roles",
+    request_body = MaybeRole,
+    responses(
+        (status = 200, description = "The created role", body = Role),
+    )
+)]
 #[post("/roles")]
-async fn create(role: web::Json<MaybeRole>) -> Result<HttpResponse, CustomError> {
+async fn create(user: User, role: web::Json<MaybeRole>) -> Result<HttpResponse, CustomError> {
     let role = role.into_inner();
     log::trace!("POST /roles/ {:?}", &role);
-    let role = Role::create(role)?;
+    let role = validation::validated(role).await?;
+    let role = db::run(move || {
+        user.authorize_admin()?;
+        Role::create(role)
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(role))
 }
 
+#[utoipa::path(
+    put,
+    path = "/roles/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the role"),
+    ),
+    request_body = MaybeRole,
+    responses(
+        (status = 200, description = "The updated role", body = Role),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/roles/{id}")]
 async fn update(
+    req: HttpRequest,
+    user: User,
     id: web::Path<i64>,
     role: web::Json<MaybeRole>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let role = role.into_inner();
     log::trace!("PUT /roles/{} {:?}", &id, &role);
-    let role = Role::update(id, role)?;
-    Ok(HttpResponse::Ok().json(role))
+    let role = validation::validated(role).await?;
+    db::run(move || user.authorize_admin()).await?;
+    let precondition = etag::precondition(&req)?;
+    let role = db::run(move || {
+        Role::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(role)
+        })
+    })
+    .await?;
+    etag::ok(&role)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/roles/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the role"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated role", body = Role),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/roles/{id}")]
+async fn patch(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /roles/{}", &id);
+    db::run(move || user.authorize_admin()).await?;
+    let precondition = etag::precondition(&req)?;
+    let role = db::run(move || {
+        Role::patch_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&role)
+}
+
+#[utoipa::path(
+    post,
+    path = "/roles/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the role"),
+    ),
+    responses(
+        (status = 200, description = "The restored role", body = Role),
+    )
+)]
+#[post("/roles/{id}/restore")]
+async fn restore(user: User, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /roles/{}/restore", &id);
+    let res = db::run(move || {
+        user.authorize_admin()?;
+        Role::restore(id)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/roles/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the role"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted role", body = Role),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/roles/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /roles/{}", &id);
-    let res = Role::delete(id)?;
+    log::trace!("DELETE /roles/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        user.authorize_admin()?;
+        if purge {
+            Role::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Role::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_name,
+    find_by_user,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(find_by_id);
     comfig.service(find_by_name);
     comfig.service(find_by_user);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
@@ -1,13 +1,28 @@
 use crate::db;
 use crate::error_handler::CustomError;
 use crate::locations::Location;
+use crate::pagination::{paginate, Page, PageQuery};
 use crate::schema::rooms;
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation::{self, MAX_NAME};
 use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
 
 #[derive(
-    Debug, Serialize, Deserialize, Identifiable, Queryable, AsChangeset, Insertable, Associations,
+    Debug,
+    Clone,
+    Serialize,
+    Deserialize,
+    Identifiable,
+    Queryable,
+    AsChangeset,
+    Insertable,
+    Associations,
+    ToSchema,
 )]
 #[belongs_to(Location)]
 #[table_name = "rooms"]
@@ -17,31 +32,60 @@ pub struct Room {
     pub location_id: i64,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable)]
+#[derive(Debug, Serialize, Deserialize, AsChangeset, Insertable, ToSchema, Validate)]
 #[table_name = "rooms"]
+#[validate(context = PgConnection)]
 pub struct MaybeRoom {
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub name: String,
+    #[validate(custom(function = "validation::location_exists", use_context))]
     pub location_id: i64,
 }
 
+// Foreign keys that keep a room from being purged
+const REFERENCES: &[Reference] = &[Reference {
+    table: "loans",
+    column: "room_id",
+}];
+
 impl Room {
     pub fn find_all() -> Result<Vec<Self>, CustomError> {
         let conn = db::connection()?;
-        let rooms = rooms::table.load::<Room>(&conn)?;
+        let rooms = rooms::table
+            .filter(rooms::deleted.eq(false))
+            .load::<Room>(&conn)?;
         Ok(rooms)
     }
 
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            rooms::table.filter(rooms::deleted.eq(false)),
+            rooms::id,
+            page,
+            &conn
+        )
+    }
+
     pub fn find_by_id(id: i64) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let room = rooms::table.filter(rooms::id.eq(id)).first(&conn)?;
+        let room = rooms::table
+            .filter(rooms::id.eq(id))
+            .filter(rooms::deleted.eq(false))
+            .first(&conn)?;
         Ok(room)
     }
 
     pub fn find_by_name(name: String) -> Result<Self, CustomError> {
         let conn = db::connection()?;
-        let room = rooms::table.filter(rooms::name.eq(name)).first(&conn)?;
+        let room = rooms::table
+            .filter(rooms::name.eq(name))
+            .filter(rooms::deleted.eq(false))
+            .first(&conn)?;
         Ok(room)
     }
 
@@ -49,6 +93,7 @@ impl Room {
         let conn = db::connection()?;
         let rooms = rooms::table
             .filter(rooms::location_id.eq(id))
+            .filter(rooms::deleted.eq(false))
             .load::<Room>(&conn)?;
         Ok(rooms)
     }
@@ -62,17 +107,23 @@ impl Room {
     }
 
     pub fn update(id: i64, room: MaybeRoom) -> Result<Self, CustomError> {
-        let conn = db::connection()?;
-        let room = diesel::update(rooms::table)
-            .filter(rooms::id.eq(id))
-            .set(room)
-            .get_result(&conn)?;
-        Ok(room)
+        Self::update_with(id, |_, _| Ok(room))
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    // Update with what changes makes of the row as it is once locked
+    pub fn update_with<F>(id: i64, changes: F) -> Result<Self, CustomError>
+    where
+        F: FnOnce(&PgConnection, &Self) -> Result<MaybeRoom, CustomError>,
+    {
         let conn = db::connection()?;
-        let res = diesel::delete(rooms::table.filter(rooms::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        conn.transaction(|| {
+            let room = changes(&conn, &Self::lock(&conn, id)?)?;
+            let room = diesel::update(rooms::table.find(id))
+                .set(room)
+                .get_result(&conn)?;
+            Ok(room)
+        })
     }
+
+    soft_delete_methods!(rooms, Room, "Room", REFERENCES);
 }
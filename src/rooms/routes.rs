@@ -1,72 +1,277 @@
+use crate::db;
 use crate::error_handler::CustomError;
+use crate::etag;
+use crate::merge_patch;
 use crate::rooms::{MaybeRoom, Room};
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::User;
+use crate::validation;
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use log;
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/rooms",
+    responses(
+        (status = 200, description = "The rooms", body = [Room]),
+    )
+)]
 #[get("/rooms")]
 async fn find_all() -> Result<HttpResponse, CustomError> {
-    let rooms = Room::find_all()?;
+    let rooms = db::run(Room::find_all).await?;
     Ok(HttpResponse::Ok().json(rooms))
 }
 
+#[utoipa::path(
+    get,
+    path = "/rooms/all",
+    responses(
+        (status = 200, description = "The rooms, deleted or not", body = [Room]),
+    )
+)]
+#[get("/rooms/all")]
+async fn find_with_deleted() -> Result<HttpResponse, CustomError> {
+    let rooms = db::run(Room::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+#[utoipa::path(
+    get,
+    path = "/rooms/deleted",
+    responses(
+        (status = 200, description = "The deleted rooms", body = [Room]),
+    )
+)]
+#[get("/rooms/deleted")]
+async fn find_deleted() -> Result<HttpResponse, CustomError> {
+    let rooms = db::run(Room::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+#[utoipa::path(
+    get,
+    path = "/rooms/id/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the room"),
+    ),
+    responses(
+        (status = 200, description = "The room", body = Room),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/rooms/id/{id}")]
-async fn find_by_id(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn find_by_id(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /rooms/id/{}", &id);
-    let room = Room::find_by_id(id)?;
-    Ok(HttpResponse::Ok().json(room))
+    let room = db::run(move || Room::find_by_id(id)).await?;
+    etag::respond(&req, &room)
 }
 
+#[utoipa::path(
+    get,
+    path = "/rooms/name/{name}",
+    params(
+        ("name" = String, Path, description = "Name of the room"),
+    ),
+    responses(
+        (status = 200, description = "The room with the name", body = Room),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
 #[get("/rooms/name/{name}")]
-async fn find_by_name(name: web::Path<String>) -> Result<HttpResponse, CustomError> {
+async fn find_by_name(
+    req: HttpRequest,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CustomError> {
     let name = name.into_inner();
     log::trace!("GET /rooms/name/{}", &name);
-    let room = Room::find_by_name(name)?;
-    Ok(HttpResponse::Ok().json(room))
+    let room = db::run(move || Room::find_by_name(name)).await?;
+    etag::respond(&req, &room)
 }
 
+#[utoipa::path(
+    get,
+    path = "/rooms/location/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+    ),
+    responses(
+        (status = 200, description = "The rooms of the location", body = [Room]),
+    )
+)]
 #[get("/rooms/location/{id}")]
 async fn find_by_location(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     log::trace!("GET /rooms/location/{}", &id);
-    let rooms = Room::find_by_location(id)?;
+    let rooms = db::run(move || Room::find_by_location(id)).await?;
     Ok(HttpResponse::Ok().json(rooms))
 }
 
+#[utoipa::path(
+    post,
+    path = "/rooms",
+    request_body = MaybeRoom,
+    responses(
+        (status = 200, description = "The created room", body = Room),
+    )
+)]
 #[post("/rooms")]
 async fn create(room: web::Json<MaybeRoom>) -> Result<HttpResponse, CustomError> {
     let room = room.into_inner();
     log::trace!("POST /rooms/ {:?}", &room);
-    let room = Room::create(room)?;
+    let room = validation::validated(room).await?;
+    let room = db::run(move || Room::create(room)).await?;
     Ok(HttpResponse::Ok().json(room))
 }
 
+#[utoipa::path(
+    put,
+    path = "/rooms/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the room"),
+    ),
+    request_body = MaybeRoom,
+    responses(
+        (status = 200, description = "The updated room", body = Room),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[put("/rooms/{id}")]
 async fn update(
+    req: HttpRequest,
     id: web::Path<i64>,
     room: web::Json<MaybeRoom>,
 ) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
     let room = room.into_inner();
     log::trace!("PUT /rooms/{} {:?}", &id, &room);
-    let room = Room::update(id, room)?;
-    Ok(HttpResponse::Ok().json(room))
+    let room = validation::validated(room).await?;
+    let precondition = etag::precondition(&req)?;
+    let room = db::run(move || {
+        Room::update_with(id, |_, current| {
+            precondition.check(current)?;
+            Ok(room)
+        })
+    })
+    .await?;
+    etag::ok(&room)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/rooms/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the room"),
+    ),
+    request_body(content = serde_json::Value, content_type = "application/merge-patch+json"),
+    responses(
+        (status = 200, description = "The updated room", body = Room),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
+#[patch("/rooms/{id}")]
+async fn patch(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    body: web::Bytes,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("PATCH /rooms/{}", &id);
+    let precondition = etag::precondition(&req)?;
+    let room = db::run(move || {
+        Room::update_with(id, |conn, current| {
+            precondition.check(current)?;
+            merge_patch::apply(conn, current, &body)
+        })
+    })
+    .await?;
+    etag::ok(&room)
+}
+
+#[utoipa::path(
+    post,
+    path = "/rooms/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the room"),
+    ),
+    responses(
+        (status = 200, description = "The restored room", body = Room),
+    )
+)]
+#[post("/rooms/{id}/restore")]
+async fn restore(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /rooms/{}/restore", &id);
+    let res = db::run(move || Room::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
+#[utoipa::path(
+    delete,
+    path = "/rooms/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the room"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted room", body = Room),
+        (status = 412, description = "Modified since the ETag in If-Match"),
+    )
+)]
 #[delete("/rooms/{id}")]
-async fn delete(id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+async fn delete(
+    req: HttpRequest,
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
     let id = id.into_inner();
-    log::trace!("DELETE /rooms/{}", &id);
-    let res = Room::delete(id)?;
+    log::trace!("DELETE /rooms/{} {:?}", &id, &query);
+    let precondition = etag::precondition(&req)?;
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            Room::purge_checked(id, |current| precondition.check(current))
+        } else {
+            Room::delete_checked(id, |current| precondition.check(current))
+        }
+    })
+    .await?;
     Ok(HttpResponse::Ok().json(res))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    find_by_id,
+    find_by_name,
+    find_by_location,
+    create,
+    update,
+    patch,
+    restore,
+    delete
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
     comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(find_by_id);
     comfig.service(find_by_name);
     comfig.service(find_by_location);
     comfig.service(create);
     comfig.service(update);
+    comfig.service(patch);
+    comfig.service(restore);
     comfig.service(delete);
 }
@@ -0,0 +1,136 @@
+use crate::alerts::Alert;
+use crate::asset_tags::AssetTag;
+use crate::assets::Asset;
+use crate::contact_events::{ContactEvent, MaybeContactEvent};
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::locations::Location;
+use crate::schema::{alerts, contact_events, loans, locations, maintenance_plans};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LastSeen {
+    pub location_id: i64,
+    pub location_name: Option<String>,
+    pub seen_at: NaiveDateTime,
+}
+
+// What a scanned label belongs to, with the contact event the scan recorded if any
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ScanSummary {
+    pub asset_tag: AssetTag,
+    pub asset: Option<Asset>,
+    pub contact_event: Option<ContactEvent>,
+    pub last_seen: Option<LastSeen>,
+    pub open_alerts: Vec<Alert>,
+}
+
+fn not_found_as_none<T>(result: Result<T, CustomError>) -> Result<Option<T>, CustomError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.error_status_code == 404 => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn find_asset(asset_tag: &AssetTag) -> Result<Option<Asset>, CustomError> {
+    if let Some(asset_id) = asset_tag.asset_id {
+        if let Some(asset) = not_found_as_none(Asset::find_by_id(asset_id))? {
+            return Ok(Some(asset));
+        }
+    }
+    Ok(Asset::find_by_asset_tag(asset_tag.id)?.into_iter().next())
+}
+
+fn find_last_seen(asset_tag_id: i64) -> Result<Option<LastSeen>, CustomError> {
+    let conn = db::connection()?;
+    let last_seen = contact_events::table
+        .inner_join(locations::table)
+        .select((
+            contact_events::location_id,
+            locations::name,
+            contact_events::created_at,
+        ))
+        .filter(contact_events::asset_tag_id.eq(asset_tag_id))
+        .filter(contact_events::deleted.eq(false))
+        .order((contact_events::created_at.desc(), contact_events::id.desc()))
+        .first::<(i64, Option<String>, NaiveDateTime)>(&conn)
+        .optional()?;
+    Ok(
+        last_seen.map(|(location_id, location_name, seen_at)| LastSeen {
+            location_id,
+            location_name,
+            seen_at,
+        }),
+    )
+}
+
+// Alerts still standing against an asset: overdue open loans and overdue maintenance
+fn find_open_alerts(asset_id: i64) -> Result<Vec<Alert>, CustomError> {
+    let conn = db::connection()?;
+    let mut alert_ids = loans::table
+        .select(loans::alert_id)
+        .filter(loans::asset_id.eq(asset_id))
+        .filter(loans::returned_at.is_null())
+        .filter(loans::alert_id.is_not_null())
+        .load::<Option<i64>>(&conn)?;
+    alert_ids.extend(
+        maintenance_plans::table
+            .select(maintenance_plans::alert_id)
+            .filter(maintenance_plans::asset_id.eq(asset_id))
+            .filter(maintenance_plans::alert_id.is_not_null())
+            .load::<Option<i64>>(&conn)?,
+    );
+    let alerts = alerts::table
+        .filter(alerts::id.eq_any(alert_ids.into_iter().flatten().collect::<Vec<i64>>()))
+        .filter(alerts::deleted.eq(false))
+        .order((alerts::created_at.desc(), alerts::id.desc()))
+        .load::<Alert>(&conn)?;
+    Ok(alerts)
+}
+
+// Look up the asset tag with a serial number, recording a sighting at location_id when given
+pub fn scan(serial_number: String, location_id: Option<i64>) -> Result<ScanSummary, CustomError> {
+    let asset_tag = not_found_as_none(AssetTag::find_by_serial_number(serial_number.clone()))?
+        .ok_or_else(|| {
+            CustomError::new(
+                404,
+                format!("No asset tag has serial number '{}'", serial_number),
+            )
+        })?;
+
+    let contact_event = match location_id {
+        Some(location_id) => {
+            if not_found_as_none(Location::find_by_id(location_id))?.is_none() {
+                return Err(CustomError::new(
+                    400,
+                    format!("Location {} does not exist", location_id),
+                ));
+            }
+            Some(ContactEvent::create(MaybeContactEvent {
+                asset_tag_id: asset_tag.id,
+                location_id: Some(location_id),
+                alert_id: None,
+                deleted: false,
+            })?)
+        }
+        None => None,
+    };
+
+    let asset = find_asset(&asset_tag)?;
+    let last_seen = find_last_seen(asset_tag.id)?;
+    let open_alerts = match &asset {
+        Some(asset) => find_open_alerts(asset.id)?,
+        None => Vec::new(),
+    };
+    Ok(ScanSummary {
+        asset_tag,
+        asset,
+        contact_event,
+        last_seen,
+        open_alerts,
+    })
+}
@@ -0,0 +1,77 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::labels;
+use crate::scan::{self, ScanSummary};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct Sighting {
+    location_id: i64,
+}
+
+// The code segment of the path, decoded once. The router already decodes all of it but '/'
+// and '+', so decoding its match again would turn a "%25" in a serial number into '%'.
+fn code(req: &HttpRequest) -> String {
+    let path = req.uri().path();
+    labels::percent_decode(&path[path.rfind('/').map_or(0, |slash| slash + 1)..])
+}
+
+// The code is a serial number from a barcode or the link in a label's QR code
+#[utoipa::path(
+    get,
+    path = "/scan/{code}",
+    params(
+        ("code" = String, Path, description = "A serial number or the link in a label's QR code"),
+    ),
+    responses(
+        (status = 200, description = "What the label belongs to", body = ScanSummary),
+    )
+)]
+#[get("/scan/{code}")]
+async fn find_by_code(req: HttpRequest) -> Result<HttpResponse, CustomError> {
+    let code = code(&req);
+    log::trace!("GET /scan/{}", &code);
+    let serial_number = labels::serial_number(&code);
+    let summary = db::run(move || scan::scan(serial_number, None)).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Like find_by_code, recording a sighting of the label at a location first
+#[utoipa::path(
+    post,
+    path = "/scan/{code}",
+    params(
+        ("code" = String, Path, description = "A serial number or the link in a label's QR code"),
+    ),
+    request_body = Sighting,
+    responses(
+        (status = 200, description = "What the label belongs to, with the recorded contact event", body = ScanSummary),
+    )
+)]
+#[post("/scan/{code}")]
+async fn record(
+    req: HttpRequest,
+    sighting: web::Json<Sighting>,
+) -> Result<HttpResponse, CustomError> {
+    let code = code(&req);
+    log::trace!("POST /scan/{} {:?}", &code, &sighting);
+    let serial_number = labels::serial_number(&code);
+    let location_id = sighting.location_id;
+    let summary = db::run(move || scan::scan(serial_number, Some(location_id))).await?;
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(find_by_code, record))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_by_code);
+    comfig.service(record);
+}
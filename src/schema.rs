@@ -6,6 +6,8 @@ table! {
         user_id -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -15,6 +17,8 @@ table! {
         name -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -28,6 +32,7 @@ table! {
         updated_at -> Timestamp,
         asset_id -> Nullable<Int8>,
         deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -38,6 +43,33 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    attachments (id) {
+        id -> Int8,
+        asset_id -> Int8,
+        user_id -> Int8,
+        filename -> Varchar,
+        content_type -> Varchar,
+        size -> Int8,
+        sha256 -> Varchar,
+        storage_key -> Varchar,
+        thumbnail_key -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    comment_revisions (id) {
+        id -> Int8,
+        comment_id -> Int8,
+        user_id -> Int8,
+        content -> Text,
+        created_at -> Timestamp,
     }
 }
 
@@ -46,9 +78,15 @@ table! {
         id -> Int8,
         content -> Text,
         user_id -> Int8,
-        asset_tag_id -> Int8,
+        asset_tag_id -> Nullable<Int8>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        asset_id -> Nullable<Int8>,
+        location_id -> Nullable<Int8>,
+        alert_id -> Nullable<Int8>,
+        parent_id -> Nullable<Int8>,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -61,6 +99,40 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    feed_tokens (user_id) {
+        user_id -> Int8,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    loans (id) {
+        id -> Int8,
+        asset_id -> Int8,
+        user_id -> Int8,
+        location_id -> Nullable<Int8>,
+        room_id -> Nullable<Int8>,
+        alert_id -> Nullable<Int8>,
+        due_at -> Timestamp,
+        returned_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    location_subnets (id) {
+        id -> Int8,
+        location_id -> Int8,
+        subnet -> Inet,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -73,6 +145,53 @@ table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         ip -> Nullable<Inet>,
+        parent_id -> Nullable<Int8>,
+        kind -> Varchar,
+        footprint -> Nullable<Jsonb>,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    maintenance_plans (id) {
+        id -> Int8,
+        asset_id -> Int8,
+        user_id -> Int8,
+        alert_id -> Nullable<Int8>,
+        name -> Varchar,
+        interval_days -> Int4,
+        next_due_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    maintenance_records (id) {
+        id -> Int8,
+        asset_id -> Int8,
+        maintenance_plan_id -> Nullable<Int8>,
+        user_id -> Int8,
+        performed_at -> Timestamp,
+        notes -> Nullable<Text>,
+        certificate -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    reservations (id) {
+        id -> Int8,
+        asset_id -> Int8,
+        user_id -> Int8,
+        series_id -> Nullable<Int8>,
+        starts_at -> Timestamp,
+        ends_at -> Timestamp,
+        note -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -83,6 +202,8 @@ table! {
         user_id -> Nullable<Int8>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -93,6 +214,8 @@ table! {
         location_id -> Int8,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
@@ -103,15 +226,39 @@ table! {
         token -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        deleted -> Bool,
+        deleted_at -> Nullable<Timestamp>,
     }
 }
 
 joinable!(alerts -> users (user_id));
+joinable!(attachments -> assets (asset_id));
+joinable!(attachments -> users (user_id));
+joinable!(comment_revisions -> comments (comment_id));
+joinable!(comment_revisions -> users (user_id));
+joinable!(comments -> alerts (alert_id));
 joinable!(comments -> asset_tags (asset_tag_id));
+joinable!(comments -> assets (asset_id));
+joinable!(comments -> locations (location_id));
 joinable!(comments -> users (user_id));
 joinable!(contact_events -> alerts (alert_id));
 joinable!(contact_events -> asset_tags (asset_tag_id));
 joinable!(contact_events -> locations (location_id));
+joinable!(feed_tokens -> users (user_id));
+joinable!(loans -> alerts (alert_id));
+joinable!(loans -> assets (asset_id));
+joinable!(loans -> locations (location_id));
+joinable!(loans -> rooms (room_id));
+joinable!(loans -> users (user_id));
+joinable!(location_subnets -> locations (location_id));
+joinable!(maintenance_plans -> alerts (alert_id));
+joinable!(maintenance_plans -> assets (asset_id));
+joinable!(maintenance_plans -> users (user_id));
+joinable!(maintenance_records -> assets (asset_id));
+joinable!(maintenance_records -> maintenance_plans (maintenance_plan_id));
+joinable!(maintenance_records -> users (user_id));
+joinable!(reservations -> assets (asset_id));
+joinable!(reservations -> users (user_id));
 joinable!(roles -> users (user_id));
 joinable!(rooms -> locations (location_id));
 
@@ -120,9 +267,17 @@ allow_tables_to_appear_in_same_query!(
     asset_scanners,
     asset_tags,
     assets,
+    attachments,
+    comment_revisions,
     comments,
     contact_events,
+    feed_tokens,
+    loans,
+    location_subnets,
     locations,
+    maintenance_plans,
+    maintenance_records,
+    reservations,
     roles,
     rooms,
     users,
@@ -0,0 +1,127 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Float4, Nullable, Text};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/*
+ * Full-text search across asset tags, attachments, locations, rooms and comments
+ *
+ * Each table is searched by a tsvector document whose expression matches one of
+ * the GIN indexes of the create_search_indexes migration, so keep the two in sync.
+ * Queries use websearch syntax: words, "quoted phrases", OR and -excluded words.
+ */
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+const ASSET_TAG_DOCUMENT: &str = "setweight(to_tsvector('english', name), 'A') \
+    || setweight(to_tsvector('english', serial_number), 'A') \
+    || setweight(to_tsvector('english', coalesce(description, '')), 'B')";
+const ATTACHMENT_DOCUMENT: &str = "to_tsvector('english', filename)";
+const LOCATION_DOCUMENT: &str = "to_tsvector('english', coalesce(name, ''))";
+const ROOM_DOCUMENT: &str = "to_tsvector('english', name)";
+const COMMENT_DOCUMENT: &str = "to_tsvector('english', content)";
+
+const HEADLINE_OPTIONS: &str = "StartSel=<mark>, StopSel=</mark>, MaxFragments=2";
+
+// The text escaped for HTML, so that only the <mark>s of its headline are markup
+fn escaped(text: &str) -> String {
+    [
+        ("&", "&amp;"),
+        ("<", "&lt;"),
+        (">", "&gt;"),
+        ("\"", "&quot;"),
+        ("''", "&#39;"),
+    ]
+    .iter()
+    .fold(text.to_string(), |text, (from, to)| {
+        format!("replace({}, '{}', '{}')", text, from, to)
+    })
+}
+
+// A match of one of the searched tables, with the asset it belongs to if any
+#[derive(Debug, Serialize, Deserialize, QueryableByName, ToSchema)]
+pub struct SearchResult {
+    #[sql_type = "Text"]
+    pub kind: String,
+    #[sql_type = "BigInt"]
+    pub id: i64,
+    #[sql_type = "Nullable<BigInt>"]
+    pub asset_id: Option<i64>,
+    #[sql_type = "Text"]
+    pub title: String,
+    #[sql_type = "Text"]
+    pub snippet: String,
+    #[sql_type = "Float4"]
+    pub rank: f32,
+}
+
+// Best matches first, with the query words of each HTML-escaped snippet wrapped in <mark>
+pub fn search(q: &str, limit: Option<i64>) -> Result<Vec<SearchResult>, CustomError> {
+    let q = q.trim();
+    if q.is_empty() {
+        return Err(CustomError::new(
+            400,
+            String::from("The search query must not be empty"),
+        ));
+    }
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(CustomError::new(
+            400,
+            format!("The limit must be between 1 and {}", MAX_LIMIT),
+        ));
+    }
+
+    let sql = format!(
+        "WITH query AS (SELECT websearch_to_tsquery('english', $1) AS q)
+        SELECT kind, id, asset_id, title, snippet, rank FROM (
+            SELECT 'asset_tag' AS kind, asset_tags.id, asset_tags.asset_id, asset_tags.name AS title,
+                ts_headline('english', {asset_tag_text}, q, '{headline}') AS snippet,
+                ts_rank({asset_tag}, q) AS rank
+            FROM asset_tags, query WHERE NOT asset_tags.deleted AND {asset_tag} @@ q
+            UNION ALL
+            SELECT 'attachment', attachments.id, attachments.asset_id, filename,
+                ts_headline('english', {attachment_text}, q, '{headline}'),
+                ts_rank({attachment}, q)
+            FROM attachments JOIN assets ON assets.id = attachments.asset_id, query
+            WHERE NOT assets.deleted AND {attachment} @@ q
+            UNION ALL
+            SELECT 'location', locations.id, NULL, coalesce(name, ''),
+                ts_headline('english', {location_text}, q, '{headline}'),
+                ts_rank({location}, q)
+            FROM locations, query WHERE NOT locations.deleted AND {location} @@ q
+            UNION ALL
+            SELECT 'room', rooms.id, NULL, name,
+                ts_headline('english', {room_text}, q, '{headline}'),
+                ts_rank({room}, q)
+            FROM rooms, query WHERE NOT rooms.deleted AND {room} @@ q
+            UNION ALL
+            SELECT 'comment', comments.id, comments.asset_id, left(content, 80),
+                ts_headline('english', {comment_text}, q, '{headline}'),
+                ts_rank({comment}, q)
+            FROM comments, query WHERE NOT comments.deleted AND {comment} @@ q
+        ) results
+        ORDER BY rank DESC, kind, id
+        LIMIT $2",
+        headline = HEADLINE_OPTIONS,
+        asset_tag_text = escaped("concat_ws(' ', name, serial_number, description)"),
+        attachment_text = escaped("filename"),
+        location_text = escaped("coalesce(name, '')"),
+        room_text = escaped("name"),
+        comment_text = escaped("content"),
+        asset_tag = ASSET_TAG_DOCUMENT,
+        attachment = ATTACHMENT_DOCUMENT,
+        location = LOCATION_DOCUMENT,
+        room = ROOM_DOCUMENT,
+        comment = COMMENT_DOCUMENT,
+    );
+    let conn = db::connection()?;
+    let results = diesel::sql_query(sql)
+        .bind::<Text, _>(q)
+        .bind::<BigInt, _>(limit)
+        .load::<SearchResult>(&conn)?;
+    Ok(results)
+}
@@ -0,0 +1,43 @@
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::search::{self, SearchResult};
+use actix_web::{get, web, HttpResponse};
+use serde::Deserialize;
+use utoipa::{IntoParams, OpenApi};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(
+        SearchQuery,
+    ),
+    responses(
+        (status = 200, description = "The best matches first", body = [SearchResult]),
+    )
+)]
+#[get("/search")]
+async fn find_all(query: web::Query<SearchQuery>) -> Result<HttpResponse, CustomError> {
+    let query = query.into_inner();
+    log::trace!("GET /search {:?}", &query);
+    let results = db::run(move || search::search(&query.q, query.limit)).await?;
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(find_all))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+}
@@ -0,0 +1,365 @@
+use crate::error_handler::CustomError;
+use crate::users::User;
+use chrono::NaiveDateTime;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool, Timestamp};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+/*
+ * Soft-deleting, restoring and purging rows
+ *
+ * Every table soft-deletes by setting deleted and deleted_at. Rows that belong
+ * to a soft-deleted row are soft-deleted along with it at the same deleted_at,
+ * which is how restoring the row knows which of them to bring back.
+ *
+ * A row can only be purged once no other row references it, so each table
+ * lists the foreign keys pointing at it. Soft-deleted rows still count as
+ * references, since the database enforces them all the same.
+ */
+
+// A foreign key column in another table that references the purged table's id
+pub struct Reference {
+    pub table: &'static str,
+    pub column: &'static str,
+}
+
+// Which rows belong to a row of a table, and are soft-deleted and restored with it
+const CASCADES: &[(&str, Reference)] = &[
+    (
+        "assets",
+        Reference {
+            table: "asset_tags",
+            column: "asset_id",
+        },
+    ),
+    (
+        "assets",
+        Reference {
+            table: "comments",
+            column: "asset_id",
+        },
+    ),
+    (
+        "asset_tags",
+        Reference {
+            table: "contact_events",
+            column: "asset_tag_id",
+        },
+    ),
+    (
+        "asset_tags",
+        Reference {
+            table: "comments",
+            column: "asset_tag_id",
+        },
+    ),
+    (
+        "alerts",
+        Reference {
+            table: "comments",
+            column: "alert_id",
+        },
+    ),
+    (
+        "comments",
+        Reference {
+            table: "comments",
+            column: "parent_id",
+        },
+    ),
+    (
+        "locations",
+        Reference {
+            table: "locations",
+            column: "parent_id",
+        },
+    ),
+    (
+        "locations",
+        Reference {
+            table: "rooms",
+            column: "location_id",
+        },
+    ),
+    (
+        "locations",
+        Reference {
+            table: "comments",
+            column: "location_id",
+        },
+    ),
+    (
+        "users",
+        Reference {
+            table: "roles",
+            column: "user_id",
+        },
+    ),
+    (
+        "users",
+        Reference {
+            table: "alerts",
+            column: "user_id",
+        },
+    ),
+];
+
+// DELETE soft-deletes unless ?purge=true asks for a hard delete
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteQuery {
+    #[serde(default)]
+    pub purge: bool,
+}
+
+// Purging cannot be undone, so only admins may do it
+pub fn authorize_purge(user: &User) -> Result<(), CustomError> {
+    user.authorize_admin()
+}
+
+#[derive(QueryableByName)]
+struct Referenced {
+    #[sql_type = "Bool"]
+    referenced: bool,
+}
+
+#[derive(QueryableByName)]
+struct Id {
+    #[sql_type = "BigInt"]
+    id: i64,
+}
+
+fn propagate(
+    conn: &PgConnection,
+    table: &str,
+    id: i64,
+    deleted_at: NaiveDateTime,
+    deleted: bool,
+) -> Result<(), CustomError> {
+    for (_, reference) in CASCADES.iter().filter(|(parent, _)| *parent == table) {
+        let query = if deleted {
+            format!(
+                "UPDATE {} SET deleted = TRUE, deleted_at = $2 \
+                 WHERE {} = $1 AND NOT deleted RETURNING id",
+                reference.table, reference.column
+            )
+        } else {
+            format!(
+                "UPDATE {} SET deleted = FALSE, deleted_at = NULL \
+                 WHERE {} = $1 AND deleted AND deleted_at = $2 RETURNING id",
+                reference.table, reference.column
+            )
+        };
+        let ids: Vec<Id> = diesel::sql_query(query)
+            .bind::<BigInt, _>(id)
+            .bind::<Timestamp, _>(deleted_at)
+            .load(conn)?;
+        for child in ids {
+            propagate(conn, reference.table, child.id, deleted_at, deleted)?;
+        }
+    }
+    Ok(())
+}
+
+// Soft-delete what belongs to a row that was just soft-deleted
+pub fn cascade(
+    conn: &PgConnection,
+    table: &str,
+    id: i64,
+    deleted_at: Option<NaiveDateTime>,
+) -> Result<(), CustomError> {
+    match deleted_at {
+        Some(deleted_at) => propagate(conn, table, id, deleted_at, true),
+        None => Ok(()),
+    }
+}
+
+// Restore what was soft-deleted along with a row that is being restored
+pub fn restore_cascade(
+    conn: &PgConnection,
+    table: &str,
+    id: i64,
+    deleted_at: Option<NaiveDateTime>,
+) -> Result<(), CustomError> {
+    match deleted_at {
+        Some(deleted_at) => propagate(conn, table, id, deleted_at, false),
+        None => Ok(()),
+    }
+}
+
+// Refuse to purge a row that other rows still reference, naming their tables
+pub fn check_unreferenced(
+    conn: &PgConnection,
+    references: &[Reference],
+    name: &str,
+    id: i64,
+) -> Result<(), CustomError> {
+    let mut tables: Vec<&str> = Vec::new();
+    for reference in references {
+        let referenced: Referenced = diesel::sql_query(format!(
+            "SELECT EXISTS (SELECT 1 FROM {} WHERE {} = $1) AS referenced",
+            reference.table, reference.column
+        ))
+        .bind::<BigInt, _>(id)
+        .get_result(conn)?;
+        if referenced.referenced && !tables.contains(&reference.table) {
+            tables.push(reference.table);
+        }
+    }
+    if tables.is_empty() {
+        Ok(())
+    } else {
+        Err(CustomError::new(
+            409,
+            format!(
+                "{} {} is still referenced by {}",
+                name,
+                id,
+                tables.join(", ")
+            ),
+        ))
+    }
+}
+
+// Purge the unreferenced rows of a table that were soft-deleted before a time
+pub fn purge_expired(
+    conn: &PgConnection,
+    table: &str,
+    references: &[Reference],
+    before: NaiveDateTime,
+) -> Result<usize, CustomError> {
+    let unreferenced: String = references
+        .iter()
+        .map(|reference| {
+            format!(
+                " AND NOT EXISTS (SELECT 1 FROM {} r WHERE r.{} = t.id)",
+                reference.table, reference.column
+            )
+        })
+        .collect();
+    // Rows flagged through an update rather than a delete have no deleted_at
+    let purged = diesel::sql_query(format!(
+        "DELETE FROM {} t WHERE t.deleted AND COALESCE(t.deleted_at, t.updated_at) < $1{}",
+        table, unreferenced
+    ))
+    .bind::<Timestamp, _>(before)
+    .execute(conn)?;
+    Ok(purged)
+}
+
+// The soft-delete, restore and purge methods every soft-deleting model shares,
+// given its schema module, its row type, its name in errors and its references,
+// along with lock, which the model's own writes lock the row with
+macro_rules! soft_delete_methods {
+    ($table:ident, $model:ty, $name:expr, $references:expr) => {
+        pub fn find_with_deleted() -> Result<Vec<Self>, $crate::error_handler::CustomError> {
+            let conn = $crate::db::connection()?;
+            let rows = $table::table.load::<$model>(&conn)?;
+            Ok(rows)
+        }
+
+        pub fn find_deleted() -> Result<Vec<Self>, $crate::error_handler::CustomError> {
+            let conn = $crate::db::connection()?;
+            let rows = $table::table
+                .filter($table::deleted.eq(true))
+                .load::<$model>(&conn)?;
+            Ok(rows)
+        }
+
+        // The row, locked until the transaction on conn ends, so what a write
+        // checks of it still holds when the write commits
+        fn lock(
+            conn: &diesel::pg::PgConnection,
+            id: i64,
+        ) -> Result<Self, $crate::error_handler::CustomError> {
+            let row = $table::table
+                .filter($table::id.eq(id))
+                .filter($table::deleted.eq(false))
+                .for_update()
+                .first(conn)?;
+            Ok(row)
+        }
+
+        pub fn delete(id: i64) -> Result<Self, $crate::error_handler::CustomError> {
+            Self::delete_checked(id, |_| Ok(()))
+        }
+
+        // Soft-delete, unless check refuses the row as it is once locked
+        pub fn delete_checked<F>(
+            id: i64,
+            check: F,
+        ) -> Result<Self, $crate::error_handler::CustomError>
+        where
+            F: FnOnce(&Self) -> Result<(), $crate::error_handler::CustomError>,
+        {
+            let conn = $crate::db::connection()?;
+            conn.transaction(|| {
+                check(&Self::lock(&conn, id)?)?;
+                let row: $model = diesel::update($table::table.find(id))
+                    .set((
+                        $table::deleted.eq(true),
+                        $table::deleted_at.eq(diesel::dsl::now.nullable()),
+                    ))
+                    .get_result(&conn)?;
+                $crate::soft_delete::cascade(&conn, stringify!($table), row.id, row.deleted_at)?;
+                Ok(row)
+            })
+        }
+
+        pub fn restore(id: i64) -> Result<Self, $crate::error_handler::CustomError> {
+            let conn = $crate::db::connection()?;
+            conn.transaction(|| {
+                let row: $model = $table::table
+                    .filter($table::id.eq(id))
+                    .filter($table::deleted.eq(true))
+                    .first(&conn)?;
+                $crate::soft_delete::restore_cascade(
+                    &conn,
+                    stringify!($table),
+                    row.id,
+                    row.deleted_at,
+                )?;
+                let row = diesel::update($table::table.find(id))
+                    .set((
+                        $table::deleted.eq(false),
+                        $table::deleted_at.eq(None::<chrono::NaiveDateTime>),
+                    ))
+                    .get_result(&conn)?;
+                Ok(row)
+            })
+        }
+
+        // Hard-delete, whether or not it was soft-deleted first
+        pub fn purge(id: i64) -> Result<Self, $crate::error_handler::CustomError> {
+            Self::purge_checked(id, |_| Ok(()))
+        }
+
+        // Hard-delete, unless check refuses the row as it is once locked
+        pub fn purge_checked<F>(
+            id: i64,
+            check: F,
+        ) -> Result<Self, $crate::error_handler::CustomError>
+        where
+            F: FnOnce(&Self) -> Result<(), $crate::error_handler::CustomError>,
+        {
+            let conn = $crate::db::connection()?;
+            conn.transaction(|| {
+                check(&$table::table.find(id).for_update().first::<$model>(&conn)?)?;
+                $crate::soft_delete::check_unreferenced(&conn, $references, $name, id)?;
+                let row = diesel::delete($table::table.find(id)).get_result(&conn)?;
+                Ok(row)
+            })
+        }
+
+        pub fn purge_expired(
+            before: chrono::NaiveDateTime,
+        ) -> Result<usize, $crate::error_handler::CustomError> {
+            let conn = $crate::db::connection()?;
+            $crate::soft_delete::purge_expired(&conn, stringify!($table), $references, before)
+        }
+    };
+}
+
+pub(crate) use soft_delete_methods;
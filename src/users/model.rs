@@ -1,15 +1,25 @@
 use crate::db;
 use crate::error_handler::CustomError;
-use crate::schema::users;
+use crate::pagination::{paginate, Page, PageQuery};
+use crate::roles::ADMIN;
+use crate::schema::{feed_tokens, roles, users};
+use crate::soft_delete::{soft_delete_methods, Reference};
+use crate::validation::MAX_NAME;
 use chrono::NaiveDateTime;
 use crypto::bcrypt;
 use crypto::buffer::{BufferResult, ReadBuffer, WriteBuffer};
 use crypto::{aes, blockmodes, buffer, symmetriccipher};
+use diesel::dsl::now;
+use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use lazy_static::lazy_static;
 use rand::{RngCore, SeedableRng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
+use utoipa::ToSchema;
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
 
 /*
  * 1. All endpoints require Bearer Token Authentication
@@ -136,22 +146,45 @@ fn symmetric_decrypt(
     Ok(result)
 }
 
-#[derive(Debug, Identifiable, Queryable, AsChangeset, Insertable)]
+#[derive(Debug, Clone, Serialize, Identifiable, Queryable, AsChangeset, Insertable, ToSchema)]
 #[table_name = "users"]
 pub struct User {
     pub id: i64,
     pub username: String,
+    #[serde(skip_serializing)]
     pub token: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub deleted: bool,
+    pub deleted_at: Option<NaiveDateTime>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct AuthUser {
     pub id: i64,
     pub token: String,
 }
 
+// A token that only reads calendar feeds, shown once when it is issued
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct FeedToken {
+    pub user_id: i64,
+    pub token: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "feed_tokens"]
+struct NewFeedToken {
+    user_id: i64,
+    token_hash: String,
+}
+
+// Feed tokens are random, so a fast hash keeps a leaked table from giving them away
+fn hash_feed_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
 #[derive(Serialize, AsChangeset, Insertable)]
 #[table_name = "users"]
 pub struct InsertableUser {
@@ -159,13 +192,82 @@ pub struct InsertableUser {
     pub token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+// Tokens join the username to the rest with $, so usernames can't have one
+fn validate_username(username: &str) -> Result<(), ValidationError> {
+    match username.contains('$') {
+        false => Ok(()),
+        true => Err(ValidationError::new("username").with_message("must not contain $".into())),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema, Validate)]
+#[validate(context = PgConnection)]
 pub struct MaybeUser {
+    #[validate(length(min = 1, max = MAX_NAME), custom(function = "validate_username"))]
     pub username: String,
+    #[validate(length(min = 1, max = MAX_NAME))]
     pub password: String,
 }
 
+// Foreign keys that keep a user from being purged
+const REFERENCES: &[Reference] = &[
+    Reference {
+        table: "alerts",
+        column: "user_id",
+    },
+    Reference {
+        table: "attachments",
+        column: "user_id",
+    },
+    Reference {
+        table: "comment_revisions",
+        column: "user_id",
+    },
+    Reference {
+        table: "comments",
+        column: "user_id",
+    },
+    Reference {
+        table: "loans",
+        column: "user_id",
+    },
+    Reference {
+        table: "maintenance_plans",
+        column: "user_id",
+    },
+    Reference {
+        table: "maintenance_records",
+        column: "user_id",
+    },
+    Reference {
+        table: "reservations",
+        column: "user_id",
+    },
+    Reference {
+        table: "roles",
+        column: "user_id",
+    },
+];
+
 impl User {
+    pub fn find_all() -> Result<Vec<Self>, CustomError> {
+        let conn = db::connection()?;
+        let users = users::table
+            .filter(users::deleted.eq(false))
+            .load::<User>(&conn)?;
+        Ok(users)
+    }
+
+    pub fn find_page(page: &PageQuery) -> Result<Page<Self>, CustomError> {
+        let conn = db::connection()?;
+        paginate!(
+            users::table.filter(users::deleted.eq(false)),
+            users::id,
+            page,
+            &conn
+        )
+    }
+
     // This is an external token not an internal token, which would just be a bcrypted password
     pub fn find_by_token(token: String) -> Result<Self, CustomError> {
         // Token should be base64 encoded string "username$token"
@@ -175,15 +277,13 @@ impl User {
         let key: [u8; 32] = secret[0..32].try_into()?;
         let iv: [u8; 16] = secret[32..48].try_into()?;
 
-        let message = symmetric_decrypt(token.as_slice(), &key, &iv)?;
+        let message = symmetric_decrypt(token.as_slice(), &key, &iv)
+            .map_err(|_| CustomError::malformed_token())?;
         let message = String::from_utf8(message)?;
         log::trace!("Parsing token: [{}]", message);
         let parts: Vec<&str> = message.split("$").collect();
         if parts.len() != 5 {
-            return Err(CustomError {
-                error_message: String::from("Unauthorized"),
-                error_status_code: 401,
-            });
+            return Err(CustomError::malformed_token());
         }
         let username = parts[0];
         let password = parts[1..].join("$");
@@ -197,10 +297,54 @@ impl User {
         let user = users::table
             .filter(users::username.eq(username))
             .filter(users::token.eq(password))
+            .filter(users::deleted.eq(false))
+            .first(&conn)?;
+        Ok(user)
+    }
+
+    // Calendar apps put the token in the feed URL, so it is not the API token
+    pub fn find_by_feed_token(token: String) -> Result<Self, CustomError> {
+        let conn = db::connection()?;
+        let user = users::table
+            .inner_join(feed_tokens::table)
+            .filter(feed_tokens::token_hash.eq(hash_feed_token(&token)))
+            .filter(users::deleted.eq(false))
+            .select(users::all_columns)
             .first(&conn)?;
         Ok(user)
     }
 
+    // Issuing a feed token revokes the one issued before
+    pub fn issue_feed_token(id: i64) -> Result<FeedToken, CustomError> {
+        let conn = db::connection()?;
+        let token = Uuid::new_v4().to_simple().to_string();
+        let feed_token = NewFeedToken {
+            user_id: id,
+            token_hash: hash_feed_token(&token),
+        };
+        let created_at = diesel::insert_into(feed_tokens::table)
+            .values(&feed_token)
+            .on_conflict(feed_tokens::user_id)
+            .do_update()
+            .set((
+                feed_tokens::token_hash.eq(&feed_token.token_hash),
+                feed_tokens::created_at.eq(now),
+            ))
+            .returning(feed_tokens::created_at)
+            .get_result(&conn)?;
+        Ok(FeedToken {
+            user_id: id,
+            token,
+            created_at,
+        })
+    }
+
+    pub fn revoke_feed_token(id: i64) -> Result<usize, CustomError> {
+        let conn = db::connection()?;
+        let res = diesel::delete(feed_tokens::table.find(id)).execute(&conn)?;
+        Ok(res)
+    }
+
     pub fn update(id: i64, user: MaybeUser) -> Result<Self, CustomError> {
         let conn = db::connection()?;
         let db_token = Self::internal_token(user.username.clone(), user.password)?;
@@ -216,6 +360,7 @@ impl User {
         };
         let user = diesel::update(users::table)
             .filter(users::id.eq(id))
+            .filter(users::deleted.eq(false))
             .filter(users::username.eq(user.username))
             .set(insertable_user)
             .get_result(&conn)?;
@@ -234,10 +379,36 @@ impl User {
         Ok(user)
     }
 
-    pub fn delete(id: i64) -> Result<usize, CustomError> {
+    soft_delete_methods!(users, User, "User", REFERENCES);
+
+    pub fn is_admin(&self) -> Result<bool, CustomError> {
         let conn = db::connection()?;
-        let res = diesel::delete(users::table.filter(users::id.eq(id))).execute(&conn)?;
-        Ok(res)
+        let is_admin = diesel::select(diesel::dsl::exists(
+            roles::table
+                .filter(roles::user_id.eq(self.id))
+                .filter(roles::name.eq(ADMIN))
+                .filter(roles::deleted.eq(false)),
+        ))
+        .get_result(&conn)?;
+        Ok(is_admin)
+    }
+
+    // Only an admin may act on another user's account
+    pub fn authorize(&self, id: i64) -> Result<(), CustomError> {
+        if self.id == id || self.is_admin()? {
+            Ok(())
+        } else {
+            Err(CustomError::new(403, String::from("Forbidden")))
+        }
+    }
+
+    // Fails with 403 unless the user is an admin, such as for managing the roles that make one
+    pub fn authorize_admin(&self) -> Result<(), CustomError> {
+        if self.is_admin()? {
+            Ok(())
+        } else {
+            Err(CustomError::new(403, String::from("Forbidden")))
+        }
     }
 
     pub fn count() -> Result<i64, CustomError> {
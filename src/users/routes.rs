@@ -1,34 +1,87 @@
+use crate::db;
 use crate::error_handler::CustomError;
-use crate::users::{AuthUser, MaybeUser, User};
-use actix_web::{dev::Payload, post, put, web, FromRequest, HttpRequest, HttpResponse};
+use crate::soft_delete::{self, DeleteQuery};
+use crate::users::{AuthUser, FeedToken, MaybeUser, User};
+use crate::validation;
+use actix_web::{
+    delete, dev::Payload, get, post, put, web, FromRequest, HttpRequest, HttpResponse,
+};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
-use futures::executor::block_on;
-use futures_util::future::{err, ok, Ready};
+use futures::future::{FutureExt, LocalBoxFuture};
 use log;
 use std::convert::TryInto;
+use utoipa::OpenApi;
 
 impl FromRequest for User {
     type Error = CustomError;
-    type Future = Ready<Result<Self, Self::Error>>;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
     type Config = ();
 
     fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
-        let bearer_auth = block_on(BearerAuth::from_request(req, payload));
-        let bearer_auth = match bearer_auth {
-            Ok(auth) => auth,
-            Err(error) => return err(Self::Error::from(error)),
-        };
-        let user = match User::find_by_token(String::from(bearer_auth.token())) {
-            Ok(user) => user,
-            Err(error) => match error.error_status_code {
-                404 => return err(CustomError::new(401, String::from("Unauthorized"))),
-                _ => return err(Self::Error::from(error)),
-            },
-        };
-        ok(user)
+        let bearer_auth = BearerAuth::from_request(req, payload);
+        async move {
+            let token = String::from(bearer_auth.await?.token());
+            match db::run(move || User::find_by_token(token)).await {
+                Err(error) if error.error_status_code == 404 => {
+                    Err(CustomError::new(401, String::from("Unauthorized")))
+                }
+                res => res,
+            }
+        }
+        .boxed_local()
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/users",
+    responses(
+        (status = 200, description = "The users", body = [User]),
+    )
+)]
+#[get("/users")]
+async fn find_all(_user: User) -> Result<HttpResponse, CustomError> {
+    let users = db::run(User::find_all).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/all",
+    responses(
+        (status = 200, description = "The users, deleted or not", body = [User]),
+    )
+)]
+#[get("/users/all")]
+async fn find_with_deleted(_user: User) -> Result<HttpResponse, CustomError> {
+    let users = db::run(User::find_with_deleted).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/deleted",
+    responses(
+        (status = 200, description = "The deleted users", body = [User]),
+    )
+)]
+#[get("/users/deleted")]
+async fn find_deleted(_user: User) -> Result<HttpResponse, CustomError> {
+    let users = db::run(User::find_deleted).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    request_body = MaybeUser,
+    responses(
+        (status = 200, description = "The updated user with a new token", body = AuthUser),
+    )
+)]
 #[put("/users/{id}")]
 async fn update(
     user: User,
@@ -41,26 +94,142 @@ async fn update(
         return Err(CustomError::new(401, String::from("Unauthorized")));
     }
     let maybe_user = maybe_user.into_inner();
-    let user = User::update(id, maybe_user)?;
+    let maybe_user = validation::validated(maybe_user).await?;
+    let user = db::run(move || User::update(id, maybe_user)).await?;
     let auth_user: AuthUser = user.try_into()?;
     Ok(HttpResponse::Ok().json(auth_user))
 }
 
+#[utoipa::path(
+    post,
+    path = "/users",
+    request_body = MaybeUser,
+    responses(
+        (status = 200, description = "The created user with its token", body = AuthUser),
+    )
+)]
 #[post("/users")]
 async fn create(user: web::Json<MaybeUser>) -> Result<HttpResponse, CustomError> {
     let user = user.into_inner();
     log::trace!("POST /users");
-    let user = User::create(user)?;
+    let user = validation::validated(user).await?;
+    let user = db::run(move || User::create(user)).await?;
     let auth_user: AuthUser = user.try_into()?;
     Ok(HttpResponse::Ok().json(auth_user))
 }
 
+#[utoipa::path(
+    post,
+    path = "/users/{id}/restore",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The restored user", body = User),
+    )
+)]
+#[post("/users/{id}/restore")]
+async fn restore(user: User, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /users/{}/restore", &id);
+    // A deleted user cannot log in to restore themselves
+    db::run(move || user.authorize_admin()).await?;
+    let res = db::run(move || User::restore(id)).await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+        DeleteQuery,
+    ),
+    responses(
+        (status = 200, description = "The deleted user", body = User),
+    )
+)]
+#[delete("/users/{id}")]
+async fn delete(
+    user: User,
+    id: web::Path<i64>,
+    query: web::Query<DeleteQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /users/{} {:?}", &id, &query);
+    let purge = query.purge;
+    let res = db::run(move || {
+        if purge {
+            soft_delete::authorize_purge(&user)?;
+            User::purge(id)
+        } else {
+            user.authorize(id)?;
+            User::delete(id)
+        }
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/{id}/feed_token",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "A new token for the user's calendar feeds, replacing the last one", body = FeedToken),
+    )
+)]
+#[post("/users/{id}/feed_token")]
+async fn issue_feed_token(user: User, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("POST /users/{}/feed_token", &id);
+    let feed_token = db::run(move || {
+        user.authorize(id)?;
+        User::issue_feed_token(id)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(feed_token))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{id}/feed_token",
+    params(
+        ("id" = i64, Path, description = "Id of the user"),
+    ),
+    responses(
+        (status = 200, description = "The number of revoked feed tokens", body = usize),
+    )
+)]
+#[delete("/users/{id}/feed_token")]
+async fn revoke_feed_token(user: User, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("DELETE /users/{}/feed_token", &id);
+    let res = db::run(move || {
+        user.authorize(id)?;
+        User::revoke_feed_token(id)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(res))
+}
+
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = MaybeUser,
+    responses(
+        (status = 200, description = "The user with a new token", body = AuthUser),
+    )
+)]
 #[post("/login")]
 async fn login(user: web::Json<MaybeUser>) -> Result<HttpResponse, CustomError> {
     let user = user.into_inner();
     log::trace!("POST /login");
     let user_clone = user.clone();
-    let auth_user: AuthUser = match user.try_into() {
+    let auth_user = db::run(move || -> Result<AuthUser, CustomError> { user.try_into() }).await;
+    let auth_user = match auth_user {
         Ok(user) => user,
         Err(err) => {
             log::trace!("Login for '{}' failed with {:?}", user_clone.username, err);
@@ -70,8 +239,34 @@ async fn login(user: web::Json<MaybeUser>) -> Result<HttpResponse, CustomError>
     Ok(HttpResponse::Ok().json(auth_user))
 }
 
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_all,
+    find_with_deleted,
+    find_deleted,
+    update,
+    create,
+    restore,
+    delete,
+    issue_feed_token,
+    revoke_feed_token,
+    login
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
 pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_all);
+    comfig.service(find_with_deleted);
+    comfig.service(find_deleted);
     comfig.service(update);
     comfig.service(create);
+    comfig.service(restore);
+    comfig.service(delete);
+    comfig.service(issue_feed_token);
+    comfig.service(revoke_feed_token);
     comfig.service(login);
 }
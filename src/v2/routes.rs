@@ -0,0 +1,624 @@
+use crate::alerts::Alert;
+use crate::asset_scanners::AssetScanner;
+use crate::asset_tags::AssetTag;
+use crate::assets::Asset;
+use crate::attachments::Attachment;
+use crate::comments::Comment;
+use crate::contact_events::ContactEvent;
+use crate::db;
+use crate::error_handler::CustomError;
+use crate::etag;
+use crate::include::{self, IncludeQuery};
+use crate::loans::Loan;
+use crate::locations::Location;
+use crate::maintenance_plans::MaintenancePlan;
+use crate::maintenance_records::MaintenanceRecord;
+use crate::pagination::{Page, PageQuery};
+use crate::reservations::Reservation;
+use crate::roles::Role;
+use crate::rooms::Room;
+use crate::users::User;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use utoipa::OpenApi;
+
+/*
+ * The routes /api/v2 changes, registered ahead of those it shares with v1
+ *
+ * Listings come a page at a time, and single resources are at /{resource}/{id}.
+ * Ids only match digits, so that /{resource}/all and the like still reach the
+ * shared routes.
+ */
+
+#[utoipa::path(
+    get,
+    path = "/alerts",
+    params(PageQuery, IncludeQuery),
+    responses(
+        (status = 200, description = "A page of the alerts", body = Page<Alert>),
+    )
+)]
+#[get("/alerts")]
+async fn find_alerts(
+    page: web::Query<PageQuery>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let alerts = db::run(move || {
+        Alert::find_page(&page)?.try_map(|alerts| include::alerts(alerts, &query.include))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(alerts))
+}
+
+#[utoipa::path(
+    get,
+    path = "/alerts/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the alert"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The alert", body = Alert),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/alerts/{id:\\d+}")]
+async fn find_alert(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/alerts/{} {:?}", &id, &query);
+    let alert =
+        db::run(move || include::single(Alert::find_by_id(id)?, &query.include, include::alerts))
+            .await?;
+    etag::respond(&req, &alert)
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_scanners",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the asset scanners", body = Page<AssetScanner>),
+    )
+)]
+#[get("/asset_scanners")]
+async fn find_asset_scanners(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let asset_scanners = db::run(move || AssetScanner::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(asset_scanners))
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_scanners/{id}",
+    params(("id" = i64, Path, description = "Id of the asset scanner")),
+    responses(
+        (status = 200, description = "The asset scanner", body = AssetScanner),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/asset_scanners/{id:\\d+}")]
+async fn find_asset_scanner(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/asset_scanners/{}", &id);
+    let asset_scanner = db::run(move || AssetScanner::find_by_id(id)).await?;
+    etag::respond(&req, &asset_scanner)
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_tags",
+    params(PageQuery, IncludeQuery),
+    responses(
+        (status = 200, description = "A page of the asset tags", body = Page<AssetTag>),
+    )
+)]
+#[get("/asset_tags")]
+async fn find_asset_tags(
+    page: web::Query<PageQuery>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let asset_tags = db::run(move || {
+        AssetTag::find_page(&page)?
+            .try_map(|asset_tags| include::asset_tags(asset_tags, &query.include))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(asset_tags))
+}
+
+#[utoipa::path(
+    get,
+    path = "/asset_tags/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset tag"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The asset tag", body = AssetTag),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/asset_tags/{id:\\d+}")]
+async fn find_asset_tag(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/asset_tags/{} {:?}", &id, &query);
+    let asset_tag = db::run(move || {
+        include::single(
+            AssetTag::find_by_id(id)?,
+            &query.include,
+            include::asset_tags,
+        )
+    })
+    .await?;
+    etag::respond(&req, &asset_tag)
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets",
+    params(PageQuery, IncludeQuery),
+    responses(
+        (status = 200, description = "A page of the assets", body = Page<Asset>),
+    )
+)]
+#[get("/assets")]
+async fn find_assets(
+    page: web::Query<PageQuery>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let assets = db::run(move || {
+        Asset::find_page(&page)?.try_map(|assets| include::assets(assets, &query.include))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(assets))
+}
+
+#[utoipa::path(
+    get,
+    path = "/assets/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the asset"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The asset", body = Asset),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/assets/{id:\\d+}")]
+async fn find_asset(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/assets/{} {:?}", &id, &query);
+    let asset =
+        db::run(move || include::single(Asset::find_by_id(id)?, &query.include, include::assets))
+            .await?;
+    etag::respond(&req, &asset)
+}
+
+#[utoipa::path(
+    get,
+    path = "/attachments/{id}",
+    params(("id" = i64, Path, description = "Id of the attachment")),
+    responses(
+        (status = 200, description = "The attachment", body = Attachment),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/attachments/{id:\\d+}")]
+async fn find_attachment(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/attachments/{}", &id);
+    let attachment = db::run(move || Attachment::find_by_id(id)).await?;
+    etag::respond(&req, &attachment)
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the comments", body = Page<Comment>),
+    )
+)]
+#[get("/comments")]
+async fn find_comments(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let comments = db::run(move || Comment::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+#[utoipa::path(
+    get,
+    path = "/comments/{id}",
+    params(("id" = i64, Path, description = "Id of the comment")),
+    responses(
+        (status = 200, description = "The comment", body = Comment),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/comments/{id:\\d+}")]
+async fn find_comment(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/comments/{}", &id);
+    let comment = db::run(move || Comment::find_by_id(id)).await?;
+    etag::respond(&req, &comment)
+}
+
+#[utoipa::path(
+    get,
+    path = "/contact_events",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the contact events", body = Page<ContactEvent>),
+    )
+)]
+#[get("/contact_events")]
+async fn find_contact_events(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let contact_events = db::run(move || ContactEvent::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(contact_events))
+}
+
+#[utoipa::path(
+    get,
+    path = "/contact_events/{id}",
+    params(("id" = i64, Path, description = "Id of the contact event")),
+    responses(
+        (status = 200, description = "The contact event", body = ContactEvent),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/contact_events/{id:\\d+}")]
+async fn find_contact_event(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/contact_events/{}", &id);
+    let contact_event = db::run(move || ContactEvent::find_by_id(id)).await?;
+    etag::respond(&req, &contact_event)
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the loans", body = Page<Loan>),
+    )
+)]
+#[get("/loans")]
+async fn find_loans(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let loans = db::run(move || Loan::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(loans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/loans/{id}",
+    params(("id" = i64, Path, description = "Id of the loan")),
+    responses(
+        (status = 200, description = "The loan", body = Loan),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/loans/{id:\\d+}")]
+async fn find_loan(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/loans/{}", &id);
+    let loan = db::run(move || Loan::find_by_id(id)).await?;
+    etag::respond(&req, &loan)
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations",
+    params(PageQuery, IncludeQuery),
+    responses(
+        (status = 200, description = "A page of the locations", body = Page<Location>),
+    )
+)]
+#[get("/locations")]
+async fn find_locations(
+    page: web::Query<PageQuery>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let locations = db::run(move || {
+        Location::find_page(&page)?
+            .try_map(|locations| include::locations(locations, &query.include))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/locations/{id}",
+    params(
+        ("id" = i64, Path, description = "Id of the location"),
+        IncludeQuery,
+    ),
+    responses(
+        (status = 200, description = "The location", body = Location),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/locations/{id:\\d+}")]
+async fn find_location(
+    req: HttpRequest,
+    id: web::Path<i64>,
+    query: web::Query<IncludeQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/locations/{} {:?}", &id, &query);
+    let location = db::run(move || {
+        include::single(
+            Location::find_by_id(id)?,
+            &query.include,
+            include::locations,
+        )
+    })
+    .await?;
+    etag::respond(&req, &location)
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_plans",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the maintenance plans", body = Page<MaintenancePlan>),
+    )
+)]
+#[get("/maintenance_plans")]
+async fn find_maintenance_plans(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let maintenance_plans = db::run(move || MaintenancePlan::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_plans))
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_plans/{id}",
+    params(("id" = i64, Path, description = "Id of the maintenance plan")),
+    responses(
+        (status = 200, description = "The maintenance plan", body = MaintenancePlan),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/maintenance_plans/{id:\\d+}")]
+async fn find_maintenance_plan(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/maintenance_plans/{}", &id);
+    let maintenance_plan = db::run(move || MaintenancePlan::find_by_id(id)).await?;
+    etag::respond(&req, &maintenance_plan)
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the maintenance records", body = Page<MaintenanceRecord>),
+    )
+)]
+#[get("/maintenance_records")]
+async fn find_maintenance_records(
+    page: web::Query<PageQuery>,
+) -> Result<HttpResponse, CustomError> {
+    let maintenance_records = db::run(move || MaintenanceRecord::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(maintenance_records))
+}
+
+#[utoipa::path(
+    get,
+    path = "/maintenance_records/{id}",
+    params(("id" = i64, Path, description = "Id of the maintenance record")),
+    responses(
+        (status = 200, description = "The maintenance record", body = MaintenanceRecord),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/maintenance_records/{id:\\d+}")]
+async fn find_maintenance_record(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/maintenance_records/{}", &id);
+    let maintenance_record = db::run(move || MaintenanceRecord::find_by_id(id)).await?;
+    etag::respond(&req, &maintenance_record)
+}
+
+#[utoipa::path(
+    get,
+    path = "/reservations",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the reservations", body = Page<Reservation>),
+    )
+)]
+#[get("/reservations")]
+async fn find_reservations(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let reservations = db::run(move || Reservation::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(reservations))
+}
+
+#[utoipa::path(
+    get,
+    path = "/reservations/{id}",
+    params(("id" = i64, Path, description = "Id of the reservation")),
+    responses(
+        (status = 200, description = "The reservation", body = Reservation),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/reservations/{id:\\d+}")]
+async fn find_reservation(
+    req: HttpRequest,
+    id: web::Path<i64>,
+) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/reservations/{}", &id);
+    let reservation = db::run(move || Reservation::find_by_id(id)).await?;
+    etag::respond(&req, &reservation)
+}
+
+#[utoipa::path(
+    get,
+    path = "/roles",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the roles", body = Page<Role>),
+    )
+)]
+#[get("/roles")]
+async fn find_roles(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let roles = db::run(move || Role::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(roles))
+}
+
+#[utoipa::path(
+    get,
+    path = "/roles/{id}",
+    params(("id" = i64, Path, description = "Id of the role")),
+    responses(
+        (status = 200, description = "The role", body = Role),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/roles/{id:\\d+}")]
+async fn find_role(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/roles/{}", &id);
+    let role = db::run(move || Role::find_by_id(id)).await?;
+    etag::respond(&req, &role)
+}
+
+#[utoipa::path(
+    get,
+    path = "/rooms",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the rooms", body = Page<Room>),
+    )
+)]
+#[get("/rooms")]
+async fn find_rooms(page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let rooms = db::run(move || Room::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(rooms))
+}
+
+#[utoipa::path(
+    get,
+    path = "/rooms/{id}",
+    params(("id" = i64, Path, description = "Id of the room")),
+    responses(
+        (status = 200, description = "The room", body = Room),
+        (status = 304, description = "Not modified since the ETag in If-None-Match"),
+    )
+)]
+#[get("/rooms/{id:\\d+}")]
+async fn find_room(req: HttpRequest, id: web::Path<i64>) -> Result<HttpResponse, CustomError> {
+    let id = id.into_inner();
+    log::trace!("GET /api/v2/rooms/{}", &id);
+    let room = db::run(move || Room::find_by_id(id)).await?;
+    etag::respond(&req, &room)
+}
+
+#[utoipa::path(
+    get,
+    path = "/users",
+    params(PageQuery),
+    responses(
+        (status = 200, description = "A page of the users", body = Page<User>),
+    )
+)]
+#[get("/users")]
+async fn find_users(_user: User, page: web::Query<PageQuery>) -> Result<HttpResponse, CustomError> {
+    let users = db::run(move || User::find_page(&page)).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    find_alerts,
+    find_alert,
+    find_asset_scanners,
+    find_asset_scanner,
+    find_asset_tags,
+    find_asset_tag,
+    find_assets,
+    find_asset,
+    find_attachment,
+    find_comments,
+    find_comment,
+    find_contact_events,
+    find_contact_event,
+    find_loans,
+    find_loan,
+    find_locations,
+    find_location,
+    find_maintenance_plans,
+    find_maintenance_plan,
+    find_maintenance_records,
+    find_maintenance_record,
+    find_reservations,
+    find_reservation,
+    find_roles,
+    find_role,
+    find_rooms,
+    find_room,
+    find_users
+))]
+struct ApiDoc;
+
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+pub fn init_routes(comfig: &mut web::ServiceConfig) {
+    comfig.service(find_alerts);
+    comfig.service(find_alert);
+    comfig.service(find_asset_scanners);
+    comfig.service(find_asset_scanner);
+    comfig.service(find_asset_tags);
+    comfig.service(find_asset_tag);
+    comfig.service(find_assets);
+    comfig.service(find_asset);
+    comfig.service(find_attachment);
+    comfig.service(find_comments);
+    comfig.service(find_comment);
+    comfig.service(find_contact_events);
+    comfig.service(find_contact_event);
+    comfig.service(find_loans);
+    comfig.service(find_loan);
+    comfig.service(find_locations);
+    comfig.service(find_location);
+    comfig.service(find_maintenance_plans);
+    comfig.service(find_maintenance_plan);
+    comfig.service(find_maintenance_records);
+    comfig.service(find_maintenance_record);
+    comfig.service(find_reservations);
+    comfig.service(find_reservation);
+    comfig.service(find_roles);
+    comfig.service(find_role);
+    comfig.service(find_rooms);
+    comfig.service(find_room);
+    comfig.service(find_users);
+}
@@ -0,0 +1,126 @@
+use crate::db;
+use crate::error_handler::{CustomError, ErrorDetail};
+use crate::schema::{asset_tags, locations, users};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use validator::{ValidateArgs, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+/*
+ * Validating what clients send
+ *
+ * Every body a route or mutation takes declares its limits with
+ * #[validate(...)], and is checked before the model layer sees it. A body
+ * breaking them gets a 422 with an error for each field, rather than whatever
+ * the database makes of it. References are checked to exist too, leaving out
+ * soft-deleted rows, as the model layer can't find those either. They are
+ * looked up on the connection the caller holds, declared with
+ * #[validate(context = PgConnection)], so a write validating inside its
+ * transaction never waits on the pool for a second connection.
+ */
+
+// The longest name, reason, serial number and the like
+pub const MAX_NAME: u64 = 255;
+// The longest free text, like a comment or a description
+pub const MAX_TEXT: u64 = 10_000;
+
+fn message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("length", Some(min), Some(max)) => format!("must be {} to {} characters long", min, max),
+        ("length", Some(min), None) => format!("must be at least {} characters long", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters long", max),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        ("range", Some(min), None) => format!("must be at least {}", min),
+        ("range", None, Some(max)) => format!("must be at most {}", max),
+        (code, _, _) => format!("is invalid ({})", code),
+    }
+}
+
+// Nested bodies name their fields by path, like recurrence.count
+fn details(errors: &ValidationErrors, prefix: &str) -> Vec<ErrorDetail> {
+    let mut details = Vec::new();
+    for (field, kind) in errors.errors() {
+        let field = format!("{}{}", prefix, field);
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                details.extend(errors.iter().map(|error| ErrorDetail {
+                    field: field.clone(),
+                    message: message(error),
+                }))
+            }
+            ValidationErrorsKind::Struct(errors) => {
+                details.extend(self::details(errors, &format!("{}.", field)))
+            }
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    details.extend(self::details(errors, &format!("{}[{}].", field, index)))
+                }
+            }
+        }
+    }
+    details.sort_by(|a, b| a.field.cmp(&b.field));
+    details
+}
+
+// A reference that couldn't be looked up, which is the server's fault, not the body's
+fn unavailable(errors: &ValidationErrors) -> bool {
+    errors.errors().values().any(|kind| match kind {
+        ValidationErrorsKind::Field(errors) => errors.iter().any(|error| error.code == UNAVAILABLE),
+        ValidationErrorsKind::Struct(errors) => unavailable(errors),
+        ValidationErrorsKind::List(errors) => errors.values().any(|errors| unavailable(errors)),
+    })
+}
+
+pub fn validate<T>(input: &T, conn: &PgConnection) -> Result<(), CustomError>
+where
+    T: for<'a> ValidateArgs<'a, Args = &'a PgConnection>,
+{
+    input.validate_with_args(conn).map_err(|errors| {
+        if unavailable(&errors) {
+            return CustomError::new(500, String::from("Failed looking up references"));
+        }
+        CustomError::new(422, String::from("The request is invalid"))
+            .with_details(details(&errors, ""))
+    })
+}
+
+// References are looked up, so routes validate on the blocking thread pool
+pub async fn validated<T>(input: T) -> Result<T, CustomError>
+where
+    T: for<'a> ValidateArgs<'a, Args = &'a PgConnection> + Send + 'static,
+{
+    db::run(move || {
+        let conn = db::connection()?;
+        validate(&input, &conn).map(|()| input)
+    })
+    .await
+}
+
+const UNAVAILABLE: &str = "unavailable";
+
+// A reference to a row that can be found, for #[validate(custom(function = ..., use_context))]
+macro_rules! exists {
+    ($name:ident, $table:ident, $noun:expr) => {
+        pub fn $name(id: i64, conn: &PgConnection) -> Result<(), ValidationError> {
+            let found = diesel::select(diesel::dsl::exists(
+                $table::table
+                    .filter($table::id.eq(id))
+                    .filter($table::deleted.eq(false)),
+            ))
+            .get_result::<bool>(conn);
+            match found {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(ValidationError::new("not_found")
+                    .with_message(format!("{} {} does not exist", $noun, id).into())),
+                Err(_) => Err(ValidationError::new(UNAVAILABLE)),
+            }
+        }
+    };
+}
+
+exists!(asset_tag_exists, asset_tags, "Asset tag");
+exists!(location_exists, locations, "Location");
+exists!(user_exists, users, "User");
@@ -0,0 +1,35 @@
+use actix_web::middleware::DefaultHeaders;
+
+/*
+ * API versions
+ *
+ * Every route is mounted under /api/v1, and at the root as it always was so
+ * existing clients keep working. Both are deprecated in favour of /api/v2,
+ * where breaking changes land: single resources at /{resource}/{id} rather
+ * than /{resource}/id/{id}, and listings paginated in an envelope. Routes v2
+ * doesn't change are served there as they are in v1.
+ */
+
+pub const V1: &str = "/api/v1";
+pub const V2: &str = "/api/v2";
+
+// When v1 was deprecated, as an RFC 9745 date, and when it will be removed
+pub const DEPRECATION: &str = "@1792368000";
+pub const SUNSET: &str = "Tue, 19 Oct 2027 00:00:00 GMT";
+
+// Headers telling clients of v1 that it is going away and what replaces it
+pub fn deprecation() -> DefaultHeaders {
+    DefaultHeaders::new()
+        .header("Deprecation", DEPRECATION)
+        .header("Sunset", SUNSET)
+        .header("Link", format!("<{}>; rel=\"successor-version\"", V2))
+}
+
+// A path with its version prefix if any, as the route it is for was declared
+pub fn unversioned(path: &str) -> &str {
+    [V1, V2]
+        .iter()
+        .find_map(|version| path.strip_prefix(version))
+        .filter(|rest| rest.starts_with('/'))
+        .unwrap_or(path)
+}